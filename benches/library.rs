@@ -0,0 +1,115 @@
+use std::{fs, path::{Path, PathBuf}};
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use id3::{Tag, TagLike, Version};
+use tempfile::TempDir;
+
+use music_player::config::ScanConfig;
+use music_player::library::{scan_directory, search, BrowseIndex, Song};
+use music_player::replaygain::ReplayGain;
+
+const SIZES: [usize; 3] = [1_000, 10_000, 50_000];
+
+// Deterministic names so runs are comparable: artists own albums, albums own tracks
+fn synthetic_songs(count: usize) -> Vec<Song> {
+    (0..count)
+        .map(|i| {
+            let artist = format!("Artist {}", i / 100);
+            let album = format!("Album {}", i / 10);
+            let title = format!("Track {} of {}", i % 10, album);
+            Song {
+                path: PathBuf::from(format!("/music/{}/{}/{:02} {}.mp3", artist, album, i % 10, title)),
+                label: format!("{} - {}", artist, title),
+                title,
+                artist,
+                album,
+                genre: format!("Genre {}", i % 20),
+                album_artist: None,
+                year: None,
+                track: Some((i % 10 + 1) as u32),
+                duration: None,
+                bpm: None,
+                key: None,
+                dr: None,
+                tag_stats: None,
+                replay_gain: ReplayGain::default(),
+                modified: None,
+                added: std::time::UNIX_EPOCH,
+            }
+        })
+        .collect()
+}
+
+// Lays the library out on disk as Artist/Album/NN Artist - Title.mp3 with ID3 tags.
+// The files hold only a tag, which is all scanning and tag parsing look at.
+fn synthetic_library(count: usize) -> TempDir {
+    let root = tempfile::tempdir().unwrap();
+    for song in synthetic_songs(count) {
+        let dir = root.path().join(&song.artist).join(&song.album);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(format!("{} - {}.mp3", song.artist, song.title));
+        fs::write(&path, b"").unwrap();
+
+        let mut tag = Tag::new();
+        tag.set_title(song.title);
+        tag.set_artist(song.artist);
+        tag.set_album(song.album);
+        tag.set_genre(song.genre);
+        tag.write_to_path(&path, Version::Id3v24).unwrap();
+    }
+    root
+}
+
+fn scan_paths(dir: &Path) -> Vec<PathBuf> {
+    let mut paths = Vec::new();
+    scan_directory(dir, &ScanConfig::default(), |path| paths.push(path)).unwrap();
+    paths
+}
+
+fn bench_scanning(c: &mut Criterion) {
+    let mut group = c.benchmark_group("scan");
+    group.sample_size(10);
+    for count in [1_000, 10_000] {
+        let library = synthetic_library(count);
+        group.bench_with_input(BenchmarkId::new("walk", count), library.path(), |b, dir| {
+            b.iter(|| scan_paths(dir))
+        });
+        let paths = scan_paths(library.path());
+        group.bench_with_input(BenchmarkId::new("read_tags", count), &paths, |b, paths| {
+            b.iter(|| paths.iter().map(|path| Song::new(path.clone())).collect::<Vec<_>>())
+        });
+    }
+    group.finish();
+}
+
+fn bench_search(c: &mut Criterion) {
+    let mut group = c.benchmark_group("search");
+    for count in SIZES {
+        let songs = synthetic_songs(count);
+        // A query that matches a handful of songs and one that matches nothing
+        for query in ["track 7 of album 42", "no such song"] {
+            group.bench_with_input(BenchmarkId::new(query, count), &songs, |b, songs| {
+                b.iter(|| search(songs, black_box(query)))
+            });
+        }
+    }
+    group.finish();
+}
+
+fn bench_browse_index(c: &mut Criterion) {
+    let mut group = c.benchmark_group("browse_index");
+    for count in SIZES {
+        let songs = synthetic_songs(count);
+        group.bench_with_input(BenchmarkId::new("rebuild", count), &songs, |b, songs| {
+            b.iter(|| {
+                let mut index = BrowseIndex::default();
+                index.rebuild(songs);
+                index
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_scanning, bench_search, bench_browse_index);
+criterion_main!(benches);
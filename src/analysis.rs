@@ -0,0 +1,214 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::decode::{self, Diagnostics};
+use crate::library::Song;
+use crate::persist;
+
+// Length of the blocks loudness is measured over, as in the DR meter
+const BLOCK_SECONDS: u32 = 3;
+
+// What decoding a whole track found out about it
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq, Debug)]
+#[serde(default)]
+pub struct TrackAnalysis {
+    // The file as it was when measured; a newer file is measured again
+    pub modified: Option<SystemTime>,
+    // Dynamic range in dB; None when the file couldn't be decoded
+    pub dr: Option<u8>,
+    pub diagnostics: Diagnostics,
+    // Times playing it failed, counted until the file changes
+    pub failures: u32,
+}
+
+// Results by file path, kept across runs since decoding everything is slow
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct Analysis {
+    pub songs: HashMap<PathBuf, TrackAnalysis>,
+}
+
+impl Analysis {
+    // ~/.local/share/music-player/analysis.json on Linux, %APPDATA%\music-player\analysis.json on Windows
+    pub fn path() -> Option<PathBuf> {
+        dirs::data_dir().map(|dir| dir.join("music-player").join("analysis.json"))
+    }
+
+    pub fn load() -> Self {
+        Self::path()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let path = Self::path().ok_or_else(|| anyhow::anyhow!("No data directory"))?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        persist::write_atomic(&path, &serde_json::to_string(self)?)
+    }
+
+    // Only results for the file as it is now
+    pub fn get(&self, song: &Song) -> Option<&TrackAnalysis> {
+        self.songs.get(&song.path).filter(|analysis| analysis.modified == song.modified)
+    }
+
+    // Keeps what opening `song` for playback went through; false if there
+    // was nothing worth keeping
+    pub fn record_decode(&mut self, song: &Song, diagnostics: Diagnostics) -> bool {
+        let known = self.get(song);
+        let unchanged = known.map_or(diagnostics.errors.is_empty(), |known| known.diagnostics == diagnostics);
+        if unchanged && diagnostics.decoder.is_some() {
+            return false;
+        }
+        let known = known.is_some();
+        let entry = self.songs.entry(song.path.clone()).or_default();
+        if !known {
+            // Measured for an older version of the file, or not at all
+            *entry = TrackAnalysis { modified: song.modified, ..TrackAnalysis::default() };
+        }
+        if diagnostics.decoder.is_none() {
+            entry.failures += 1;
+        }
+        entry.diagnostics = diagnostics;
+        true
+    }
+
+    // Files that could not be decoded at all, or only by a fallback decoder
+    pub fn problems(&self) -> Vec<(&PathBuf, &TrackAnalysis)> {
+        let mut problems: Vec<_> = self.songs.iter()
+            .filter(|(_, analysis)| !analysis.diagnostics.errors.is_empty() || analysis.failures > 0)
+            .collect();
+        // Chronic failures first
+        problems.sort_by(|a, b| b.1.failures.cmp(&a.1.failures).then_with(|| a.0.cmp(b.0)));
+        problems
+    }
+}
+
+// Decodes the whole file to measure it
+pub fn analyze(path: &Path, modified: Option<SystemTime>) -> TrackAnalysis {
+    let (source, diagnostics) = decode::open(path);
+    let dr = source.and_then(|source| {
+        let (channels, rate) = (source.channels(), source.sample_rate());
+        dynamic_range(source.map(|sample| sample as f32 / 32768.0), channels, rate)
+    });
+    TrackAnalysis { modified, dr, diagnostics, failures: 0 }
+}
+
+// The DR meter's score: per channel, how far the second-highest block peak
+// sits above the RMS of the loudest fifth of the 3 second blocks, averaged
+// over the channels and rounded to whole dB. Heavily limited masters score
+// 3-6, dynamic ones 12 and up.
+pub fn dynamic_range(samples: impl Iterator<Item = f32>, channels: u16, sample_rate: u32) -> Option<u8> {
+    let channels = channels.max(1) as usize;
+    let block_len = (sample_rate * BLOCK_SECONDS) as usize;
+    // Per channel: (sum of squares, peak) of each block
+    let mut blocks: Vec<Vec<(f64, f32)>> = vec![Vec::new(); channels];
+    let mut filled = 0;
+    for (i, sample) in samples.enumerate() {
+        let channel = i % channels;
+        if channel == 0 {
+            if filled % block_len == 0 {
+                blocks.iter_mut().for_each(|blocks| blocks.push((0.0, 0.0)));
+            }
+            filled += 1;
+        }
+        let block = blocks[channel].last_mut()?;
+        block.0 += (sample as f64).powi(2);
+        block.1 = block.1.max(sample.abs());
+    }
+
+    let mut scores = Vec::new();
+    for channel in blocks {
+        let count = channel.len();
+        if count == 0 {
+            return None;
+        }
+        let last_len = match filled % block_len {
+            0 => block_len,
+            partial => partial,
+        };
+        // RMS scaled by √2 so a full-scale sine measures 0 dB, as the meter does
+        let mut rms: Vec<f64> = channel.iter()
+            .enumerate()
+            .map(|(i, (squares, _))| {
+                let len = if i + 1 == count { last_len } else { block_len };
+                (2.0 * squares / len as f64).sqrt()
+            })
+            .collect();
+        let mut peaks: Vec<f32> = channel.iter().map(|(_, peak)| *peak).collect();
+        rms.sort_by(|a, b| b.total_cmp(a));
+        peaks.sort_by(|a, b| b.total_cmp(a));
+
+        let loudest = ((count as f64 * 0.2).round() as usize).max(1);
+        let top_rms = (rms[..loudest].iter().map(|r| r * r).sum::<f64>() / loudest as f64).sqrt();
+        let peak = peaks.get(1).copied().unwrap_or(peaks[0]) as f64;
+        if top_rms <= 0.0 || peak <= 0.0 {
+            return None;
+        }
+        scores.push(20.0 * (peak / top_rms).log10());
+    }
+    let score = scores.iter().sum::<f64>() / scores.len() as f64;
+    Some(score.round().clamp(0.0, u8::MAX as f64) as u8)
+}
+
+// Groups of songs that look like the same recording (same artist and title),
+// each sorted best mastered first, unmeasured last
+pub fn duplicates(songs: &[Song]) -> Vec<Vec<usize>> {
+    let mut groups: BTreeMap<(String, String), Vec<usize>> = BTreeMap::new();
+    for (i, song) in songs.iter().enumerate() {
+        groups.entry((song.artist.to_lowercase(), song.title.to_lowercase())).or_default().push(i);
+    }
+    groups.into_values()
+        .filter(|group| group.len() > 1)
+        .map(|mut group| {
+            group.sort_by_key(|&i| std::cmp::Reverse(songs[i].dr));
+            group
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::TAU;
+
+    const RATE: u32 = 1000;
+
+    fn sine(amplitude: f32, seconds: u32) -> impl Iterator<Item = f32> {
+        (0..RATE * seconds).map(move |i| amplitude * (i as f32 * TAU * 50.0 / RATE as f32).sin())
+    }
+
+    #[test]
+    fn squashed_audio_scores_low_and_peaky_audio_high() {
+        // A steady sine has its peaks right at its (scaled) RMS
+        assert_eq!(dynamic_range(sine(0.5, 30), 1, RATE), Some(0));
+
+        // Quiet music with a couple of full-scale transients
+        let mut samples: Vec<f32> = sine(0.1, 30).collect();
+        samples[100] = 1.0;
+        samples[RATE as usize * 10] = 1.0;
+        assert_eq!(dynamic_range(samples.into_iter(), 1, RATE), Some(20));
+
+        assert_eq!(dynamic_range(std::iter::empty(), 2, RATE), None);
+    }
+
+    #[test]
+    fn duplicates_put_the_most_dynamic_first() {
+        let mut songs: Vec<Song> = ["Radiohead - Karma Police", "Björk - Hyperballad", "radiohead - karma police"]
+            .iter()
+            .map(|name| Song::new(PathBuf::from(format!("/music/{}.mp3", name))))
+            .collect();
+        songs[0].dr = Some(7);
+        songs[2].dr = Some(12);
+        assert_eq!(duplicates(&songs), vec![vec![2, 0]]);
+    }
+}
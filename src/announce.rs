@@ -0,0 +1,302 @@
+use std::{
+    fs,
+    io::{Read, Write},
+    net::{TcpListener, TcpStream, UdpSocket},
+    sync::{
+        mpsc::{self, Sender},
+        Arc, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+use anyhow::Result;
+use serde::Serialize;
+
+use music_player::config::{Config, HttpConfig, MqttConfig, NowPlayingFileConfig, OscConfig, WebhookConfig};
+use music_player::history::Entry;
+use music_player::metrics::Metrics;
+use music_player::net;
+use music_player::webhooks::{self, Delivery, Event, Log};
+use crate::remote::RemoteServer;
+use crate::Action;
+
+#[derive(Serialize, Clone)]
+pub struct NowPlaying {
+    pub state: &'static str,
+    pub title: String,
+    pub artist: String,
+    pub album: String,
+    pub genre: String,
+    pub path: String,
+}
+
+// What a webhook is POSTed: the track the event is about, if any, and the
+// queue as it is now
+#[derive(Serialize)]
+pub struct Hook {
+    pub event: Event,
+    pub track: Option<NowPlaying>,
+    pub queue: Vec<String>,
+}
+
+// Publishes now-playing changes to MQTT/OSC from a background thread so a slow
+// or unreachable broker never stalls the UI, and keeps the HTTP API current.
+pub struct Announcer {
+    tx: Option<Sender<NowPlaying>>,
+    file_tx: Option<Sender<NowPlaying>>,
+    remote: Option<RemoteServer>,
+    http: Option<HttpConfig>,
+    // A worker each, so one slow or failing URL doesn't hold up the others
+    webhooks: Vec<(WebhookConfig, Sender<(Event, String)>)>,
+    deliveries: Arc<Mutex<Log>>,
+}
+
+impl Announcer {
+    pub fn new(config: &Config, listeners: Vec<TcpListener>, commands: Sender<Action>) -> Result<Self> {
+        let remote = match &config.http {
+            Some(http) => Some(RemoteServer::start(http, listeners, commands)?),
+            None => None,
+        };
+        let (tx, file_tx) = spawn_workers(config);
+        let deliveries = Arc::new(Mutex::new(Log::default()));
+        let webhooks = spawn_webhooks(config, &deliveries);
+        Ok(Announcer { tx, file_tx, remote, http: config.http.clone(), webhooks, deliveries })
+    }
+
+    // Applies a reloaded config. The HTTP listener thread can't be stopped, so
+    // a changed [http] section is reported and left for the next start.
+    pub fn reconfigure(&mut self, config: &Config) -> Result<()> {
+        // Replacing the senders ends the old worker threads
+        (self.tx, self.file_tx) = spawn_workers(config);
+        self.webhooks = spawn_webhooks(config, &self.deliveries);
+        if config.http != self.http {
+            return Err(anyhow::anyhow!("HTTP settings take effect after a restart"));
+        }
+        Ok(())
+    }
+
+    // Only the HTTP API keeps the listening history, so there's no point
+    // putting it together without one
+    pub fn serves_history(&self) -> bool {
+        self.remote.is_some()
+    }
+
+    pub fn set_history(&self, history: Vec<Entry>) {
+        if let Some(remote) = &self.remote {
+            remote.set_history(history);
+        }
+    }
+
+    pub fn update_metrics(&self, update: impl FnOnce(&mut Metrics)) {
+        if let Some(remote) = &self.remote {
+            remote.update_metrics(update);
+        }
+    }
+
+    pub fn record_play(&self, entry: Entry) {
+        if let Some(remote) = &self.remote {
+            remote.record_play(entry);
+        }
+    }
+
+    // Whether any webhook wants `event`, before going to the trouble of `hook`
+    pub fn hooks(&self, event: Event) -> bool {
+        self.webhooks.iter().any(|(config, _)| config.events.contains(&event))
+    }
+
+    pub fn hook(&self, hook: &Hook) {
+        let Ok(json) = serde_json::to_string(hook) else {
+            return;
+        };
+        for (config, tx) in &self.webhooks {
+            if config.events.contains(&hook.event) {
+                let _ = tx.send((hook.event, json.clone()));
+            }
+        }
+    }
+
+    // Newest first
+    pub fn deliveries(&self) -> Vec<Delivery> {
+        self.deliveries.lock().unwrap().recent()
+    }
+
+    pub fn announce(&self, now_playing: NowPlaying) {
+        if let Some(remote) = &self.remote {
+            remote.update(&now_playing);
+        }
+        if let Some(file_tx) = &self.file_tx {
+            let _ = file_tx.send(now_playing.clone());
+        }
+        if let Some(tx) = &self.tx {
+            let _ = tx.send(now_playing);
+        }
+    }
+}
+
+fn spawn_workers(config: &Config) -> (Option<Sender<NowPlaying>>, Option<Sender<NowPlaying>>) {
+    // Separate thread because opening a FIFO blocks until something reads it
+    let file_tx = config.now_playing_file.clone().map(|file_config| {
+        let (tx, rx) = mpsc::channel::<NowPlaying>();
+        thread::spawn(move || {
+            while let Ok(now_playing) = rx.recv() {
+                let _ = write_now_playing_file(&file_config, &now_playing);
+            }
+        });
+        tx
+    });
+
+    let config = config.announce.clone();
+    if config.mqtt.is_none() && config.osc.is_none() {
+        return (None, file_tx);
+    }
+
+    let (tx, rx) = mpsc::channel::<NowPlaying>();
+    thread::spawn(move || {
+        while let Ok(now_playing) = rx.recv() {
+            let json = match serde_json::to_string(&now_playing) {
+                Ok(json) => json,
+                Err(_) => continue,
+            };
+            // Failures are ignored: announcements are best effort
+            if let Some(mqtt) = &config.mqtt {
+                let _ = publish_mqtt(mqtt, json.as_bytes());
+            }
+            if let Some(osc) = &config.osc {
+                let _ = send_osc(osc, &json);
+            }
+        }
+    });
+    (Some(tx), file_tx)
+}
+
+fn spawn_webhooks(config: &Config, log: &Arc<Mutex<Log>>) -> Vec<(WebhookConfig, Sender<(Event, String)>)> {
+    config.announce.webhooks.iter()
+        .filter(|webhook| !webhook.url.is_empty())
+        .map(|webhook| {
+            let (tx, rx) = mpsc::channel::<(Event, String)>();
+            let (config, log) = (webhook.clone(), log.clone());
+            thread::spawn(move || {
+                while let Ok((event, json)) = rx.recv() {
+                    let delivery = webhooks::deliver(event, &config.url, config.retries, Duration::from_secs(1), || post_json(&config.url, &json));
+                    log.lock().unwrap().push(delivery);
+                }
+            });
+            (webhook.clone(), tx)
+        })
+        .collect()
+}
+
+fn post_json(url: &str, json: &str) -> Result<()> {
+    net::post(url, "application/json", json.as_bytes())?;
+    Ok(())
+}
+
+impl NowPlaying {
+    // Fills in {title}, {artist}, {album}, {genre}, {state} and {path}
+    pub fn format(&self, template: &str) -> String {
+        template
+            .replace("{title}", &self.title)
+            .replace("{artist}", &self.artist)
+            .replace("{album}", &self.album)
+            .replace("{genre}", &self.genre)
+            .replace("{state}", self.state)
+            .replace("{path}", &self.path)
+    }
+}
+
+fn write_now_playing_file(config: &NowPlayingFileConfig, now_playing: &NowPlaying) -> Result<()> {
+    let line = if config.format == "json" {
+        serde_json::to_string(now_playing)?
+    } else {
+        now_playing.format(&config.format)
+    };
+    // Truncating write: status bars re-read a plain file, FIFO readers get one line per change
+    fs::write(&config.path, format!("{}\n", line))?;
+    Ok(())
+}
+
+fn push_mqtt_string(buf: &mut Vec<u8>, s: &str) {
+    buf.extend_from_slice(&(s.len() as u16).to_be_bytes());
+    buf.extend_from_slice(s.as_bytes());
+}
+
+fn mqtt_packet(header: u8, body: &[u8]) -> Vec<u8> {
+    let mut packet = vec![header];
+    // MQTT variable length encoding of the remaining length
+    let mut len = body.len();
+    loop {
+        let mut byte = (len % 128) as u8;
+        len /= 128;
+        if len > 0 {
+            byte |= 0x80;
+        }
+        packet.push(byte);
+        if len == 0 {
+            break;
+        }
+    }
+    packet.extend_from_slice(body);
+    packet
+}
+
+// Minimal MQTT 3.1.1 client: connect, publish a retained QoS 0 message, disconnect.
+// Track changes are rare enough that a connection per message is fine.
+fn publish_mqtt(config: &MqttConfig, payload: &[u8]) -> Result<()> {
+    let mut stream = TcpStream::connect((config.host.as_str(), config.port))?;
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    stream.set_write_timeout(Some(Duration::from_secs(5)))?;
+
+    let mut flags = 0x02; // clean session
+    if config.username.is_some() {
+        flags |= 0x80;
+    }
+    if config.password.is_some() {
+        flags |= 0x40;
+    }
+
+    let mut connect = Vec::new();
+    push_mqtt_string(&mut connect, "MQTT");
+    connect.push(4); // protocol level 3.1.1
+    connect.push(flags);
+    connect.extend_from_slice(&60u16.to_be_bytes()); // keep alive
+    push_mqtt_string(&mut connect, &config.client_id);
+    if let Some(username) = &config.username {
+        push_mqtt_string(&mut connect, username);
+    }
+    if let Some(password) = &config.password {
+        push_mqtt_string(&mut connect, password);
+    }
+    stream.write_all(&mqtt_packet(0x10, &connect))?;
+
+    let mut connack = [0u8; 4];
+    stream.read_exact(&mut connack)?;
+    if connack[0] != 0x20 || connack[3] != 0 {
+        return Err(anyhow::anyhow!("MQTT connection refused (code {})", connack[3]));
+    }
+
+    let mut publish = Vec::new();
+    push_mqtt_string(&mut publish, &config.topic);
+    publish.extend_from_slice(payload);
+    stream.write_all(&mqtt_packet(0x31, &publish))?; // PUBLISH, QoS 0, retain
+    stream.write_all(&[0xE0, 0x00])?; // DISCONNECT
+    Ok(())
+}
+
+fn push_osc_string(buf: &mut Vec<u8>, s: &str) {
+    buf.extend_from_slice(s.as_bytes());
+    // OSC strings are null terminated and padded to a multiple of 4 bytes
+    let padding = 4 - (s.len() % 4);
+    buf.extend(std::iter::repeat_n(0, padding));
+}
+
+fn send_osc(config: &OscConfig, json: &str) -> Result<()> {
+    let mut message = Vec::new();
+    push_osc_string(&mut message, &config.address);
+    push_osc_string(&mut message, ",s");
+    push_osc_string(&mut message, json);
+
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    socket.send_to(&message, config.target.as_str())?;
+    Ok(())
+}
@@ -0,0 +1,134 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::import::{self, ImportedTrack};
+use crate::library::Song;
+use crate::persist;
+use crate::state::State;
+
+const VERSION: u32 = 1;
+
+// Everything needed to set the player up on another machine except the audio
+// itself, written by `:export state FILE` as one JSON file
+#[derive(Serialize, Deserialize)]
+pub struct Archive {
+    pub version: u32,
+    // config.toml exactly as it was written, comments and all
+    pub config: Option<String>,
+    pub state: State,
+    pub music_dirs: Vec<PathBuf>,
+    pub songs: Vec<SongRecord>,
+    pub playlists: Vec<PlaylistRecord>,
+}
+
+// Tags of a library song, for finding it again once the files live elsewhere
+#[derive(Serialize, Deserialize)]
+pub struct SongRecord {
+    pub path: PathBuf,
+    pub title: String,
+    pub artist: String,
+    pub album: String,
+    pub genre: String,
+    pub track: Option<u32>,
+    pub duration_ms: Option<u64>,
+}
+
+impl From<&Song> for SongRecord {
+    fn from(song: &Song) -> Self {
+        SongRecord {
+            path: song.path.clone(),
+            title: song.title.clone(),
+            artist: song.artist.clone(),
+            album: song.album.clone(),
+            genre: song.genre.clone(),
+            track: song.track,
+            duration_ms: song.duration.map(|duration| duration.as_millis() as u64),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PlaylistRecord {
+    pub name: String,
+    pub entries: Vec<PathBuf>,
+}
+
+impl Archive {
+    pub fn new(
+        config: Option<String>,
+        state: State,
+        music_dirs: Vec<PathBuf>,
+        songs: &[Song],
+        playlists: Vec<PlaylistRecord>,
+    ) -> Self {
+        Archive {
+            version: VERSION,
+            config,
+            state,
+            music_dirs,
+            songs: songs.iter().map(SongRecord::from).collect(),
+            playlists,
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        persist::write_atomic(path, &serde_json::to_string_pretty(self)?)
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let archive: Archive = serde_json::from_str(&fs::read_to_string(path)?)
+            .map_err(|e| anyhow::anyhow!("Not a state archive: {}", e))?;
+        if archive.version > VERSION {
+            return Err(anyhow::anyhow!("Archive is from a newer version of the player"));
+        }
+        Ok(archive)
+    }
+
+    // The library song that an exported path is most likely to be now,
+    // matched on the tags recorded for it
+    pub fn relink(&self, path: &Path, songs: &[Song]) -> Option<usize> {
+        let record = self.songs.iter().find(|record| record.path == path)?;
+        let track = ImportedTrack {
+            title: record.title.clone(),
+            artist: record.artist.clone(),
+            duration: record.duration_ms.map(Duration::from_millis),
+        };
+        import::match_tracks(&[track], songs).matched.first().copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip_and_relink_by_tags() {
+        let old = vec![Song::new(PathBuf::from("/old/Radiohead - Karma Police.mp3"))];
+        let archive = Archive::new(
+            Some(String::from("# mine\n")),
+            State::default(),
+            vec![PathBuf::from("/old")],
+            &old,
+            vec![PlaylistRecord { name: String::from("Mix"), entries: vec![old[0].path.clone()] }],
+        );
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("setup.json");
+        archive.save(&path).unwrap();
+        let loaded = Archive::load(&path).unwrap();
+        assert_eq!(loaded.config.as_deref(), Some("# mine\n"));
+        assert_eq!(loaded.playlists[0].entries, vec![PathBuf::from("/old/Radiohead - Karma Police.mp3")]);
+
+        let new = vec![
+            Song::new(PathBuf::from("/new/Björk - Hyperballad.mp3")),
+            Song::new(PathBuf::from("/new/renamed/Radiohead - Karma Police.flac")),
+        ];
+        assert_eq!(loaded.relink(&old[0].path, &new), Some(1));
+        assert_eq!(loaded.relink(Path::new("/old/unknown.mp3"), &new), None);
+    }
+}
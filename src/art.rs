@@ -0,0 +1,237 @@
+use std::{
+    fs,
+    io::Cursor,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+use id3::Tag;
+use image::{imageops::FilterType, ImageOutputFormat};
+use serde_json::Value;
+
+use crate::library::{Song, UNKNOWN_ALBUM};
+use crate::net::curl;
+use crate::persist;
+
+// Thumbnails fit in a square this many pixels wide
+pub const THUMBNAIL_SIZE: u32 = 300;
+
+// Embedded ID3 picture first, then a cover image next to the file
+pub fn cover_art(path: &Path) -> Option<(String, Vec<u8>)> {
+    if let Ok(tag) = Tag::read_from_path(path) {
+        let picture = tag.pictures()
+            .find(|p| p.picture_type == id3::frame::PictureType::CoverFront)
+            .or_else(|| tag.pictures().next());
+        if let Some(picture) = picture {
+            return Some((picture.mime_type.clone(), picture.data.clone()));
+        }
+    }
+
+    let dir = path.parent()?;
+    for name in ["cover.jpg", "cover.png", "folder.jpg", "folder.png", "front.jpg"] {
+        let candidate = dir.join(name);
+        if let Ok(data) = fs::read(&candidate) {
+            let mime = if name.ends_with(".png") { "image/png" } else { "image/jpeg" };
+            return Some((mime.to_string(), data));
+        }
+    }
+    None
+}
+
+// One thumbnail per album in the cache directory. Albums are looked up once:
+// embedded art, then a cover file next to the songs, then (if enabled) Cover
+// Art Archive. Albums without any art get a marker so they aren't retried.
+#[derive(Clone)]
+pub struct ArtCache {
+    // None keeps nothing on disk and only reads local art
+    dir: Option<PathBuf>,
+    fetch: bool,
+}
+
+impl ArtCache {
+    pub fn new(dir: Option<PathBuf>, fetch: bool) -> Self {
+        ArtCache { dir, fetch }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.dir.is_some()
+    }
+
+    // ~/.cache/music-player/covers on Linux, %LOCALAPPDATA%\music-player\covers on Windows
+    pub fn default_dir() -> Option<PathBuf> {
+        dirs::cache_dir().map(|dir| dir.join("music-player").join("covers"))
+    }
+
+    // The thumbnail, if the album has been looked up and had art
+    pub fn cached(&self, song: &Song) -> Option<PathBuf> {
+        let thumbnail = self.dir.as_ref()?.join(format!("{}.png", album_key(song)));
+        thumbnail.exists().then_some(thumbnail)
+    }
+
+    // Slow on a miss (decoding, maybe the network), so it belongs on a worker thread
+    pub fn lookup(&self, song: &Song) -> Option<PathBuf> {
+        let dir = self.dir.as_ref()?;
+        let key = album_key(song);
+        let thumbnail = dir.join(format!("{}.png", key));
+        let marker = dir.join(format!("{}.none", key));
+        if thumbnail.exists() {
+            return Some(thumbnail);
+        }
+        if marker.exists() {
+            return None;
+        }
+
+        let data = cover_art(&song.path)
+            .map(|(_, data)| data)
+            .or_else(|| self.fetch.then(|| fetch_cover(song)).flatten());
+        fs::create_dir_all(dir).ok()?;
+        match data.and_then(|data| make_thumbnail(&data).ok()) {
+            Some(png) => {
+                let temp = dir.join(format!(".{}.tmp", key));
+                fs::write(&temp, png).ok()?;
+                fs::rename(&temp, &thumbnail).ok()?;
+                Some(thumbnail)
+            }
+            None => {
+                let _ = fs::write(&marker, "");
+                None
+            }
+        }
+    }
+}
+
+// Songs of an album share a key; without an album name, songs in one folder do
+fn album_key(song: &Song) -> String {
+    let name = if song.album == UNKNOWN_ALBUM {
+        song.path.parent().unwrap_or(Path::new("")).to_string_lossy().to_string()
+    } else {
+        format!("{}\0{}", song.artist.to_lowercase(), song.album.to_lowercase())
+    };
+    persist::file_key(&name)
+}
+
+fn make_thumbnail(data: &[u8]) -> Result<Vec<u8>> {
+    let image = image::load_from_memory(data)?.thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE);
+    let mut png = Vec::new();
+    image.write_to(&mut Cursor::new(&mut png), ImageOutputFormat::Png)?;
+    Ok(png)
+}
+
+// Cover Art Archive needs a MusicBrainz release id: from the tag if the file
+// was tagged with Picard, otherwise from a search.
+fn fetch_cover(song: &Song) -> Option<Vec<u8>> {
+    if song.album == UNKNOWN_ALBUM {
+        return None;
+    }
+    let release = Tag::read_from_path(&song.path).ok()
+        .and_then(|tag| {
+            tag.extended_texts()
+                .find(|text| text.description == "MusicBrainz Album Id")
+                .map(|text| text.value.clone())
+        })
+        .or_else(|| search_release(&song.artist, &song.album))?;
+    curl(&[&format!("https://coverartarchive.org/release/{}/front-500", release)]).ok()
+}
+
+fn search_release(artist: &str, album: &str) -> Option<String> {
+    let query = format!("release:\"{}\" AND artist:\"{}\"", album.replace('"', ""), artist.replace('"', ""));
+    let body = curl(&[
+        "-G",
+        "https://musicbrainz.org/ws/2/release/",
+        "--data-urlencode",
+        &format!("query={}", query),
+        "-d",
+        "fmt=json",
+        "-d",
+        "limit=1",
+    ]).ok()?;
+    let json: Value = serde_json::from_slice(&body).ok()?;
+    json["releases"][0]["id"].as_str().map(str::to_string)
+}
+
+// Cover shrunk to one colour per half character cell. Drawing "▀" with the
+// top pixel as foreground and the bottom one as background gives square-ish
+// pixels on any terminal with true colour, no graphics protocol needed.
+pub struct CoverBlocks {
+    pub cols: u32,
+    pub rows: u32,
+    pixels: Vec<[u8; 3]>,
+}
+
+impl CoverBlocks {
+    pub fn from_image(data: &[u8], cols: u32, rows: u32) -> Option<Self> {
+        let image = image::load_from_memory(data).ok()?;
+        let small = image.resize_exact(cols, rows * 2, FilterType::Triangle).to_rgb8();
+        Some(CoverBlocks {
+            cols,
+            rows,
+            pixels: small.pixels().map(|p| p.0).collect(),
+        })
+    }
+
+    pub fn load(path: &Path, cols: u32, rows: u32) -> Option<Self> {
+        let (_, data) = cover_art(path)?;
+        Self::from_image(&data, cols, rows)
+    }
+
+    // From an image file, such as a cached thumbnail
+    pub fn from_file(path: &Path, cols: u32, rows: u32) -> Option<Self> {
+        Self::from_image(&fs::read(path).ok()?, cols, rows)
+    }
+
+    // Colours for the upper and lower half of the cell at (col, row)
+    pub fn cell(&self, col: u32, row: u32) -> ([u8; 3], [u8; 3]) {
+        let top = (row * 2 * self.cols + col) as usize;
+        let bottom = ((row * 2 + 1) * self.cols + col) as usize;
+        (self.pixels[top], self.pixels[bottom])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgb, RgbImage};
+
+    use super::*;
+
+    #[test]
+    fn cells_pair_top_and_bottom_pixels() {
+        let rows = [[255, 0, 0], [0, 255, 0], [0, 0, 255], [255, 255, 255]];
+        let image = RgbImage::from_fn(2, 4, |_, y| Rgb(rows[y as usize]));
+        let mut png = Vec::new();
+        image.write_to(&mut Cursor::new(&mut png), ImageOutputFormat::Png).unwrap();
+
+        let cover = CoverBlocks::from_image(&png, 2, 2).unwrap();
+        assert_eq!(cover.cell(0, 0), (rows[0], rows[1]));
+        assert_eq!(cover.cell(1, 1), (rows[2], rows[3]));
+    }
+
+    #[test]
+    fn cache_makes_one_thumbnail_per_album() {
+        let music = tempfile::tempdir().unwrap();
+        let cache = tempfile::tempdir().unwrap();
+        let image = RgbImage::from_pixel(600, 400, Rgb([200, 10, 10]));
+        image.save(music.path().join("cover.png")).unwrap();
+
+        let mut first = Song::new(music.path().join("Artist - One.mp3"));
+        first.album = String::from("Album");
+        let mut second = Song::new(music.path().join("Artist - Two.mp3"));
+        second.album = String::from("ALBUM");
+        let art = ArtCache::new(Some(cache.path().to_path_buf()), false);
+        assert!(art.cached(&first).is_none());
+
+        let thumbnail = art.lookup(&first).unwrap();
+        assert_eq!(image::image_dimensions(&thumbnail).unwrap(), (THUMBNAIL_SIZE, 200));
+        assert_eq!(art.cached(&second), Some(thumbnail));
+
+        // No art anywhere: remembered as missing
+        let elsewhere = tempfile::tempdir().unwrap();
+        let lonely = Song::new(elsewhere.path().join("Someone - Else.mp3"));
+        assert!(art.lookup(&lonely).is_none());
+        assert_eq!(fs::read_dir(cache.path()).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn invalid_image_data_gives_nothing() {
+        assert!(CoverBlocks::from_image(b"not an image", 4, 2).is_none());
+    }
+}
@@ -0,0 +1,100 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::library::Song;
+
+// What spellings of one artist have in common: "The Beatles", "Beatles, The"
+// and "the beatles!" all come down to "beatles"
+pub fn normalize(name: &str) -> String {
+    let lower = name.trim().to_lowercase();
+    let moved = match lower.strip_suffix(", the") {
+        Some(rest) => format!("the {}", rest),
+        None => lower,
+    };
+    let words: Vec<String> = moved
+        .split(|c: char| c.is_whitespace() || c == '-' || c == '_')
+        .map(|word| word.chars().filter(|c| c.is_alphanumeric() || *c == '&').collect::<String>())
+        .filter(|word| !word.is_empty())
+        .collect();
+    match words.split_first() {
+        Some((first, rest)) if first == "the" && !rest.is_empty() => rest.join(" "),
+        _ => words.join(" "),
+    }
+}
+
+// Names that look like the same artist, by the one they would be merged
+// into: whichever most songs use, then the first in order
+pub fn suggest(songs: &[Song]) -> BTreeMap<String, Vec<String>> {
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    for song in songs {
+        *counts.entry(&song.artist).or_default() += 1;
+    }
+    let mut groups: HashMap<String, Vec<&str>> = HashMap::new();
+    for &name in counts.keys() {
+        groups.entry(normalize(name)).or_default().push(name);
+    }
+    groups.into_values()
+        .filter(|names| names.len() > 1)
+        .map(|mut names| {
+            names.sort_by(|a, b| counts[b].cmp(&counts[a]).then(a.cmp(b)));
+            let canonical = names.remove(0).to_string();
+            let mut variants: Vec<String> = names.into_iter().map(String::from).collect();
+            variants.sort();
+            (canonical, variants)
+        })
+        .collect()
+}
+
+// Renames the artists of `song` by `aliases`, returning whether anything changed
+pub fn apply(song: &mut Song, aliases: &HashMap<String, String>) -> bool {
+    let mut changed = false;
+    if let Some(name) = aliases.get(&song.artist) {
+        song.artist = name.clone();
+        song.label = format!("{} - {}", song.artist, song.title);
+        changed = true;
+    }
+    if let Some(name) = song.album_artist.as_ref().and_then(|artist| aliases.get(artist)) {
+        song.album_artist = Some(name.clone());
+        changed = true;
+    }
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    fn song(artist: &str) -> Song {
+        let mut song = Song::new(PathBuf::from(format!("/music/{}.mp3", artist)));
+        song.artist = artist.to_string();
+        song
+    }
+
+    #[test]
+    fn articles_case_and_punctuation_are_ignored() {
+        assert_eq!(normalize("The Beatles"), "beatles");
+        assert_eq!(normalize("Beatles, The"), "beatles");
+        assert_eq!(normalize("  the beatles! "), "beatles");
+        assert_eq!(normalize("AC/DC"), "acdc");
+        assert_eq!(normalize("Simon & Garfunkel"), "simon & garfunkel");
+        // A band called just "The" keeps its name
+        assert_eq!(normalize("The"), "the");
+    }
+
+    #[test]
+    fn suggests_merging_into_the_most_used_name() {
+        let songs = [song("The Beatles"), song("The Beatles"), song("Beatles, The"), song("the beatles"), song("Björk")];
+        let suggested = suggest(&songs);
+        assert_eq!(suggested.len(), 1);
+        assert_eq!(suggested["The Beatles"], ["Beatles, The", "the beatles"]);
+
+        let aliases = HashMap::from([(String::from("the beatles"), String::from("The Beatles"))]);
+        let mut renamed = song("the beatles");
+        renamed.album_artist = Some(String::from("the beatles"));
+        assert!(apply(&mut renamed, &aliases));
+        assert_eq!((renamed.artist.as_str(), renamed.album_artist.as_deref()), ("The Beatles", Some("The Beatles")));
+        assert!(renamed.label.starts_with("The Beatles - "));
+        assert!(!apply(&mut song("Björk"), &aliases));
+    }
+}
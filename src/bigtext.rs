@@ -0,0 +1,144 @@
+// Large lettering for the karaoke view: a 3x5 pixel font drawn with half
+// blocks, so every character takes 3 columns and 3 rows of the terminal
+
+pub const ROWS: usize = 3;
+// Glyph width plus one column of spacing
+pub const CHAR_WIDTH: usize = 4;
+
+const FONT: &[(char, [&str; 5])] = &[
+    ('A', ["###", "#.#", "###", "#.#", "#.#"]),
+    ('B', ["##.", "#.#", "##.", "#.#", "##."]),
+    ('C', ["###", "#..", "#..", "#..", "###"]),
+    ('D', ["##.", "#.#", "#.#", "#.#", "##."]),
+    ('E', ["###", "#..", "##.", "#..", "###"]),
+    ('F', ["###", "#..", "##.", "#..", "#.."]),
+    ('G', ["###", "#..", "#.#", "#.#", "###"]),
+    ('H', ["#.#", "#.#", "###", "#.#", "#.#"]),
+    ('I', ["###", ".#.", ".#.", ".#.", "###"]),
+    ('J', ["..#", "..#", "..#", "#.#", "###"]),
+    ('K', ["#.#", "#.#", "##.", "#.#", "#.#"]),
+    ('L', ["#..", "#..", "#..", "#..", "###"]),
+    ('M', ["#.#", "###", "###", "#.#", "#.#"]),
+    ('N', ["##.", "#.#", "#.#", "#.#", "#.#"]),
+    ('O', ["###", "#.#", "#.#", "#.#", "###"]),
+    ('P', ["###", "#.#", "###", "#..", "#.."]),
+    ('Q', ["###", "#.#", "#.#", "###", "..#"]),
+    ('R', ["##.", "#.#", "##.", "#.#", "#.#"]),
+    ('S', ["###", "#..", "###", "..#", "###"]),
+    ('T', ["###", ".#.", ".#.", ".#.", ".#."]),
+    ('U', ["#.#", "#.#", "#.#", "#.#", "###"]),
+    ('V', ["#.#", "#.#", "#.#", "#.#", ".#."]),
+    ('W', ["#.#", "#.#", "###", "###", "#.#"]),
+    ('X', ["#.#", "#.#", ".#.", "#.#", "#.#"]),
+    ('Y', ["#.#", "#.#", ".#.", ".#.", ".#."]),
+    ('Z', ["###", "..#", ".#.", "#..", "###"]),
+    ('0', ["###", "#.#", "#.#", "#.#", "###"]),
+    ('1', [".#.", "##.", ".#.", ".#.", "###"]),
+    ('2', ["###", "..#", "###", "#..", "###"]),
+    ('3', ["###", "..#", ".##", "..#", "###"]),
+    ('4', ["#.#", "#.#", "###", "..#", "..#"]),
+    ('5', ["###", "#..", "###", "..#", "###"]),
+    ('6', ["###", "#..", "###", "#.#", "###"]),
+    ('7', ["###", "..#", "..#", "..#", "..#"]),
+    ('8', ["###", "#.#", "###", "#.#", "###"]),
+    ('9', ["###", "#.#", "###", "..#", "###"]),
+    (' ', ["...", "...", "...", "...", "..."]),
+    ('.', ["...", "...", "...", "...", ".#."]),
+    (',', ["...", "...", "...", ".#.", "#.."]),
+    ('!', [".#.", ".#.", ".#.", "...", ".#."]),
+    ('?', ["###", "..#", ".##", "...", ".#."]),
+    ('\'', [".#.", ".#.", "...", "...", "..."]),
+    ('"', ["#.#", "#.#", "...", "...", "..."]),
+    ('-', ["...", "...", "###", "...", "..."]),
+    (':', ["...", ".#.", "...", ".#.", "..."]),
+    ('(', ["..#", ".#.", ".#.", ".#.", "..#"]),
+    (')', ["#..", ".#.", ".#.", ".#.", "#.."]),
+];
+
+fn glyph(c: char) -> Option<&'static [&'static str; 5]> {
+    let c = match c {
+        '’' | '‘' => '\'',
+        '“' | '”' => '"',
+        '–' | '—' => '-',
+        c => c.to_ascii_uppercase(),
+    };
+    FONT.iter().find(|(letter, _)| *letter == c).map(|(_, rows)| rows)
+}
+
+// The three terminal rows spelling `text`, or None if the font lacks a character
+pub fn render_line(text: &str) -> Option<[String; ROWS]> {
+    let glyphs: Vec<_> = text.chars().map(glyph).collect::<Option<_>>()?;
+    let mut rows: [String; ROWS] = Default::default();
+    for (row, line) in rows.iter_mut().enumerate() {
+        for (i, glyph) in glyphs.iter().enumerate() {
+            if i > 0 {
+                line.push(' ');
+            }
+            for col in 0..3 {
+                let lit = |pixel: usize| glyph.get(pixel).is_some_and(|r| r.as_bytes()[col] == b'#');
+                line.push(match (lit(row * 2), lit(row * 2 + 1)) {
+                    (true, true) => '█',
+                    (true, false) => '▀',
+                    (false, true) => '▄',
+                    (false, false) => ' ',
+                });
+            }
+        }
+    }
+    Some(rows)
+}
+
+// `text` broken at spaces into pieces that fit `width` columns once enlarged,
+// each rendered with `render_line`. Words too long for a line are split.
+pub fn render(text: &str, width: usize) -> Option<Vec<[String; ROWS]>> {
+    let max_chars = ((width + 1) / CHAR_WIDTH).max(1);
+    let mut pieces: Vec<String> = Vec::new();
+    let mut current = String::new();
+    for word in text.split_whitespace() {
+        let mut word: Vec<char> = word.chars().collect();
+        while !word.is_empty() {
+            let used = current.chars().count();
+            let gap = if used == 0 { 0 } else { 1 };
+            if used + gap + word.len() <= max_chars {
+                if gap == 1 {
+                    current.push(' ');
+                }
+                current.extend(word.drain(..));
+            } else if used > 0 {
+                pieces.push(std::mem::take(&mut current));
+            } else {
+                current.extend(word.drain(..max_chars));
+            }
+        }
+    }
+    if !current.is_empty() {
+        pieces.push(current);
+    }
+    pieces.iter().map(|piece| render_line(piece)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn draws_letters_with_half_blocks() {
+        assert_eq!(
+            render_line("Hi!").unwrap(),
+            [
+                String::from("█ █ ▀█▀  █ "),
+                String::from("█▀█  █   ▀ "),
+                String::from("▀ ▀ ▀▀▀  ▀ "),
+            ]
+        );
+        assert!(render_line("Björk").is_none());
+    }
+
+    #[test]
+    fn wraps_at_spaces_to_fit() {
+        let pieces = render("one more time", 31).unwrap();
+        assert_eq!(pieces.len(), 2);
+        assert_eq!(pieces[0][0].chars().count(), "one more".len() * CHAR_WIDTH - 1);
+        assert_eq!(render("celebrate", 11).unwrap().len(), 3);
+    }
+}
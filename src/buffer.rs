@@ -0,0 +1,188 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        mpsc::{self, Receiver, TryRecvError},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+use rodio::Source;
+
+use crate::decode::AudioSource;
+
+// Frames decoded per chunk handed over from the decoding thread
+const CHUNK_FRAMES: usize = 1024;
+
+// Underruns of every buffer since the player started, for /metrics
+static TOTAL_UNDERRUNS: AtomicU64 = AtomicU64::new(0);
+
+pub fn total_underruns() -> u64 {
+    TOTAL_UNDERRUNS.load(Ordering::Relaxed)
+}
+
+// How full a read-ahead buffer is, for the status line
+pub struct BufferLevel {
+    filled: AtomicUsize,
+    capacity: usize,
+    underruns: AtomicUsize,
+    // The decoding thread has reached the end of the track
+    ended: AtomicBool,
+}
+
+impl BufferLevel {
+    // 0.0 to 1.0
+    pub fn fraction(&self) -> f64 {
+        self.filled.load(Ordering::Relaxed) as f64 / self.capacity as f64
+    }
+
+    // Nothing more will fit until playback takes some, or the rest of the
+    // track is already in
+    pub fn complete(&self) -> bool {
+        self.fraction() >= 1.0 || self.ended.load(Ordering::Relaxed)
+    }
+
+    // Times the buffer ran dry and silence was played instead
+    pub fn underruns(&self) -> usize {
+        self.underruns.load(Ordering::Relaxed)
+    }
+}
+
+// Plays `source` through a buffer that a separate thread keeps up to `seconds`
+// ahead, so a disk spinning up or a network share stalling for less than that
+// isn't heard. If it does run dry, silence fills in until data arrives.
+pub struct ReadAhead {
+    chunks: Receiver<Vec<i16>>,
+    chunk: Vec<i16>,
+    position: usize,
+    silence: usize,
+    channels: u16,
+    sample_rate: u32,
+    total_duration: Option<Duration>,
+    level: Arc<BufferLevel>,
+}
+
+impl ReadAhead {
+    pub fn new(mut source: AudioSource, seconds: f32) -> Self {
+        let (channels, sample_rate) = (source.channels(), source.sample_rate());
+        let total_duration = source.total_duration();
+        let chunk_len = CHUNK_FRAMES * channels as usize;
+        let chunks = ((seconds * sample_rate as f32) as usize / CHUNK_FRAMES).max(1);
+        let level = Arc::new(BufferLevel {
+            filled: AtomicUsize::new(0),
+            // The channel's chunks, plus the one waiting to go in
+            capacity: (chunks + 1) * chunk_len,
+            underruns: AtomicUsize::new(0),
+            ended: AtomicBool::new(false),
+        });
+
+        let (tx, rx) = mpsc::sync_channel(chunks);
+        let filler = level.clone();
+        thread::spawn(move || loop {
+            let chunk: Vec<i16> = source.by_ref().take(chunk_len).collect();
+            if chunk.is_empty() {
+                filler.ended.store(true, Ordering::Relaxed);
+                break;
+            }
+            filler.filled.fetch_add(chunk.len(), Ordering::Relaxed);
+            // Blocks while the buffer is full; stops once playback is gone
+            if tx.send(chunk).is_err() {
+                break;
+            }
+        });
+
+        // The first chunk is waited for, so playback doesn't open with silence
+        let chunk = rx.recv().unwrap_or_default();
+        level.filled.fetch_sub(chunk.len(), Ordering::Relaxed);
+        ReadAhead { chunks: rx, chunk, position: 0, silence: 0, channels, sample_rate, total_duration, level }
+    }
+
+    pub fn level(&self) -> Arc<BufferLevel> {
+        self.level.clone()
+    }
+}
+
+impl Iterator for ReadAhead {
+    type Item = i16;
+
+    fn next(&mut self) -> Option<i16> {
+        if self.silence > 0 {
+            self.silence -= 1;
+            return Some(0);
+        }
+        while self.position >= self.chunk.len() {
+            match self.chunks.try_recv() {
+                Ok(chunk) => {
+                    self.level.filled.fetch_sub(chunk.len(), Ordering::Relaxed);
+                    self.chunk = chunk;
+                    self.position = 0;
+                }
+                // Whole frames of silence, so the channels stay in step
+                Err(TryRecvError::Empty) => {
+                    self.level.underruns.fetch_add(1, Ordering::Relaxed);
+                    TOTAL_UNDERRUNS.fetch_add(1, Ordering::Relaxed);
+                    self.silence = self.channels as usize - 1;
+                    return Some(0);
+                }
+                Err(TryRecvError::Disconnected) => return None,
+            }
+        }
+        self.position += 1;
+        Some(self.chunk[self.position - 1])
+    }
+}
+
+impl Source for ReadAhead {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.total_duration
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rodio::buffer::SamplesBuffer;
+
+    #[test]
+    fn plays_everything_in_order() {
+        let samples: Vec<i16> = (1..=10_000).collect();
+        let source: AudioSource = Box::new(SamplesBuffer::new(2, 1000, samples.clone()));
+        let buffer = ReadAhead::new(source, 1.0);
+        let level = buffer.level();
+        assert!(level.fraction() <= 1.0);
+        // However slow the test machine, silence only ever stands in for
+        // missing data, a whole frame at a time
+        let played: Vec<i16> = buffer.collect();
+        let silence = played.iter().filter(|&&s| s == 0).count();
+        assert_eq!(silence, level.underruns() * 2);
+        assert_eq!(played.into_iter().filter(|&s| s != 0).collect::<Vec<_>>(), samples);
+    }
+
+    #[test]
+    fn short_tracks_fill_completely() {
+        let source: AudioSource = Box::new(SamplesBuffer::new(2, 1000, vec![1i16; 100]));
+        let buffer = ReadAhead::new(source, 10.0);
+        let level = buffer.level();
+        for _ in 0..100 {
+            if level.complete() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(level.complete());
+        assert!(level.fraction() < 1.0);
+    }
+}
@@ -0,0 +1,244 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Result;
+use rusqlite::{params, Connection, Row};
+
+use crate::harmony::Camelot;
+use crate::library::Song;
+use crate::replaygain::ReplayGain;
+use crate::stats::TagStats;
+
+// Goes up whenever the columns change; a database from before is emptied and
+// filled again by the next scan, unless it can be brought up to date
+const VERSION: i32 = 2;
+
+// Version 1 had no `added`; its songs were there by the time their files
+// last changed
+const FROM_1: &str = "
+    ALTER TABLE songs ADD COLUMN added INTEGER NOT NULL DEFAULT 0;
+    UPDATE songs SET added = modified;
+";
+
+const SCHEMA: &str = "
+    DROP TABLE IF EXISTS songs;
+    CREATE TABLE songs (
+        path TEXT PRIMARY KEY,
+        modified INTEGER NOT NULL,
+        title TEXT NOT NULL,
+        artist TEXT NOT NULL,
+        album TEXT NOT NULL,
+        genre TEXT NOT NULL,
+        album_artist TEXT,
+        year INTEGER,
+        track INTEGER,
+        duration_ms INTEGER,
+        bpm INTEGER,
+        key TEXT,
+        tag_rating INTEGER,
+        tag_plays INTEGER,
+        track_gain REAL,
+        album_gain REAL,
+        track_peak REAL,
+        album_peak REAL,
+        added INTEGER NOT NULL
+    );
+";
+
+// The tags of every file as the last scan read them, so starting up only
+// reads the files that changed since. It can always be built again from the
+// files, so anything wrong with it just means reading them all.
+pub struct Catalog {
+    conn: Connection,
+}
+
+impl Catalog {
+    // ~/.local/share/music-player/library.db on Linux, %APPDATA%\music-player\library.db on Windows
+    pub fn path() -> Option<PathBuf> {
+        dirs::data_dir().map(|dir| dir.join("music-player").join("library.db"))
+    }
+
+    pub fn open() -> Result<Self> {
+        let path = Self::path().ok_or_else(|| anyhow::anyhow!("No data directory"))?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        Self::open_at(&path)
+    }
+
+    pub fn open_at(path: &Path) -> Result<Self> {
+        let conn = Connection::open(path)?;
+        // Scans of several directories can run at once, each with its own connection
+        conn.busy_timeout(Duration::from_secs(5))?;
+        let version: i32 = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        if version != VERSION {
+            conn.execute_batch(if version == 1 { FROM_1 } else { SCHEMA })?;
+            conn.pragma_update(None, "user_version", VERSION)?;
+        }
+        Ok(Catalog { conn })
+    }
+
+    // Everything in it, by path
+    pub fn load(&self) -> Result<HashMap<PathBuf, Song>> {
+        let mut statement = self.conn.prepare("SELECT * FROM songs")?;
+        let songs = statement.query_map([], song)?.collect::<rusqlite::Result<Vec<Song>>>()?;
+        Ok(songs.into_iter().map(|song| (song.path.clone(), song)).collect())
+    }
+
+    // Adds or replaces `songs` and drops `gone`, all in one transaction. A song
+    // already in it keeps when it was added.
+    pub fn update(&mut self, songs: &[Song], gone: &[PathBuf]) -> Result<()> {
+        let transaction = self.conn.transaction()?;
+        {
+            let mut insert = transaction.prepare(
+                "INSERT INTO songs VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19)
+                 ON CONFLICT (path) DO UPDATE SET
+                    modified = excluded.modified, title = excluded.title, artist = excluded.artist,
+                    album = excluded.album, genre = excluded.genre, album_artist = excluded.album_artist,
+                    year = excluded.year, track = excluded.track, duration_ms = excluded.duration_ms,
+                    bpm = excluded.bpm, key = excluded.key, tag_rating = excluded.tag_rating,
+                    tag_plays = excluded.tag_plays, track_gain = excluded.track_gain, album_gain = excluded.album_gain,
+                    track_peak = excluded.track_peak, album_peak = excluded.album_peak",
+            )?;
+            for song in songs {
+                // Paths that aren't UTF-8 are read every time
+                let (Some(path), Some(modified)) = (song.path.to_str(), song.modified.and_then(nanos)) else {
+                    continue;
+                };
+                let replay_gain = &song.replay_gain;
+                insert.execute(params![
+                    path,
+                    modified,
+                    song.title,
+                    song.artist,
+                    song.album,
+                    song.genre,
+                    song.album_artist,
+                    song.year,
+                    song.track,
+                    song.duration.map(|duration| duration.as_millis() as i64),
+                    song.bpm,
+                    song.key.map(|key| key.to_string()),
+                    song.tag_stats.and_then(|stats| stats.rating),
+                    song.tag_stats.map(|stats| stats.plays as i64),
+                    replay_gain.track,
+                    replay_gain.album,
+                    replay_gain.track_peak,
+                    replay_gain.album_peak,
+                    nanos(song.added).unwrap_or_default(),
+                ])?;
+            }
+            let mut delete = transaction.prepare("DELETE FROM songs WHERE path = ?1")?;
+            for path in gone.iter().filter_map(|path| path.to_str()) {
+                delete.execute([path])?;
+            }
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+}
+
+// The song at `path` as `cached` has it, if the file hasn't changed since
+pub fn cached(cached: &HashMap<PathBuf, Song>, path: &Path) -> Option<Song> {
+    let song = cached.get(path)?;
+    let modified = fs::metadata(path).and_then(|m| m.modified()).ok()?;
+    // Compared as stored, in case the file system keeps finer times
+    (song.modified.and_then(nanos) == nanos(modified)).then(|| song.clone())
+}
+
+fn nanos(time: SystemTime) -> Option<i64> {
+    time.duration_since(UNIX_EPOCH).ok().and_then(|since| i64::try_from(since.as_nanos()).ok())
+}
+
+fn song(row: &Row) -> rusqlite::Result<Song> {
+    let path: String = row.get("path")?;
+    let title: String = row.get("title")?;
+    let artist: String = row.get("artist")?;
+    let tag_rating: Option<u8> = row.get("tag_rating")?;
+    let tag_plays: Option<i64> = row.get("tag_plays")?;
+    Ok(Song {
+        path: PathBuf::from(path),
+        label: format!("{} - {}", artist, title),
+        title,
+        artist,
+        album: row.get("album")?,
+        genre: row.get("genre")?,
+        album_artist: row.get("album_artist")?,
+        year: row.get("year")?,
+        track: row.get("track")?,
+        duration: row.get::<_, Option<i64>>("duration_ms")?.map(|ms| Duration::from_millis(ms as u64)),
+        bpm: row.get("bpm")?,
+        key: row.get::<_, Option<String>>("key")?.and_then(|key| Camelot::parse(&key)),
+        dr: None,
+        tag_stats: tag_plays.map(|plays| TagStats { rating: tag_rating, plays: plays as u64 }),
+        replay_gain: ReplayGain {
+            track: row.get("track_gain")?,
+            album: row.get("album_gain")?,
+            track_peak: row.get("track_peak")?,
+            album_peak: row.get("album_peak")?,
+        },
+        modified: Some(UNIX_EPOCH + Duration::from_nanos(row.get::<_, i64>("modified")? as u64)),
+        added: UNIX_EPOCH + Duration::from_nanos(row.get::<_, i64>("added")? as u64),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_songs_until_their_files_change() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("Can - Vitamin C.mp3");
+        fs::write(&path, b"").unwrap();
+        let mut song = Song::new(path.clone());
+        song.album = String::from("Ege Bamyasi");
+        song.duration = Some(Duration::from_millis(213_500));
+        song.key = Camelot::parse("8A");
+        song.tag_stats = Some(TagStats { rating: Some(4), plays: 12 });
+        song.replay_gain.track = Some(-6.5);
+
+        let mut catalog = Catalog::open_at(&dir.path().join("library.db")).unwrap();
+        catalog.update(std::slice::from_ref(&song), &[]).unwrap();
+        let loaded = Catalog::open_at(&dir.path().join("library.db")).unwrap().load().unwrap();
+        let found = cached(&loaded, &path).unwrap();
+        assert_eq!((found.label.as_str(), found.album.as_str()), ("Can - Vitamin C", "Ege Bamyasi"));
+        assert_eq!((found.duration, found.key, found.tag_stats), (song.duration, song.key, song.tag_stats));
+        assert_eq!((found.replay_gain, found.modified), (song.replay_gain, song.modified));
+
+        assert_eq!(found.added, song.added);
+
+        let file = fs::File::options().write(true).open(&path).unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(60)).unwrap();
+        assert!(cached(&loaded, &path).is_none());
+
+        // Read again, but still added when it was first
+        let mut changed = Song::new(path.clone());
+        changed.album = String::from("Soundtracks");
+        catalog.update(&[changed], &[]).unwrap();
+        let reloaded = catalog.load().unwrap();
+        assert_eq!((reloaded[&path].album.as_str(), reloaded[&path].added), ("Soundtracks", song.added));
+
+        catalog.update(&[], &[path]).unwrap();
+        assert!(catalog.load().unwrap().is_empty());
+    }
+
+    #[test]
+    fn songs_from_before_added_was_kept_were_added_when_last_changed() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = dir.path().join("library.db");
+        let old = Connection::open(&db).unwrap();
+        old.execute_batch(&SCHEMA.replace(",\n        added INTEGER NOT NULL", "")).unwrap();
+        old.execute("INSERT INTO songs (path, modified, title, artist, album, genre) VALUES ('/music/a.mp3', 5000, 'A', 'B', '', '')", []).unwrap();
+        old.pragma_update(None, "user_version", 1).unwrap();
+        drop(old);
+
+        let loaded = Catalog::open_at(&db).unwrap().load().unwrap();
+        let song = &loaded[Path::new("/music/a.mp3")];
+        assert_eq!(Some(song.added), song.modified);
+    }
+}
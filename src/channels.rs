@@ -0,0 +1,294 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use rand::{rngs::StdRng, SeedableRng};
+use rodio::Source;
+
+use crate::decode::AudioSource;
+use crate::dither;
+
+// -3 dB
+const HALF_POWER: f32 = std::f32::consts::FRAC_1_SQRT_2;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Speaker {
+    FrontLeft,
+    FrontRight,
+    Center,
+    Lfe,
+    BackLeft,
+    BackRight,
+    BackCenter,
+    SideLeft,
+    SideRight,
+}
+
+// Speakers in the order FLAC and WAV files lay their channels out
+fn speakers(channels: u16) -> Vec<Speaker> {
+    use Speaker::*;
+    match channels {
+        1 => vec![Center],
+        2 => vec![FrontLeft, FrontRight],
+        3 => vec![FrontLeft, FrontRight, Center],
+        4 => vec![FrontLeft, FrontRight, BackLeft, BackRight],
+        5 => vec![FrontLeft, FrontRight, Center, BackLeft, BackRight],
+        6 => vec![FrontLeft, FrontRight, Center, Lfe, BackLeft, BackRight],
+        7 => vec![FrontLeft, FrontRight, Center, Lfe, BackCenter, SideLeft, SideRight],
+        _ => vec![FrontLeft, FrontRight, Center, Lfe, BackLeft, BackRight, SideLeft, SideRight],
+    }
+}
+
+// Where a speaker's channel goes when the output has no such speaker: the
+// first set of speakers the output does have. The LFE channel is dropped, as
+// the main channels already carry the bass.
+fn fold(speaker: Speaker) -> &'static [&'static [Speaker]] {
+    use Speaker::*;
+    match speaker {
+        FrontLeft | FrontRight => &[&[Center]],
+        Center => &[&[FrontLeft, FrontRight]],
+        Lfe => &[],
+        BackLeft => &[&[SideLeft], &[FrontLeft], &[Center]],
+        BackRight => &[&[SideRight], &[FrontRight], &[Center]],
+        SideLeft => &[&[BackLeft], &[FrontLeft], &[Center]],
+        SideRight => &[&[BackRight], &[FrontRight], &[Center]],
+        BackCenter => &[&[BackLeft, BackRight], &[SideLeft, SideRight], &[FrontLeft, FrontRight], &[Center]],
+    }
+}
+
+// How a channel count is usually called
+pub fn layout_name(channels: u16) -> String {
+    match channels {
+        1 => String::from("mono"),
+        2 => String::from("stereo"),
+        4 => String::from("quad"),
+        5 => String::from("5.0"),
+        6 => String::from("5.1"),
+        7 => String::from("6.1"),
+        8 => String::from("7.1"),
+        n => format!("{} ch", n),
+    }
+}
+
+// Gain from each input channel to each output channel, `matrix[out][in]`,
+// scaled down as a whole if any output could otherwise clip
+pub fn matrix(from: u16, to: u16) -> Vec<Vec<f32>> {
+    let (inputs, outputs) = (speakers(from), speakers(to));
+    let mut matrix = vec![vec![0.0; from as usize]; to as usize];
+    for (i, speaker) in inputs.iter().enumerate().take(from as usize) {
+        // Mono files are meant for both speakers at full level
+        let targets: Vec<(usize, f32)> = if from == 1 && to > 1 {
+            vec![(0, 1.0), (1, 1.0)]
+        } else if let Some(out) = outputs.iter().take(to as usize).position(|s| s == speaker) {
+            vec![(out, 1.0)]
+        } else {
+            // A speaker moved elsewhere comes in 3 dB down
+            fold(*speaker).iter()
+                .find(|group| group.iter().all(|s| outputs.contains(s)))
+                .map_or_else(Vec::new, |group| {
+                    group.iter()
+                        .filter_map(|s| outputs.iter().position(|o| o == s))
+                        .map(|out| (out, HALF_POWER))
+                        .collect()
+                })
+        };
+        for (out, gain) in targets {
+            matrix[out][i] += gain;
+        }
+    }
+    let loudest = matrix.iter().map(|row| row.iter().sum::<f32>()).fold(1.0, f32::max);
+    matrix.iter_mut().flatten().for_each(|gain| *gain /= loudest);
+    matrix
+}
+
+// Converts `source` to `channels` channels through `matrix`, or leaves it
+// alone if it has that many already. `dither` is applied when the mix
+// needs rounding.
+pub fn remix(source: AudioSource, channels: u16, dither: bool) -> AudioSource {
+    let from = source.channels();
+    if from == channels || from == 0 || channels == 0 {
+        return source;
+    }
+    let matrix = matrix(from, channels);
+    let exact = matrix.iter().flatten().all(|&gain| gain == 0.0 || gain == 1.0);
+    Box::new(Remix {
+        source,
+        matrix,
+        input: vec![0; from as usize],
+        frame: Vec::new(),
+        position: 0,
+        dither: (dither && !exact).then(StdRng::from_entropy),
+    })
+}
+
+struct Remix {
+    source: AudioSource,
+    matrix: Vec<Vec<f32>>,
+    input: Vec<i16>,
+    frame: Vec<i16>,
+    position: usize,
+    dither: Option<StdRng>,
+}
+
+impl Iterator for Remix {
+    type Item = i16;
+
+    fn next(&mut self) -> Option<i16> {
+        if self.position >= self.frame.len() {
+            for sample in self.input.iter_mut() {
+                // A partial frame at the end is dropped
+                *sample = self.source.next()?;
+            }
+            self.frame = self.matrix.iter()
+                .map(|row| {
+                    let mixed: f32 = row.iter().zip(&self.input).map(|(gain, &s)| gain * s as f32).sum();
+                    let noise = self.dither.as_mut().map_or(0.0, dither::noise);
+                    (mixed + noise).round().clamp(i16::MIN as f32, i16::MAX as f32) as i16
+                })
+                .collect();
+            self.position = 0;
+        }
+        self.position += 1;
+        Some(self.frame[self.position - 1])
+    }
+}
+
+impl Source for Remix {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        self.matrix.len() as u16
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.source.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.source.total_duration()
+    }
+}
+
+// Whether the playing track is summed to mono, shared with the audio thread,
+// which switches it on PlayerMessage::SetChannelMode
+pub type MonoSwitch = Arc<AtomicBool>;
+
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum Mix {
+    #[default]
+    Channels,
+    // Every channel carries their average, for listening with one ear
+    Mono,
+}
+
+pub fn set_mix(switch: &MonoSwitch, mix: Mix) {
+    switch.store(mix == Mix::Mono, Ordering::Relaxed);
+}
+
+// Sums each frame into all its channels while the switch is on. Frames are
+// read whole either way, so a switch halfway through a track keeps left on
+// the left.
+pub struct Mono<S> {
+    source: S,
+    switch: MonoSwitch,
+    frame: Vec<i16>,
+    position: usize,
+}
+
+impl<S: Source<Item = i16>> Mono<S> {
+    pub fn new(source: S, switch: MonoSwitch) -> Self {
+        Mono { source, switch, frame: Vec::new(), position: 0 }
+    }
+}
+
+impl<S: Source<Item = i16>> Iterator for Mono<S> {
+    type Item = i16;
+
+    fn next(&mut self) -> Option<i16> {
+        if self.position >= self.frame.len() {
+            self.frame.clear();
+            for _ in 0..self.source.channels().max(1) {
+                // A partial frame at the end is dropped
+                self.frame.push(self.source.next()?);
+            }
+            if self.switch.load(Ordering::Relaxed) {
+                let sum: i32 = self.frame.iter().map(|&sample| sample as i32).sum();
+                let average = (sum / self.frame.len() as i32) as i16;
+                self.frame.fill(average);
+            }
+            self.position = 0;
+        }
+        self.position += 1;
+        Some(self.frame[self.position - 1])
+    }
+}
+
+impl<S: Source<Item = i16>> Source for Mono<S> {
+    fn current_frame_len(&self) -> Option<usize> {
+        self.source.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.source.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.source.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.source.total_duration()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rodio::buffer::SamplesBuffer;
+
+    fn close(row: &[f32], expected: &[f32]) -> bool {
+        row.len() == expected.len() && row.iter().zip(expected).all(|(a, b)| (a - b).abs() < 1e-3)
+    }
+
+    #[test]
+    fn surround_folds_into_stereo() {
+        // FL FR C LFE BL BR: centre and surrounds at -3 dB, no LFE, then
+        // scaled so a full-scale mix can't clip
+        let m = matrix(6, 2);
+        let scale = 1.0 / (1.0 + 2.0 * HALF_POWER);
+        let h = HALF_POWER * scale;
+        assert!(close(&m[0], &[scale, 0.0, h, 0.0, h, 0.0]));
+        assert!(close(&m[1], &[0.0, scale, h, 0.0, 0.0, h]));
+
+        // 7.1's side channels fold into the 5.1 surrounds
+        let m = matrix(8, 6);
+        assert!((m[4][6] - m[4][4] * HALF_POWER).abs() < 1e-6);
+    }
+
+    #[test]
+    fn stereo_and_mono_fill_the_front_speakers() {
+        let m = matrix(2, 6);
+        assert!(close(&m[0], &[1.0, 0.0]) && close(&m[1], &[0.0, 1.0]));
+        assert!(m[2..].iter().flatten().all(|&gain| gain == 0.0));
+
+        let mono: AudioSource = Box::new(SamplesBuffer::new(1, 44_100, vec![100i16, -5]));
+        let stereo = remix(mono, 2, true);
+        assert_eq!(stereo.channels(), 2);
+        assert_eq!(stereo.collect::<Vec<_>>(), vec![100, 100, -5, -5]);
+    }
+
+    #[test]
+    fn mono_averages_each_frame_while_on() {
+        let switch = MonoSwitch::default();
+        let mut mono = Mono::new(SamplesBuffer::new(2, 44_100, vec![100i16, -20, 100, -20, 7]), switch.clone());
+        assert_eq!(mono.by_ref().take(2).collect::<Vec<_>>(), [100, -20]);
+        set_mix(&switch, Mix::Mono);
+        assert_eq!(mono.collect::<Vec<_>>(), [40, 40]);
+    }
+}
@@ -0,0 +1,55 @@
+use std::{
+    process::Command,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+// The local time zone's offset from UTC in seconds, as `date` reports it.
+// Falls back to UTC where there is no `date`, such as on Windows.
+pub fn utc_offset() -> i64 {
+    Command::new("date")
+        .arg("+%z")
+        .output()
+        .ok()
+        .and_then(|output| parse_offset(String::from_utf8_lossy(&output.stdout).trim()))
+        .unwrap_or(0)
+}
+
+// "+0130" or "-0500"
+fn parse_offset(text: &str) -> Option<i64> {
+    let (sign, digits) = match text.strip_prefix('-') {
+        Some(digits) => (-1, digits),
+        None => (1, text.strip_prefix('+')?),
+    };
+    if digits.len() != 4 || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let hours: i64 = digits[..2].parse().ok()?;
+    let minutes: i64 = digits[2..].parse().ok()?;
+    Some(sign * (hours * 3600 + minutes * 60))
+}
+
+// "21:07" at `now` in a zone `offset` seconds from UTC
+pub fn hours_minutes(now: SystemTime, offset: i64) -> String {
+    let secs = now.duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs() as i64);
+    let of_day = (secs + offset).rem_euclid(24 * 3600);
+    format!("{:02}:{:02}", of_day / 3600, of_day % 3600 / 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn offsets_shift_the_time_of_day() {
+        assert_eq!(parse_offset("+0130"), Some(5400));
+        assert_eq!(parse_offset("-0500"), Some(-18_000));
+        assert_eq!(parse_offset("UTC"), None);
+
+        // 2024-03-01 00:30:00 UTC
+        let now = UNIX_EPOCH + Duration::from_secs(1_709_253_000);
+        assert_eq!(hours_minutes(now, 0), "00:30");
+        assert_eq!(hours_minutes(now, -18_000), "19:30");
+        assert_eq!(hours_minutes(now, 5400), "02:00");
+    }
+}
@@ -0,0 +1,280 @@
+use std::{cmp::Ordering, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+use crate::library::{format_duration, Song};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Column {
+    Position,
+    Track,
+    Title,
+    Artist,
+    Album,
+    Genre,
+    Duration,
+    Bpm,
+    // Camelot notation, for mixing in key
+    Key,
+    // Dynamic range, higher is less compressed
+    Dr,
+}
+
+impl Column {
+    pub fn header(self) -> &'static str {
+        match self {
+            Column::Position => "#",
+            Column::Track => "Track",
+            Column::Title => "Title",
+            Column::Artist => "Artist",
+            Column::Album => "Album",
+            Column::Genre => "Genre",
+            Column::Duration => "Time",
+            Column::Bpm => "BPM",
+            Column::Key => "Key",
+            Column::Dr => "DR",
+        }
+    }
+
+    // `position` is the 1-based row number within the view
+    pub fn cell(self, song: &Song, position: usize) -> String {
+        match self {
+            Column::Position => position.to_string(),
+            Column::Track => song.track.map(|track| track.to_string()).unwrap_or_default(),
+            Column::Title => song.title.clone(),
+            Column::Artist => song.artist.clone(),
+            Column::Album => song.album.clone(),
+            Column::Genre => song.genre.clone(),
+            Column::Duration => song.duration.map(format_duration).unwrap_or_default(),
+            Column::Bpm => song.bpm.map(|bpm| bpm.to_string()).unwrap_or_default(),
+            Column::Key => song.key.map(|key| key.to_string()).unwrap_or_default(),
+            Column::Dr => song.dr.map(|dr| format!("DR{}", dr)).unwrap_or_default(),
+        }
+    }
+
+    // Numbers line up on the right
+    pub fn align_right(self) -> bool {
+        matches!(self, Column::Position | Column::Track | Column::Duration | Column::Bpm | Column::Key | Column::Dr)
+    }
+
+    // Text compares case-insensitively. Position is the list order itself, so
+    // everything compares equal and a stable sort leaves the list alone.
+    pub fn compare(self, a: &Song, b: &Song) -> Ordering {
+        let text = |a: &str, b: &str| {
+            a.chars().flat_map(char::to_lowercase).cmp(b.chars().flat_map(char::to_lowercase))
+        };
+        match self {
+            Column::Position => Ordering::Equal,
+            Column::Track => a.track.cmp(&b.track),
+            Column::Title => text(&a.title, &b.title),
+            Column::Artist => text(&a.artist, &b.artist),
+            Column::Album => text(&a.album, &b.album),
+            Column::Genre => text(&a.genre, &b.genre),
+            Column::Duration => a.duration.cmp(&b.duration),
+            Column::Bpm => a.bpm.cmp(&b.bpm),
+            Column::Key => a.key.cmp(&b.key),
+            Column::Dr => a.dr.cmp(&b.dr),
+        }
+    }
+}
+
+// What the library is kept sorted by, so the song list, an artist's albums
+// and search results all follow it; `S` cycles through them. Without one
+// songs stay in the order the scan found them.
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum LibrarySort {
+    Title,
+    Artist,
+    // Then by track number
+    Album,
+    // The songs the library got most recently first, as with recently added
+    Added,
+    Duration,
+}
+
+impl LibrarySort {
+    pub fn next(self) -> Self {
+        match self {
+            LibrarySort::Title => LibrarySort::Artist,
+            LibrarySort::Artist => LibrarySort::Album,
+            LibrarySort::Album => LibrarySort::Added,
+            LibrarySort::Added => LibrarySort::Duration,
+            LibrarySort::Duration => LibrarySort::Title,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            LibrarySort::Title => "title",
+            LibrarySort::Artist => "artist",
+            LibrarySort::Album => "album and track",
+            LibrarySort::Added => "date added",
+            LibrarySort::Duration => "duration",
+        }
+    }
+
+    // Later columns break ties, so an artist's songs come album by album
+    pub fn compare(self, a: &Song, b: &Song) -> Ordering {
+        let by = |columns: &[Column]| {
+            columns.iter().fold(Ordering::Equal, |ordering, column| ordering.then_with(|| column.compare(a, b)))
+        };
+        match self {
+            LibrarySort::Title => by(&[Column::Title, Column::Artist]),
+            LibrarySort::Artist => by(&[Column::Artist, Column::Album, Column::Track, Column::Title]),
+            LibrarySort::Album => by(&[Column::Album, Column::Track, Column::Title]),
+            LibrarySort::Added => b.added.cmp(&a.added),
+            LibrarySort::Duration => by(&[Column::Duration]),
+        }
+    }
+}
+
+// A column as written in the config: "title" shares the free space with the
+// other unsized columns, "duration:6" is always 6 cells wide
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct ColumnSpec {
+    pub column: Column,
+    pub width: Option<u16>,
+}
+
+impl FromStr for ColumnSpec {
+    type Err = anyhow::Error;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let (name, width) = match spec.split_once(':') {
+            Some((name, width)) => {
+                let width = width.trim().parse()
+                    .map_err(|_| anyhow::anyhow!("Invalid width in column \"{}\"", spec))?;
+                (name, Some(width))
+            }
+            None => (spec, None),
+        };
+        let column = match name.trim().to_lowercase().as_str() {
+            "position" | "#" => Column::Position,
+            "track" => Column::Track,
+            "title" => Column::Title,
+            "artist" => Column::Artist,
+            "album" => Column::Album,
+            "genre" => Column::Genre,
+            "duration" | "time" => Column::Duration,
+            "bpm" | "tempo" => Column::Bpm,
+            "key" => Column::Key,
+            "dr" | "dynamic_range" => Column::Dr,
+            _ => return Err(anyhow::anyhow!("Unknown column \"{}\"", name.trim())),
+        };
+        Ok(ColumnSpec { column, width })
+    }
+}
+
+impl TryFrom<String> for ColumnSpec {
+    type Error = anyhow::Error;
+
+    fn try_from(spec: String) -> Result<Self, Self::Error> {
+        spec.parse()
+    }
+}
+
+// Splits `total` cells between the columns, one cell apart. Fixed widths are
+// honoured first (and cut off at the edge); the rest is shared evenly.
+pub fn widths(columns: &[ColumnSpec], total: u16) -> Vec<u16> {
+    let gaps = columns.len().saturating_sub(1) as u16;
+    let mut free = total.saturating_sub(gaps);
+    let mut widths: Vec<u16> = columns.iter()
+        .map(|spec| {
+            let width = spec.width.unwrap_or(0).min(free);
+            free -= width;
+            width
+        })
+        .collect();
+
+    let flexible = columns.iter().filter(|spec| spec.width.is_none()).count() as u16;
+    if let Some(share) = free.checked_div(flexible) {
+        let mut extra = free % flexible;
+        for (width, spec) in widths.iter_mut().zip(columns) {
+            if spec.width.is_none() {
+                *width = share + u16::from(extra > 0);
+                extra = extra.saturating_sub(1);
+            }
+        }
+    }
+    widths
+}
+
+// Orders song indices by a column; ties keep their current order
+pub fn sort(indices: &mut [usize], songs: &[Song], column: Column, descending: bool) {
+    indices.sort_by(|&a, &b| {
+        let ordering = column.compare(&songs[a], &songs[b]);
+        if descending { ordering.reverse() } else { ordering }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn spec(s: &str) -> ColumnSpec {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_names_and_widths() {
+        assert_eq!(spec("Title"), ColumnSpec { column: Column::Title, width: None });
+        assert_eq!(spec("duration:6"), ColumnSpec { column: Column::Duration, width: Some(6) });
+        assert_eq!(spec("#:3"), ColumnSpec { column: Column::Position, width: Some(3) });
+        assert_eq!(spec("key:3"), ColumnSpec { column: Column::Key, width: Some(3) });
+        assert!("rating".parse::<ColumnSpec>().is_err());
+        assert!("title:wide".parse::<ColumnSpec>().is_err());
+    }
+
+    #[test]
+    fn fixed_widths_first_then_even_shares() {
+        let columns = [spec("position:3"), spec("title"), spec("artist")];
+        // 3 fixed + 2 gaps leaves 15, split 8/7
+        assert_eq!(widths(&columns, 20), vec![3, 8, 7]);
+        // Too narrow: the fixed column is cut and nothing is left over
+        assert_eq!(widths(&columns, 4), vec![2, 0, 0]);
+    }
+
+    #[test]
+    fn sort_is_stable_and_case_insensitive() {
+        let songs: Vec<Song> = ["b - Zebra", "A - apple", "a - Mango"]
+            .iter()
+            .map(|name| Song::new(PathBuf::from(format!("/music/{}.mp3", name))))
+            .collect();
+        let mut indices = vec![0, 1, 2];
+        sort(&mut indices, &songs, Column::Artist, false);
+        assert_eq!(indices, vec![1, 2, 0]);
+        sort(&mut indices, &songs, Column::Title, true);
+        assert_eq!(indices, vec![0, 2, 1]);
+        sort(&mut indices, &songs, Column::Position, false);
+        assert_eq!(indices, vec![0, 2, 1]);
+    }
+
+    #[test]
+    fn library_sorts_break_ties() {
+        let song = |name: &str, album: &str, track: u32, added: u64| {
+            let mut song = Song::new(PathBuf::from(format!("/music/{}.mp3", name)));
+            song.album = album.to_string();
+            song.track = Some(track);
+            song.added = std::time::UNIX_EPOCH + std::time::Duration::from_secs(added);
+            song
+        };
+        let songs = [
+            song("Can - Vitamin C", "Ege Bamyasi", 3, 10),
+            song("Can - Pinch", "Ege Bamyasi", 1, 0),
+            song("Blur - Song 2", "Blur", 2, 20),
+        ];
+        let order = |sort: LibrarySort| {
+            let mut indices = vec![0, 1, 2];
+            indices.sort_by(|&a, &b| sort.compare(&songs[a], &songs[b]));
+            indices
+        };
+        assert_eq!(order(LibrarySort::Title), vec![1, 2, 0]);
+        assert_eq!(order(LibrarySort::Artist), vec![2, 1, 0]);
+        assert_eq!(order(LibrarySort::Album), vec![2, 1, 0]);
+        assert_eq!(order(LibrarySort::Added), vec![2, 0, 1]);
+        assert_eq!(LibrarySort::Duration.next(), LibrarySort::Title);
+    }
+}
@@ -0,0 +1,143 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use rodio::Source;
+
+// How fast the gain comes down on a peak, and goes back up after it
+const ATTACK: Duration = Duration::from_millis(5);
+const RELEASE: Duration = Duration::from_millis(250);
+// Nothing is made louder than this, so near-silence doesn't turn into hiss
+const MAX_BOOST_DB: f32 = 18.0;
+
+// Night mode as set from the UI and config, shared with the audio thread so
+// a toggle reaches the playing track straight away
+#[derive(Default)]
+pub struct Settings {
+    enabled: AtomicBool,
+    // f32 bits
+    threshold_db: AtomicU32,
+    ratio: AtomicU32,
+}
+
+pub type Night = Arc<Settings>;
+
+pub fn night(threshold_db: f32, ratio: f32) -> Night {
+    let night = Night::default();
+    configure(&night, threshold_db, ratio);
+    night
+}
+
+pub fn configure(night: &Night, threshold_db: f32, ratio: f32) {
+    night.threshold_db.store(threshold_db.min(0.0).to_bits(), Ordering::Relaxed);
+    night.ratio.store(ratio.max(1.0).to_bits(), Ordering::Relaxed);
+}
+
+pub fn set_enabled(night: &Night, enabled: bool) {
+    night.enabled.store(enabled, Ordering::Relaxed);
+}
+
+pub fn enabled(night: &Night) -> bool {
+    night.enabled.load(Ordering::Relaxed)
+}
+
+// The change in dB for a signal at `level_db`: above the threshold only
+// 1/ratio of each further dB gets through, and everything is then made up by
+// half of what a full-scale peak loses, so quiet passages come up
+pub fn gain_db(level_db: f32, threshold_db: f32, ratio: f32) -> f32 {
+    let makeup = -threshold_db * (1.0 - 1.0 / ratio) / 2.0;
+    let reduction = if level_db > threshold_db { (level_db - threshold_db) * (1.0 - 1.0 / ratio) } else { 0.0 };
+    (makeup - reduction).min(MAX_BOOST_DB)
+}
+
+// Compressor and limiter for late-night listening. One envelope follows the
+// peaks of all channels, so the stereo image doesn't wander. Whatever still
+// overshoots is held at full scale. Samples come out as floats, so the gain
+// is only rounded at the dither stage.
+pub struct Compressor<S> {
+    source: S,
+    night: Night,
+    envelope: f32,
+}
+
+impl<S: Source<Item = i16>> Compressor<S> {
+    pub fn new(source: S, night: Night) -> Self {
+        Compressor { source, night, envelope: 0.0 }
+    }
+
+    // How much of the way to a new level the envelope moves each sample
+    fn coefficient(&self, time: Duration) -> f32 {
+        let samples = self.source.sample_rate() as f32 * self.source.channels() as f32 * time.as_secs_f32();
+        1.0 - (-1.0 / samples.max(1.0)).exp()
+    }
+}
+
+impl<S: Source<Item = i16>> Iterator for Compressor<S> {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let value = self.source.next()? as f32 / 32768.0;
+        if !enabled(&self.night) {
+            self.envelope = 0.0;
+            return Some(value);
+        }
+        let time = if value.abs() > self.envelope { ATTACK } else { RELEASE };
+        self.envelope += (value.abs() - self.envelope) * self.coefficient(time);
+        let threshold = f32::from_bits(self.night.threshold_db.load(Ordering::Relaxed));
+        let ratio = f32::from_bits(self.night.ratio.load(Ordering::Relaxed));
+        let level_db = 20.0 * self.envelope.max(1e-6).log10();
+        let gain = 10f32.powf(gain_db(level_db, threshold, ratio) / 20.0);
+        Some((value * gain).clamp(-1.0, 1.0))
+    }
+}
+
+impl<S: Source<Item = i16>> Source for Compressor<S> {
+    fn current_frame_len(&self) -> Option<usize> {
+        self.source.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.source.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.source.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.source.total_duration()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rodio::buffer::SamplesBuffer;
+
+    #[test]
+    fn quiet_comes_up_and_loud_comes_down() {
+        // -24 dB at 4:1 makes up 9 dB
+        assert_eq!(gain_db(-40.0, -24.0, 4.0), 9.0);
+        assert_eq!(gain_db(0.0, -24.0, 4.0), -9.0);
+        assert_eq!(gain_db(-60.0, -60.0, 20.0), MAX_BOOST_DB);
+    }
+
+    #[test]
+    fn passes_samples_through_while_off() {
+        let samples = vec![1000, -32768, 32767, 0];
+        let night = night(-24.0, 4.0);
+        let off: Vec<f32> = Compressor::new(SamplesBuffer::new(1, 1000, samples.clone()), night.clone()).collect();
+        assert_eq!(off, [1000.0 / 32768.0, -1.0, 32767.0 / 32768.0, 0.0]);
+
+        set_enabled(&night, true);
+        let loud = vec![30_000i16; 2000];
+        let on: Vec<f32> = Compressor::new(SamplesBuffer::new(1, 1000, loud), night).collect();
+        // Once the envelope has caught up, a near full-scale tone is about 9 dB down
+        let last = *on.last().unwrap() * 32768.0;
+        assert!((last / 30_000.0 - 10f32.powf(-9.0 / 20.0)).abs() < 0.05, "{}", last);
+    }
+}
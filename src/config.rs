@@ -0,0 +1,595 @@
+use std::{collections::HashMap, fs, path::PathBuf, time::Duration};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::columns::{ColumnSpec, LibrarySort};
+use crate::grouping::Grouping;
+use crate::library::format_duration;
+use crate::webhooks::Event;
+
+#[derive(Deserialize, Default, Clone)]
+#[serde(default)]
+pub struct Config {
+    pub announce: AnnounceConfig,
+    pub art: ArtConfig,
+    // Artist names shown as another, without touching the tags:
+    // "Beatles, The" = "The Beatles". `:artists merge` adds to these.
+    pub artist_aliases: HashMap<String, String>,
+    // Extra browse tabs, e.g. browse = ["genre / albumartist / album", "Decades = decade / album"]
+    pub browse: Vec<Grouping>,
+    pub columns: ColumnsConfig,
+    pub genres: GenresConfig,
+    pub goals: GoalsConfig,
+    pub http: Option<HttpConfig>,
+    pub keys: KeysConfig,
+    pub layout: LayoutConfig,
+    // Songs stay in the order they were found without one
+    pub library_sort: Option<LibrarySort>,
+    pub lyrics: LyricsConfig,
+    pub now_playing_file: Option<NowPlayingFileConfig>,
+    pub output: OutputConfig,
+    pub pins: PinsConfig,
+    pub playlist_dir: Option<PathBuf>,
+    pub power: PowerMode,
+    pub queue: QueueConfig,
+    pub rescan_interval_secs: Option<u64>,
+    pub scan: ScanConfig,
+    // Minutes without input before the screensaver comes on; off if unset
+    pub screensaver_after_mins: Option<u64>,
+    pub sections: SectionsConfig,
+    pub tag_sync: TagSyncConfig,
+    pub time_display: TimeDisplay,
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct ScanConfig {
+    pub extensions: Vec<String>,
+    pub follow_links: bool,
+    pub max_depth: Option<usize>,
+    // Adding a directory beyond these limits asks for confirmation first
+    pub max_files: usize,
+    pub max_total_size_mb: u64,
+    // Leave out files whose audio is the same as one already found, such as
+    // copies in another directory. Files whose audio is as long as another's
+    // are read in full to tell.
+    pub skip_copies: bool,
+}
+
+impl Default for ScanConfig {
+    fn default() -> Self {
+        ScanConfig {
+            // No Opus yet: neither Symphonia 0.5 nor rodio decodes it, and
+            // libopus bindings would need the C library to build. Adding
+            // "opus" lists the files, but they fail to play.
+            extensions: ["mp3", "ogg", "flac", "m4a", "aac", "wav", "aiff", "aif"].into_iter().map(String::from).collect(),
+            follow_links: true,
+            max_depth: Some(32),
+            max_files: 100_000,
+            max_total_size_mb: 2 * 1024 * 1024,
+            skip_copies: false,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct LayoutConfig {
+    pub sidebar: bool,
+    // Percentage of the width taken by the Now Playing/Queue sidebar
+    pub sidebar_width: u16,
+}
+
+impl LayoutConfig {
+    pub const MIN_SIDEBAR_WIDTH: u16 = 15;
+    pub const MAX_SIDEBAR_WIDTH: u16 = 60;
+}
+
+impl Default for LayoutConfig {
+    fn default() -> Self {
+        LayoutConfig {
+            sidebar: true,
+            sidebar_width: 30,
+        }
+    }
+}
+
+// Artists and albums listed first in the Artists and Albums tabs, in the
+// order they were pinned. `f` there pins and unpins.
+#[derive(Deserialize, Serialize, Default, Clone, PartialEq, Debug)]
+#[serde(default)]
+pub struct PinsConfig {
+    pub artists: Vec<String>,
+    // Album, then its artist: albums = [["Discovery", "Daft Punk"]]
+    pub albums: Vec<(String, String)>,
+}
+
+// How genre tags group, without touching the tags. Both ignore case:
+//
+// [genres.aliases]
+// "Alt Rock" = "Alternative Rock"
+// [genres.parents]
+// "Drum & Bass" = "Electronic"
+#[derive(Deserialize, Default, Clone)]
+#[serde(default)]
+pub struct GenresConfig {
+    pub aliases: HashMap<String, String>,
+    // Subgenre → the broader genre it's also listed under
+    pub parents: HashMap<String, String>,
+}
+
+// Targets shown against how listening is going in :stats; unset ones aren't
+#[derive(Deserialize, Default, Clone, Copy)]
+#[serde(default)]
+pub struct GoalsConfig {
+    pub daily_plays: Option<usize>,
+    // Albums heard for the first time, Monday to Sunday
+    pub weekly_new_albums: Option<usize>,
+}
+
+// Short lists above the full one in the Songs tab, each up to this many
+// songs; 0 leaves it out. Space on a section's header folds it away.
+#[derive(Deserialize, Default, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct SectionsConfig {
+    pub recently_played: usize,
+    pub most_played: usize,
+    pub recently_added: usize,
+}
+
+// Cover thumbnails are cached per album, by default under the user cache dir
+#[derive(Deserialize, Default, Clone)]
+#[serde(default)]
+pub struct ArtConfig {
+    pub cache_dir: Option<PathBuf>,
+    // Ask Cover Art Archive for albums without local art (needs curl)
+    pub fetch: bool,
+}
+
+// Lyrics come from the tags or a .lrc/.txt file next to the song. With
+// `fetch` they are also looked up online (needs curl) and kept as .lrc files.
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct LyricsConfig {
+    pub fetch: bool,
+    // Any service that answers like LRCLIB's /api/get
+    pub provider: String,
+    // Keep fetched lyrics next to the songs instead of in the cache dir
+    pub save_next_to_files: bool,
+}
+
+impl Default for LyricsConfig {
+    fn default() -> Self {
+        LyricsConfig {
+            fetch: false,
+            provider: String::from("https://lrclib.net/api/get"),
+            save_next_to_files: false,
+        }
+    }
+}
+
+// Columns shown in each song list, e.g. queue = ["position:3", "title", "duration:5"]
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct ColumnsConfig {
+    pub songs: Vec<ColumnSpec>,
+    // Songs of the artist picked in the Artists tab
+    pub artist: Vec<ColumnSpec>,
+    pub queue: Vec<ColumnSpec>,
+    pub search: Vec<ColumnSpec>,
+    // Tracks of the playlist opened in the Playlists tab
+    pub playlist: Vec<ColumnSpec>,
+}
+
+impl Default for ColumnsConfig {
+    fn default() -> Self {
+        let parse = |specs: &[&str]| specs.iter().map(|spec| spec.parse().unwrap()).collect();
+        ColumnsConfig {
+            songs: parse(&["title", "artist", "album"]),
+            artist: parse(&["track:3", "title", "album"]),
+            queue: parse(&["position:3", "title", "duration:5"]),
+            search: parse(&["title", "artist", "album"]),
+            playlist: parse(&["position:3", "title", "artist", "album"]),
+        }
+    }
+}
+
+// How the playing time is shown; `t` cycles through them
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum TimeDisplay {
+    #[default]
+    Elapsed,
+    Remaining,
+    Percent,
+}
+
+impl TimeDisplay {
+    pub fn next(self) -> Self {
+        match self {
+            TimeDisplay::Elapsed => TimeDisplay::Remaining,
+            TimeDisplay::Remaining => TimeDisplay::Percent,
+            TimeDisplay::Percent => TimeDisplay::Elapsed,
+        }
+    }
+
+    // Without a known length only the elapsed time can be shown
+    pub fn format(self, position: Duration, length: Option<Duration>) -> String {
+        let Some(length) = length.filter(|length| !length.is_zero()) else {
+            return format_duration(position);
+        };
+        let position = position.min(length);
+        match self {
+            TimeDisplay::Elapsed => format!("{} / {}", format_duration(position), format_duration(length)),
+            TimeDisplay::Remaining => format!("−{} / {}", format_duration(length - position), format_duration(length)),
+            TimeDisplay::Percent => format!("{}%", position.as_millis() * 100 / length.as_millis()),
+        }
+    }
+}
+
+// Low power ticks less often, redraws only when something shown changes and
+// drops the animations. Auto switches to it while on battery.
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum PowerMode {
+    #[default]
+    Auto,
+    Normal,
+    Low,
+}
+
+// What the queue does with tracks as they are added and played
+#[derive(Deserialize, Default, Clone, Copy)]
+#[serde(default)]
+pub struct QueueConfig {
+    pub played: PlayedPolicy,
+    pub duplicates: DuplicatePolicy,
+    // For parties, so one guest can't queue up the whole night
+    pub max_length: Option<usize>,
+}
+
+// Played tracks leave the queue, or stay in it greyed out
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum PlayedPolicy {
+    #[default]
+    Remove,
+    Keep,
+}
+
+// Whether a track already waiting in the queue can be queued again
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum DuplicatePolicy {
+    #[default]
+    Allow,
+    Reject,
+}
+
+// Keeps ratings and play counts in the files' POPM tags as well (MP3 only)
+#[derive(Deserialize, Default, Clone, Copy)]
+#[serde(default)]
+pub struct TagSyncConfig {
+    pub enabled: bool,
+    // Whose rating wins when it was changed both in the player and in the tags
+    pub conflicts: TagConflict,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum TagConflict {
+    #[default]
+    Player,
+    Tags,
+}
+
+// Key names are single characters or F1-F12
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct KeysConfig {
+    // Jump straight to the Songs, Artists, Albums, Genres, Queue, Search, Playlists and
+    // Folders tabs, then the configured browse tabs
+    pub tabs: Vec<String>,
+}
+
+impl Default for KeysConfig {
+    fn default() -> Self {
+        KeysConfig {
+            // One for each of the eight built-in tabs
+            tabs: (1..=8).map(|n| n.to_string()).collect(),
+        }
+    }
+}
+
+#[derive(Deserialize, Default, Clone)]
+#[serde(default)]
+pub struct AnnounceConfig {
+    pub mqtt: Option<MqttConfig>,
+    pub osc: Option<OscConfig>,
+    // Each [[announce.webhooks]] gets events POSTed to it as JSON
+    pub webhooks: Vec<WebhookConfig>,
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct WebhookConfig {
+    pub url: String,
+    // Which of "start", "stop", "skip" and "queue" to send; all by default
+    pub events: Vec<Event>,
+    // Further attempts after a failed delivery, each waiting twice as long
+    pub retries: u32,
+}
+
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        WebhookConfig {
+            url: String::new(),
+            events: Event::ALL.to_vec(),
+            retries: 3,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct MqttConfig {
+    pub host: String,
+    pub port: u16,
+    pub topic: String,
+    pub client_id: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+impl Default for MqttConfig {
+    fn default() -> Self {
+        MqttConfig {
+            host: String::from("localhost"),
+            port: 1883,
+            topic: String::from("music-player/now_playing"),
+            client_id: String::from("music-player"),
+            username: None,
+            password: None,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct OscConfig {
+    pub target: String,
+    pub address: String,
+}
+
+impl Default for OscConfig {
+    fn default() -> Self {
+        OscConfig {
+            target: String::from("127.0.0.1:9000"),
+            address: String::from("/music-player/now_playing"),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct HttpConfig {
+    pub bind: String,
+    // What requests without the token may do; only look, unless set
+    pub access: Access,
+    // Requests sending "Authorization: Bearer <token>" get full control on
+    // any address
+    pub token: Option<String>,
+    // More addresses to serve on, each with its own access, such as the LAN
+    // read-only next to full control on localhost
+    pub listen: Vec<ListenConfig>,
+    // Serve HTTPS instead, on every address
+    pub tls: Option<TlsConfig>,
+}
+
+#[derive(Deserialize, Clone, PartialEq)]
+pub struct TlsConfig {
+    // PEM files: the certificate chain, then its private key
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        HttpConfig {
+            bind: String::from("127.0.0.1:8080"),
+            access: Access::ReadOnly,
+            token: None,
+            listen: Vec::new(),
+            tls: None,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, PartialEq)]
+pub struct ListenConfig {
+    pub bind: String,
+    #[serde(default)]
+    pub access: Access,
+}
+
+// What a client of the HTTP API may do, each level allowing what the ones
+// before it do
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "kebab-case")]
+pub enum Access {
+    // Nothing without the token
+    None,
+    // Only look: the GET endpoints
+    #[default]
+    ReadOnly,
+    // Add tracks to the queue
+    Queue,
+    // Play, pause, skip and clear the queue
+    Full,
+}
+
+// How decoded audio gets to the sound card
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct OutputConfig {
+    // Decoded this far ahead of playback on its own thread, to ride out slow
+    // disks and network shares; 0 decodes as it plays
+    pub read_ahead_secs: f32,
+    // Show how full the read-ahead buffer is next to the playback status
+    pub show_buffer: bool,
+    // How tracks are converted when their sample rate isn't the device's
+    pub resample_quality: ResampleQuality,
+    // Add TPDF dither when a 16-bit device gets the result of the volume,
+    // which is worked out in float
+    pub dither: bool,
+    // Read when the player starts, as it decides how the device is opened
+    pub channels: ChannelMode,
+    // Open and buffer the next track while this one plays, so it starts
+    // without waiting on the disk
+    pub preload_next: bool,
+    // Start the next track on the sample the last one ends, with no gap
+    pub gapless: bool,
+    // Which ReplayGain tag levels tracks out
+    pub replay_gain: ReplayGainMode,
+    pub night: NightConfig,
+    // Play every channel's average on all of them, for one ear; M toggles it
+    pub mono: bool,
+    // Tracks fade in this long as they start and out as playback pauses or
+    // stops; 0 cuts straight in and out
+    pub fade_ms: u64,
+}
+
+impl Default for OutputConfig {
+    fn default() -> Self {
+        OutputConfig {
+            read_ahead_secs: 2.0,
+            show_buffer: false,
+            resample_quality: ResampleQuality::default(),
+            dither: false,
+            channels: ChannelMode::default(),
+            preload_next: true,
+            gapless: true,
+            replay_gain: ReplayGainMode::default(),
+            night: NightConfig::default(),
+            mono: false,
+            fade_ms: 200,
+        }
+    }
+}
+
+// Night mode: a compressor that brings quiet passages up and loud peaks
+// down. N toggles it; `enabled` is what it starts as.
+#[derive(Deserialize, Clone, Copy)]
+#[serde(default)]
+pub struct NightConfig {
+    pub enabled: bool,
+    // Peaks above this many dB below full scale are compressed
+    pub threshold_db: f32,
+    // 4 lets a quarter of each dB over the threshold through
+    pub ratio: f32,
+}
+
+impl Default for NightConfig {
+    fn default() -> Self {
+        NightConfig {
+            enabled: false,
+            threshold_db: -24.0,
+            ratio: 4.0,
+        }
+    }
+}
+
+// Fast interpolates linearly, medium along a spline, and high filters with a
+// windowed sinc, which costs the most CPU but adds no audible artifacts
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ResampleQuality {
+    Fast,
+    Medium,
+    #[default]
+    High,
+}
+
+// Track gain evens out every track, album gain keeps the loudness differences
+// within an album; each falls back on the other when a file only has one
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ReplayGainMode {
+    Off,
+    #[default]
+    Track,
+    Album,
+}
+
+// Surround files are either mixed down for a stereo output, or played on
+// as many speakers as the device has, with stereo files on the front pair
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ChannelMode {
+    #[default]
+    Downmix,
+    Passthrough,
+}
+
+impl ResampleQuality {
+    pub fn label(self) -> &'static str {
+        match self {
+            ResampleQuality::Fast => "fast",
+            ResampleQuality::Medium => "medium",
+            ResampleQuality::High => "high",
+        }
+    }
+}
+
+// `format` is either "json" or a template such as "{artist} - {title}"
+#[derive(Deserialize, Clone)]
+pub struct NowPlayingFileConfig {
+    pub path: PathBuf,
+    #[serde(default = "default_now_playing_format")]
+    pub format: String,
+}
+
+fn default_now_playing_format() -> String {
+    String::from("{artist} - {title}")
+}
+
+impl Config {
+    // ~/.config/music-player/config.toml on Linux, %APPDATA%\music-player\config.toml on Windows
+    pub fn path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("music-player").join("config.toml"))
+    }
+
+    // Where playlists are kept as .m3u files, by default next to the config file
+    pub fn playlist_dir(&self) -> Option<PathBuf> {
+        self.playlist_dir.clone()
+            .or_else(|| dirs::config_dir().map(|dir| dir.join("music-player").join("playlists")))
+    }
+
+    pub fn load() -> Result<Self> {
+        let path = match Self::path() {
+            Some(path) if path.exists() => path,
+            _ => return Ok(Config::default()),
+        };
+        let contents = fs::read_to_string(&path)?;
+        toml::from_str(&contents)
+            .map_err(|e| anyhow::anyhow!("Invalid config {}: {}", path.display(), e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn time_display_formats() {
+        let position = Duration::from_secs(83);
+        let length = Some(Duration::from_secs(332));
+        assert_eq!(TimeDisplay::Elapsed.format(position, length), "1:23 / 5:32");
+        assert_eq!(TimeDisplay::Remaining.format(position, length), "−4:09 / 5:32");
+        assert_eq!(TimeDisplay::Percent.format(position, length), "25%");
+        // Unknown length falls back to elapsed time
+        assert_eq!(TimeDisplay::Percent.format(position, None), "1:23");
+        assert_eq!(TimeDisplay::Percent.next().next(), TimeDisplay::Remaining);
+    }
+}
@@ -0,0 +1,89 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::persist;
+
+// Pads per track, bound to the keys 1-8 in perform mode
+pub const PADS: usize = 8;
+
+// Cue points by file path, in milliseconds from the start of the track
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct Cues {
+    pub songs: HashMap<PathBuf, [Option<u64>; PADS]>,
+}
+
+impl Cues {
+    // ~/.local/share/music-player/cues.json on Linux, %APPDATA%\music-player\cues.json on Windows
+    pub fn path() -> Option<PathBuf> {
+        dirs::data_dir().map(|dir| dir.join("music-player").join("cues.json"))
+    }
+
+    // A missing or unreadable file means no cues yet
+    pub fn load() -> Self {
+        Self::path()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let path = Self::path().ok_or_else(|| anyhow::anyhow!("No data directory"))?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        persist::write_atomic(&path, &serde_json::to_string(self)?)
+    }
+
+    pub fn get(&self, path: &Path, pad: usize) -> Option<Duration> {
+        self.songs.get(path)?.get(pad).copied().flatten().map(Duration::from_millis)
+    }
+
+    pub fn set(&mut self, path: &Path, pad: usize, at: Duration) {
+        if pad < PADS {
+            self.songs.entry(path.to_path_buf()).or_default()[pad] = Some(at.as_millis() as u64);
+        }
+    }
+
+    pub fn clear(&mut self, path: &Path, pad: usize) {
+        if let Some(pads) = self.songs.get_mut(path) {
+            if let Some(cue) = pads.get_mut(pad) {
+                *cue = None;
+            }
+            // Tracks without cues don't stay in the file
+            if pads.iter().all(Option::is_none) {
+                self.songs.remove(path);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_get_and_clear() {
+        let mut cues = Cues::default();
+        let song = Path::new("/music/song.mp3");
+        cues.set(song, 2, Duration::from_millis(61_500));
+        cues.set(song, PADS, Duration::from_secs(1));
+        assert_eq!(cues.get(song, 2), Some(Duration::from_millis(61_500)));
+        assert_eq!(cues.get(song, 0), None);
+
+        let json = serde_json::to_string(&cues).unwrap();
+        let cues: Cues = serde_json::from_str(&json).unwrap();
+        assert_eq!(cues.get(song, 2), Some(Duration::from_millis(61_500)));
+
+        let mut cues = cues;
+        cues.clear(song, 2);
+        assert!(cues.songs.is_empty());
+    }
+}
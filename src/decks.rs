@@ -0,0 +1,172 @@
+use std::{
+    f32::consts::FRAC_PI_2,
+    time::{Duration, Instant},
+};
+
+use crate::library::Song;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DeckSide {
+    A,
+    B,
+}
+
+// A one-knob EQ: cut the highs, cut the lows, or leave the track alone
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub enum Filter {
+    #[default]
+    Flat,
+    LowPass,
+    HighPass,
+}
+
+impl Filter {
+    pub fn next(self) -> Self {
+        match self {
+            Filter::Flat => Filter::LowPass,
+            Filter::LowPass => Filter::HighPass,
+            Filter::HighPass => Filter::Flat,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Filter::Flat => "Flat",
+            Filter::LowPass => "Bass only",
+            Filter::HighPass => "No bass",
+        }
+    }
+
+    // For handing to the audio thread through an atomic
+    pub fn as_u8(self) -> u8 {
+        self as u8
+    }
+
+    pub fn from_u8(value: u8) -> Self {
+        match value {
+            1 => Filter::LowPass,
+            2 => Filter::HighPass,
+            _ => Filter::Flat,
+        }
+    }
+}
+
+// One turntable: the loaded song and how it is being played
+pub struct Deck {
+    pub song: Option<Song>,
+    pub volume: f32,
+    pub filter: Filter,
+    played: Duration,
+    playing_since: Option<Instant>,
+}
+
+impl Default for Deck {
+    fn default() -> Self {
+        Deck { song: None, volume: 1.0, filter: Filter::Flat, played: Duration::ZERO, playing_since: None }
+    }
+}
+
+impl Deck {
+    // Loaded decks start paused, cued at the beginning
+    pub fn load(&mut self, song: Song) {
+        self.song = Some(song);
+        self.played = Duration::ZERO;
+        self.playing_since = None;
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.playing_since.is_some()
+    }
+
+    // Returns whether it is playing now
+    pub fn toggle(&mut self) -> bool {
+        match self.playing_since.take() {
+            Some(since) => self.played += since.elapsed(),
+            None if self.song.is_some() => self.playing_since = Some(Instant::now()),
+            None => {}
+        }
+        self.is_playing()
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        let elapsed = self.played + self.playing_since.map(|since| since.elapsed()).unwrap_or_default();
+        match self.song.as_ref().and_then(|song| song.duration) {
+            Some(duration) => elapsed.min(duration),
+            None => elapsed,
+        }
+    }
+
+    pub fn has_ended(&self) -> bool {
+        self.song.as_ref().and_then(|song| song.duration).is_some_and(|duration| self.elapsed() >= duration)
+    }
+
+    pub fn change_volume(&mut self, delta: f32) {
+        self.volume = (self.volume + delta).clamp(0.0, 1.0);
+    }
+}
+
+pub struct Decks {
+    pub a: Deck,
+    pub b: Deck,
+    // -1.0 is all deck A, 1.0 all deck B
+    pub crossfader: f32,
+}
+
+impl Default for Decks {
+    fn default() -> Self {
+        Decks { a: Deck::default(), b: Deck::default(), crossfader: 0.0 }
+    }
+}
+
+impl Decks {
+    pub fn deck(&mut self, side: DeckSide) -> &mut Deck {
+        match side {
+            DeckSide::A => &mut self.a,
+            DeckSide::B => &mut self.b,
+        }
+    }
+
+    pub fn move_crossfader(&mut self, delta: f32) {
+        self.crossfader = (self.crossfader + delta).clamp(-1.0, 1.0);
+    }
+
+    // Constant-power curve, so the middle isn't quieter than either end
+    pub fn gain(&self, side: DeckSide) -> f32 {
+        let angle = (self.crossfader + 1.0) / 2.0 * FRAC_PI_2;
+        match side {
+            DeckSide::A => angle.cos() * self.a.volume,
+            DeckSide::B => angle.sin() * self.b.volume,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crossfader_keeps_power_constant() {
+        let mut decks = Decks::default();
+        let (a, b) = (decks.gain(DeckSide::A), decks.gain(DeckSide::B));
+        assert!((a - b).abs() < 1e-6);
+        assert!((a * a + b * b - 1.0).abs() < 1e-6);
+
+        decks.move_crossfader(-5.0);
+        assert_eq!(decks.crossfader, -1.0);
+        assert!((decks.gain(DeckSide::A) - 1.0).abs() < 1e-6);
+        assert!(decks.gain(DeckSide::B).abs() < 1e-6);
+
+        decks.a.change_volume(-0.25);
+        assert!((decks.gain(DeckSide::A) - 0.75).abs() < 1e-6);
+    }
+
+    #[test]
+    fn empty_decks_dont_play() {
+        let mut deck = Deck::default();
+        assert!(!deck.toggle());
+        deck.load(Song::new("/music/Daft Punk - One More Time.mp3".into()));
+        assert!(deck.toggle());
+        assert!(!deck.toggle());
+        assert_eq!(Filter::from_u8(Filter::HighPass.as_u8()), Filter::HighPass);
+    }
+}
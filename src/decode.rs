@@ -0,0 +1,403 @@
+use std::{
+    fs::{self, File},
+    io::{BufReader, Cursor},
+    path::Path,
+    time::Duration,
+};
+
+use anyhow::Result;
+use rodio::{Decoder, Source};
+use serde::{Deserialize, Serialize};
+use symphonia::core::{
+    audio::{SampleBuffer, SignalSpec},
+    codecs::{self, DecoderOptions, CODEC_TYPE_NULL},
+    errors::Error as SymphoniaError,
+    formats::{FormatOptions, FormatReader, SeekMode, SeekTo},
+    io::MediaSourceStream,
+    meta::MetadataOptions,
+    probe::Hint,
+    units::Time,
+};
+
+pub type AudioSource = Box<dyn Source<Item = i16> + Send>;
+
+// How a file was decoded: the decoder that took it, and what failed before it
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq, Debug)]
+#[serde(default)]
+pub struct Diagnostics {
+    // None when nothing could decode it
+    pub decoder: Option<String>,
+    pub errors: Vec<String>,
+}
+
+impl Diagnostics {
+    // The first decoder's complaint, usually the telling one
+    pub fn first_error(&self) -> &str {
+        self.errors.first().map_or("", |e| e.as_str())
+    }
+}
+
+// What the file's audio is coded as, for Now Playing
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Codec {
+    pub name: &'static str,
+    pub bits: Option<u32>,
+    // Averaged over the whole file
+    pub kbps: Option<u32>,
+}
+
+impl Codec {
+    // "MP3 245 kbps", "FLAC 24-bit 2116 kbps"
+    pub fn label(&self) -> String {
+        let mut label = self.name.to_uppercase();
+        if let Some(bits) = self.bits {
+            label.push_str(&format!(" {}-bit", bits));
+        }
+        if let Some(kbps) = self.kbps {
+            label.push_str(&format!(" {} kbps", kbps));
+        }
+        label
+    }
+}
+
+pub fn open(path: &Path) -> (Option<AudioSource>, Diagnostics) {
+    open_at(path, Duration::ZERO)
+}
+
+// Symphonia first, which seeks straight to `from` and knows exact lengths;
+// it only has the codecs enabled in Cargo.toml. Then rodio's decoders one
+// after another: format sniffing, then the decoder the extension names (for
+// files whose headers confuse the sniffing), then both again past any junk
+// before the audio (oversized or broken tags), decoding up to `from`.
+pub fn open_at(path: &Path, from: Duration) -> (Option<AudioSource>, Diagnostics) {
+    let mut diagnostics = Diagnostics::default();
+    match Symphonia::open(path, from) {
+        Ok(source) => {
+            diagnostics.decoder = Some(String::from("symphonia"));
+            return (Some(Box::new(source)), diagnostics);
+        }
+        Err(e) => diagnostics.errors.push(format!("symphonia: {}", e)),
+    }
+    let attempts: [(&str, &dyn Fn() -> Result<AudioSource>); 3] = [
+        ("auto", &|| Ok(Box::new(Decoder::new(BufReader::new(File::open(path)?))?))),
+        ("by extension", &|| by_extension(path, BufReader::new(File::open(path)?))),
+        ("after junk", &|| {
+            let bytes = fs::read(path)?;
+            let start = stream_start(&bytes).ok_or_else(|| anyhow::anyhow!("no junk before the audio"))?;
+            let audio = bytes[start..].to_vec();
+            match Decoder::new(Cursor::new(audio.clone())) {
+                Ok(decoder) => Ok(Box::new(decoder)),
+                Err(_) => by_extension(path, Cursor::new(audio)),
+            }
+        }),
+    ];
+    for (name, attempt) in attempts {
+        match attempt() {
+            Ok(source) => {
+                diagnostics.decoder = Some(name.to_string());
+                let source: AudioSource = if from.is_zero() { source } else { Box::new(source.skip_duration(from)) };
+                return (Some(source), diagnostics);
+            }
+            Err(e) => diagnostics.errors.push(format!("{}: {}", name, e)),
+        }
+    }
+    (None, diagnostics)
+}
+
+fn by_extension<R>(path: &Path, data: R) -> Result<AudioSource>
+where
+    R: std::io::Read + std::io::Seek + Send + Sync + 'static,
+{
+    let extension = path.extension().unwrap_or_default().to_string_lossy().to_lowercase();
+    let decoder = match extension.as_str() {
+        "mp3" => Decoder::new_mp3(data)?,
+        "flac" => Decoder::new_flac(data)?,
+        "wav" => Decoder::new_wav(data)?,
+        "ogg" | "oga" => Decoder::new_vorbis(data)?,
+        _ => return Err(anyhow::anyhow!("no decoder for .{}", extension)),
+    };
+    Ok(Box::new(decoder))
+}
+
+// The codec of the file's first audio track, if Symphonia can read it
+pub fn codec(path: &Path) -> Option<Codec> {
+    let format = probe(path).ok()?;
+    let params = &format.default_track()?.codec_params;
+    let length = length(params);
+    let size = fs::metadata(path).ok()?.len();
+    Some(Codec {
+        name: symphonia::default::get_codecs().get_codec(params.codec)?.short_name,
+        bits: params.bits_per_sample,
+        kbps: length.filter(|length| !length.is_zero()).map(|length| (size as f64 * 8.0 / length.as_secs_f64() / 1000.0).round() as u32),
+    })
+}
+
+fn probe(path: &Path) -> Result<Box<dyn FormatReader>> {
+    let mut hint = Hint::new();
+    if let Some(extension) = path.extension().and_then(|extension| extension.to_str()) {
+        hint.with_extension(extension);
+    }
+    let stream = MediaSourceStream::new(Box::new(File::open(path)?), Default::default());
+    // Gapless trims the encoder's delay and padding, so lengths are the real ones
+    let options = FormatOptions { enable_gapless: true, ..FormatOptions::default() };
+    Ok(symphonia::default::get_probe().format(&hint, stream, &options, &MetadataOptions::default())?.format)
+}
+
+fn length(params: &codecs::CodecParameters) -> Option<Duration> {
+    let time = params.time_base?.calc_time(params.n_frames?);
+    Some(Duration::from_secs(time.seconds) + Duration::from_secs_f64(time.frac))
+}
+
+// Decodes a packet at a time, interleaved. Bad packets are skipped, as
+// rodio's decoders do.
+struct Symphonia {
+    format: Box<dyn FormatReader>,
+    decoder: Box<dyn codecs::Decoder>,
+    track: u32,
+    spec: SignalSpec,
+    length: Option<Duration>,
+    // The decoded packet; empty once the stream is over
+    buffer: Option<SampleBuffer<i16>>,
+    position: usize,
+}
+
+impl Symphonia {
+    fn open(path: &Path, from: Duration) -> Result<Self> {
+        let format = probe(path)?;
+        let track = format.tracks().iter()
+            .find(|track| track.codec_params.codec != CODEC_TYPE_NULL)
+            .ok_or_else(|| anyhow::anyhow!("no audio track"))?;
+        let (id, params) = (track.id, track.codec_params.clone());
+        let decoder = symphonia::default::get_codecs().make(&params, &DecoderOptions::default())?;
+        let channels = params.channels.ok_or_else(|| anyhow::anyhow!("unknown channel layout"))?;
+        let rate = params.sample_rate.ok_or_else(|| anyhow::anyhow!("unknown sample rate"))?;
+        let mut source = Symphonia {
+            format,
+            decoder,
+            track: id,
+            spec: SignalSpec::new(rate, channels),
+            length: length(&params),
+            buffer: None,
+            position: 0,
+        };
+        if from.is_zero() {
+            source.decode_packet();
+        } else {
+            source.seek(from)?;
+        }
+        // Left to the next decoder rather than played as silence
+        if source.buffer.is_none() {
+            return Err(anyhow::anyhow!("no audio could be decoded"));
+        }
+        Ok(source)
+    }
+
+    fn samples(&self) -> &[i16] {
+        self.buffer.as_ref().map_or(&[], SampleBuffer::samples)
+    }
+
+    // Lands on the exact frame: the reader stops at a packet before `to`,
+    // and what comes ahead of it is decoded and dropped
+    fn seek(&mut self, to: Duration) -> Result<()> {
+        let seeked = self.format.seek(SeekMode::Accurate, SeekTo::Time { time: Time::from(to), track_id: Some(self.track) })?;
+        self.decoder.reset();
+        let mut skip = seeked.required_ts.saturating_sub(seeked.actual_ts) as usize * self.spec.channels.count();
+        while self.decode_packet() {
+            let dropped = skip.min(self.samples().len());
+            self.position = dropped;
+            skip -= dropped;
+            if self.position < self.samples().len() {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    // False, with nothing left to play, at the end of the stream or on an
+    // error there's no getting past
+    fn decode_packet(&mut self) -> bool {
+        self.position = 0;
+        loop {
+            let Ok(packet) = self.format.next_packet() else {
+                self.buffer = None;
+                return false;
+            };
+            if packet.track_id() != self.track {
+                continue;
+            }
+            match self.decoder.decode(&packet) {
+                Ok(decoded) => {
+                    let spec = *decoded.spec();
+                    // Kept between packets unless the next one needs more room
+                    if self.spec != spec || self.buffer.as_ref().is_none_or(|buffer| buffer.capacity() < decoded.capacity() * spec.channels.count()) {
+                        self.buffer = Some(SampleBuffer::new(decoded.capacity() as u64, spec));
+                    }
+                    self.spec = spec;
+                    let buffer = self.buffer.as_mut().unwrap();
+                    buffer.copy_interleaved_ref(decoded);
+                    if !buffer.samples().is_empty() {
+                        return true;
+                    }
+                }
+                Err(SymphoniaError::DecodeError(_)) => continue,
+                Err(_) => {
+                    self.buffer = None;
+                    return false;
+                }
+            }
+        }
+    }
+}
+
+impl Iterator for Symphonia {
+    type Item = i16;
+
+    // The next packet is decoded as soon as one runs out, so the frame
+    // length is only ever 0 at the end
+    fn next(&mut self) -> Option<i16> {
+        let sample = *self.samples().get(self.position)?;
+        self.position += 1;
+        if self.position >= self.samples().len() {
+            self.decode_packet();
+        }
+        Some(sample)
+    }
+}
+
+impl Source for Symphonia {
+    // The layout can change between packets
+    fn current_frame_len(&self) -> Option<usize> {
+        Some(self.samples().len() - self.position)
+    }
+
+    fn channels(&self) -> u16 {
+        self.spec.channels.count() as u16
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.spec.rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.length
+    }
+}
+
+// Where the audio really starts, if something other than audio comes first:
+// past an ID3v2 tag, then at the first FLAC, Ogg, RIFF or MPEG frame marker
+pub fn stream_start(bytes: &[u8]) -> Option<usize> {
+    let mut start = 0;
+    if bytes.len() >= 10 && &bytes[..3] == b"ID3" {
+        // Sizes are "syncsafe": 7 bits per byte
+        let size = bytes[6..10].iter().fold(0usize, |size, &b| (size << 7) | (b & 0x7f) as usize);
+        let footer = if bytes[5] & 0x10 != 0 { 10 } else { 0 };
+        start = 10 + size + footer;
+    }
+    let found = (start..bytes.len()).find(|&i| {
+        let rest = &bytes[i..];
+        rest.starts_with(b"fLaC")
+            || rest.starts_with(b"OggS")
+            || rest.starts_with(b"RIFF")
+            || (rest.len() >= 2 && rest[0] == 0xff && rest[1] & 0xe0 == 0xe0)
+    })?;
+    (found > 0).then_some(found)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_audio_after_tags_and_junk() {
+        let mut bytes = b"ID3\x04\x00\x00\x00\x00\x01\x00".to_vec();
+        bytes.extend(vec![0xff; 128]);
+        bytes.extend(b"junk");
+        bytes.extend(b"fLaC....");
+        // The 0xff padding inside the tag isn't mistaken for a frame
+        assert_eq!(stream_start(&bytes), Some(10 + 128 + 4));
+
+        assert_eq!(stream_start(b"fLaC...."), None);
+        assert_eq!(stream_start(b"garbage\xff\xfb\x90"), Some(7));
+        assert_eq!(stream_start(b"nothing here"), None);
+    }
+
+    #[test]
+    fn codec_labels_leave_out_what_is_unknown() {
+        assert_eq!(Codec { name: "mp3", bits: None, kbps: Some(245) }.label(), "MP3 245 kbps");
+        assert_eq!(Codec { name: "flac", bits: Some(24), kbps: None }.label(), "FLAC 24-bit");
+    }
+
+    // 16-bit mono FLAC at 44.1 kHz: STREAMINFO, then one frame with the
+    // samples stored verbatim
+    fn flac(samples: &[i16]) -> Vec<u8> {
+        let crc = |bytes: &[u8], poly: u16, width: u32| {
+            let top = 1 << (width - 1);
+            bytes.iter().fold(0u16, |crc, &byte| {
+                (0..8).fold(crc ^ (byte as u16) << (width - 8), |crc, _| {
+                    let crc = if crc & top != 0 { (crc << 1) ^ poly } else { crc << 1 };
+                    if width == 8 { crc & 0xff } else { crc }
+                })
+            })
+        };
+        let n = samples.len() as u64;
+        let mut bytes = b"fLaC".to_vec();
+        bytes.extend([0x80, 0, 0, 34]);
+        bytes.extend((n as u16).to_be_bytes());
+        bytes.extend((n as u16).to_be_bytes());
+        bytes.extend([0; 6]);
+        let packed: u64 = (44_100 << 44) | (15 << 36) | n;
+        bytes.extend(packed.to_be_bytes());
+        bytes.extend([0; 16]);
+
+        let mut frame = vec![0xff, 0xf8, 0x69, 0x08, 0x00, (n - 1) as u8];
+        frame.push(crc(&frame, 0x07, 8) as u8);
+        frame.push(0x02);
+        frame.extend(samples.iter().flat_map(|s| s.to_be_bytes()));
+        frame.extend(crc(&frame, 0x8005, 16).to_be_bytes());
+        bytes.extend(frame);
+        bytes
+    }
+
+    #[test]
+    fn symphonia_decodes_flac() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("track.flac");
+        let samples: Vec<i16> = (0..32).map(|i| i * 1000 - 16_000).collect();
+        fs::write(&path, flac(&samples)).unwrap();
+        let (source, diagnostics) = open(&path);
+        assert_eq!(diagnostics.decoder.as_deref(), Some("symphonia"));
+        assert_eq!(source.unwrap().collect::<Vec<i16>>(), samples);
+    }
+
+    #[test]
+    fn symphonia_fails_when_nothing_decodes() {
+        // A header claiming audio that isn't there
+        let mut bytes = b"RIFF".to_vec();
+        bytes.extend(36u32.to_le_bytes());
+        bytes.extend(b"WAVEfmt ");
+        bytes.extend(16u32.to_le_bytes());
+        bytes.extend([1, 0, 1, 0]);
+        bytes.extend(8000u32.to_le_bytes());
+        bytes.extend(16_000u32.to_le_bytes());
+        bytes.extend([2, 0, 16, 0]);
+        bytes.extend(b"data");
+        bytes.extend(16_000u32.to_le_bytes());
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("empty.wav");
+        fs::write(&path, bytes).unwrap();
+        assert!(Symphonia::open(&path, Duration::ZERO).is_err());
+    }
+
+    #[test]
+    fn records_every_failed_attempt() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("broken.mp3");
+        fs::write(&path, b"not audio at all").unwrap();
+        let (source, diagnostics) = open(&path);
+        assert!(source.is_none());
+        assert_eq!(diagnostics.decoder, None);
+        assert_eq!(diagnostics.errors.len(), 4);
+        assert!(diagnostics.errors[0].starts_with("symphonia"));
+        assert!(diagnostics.errors[3].starts_with("after junk"));
+    }
+}
@@ -0,0 +1,125 @@
+use std::{
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use rodio::Source;
+
+// The volume, shared with the audio thread as f32 bits, since a dithered
+// track has it applied here rather than by the sink
+pub type Gain = Arc<AtomicU32>;
+
+// A track from the compressor on: samples between -1 and 1, rounded only by
+// the dither stage or the mixer
+pub type FloatSource = Box<dyn Source<Item = f32> + Send>;
+
+pub fn gain(volume: f32) -> Gain {
+    Arc::new(AtomicU32::new(volume.to_bits()))
+}
+
+pub fn set_gain(gain: &Gain, volume: f32) {
+    gain.store(volume.to_bits(), Ordering::Relaxed);
+}
+
+// Last stage before a 16-bit device: applies the volume and the track's
+// ReplayGain in float, then rounds
+// to 16 bits with TPDF dither (two uniform random values, one LSB each) so
+// the rounding error becomes steady noise instead of distortion that follows
+// the music. What comes out passes through the mixer unchanged.
+pub struct Dither<S> {
+    source: S,
+    gain: Gain,
+    track_gain: f32,
+    rng: StdRng,
+}
+
+impl<S: Source<Item = f32>> Dither<S> {
+    pub fn new(source: S, gain: Gain, track_gain: f32) -> Self {
+        Dither { source, gain, track_gain, rng: StdRng::from_entropy() }
+    }
+}
+
+// Triangular noise between -1 and 1 LSB
+pub fn noise(rng: &mut StdRng) -> f32 {
+    rng.gen::<f32>() - rng.gen::<f32>()
+}
+
+impl<S: Source<Item = f32>> Iterator for Dither<S> {
+    type Item = i16;
+
+    fn next(&mut self) -> Option<i16> {
+        let volume = f32::from_bits(self.gain.load(Ordering::Relaxed)) * self.track_gain;
+        let sample = self.source.next()? * 32768.0;
+        // Whole steps at full volume were left alone on the way here
+        if volume == 1.0 && sample.fract() == 0.0 {
+            return Some(sample.clamp(i16::MIN as f32, i16::MAX as f32) as i16);
+        }
+        let sample = sample * volume + noise(&mut self.rng);
+        Some(sample.round().clamp(i16::MIN as f32, i16::MAX as f32) as i16)
+    }
+}
+
+impl<S: Source<Item = f32>> Source for Dither<S> {
+    fn current_frame_len(&self) -> Option<usize> {
+        self.source.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.source.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.source.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.source.total_duration()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rodio::buffer::SamplesBuffer;
+
+    fn dithered(samples: Vec<f32>, volume: f32) -> Vec<i16> {
+        let source = SamplesBuffer::new(1, 44_100, samples);
+        let mut dither = Dither::new(source, gain(volume), 1.0);
+        dither.rng = StdRng::seed_from_u64(1);
+        dither.collect()
+    }
+
+    #[test]
+    fn noise_stays_within_one_step_and_averages_out() {
+        // Turned down to a level between two steps, which plain rounding
+        // would always round the same way
+        let level = 200.6 / 32768.0;
+        let out = dithered(vec![level; 10_000], 0.5);
+        assert!(out.iter().all(|&s| (99..=101).contains(&s)));
+        let mean = out.iter().map(|&s| s as f64).sum::<f64>() / out.len() as f64;
+        assert!((mean - 100.3).abs() < 0.05, "{}", mean);
+    }
+
+    #[test]
+    fn full_volume_passes_through() {
+        let samples: Vec<f32> = [0, 1, -1, 12_345, i16::MIN].iter().map(|&s| s as f32 / 32768.0).collect();
+        assert_eq!(dithered(samples, 1.0), vec![0, 1, -1, 12_345, i16::MIN]);
+        // Compressed or faded samples between steps are dithered all the same
+        let out = dithered(vec![200.6 / 32768.0; 100], 1.0);
+        assert!(out.iter().all(|&s| (200..=202).contains(&s)));
+    }
+
+    #[test]
+    fn applies_the_volume_and_clips() {
+        let out = dithered(vec![0.5, -4.0, 4.0], 0.5);
+        assert!((out[0] as i32 - 8192).abs() <= 1);
+        assert_eq!(&out[1..], &[i16::MIN, i16::MAX]);
+        // ReplayGain comes on top of the volume
+        let mut dither = Dither::new(SamplesBuffer::new(1, 44_100, vec![0.5]), gain(0.5), 0.5);
+        assert!((dither.next().unwrap() as i32 - 4096).abs() <= 1);
+    }
+}
@@ -0,0 +1,96 @@
+use std::{
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use rodio::Source;
+
+// Where the playing track is fading to, shared with the audio thread as f32
+// bits: 1 plays it as it is, 0 silences it
+pub type Target = Arc<AtomicU32>;
+
+pub fn target(level: f32) -> Target {
+    Arc::new(AtomicU32::new(level.to_bits()))
+}
+
+pub fn set(target: &Target, level: f32) {
+    target.store(level.to_bits(), Ordering::Relaxed);
+}
+
+// Moves the level smoothly towards the target over `length`, from wherever
+// it is, so a pause can come in halfway through a fade-in. It works on
+// floats, so faded samples are only rounded at the dither stage.
+pub struct Fade<S> {
+    source: S,
+    target: Target,
+    level: f32,
+    step: f32,
+}
+
+impl<S: Source<Item = f32>> Fade<S> {
+    // `from` 0 fades the track in as it starts
+    pub fn new(source: S, target: Target, from: f32, length: Duration) -> Self {
+        let samples = source.sample_rate() as f32 * source.channels() as f32 * length.as_secs_f32();
+        let step = if samples < 1.0 { 1.0 } else { 1.0 / samples };
+        Fade { source, target, level: from, step }
+    }
+}
+
+impl<S: Source<Item = f32>> Iterator for Fade<S> {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let sample = self.source.next()?;
+        let target = f32::from_bits(self.target.load(Ordering::Relaxed));
+        if self.level == target {
+            return Some(sample * target);
+        }
+        self.level = if self.level < target { (self.level + self.step).min(target) } else { (self.level - self.step).max(target) };
+        Some(sample * self.level)
+    }
+}
+
+impl<S: Source<Item = f32>> Source for Fade<S> {
+    fn current_frame_len(&self) -> Option<usize> {
+        self.source.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.source.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.source.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.source.total_duration()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rodio::buffer::SamplesBuffer;
+
+    #[test]
+    fn ramps_in_then_out() {
+        // 10 Hz mono over 0.4 s: four samples a fade
+        let target = target(1.0);
+        let mut fade = Fade::new(SamplesBuffer::new(1, 10, vec![1.0; 12]), target.clone(), 0.0, Duration::from_millis(400));
+        let fading_in: Vec<f32> = fade.by_ref().take(6).collect();
+        assert_eq!(fading_in, [0.25, 0.5, 0.75, 1.0, 1.0, 1.0]);
+        set(&target, 0.0);
+        let fading_out: Vec<f32> = fade.collect();
+        assert_eq!(fading_out, [0.75, 0.5, 0.25, 0.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn no_length_switches_at_once() {
+        let samples: Vec<f32> = Fade::new(SamplesBuffer::new(1, 10, vec![0.5; 2]), target(1.0), 0.0, Duration::ZERO).collect();
+        assert_eq!(samples, [0.5, 0.5]);
+    }
+}
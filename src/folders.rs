@@ -0,0 +1,95 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use crate::library::Song;
+use crate::tree::Node;
+
+// The folder at the top of the tree: the music directory itself when there is
+// only one, otherwise none and the directories are listed
+pub fn shown<'a>(roots: &'a [PathBuf], dir: Option<&'a Path>) -> Option<&'a Path> {
+    match (dir, roots) {
+        (Some(dir), _) => Some(dir),
+        (None, [root]) => Some(root),
+        (None, _) => None,
+    }
+}
+
+// What `dir` holds as the disk has it: subfolders by name, then its own songs
+// by file name. Only folders with songs somewhere under them are listed.
+pub fn entries(songs: &[Song], roots: &[PathBuf], dir: Option<&Path>) -> Vec<Node<PathBuf>> {
+    let Some(dir) = shown(roots, dir) else {
+        return roots.iter()
+            .map(|root| Node::Branch(root.clone(), songs.iter().filter(|song| song.path.starts_with(root)).count()))
+            .collect();
+    };
+    let mut folders: BTreeMap<PathBuf, usize> = BTreeMap::new();
+    let mut files: Vec<(&Path, usize)> = Vec::new();
+    for (index, song) in songs.iter().enumerate() {
+        let Ok(inside) = song.path.strip_prefix(dir) else {
+            continue;
+        };
+        let mut components = inside.components();
+        match (components.next(), components.next()) {
+            (Some(folder), Some(_)) => *folders.entry(dir.join(folder)).or_default() += 1,
+            (Some(_), None) => files.push((&song.path, index)),
+            _ => {}
+        }
+    }
+    files.sort_by(|a, b| a.0.file_name().cmp(&b.0.file_name()));
+    folders.into_iter()
+        .map(|(folder, count)| Node::Branch(folder, count))
+        .chain(files.into_iter().map(|(_, index)| Node::Song(index)))
+        .collect()
+}
+
+// Every song in the music directories, however deep, in path order
+pub fn songs_under(songs: &[Song], roots: &[PathBuf]) -> Vec<usize> {
+    let mut under: Vec<usize> = (0..songs.len())
+        .filter(|&index| roots.iter().any(|root| songs[index].path.starts_with(root)))
+        .collect();
+    under.sort_by(|&a, &b| songs[a].path.cmp(&songs[b].path));
+    under
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn songs(paths: &[&str]) -> Vec<Song> {
+        paths.iter().map(|path| Song::new(PathBuf::from(path))).collect()
+    }
+
+    #[test]
+    fn lists_folders_then_files() {
+        let songs = songs(&[
+            "/music/Rock/b.mp3",
+            "/music/Rock/Live/c.mp3",
+            "/music/a.mp3",
+            "/music/Jazz/d.mp3",
+            "/elsewhere/e.mp3",
+        ]);
+        let roots = [PathBuf::from("/music")];
+        assert_eq!(entries(&songs, &roots, None), vec![
+            Node::Branch(PathBuf::from("/music/Jazz"), 1),
+            Node::Branch(PathBuf::from("/music/Rock"), 2),
+            Node::Song(2),
+        ]);
+        assert_eq!(entries(&songs, &roots, Some(Path::new("/music/Rock"))), vec![
+            Node::Branch(PathBuf::from("/music/Rock/Live"), 1),
+            Node::Song(0),
+        ]);
+        assert_eq!(songs_under(&songs, &roots), vec![3, 1, 0, 2]);
+    }
+
+    #[test]
+    fn several_music_directories_are_listed_first() {
+        let songs = songs(&["/music/a.mp3", "/podcasts/b.mp3", "/podcasts/c.mp3"]);
+        let roots = [PathBuf::from("/music"), PathBuf::from("/podcasts")];
+        assert_eq!(entries(&songs, &roots, None), vec![
+            Node::Branch(PathBuf::from("/music"), 1),
+            Node::Branch(PathBuf::from("/podcasts"), 2),
+        ]);
+    }
+}
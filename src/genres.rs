@@ -0,0 +1,131 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use crate::config::GenresConfig;
+use crate::library::Song;
+
+// Genre tags as the config says to read them: spellings merged into one
+// name, and names filed under broader ones, both ignoring case
+#[derive(Default, Clone, PartialEq)]
+pub struct Genres {
+    aliases: HashMap<String, String>,
+    parents: HashMap<String, String>,
+}
+
+impl Genres {
+    pub fn new(config: &GenresConfig) -> Self {
+        let lower = |map: &HashMap<String, String>| map.iter()
+            .map(|(from, to)| (from.trim().to_lowercase(), to.trim().to_string()))
+            .collect();
+        let mut genres = Genres { aliases: lower(&config.aliases), parents: HashMap::new() };
+        // Subgenres can be given by any of their spellings
+        genres.parents = config.parents.iter()
+            .map(|(genre, parent)| (genres.canonical(genre).trim().to_lowercase(), parent.trim().to_string()))
+            .collect();
+        genres
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.aliases.is_empty() && self.parents.is_empty()
+    }
+
+    // "alt rock" → "Alternative Rock"; anything not aliased stays as it is
+    pub fn canonical<'a>(&'a self, genre: &'a str) -> &'a str {
+        self.aliases.get(&genre.trim().to_lowercase()).map_or(genre, String::as_str)
+    }
+
+    // The broader genres `genre` comes under, nearest first. A loop in the
+    // config stops where it would come back round.
+    pub fn ancestors(&self, genre: &str) -> Vec<String> {
+        let mut seen = HashSet::from([genre.to_lowercase()]);
+        let mut ancestors = Vec::new();
+        let mut current = genre.to_string();
+        while let Some(parent) = self.parents.get(&current.to_lowercase()) {
+            let parent = self.canonical(parent).to_string();
+            if !seen.insert(parent.to_lowercase()) {
+                break;
+            }
+            ancestors.push(parent.clone());
+            current = parent;
+        }
+        ancestors
+    }
+
+    // Renames the genre of `song` to its canonical name, returning whether it changed
+    pub fn apply(&self, song: &mut Song) -> bool {
+        let canonical = self.canonical(&song.genre);
+        if canonical == song.genre {
+            return false;
+        }
+        song.genre = canonical.to_string();
+        true
+    }
+
+    // The genres of the Genres tab in order, each broader one followed by
+    // those under it, with how deep they are
+    pub fn arranged<'a>(&self, genres: &'a BTreeMap<String, Vec<usize>>) -> Vec<(usize, &'a str)> {
+        let parent = |genre: &str| self.ancestors(genre).into_iter().next()
+            .and_then(|parent| genres.get_key_value(&parent).map(|(key, _)| key.as_str()));
+        let mut children: BTreeMap<Option<&str>, Vec<&str>> = BTreeMap::new();
+        for genre in genres.keys() {
+            children.entry(parent(genre)).or_default().push(genre);
+        }
+        // Genres caught in a loop have no top, so each that's left starts one
+        let roots: Vec<&str> = children.get(&None).into_iter().flatten().copied().chain(genres.keys().map(String::as_str)).collect();
+        let mut arranged = Vec::new();
+        let mut seen = HashSet::new();
+        for root in roots {
+            let mut stack = vec![(0, root)];
+            while let Some((depth, genre)) = stack.pop() {
+                if seen.insert(genre) {
+                    arranged.push((depth, genre));
+                    stack.extend(children.get(&Some(genre)).into_iter().flatten().rev().map(|&child| (depth + 1, child)));
+                }
+            }
+        }
+        arranged
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn genres() -> Genres {
+        let map = |pairs: &[(&str, &str)]| pairs.iter().map(|&(from, to)| (from.to_string(), to.to_string())).collect();
+        Genres::new(&GenresConfig {
+            aliases: map(&[("Alt Rock", "Alternative Rock"), ("DnB", "Drum & Bass")]),
+            parents: map(&[("dnb", "Electronic"), ("Jungle", "Drum & Bass"), ("Electronic", "Jungle")]),
+        })
+    }
+
+    #[test]
+    fn aliases_ignore_case() {
+        let genres = genres();
+        assert_eq!(genres.canonical("alt rock"), "Alternative Rock");
+        assert_eq!(genres.canonical("Jazz"), "Jazz");
+    }
+
+    #[test]
+    fn ancestors_stop_at_loops() {
+        let genres = genres();
+        assert_eq!(genres.ancestors("Drum & Bass"), ["Electronic", "Jungle"]);
+        assert_eq!(genres.ancestors("jungle"), ["Drum & Bass", "Electronic"]);
+        assert!(genres.ancestors("Jazz").is_empty());
+    }
+
+    #[test]
+    fn arranges_subgenres_under_their_parents() {
+        let genres = Genres::new(&GenresConfig {
+            aliases: HashMap::new(),
+            parents: HashMap::from([
+                (String::from("Drum & Bass"), String::from("Electronic")),
+                (String::from("Liquid"), String::from("Drum & Bass")),
+                (String::from("House"), String::from("Electronic")),
+            ]),
+        });
+        let keys: BTreeMap<String, Vec<usize>> = ["Drum & Bass", "Electronic", "House", "Jazz", "Liquid"].into_iter()
+            .map(|genre| (genre.to_string(), Vec::new()))
+            .collect();
+        assert_eq!(genres.arranged(&keys), [(0, "Electronic"), (1, "Drum & Bass"), (2, "Liquid"), (1, "House"), (0, "Jazz")]);
+    }
+}
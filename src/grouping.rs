@@ -0,0 +1,194 @@
+use std::{collections::BTreeMap, str::FromStr};
+
+use serde::Deserialize;
+
+use crate::library::Song;
+use crate::tree::Node;
+
+// What one level of a browse hierarchy groups songs by
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Level {
+    Artist,
+    // The album's artist where the tag gives one, else the track's
+    AlbumArtist,
+    Album,
+    Genre,
+    Year,
+    Decade,
+    Key,
+}
+
+impl Level {
+    pub fn label(self) -> &'static str {
+        match self {
+            Level::Artist => "Artist",
+            Level::AlbumArtist => "Album Artist",
+            Level::Album => "Album",
+            Level::Genre => "Genre",
+            Level::Year => "Year",
+            Level::Decade => "Decade",
+            Level::Key => "Key",
+        }
+    }
+
+    // The group `song` falls in at this level
+    pub fn value(self, song: &Song) -> String {
+        match self {
+            Level::Artist => song.artist.clone(),
+            Level::AlbumArtist => song.album_artist.clone().unwrap_or_else(|| song.artist.clone()),
+            Level::Album => song.album.clone(),
+            Level::Genre => song.genre.clone(),
+            Level::Year => song.year.map_or_else(|| String::from("Unknown Year"), |year| year.to_string()),
+            Level::Decade => song.year
+                .map_or_else(|| String::from("Unknown Decade"), |year| format!("{}s", year - year.rem_euclid(10))),
+            Level::Key => song.key.map_or_else(|| String::from("Unknown Key"), |key| key.to_string()),
+        }
+    }
+}
+
+// A browse hierarchy from the config, e.g. "genre / albumartist / album", or
+// "Decades = decade / album" to give its tab a name
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Grouping {
+    pub name: String,
+    pub levels: Vec<Level>,
+}
+
+impl FromStr for Grouping {
+    type Err = anyhow::Error;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let (name, expression) = match spec.split_once('=') {
+            Some((name, expression)) => (Some(name.trim()), expression),
+            None => (None, spec),
+        };
+        let levels = expression.split('/')
+            .map(|level| match level.trim().to_lowercase().as_str() {
+                "artist" => Ok(Level::Artist),
+                "albumartist" | "album_artist" => Ok(Level::AlbumArtist),
+                "album" => Ok(Level::Album),
+                "genre" => Ok(Level::Genre),
+                "year" => Ok(Level::Year),
+                "decade" => Ok(Level::Decade),
+                "key" => Ok(Level::Key),
+                _ => Err(anyhow::anyhow!("Unknown level \"{}\" in \"{}\"", level.trim(), spec)),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let name = match name {
+            Some(name) if !name.is_empty() => name.to_string(),
+            _ => levels.iter().map(|level| level.label()).collect::<Vec<_>>().join("/"),
+        };
+        Ok(Grouping { name, levels })
+    }
+}
+
+impl TryFrom<String> for Grouping {
+    type Error = anyhow::Error;
+
+    fn try_from(spec: String) -> Result<Self, Self::Error> {
+        spec.parse()
+    }
+}
+
+// Songs keyed by their value at every level, so everything under a group is
+// one range of the map
+#[derive(Clone)]
+pub struct GroupIndex {
+    pub grouping: Grouping,
+    groups: BTreeMap<Vec<String>, Vec<usize>>,
+}
+
+impl GroupIndex {
+    pub fn new(grouping: Grouping) -> Self {
+        GroupIndex { grouping, groups: BTreeMap::new() }
+    }
+
+    pub fn insert(&mut self, index: usize, song: &Song) {
+        let key = self.grouping.levels.iter().map(|level| level.value(song)).collect();
+        self.groups.entry(key).or_default().push(index);
+    }
+
+    fn under<'a>(&'a self, path: &'a [String]) -> impl Iterator<Item = (&'a Vec<String>, &'a Vec<usize>)> {
+        self.groups.range(path.to_vec()..).take_while(move |(key, _)| key.starts_with(path))
+    }
+
+    // The groups one level below `path`, each by its own path, or its songs
+    // once every level is picked
+    pub fn entries(&self, path: &[String]) -> Vec<Node<Vec<String>>> {
+        if path.len() >= self.grouping.levels.len() {
+            return self.songs(path).into_iter().map(Node::Song).collect();
+        }
+        let mut entries: Vec<Node<Vec<String>>> = Vec::new();
+        for (key, songs) in self.under(path) {
+            let group = &key[..=path.len()];
+            match entries.last_mut() {
+                Some(Node::Branch(last, count)) if last == group => *count += songs.len(),
+                _ => entries.push(Node::Branch(group.to_vec(), songs.len())),
+            }
+        }
+        entries
+    }
+
+    // Every song under `path`, group by group
+    pub fn songs(&self, path: &[String]) -> Vec<usize> {
+        self.under(path).flat_map(|(_, songs)| songs.iter().copied()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    fn song(genre: &str, artist: &str, album: &str, year: Option<i32>) -> Song {
+        let mut song = Song::new(PathBuf::from(format!("/music/{} - {}.mp3", artist, album)));
+        song.genre = genre.to_string();
+        song.album = album.to_string();
+        song.year = year;
+        song
+    }
+
+    #[test]
+    fn parses_levels_and_names() {
+        let grouping: Grouping = "genre / AlbumArtist / album".parse().unwrap();
+        assert_eq!(grouping.levels, vec![Level::Genre, Level::AlbumArtist, Level::Album]);
+        assert_eq!(grouping.name, "Genre/Album Artist/Album");
+
+        let grouping: Grouping = "Decades = decade / album".parse().unwrap();
+        assert_eq!(grouping.name, "Decades");
+        assert_eq!(grouping.levels, vec![Level::Decade, Level::Album]);
+
+        assert!("genre / mood".parse::<Grouping>().is_err());
+    }
+
+    #[test]
+    fn drills_down_level_by_level() {
+        let mut index = GroupIndex::new("decade / album".parse().unwrap());
+        let songs = [
+            song("Rock", "Radiohead", "OK Computer", Some(1997)),
+            song("Electronic", "Daft Punk", "Discovery", Some(2001)),
+            song("Rock", "Radiohead", "The Bends", Some(1995)),
+            song("Rock", "Radiohead", "OK Computer", Some(1997)),
+            song("Jazz", "Unknown", "Tapes", None),
+        ];
+        for (i, song) in songs.iter().enumerate() {
+            index.insert(i, song);
+        }
+        let path = |names: &[&str]| names.iter().map(|name| name.to_string()).collect::<Vec<_>>();
+        assert_eq!(index.entries(&[]), vec![
+            Node::Branch(path(&["1990s"]), 3),
+            Node::Branch(path(&["2000s"]), 1),
+            Node::Branch(path(&["Unknown Decade"]), 1),
+        ]);
+        let nineties = path(&["1990s"]);
+        assert_eq!(index.entries(&nineties), vec![
+            Node::Branch(path(&["1990s", "OK Computer"]), 2),
+            Node::Branch(path(&["1990s", "The Bends"]), 1),
+        ]);
+        let album = path(&["1990s", "OK Computer"]);
+        assert_eq!(index.entries(&album), vec![Node::Song(0), Node::Song(3)]);
+        assert_eq!(index.songs(&nineties), vec![0, 3, 2]);
+    }
+}
@@ -0,0 +1,159 @@
+use std::fmt;
+
+use crate::library::Song;
+
+// A key on the Camelot wheel: 1-12 around the circle of fifths, A for minor
+// and B for major. Neighbours on the wheel mix without clashing.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Camelot {
+    pub number: u8,
+    pub major: bool,
+}
+
+impl Camelot {
+    // Understands Camelot ("8A"), Open Key ("1m") and note names ("Am",
+    // "F# minor", "Dbmaj"), which is what tagging tools write into TKEY
+    pub fn parse(text: &str) -> Option<Self> {
+        let text = text.trim();
+        let digits: String = text.chars().take_while(char::is_ascii_digit).collect();
+        if !digits.is_empty() {
+            let number: u8 = digits.parse().ok().filter(|n| (1..=12).contains(n))?;
+            return match text[digits.len()..].trim().to_ascii_lowercase().as_str() {
+                "a" => Some(Camelot { number, major: false }),
+                "b" => Some(Camelot { number, major: true }),
+                // Open Key starts at C major = 1d, seven steps on from Camelot
+                "m" => Some(Camelot { number: (number + 6) % 12 + 1, major: false }),
+                "d" => Some(Camelot { number: (number + 6) % 12 + 1, major: true }),
+                _ => None,
+            };
+        }
+
+        let mut chars = text.chars();
+        let mut pitch: i32 = match chars.next()?.to_ascii_uppercase() {
+            'C' => 0,
+            'D' => 2,
+            'E' => 4,
+            'F' => 5,
+            'G' => 7,
+            'A' => 9,
+            'B' => 11,
+            _ => return None,
+        };
+        let mut rest = chars.as_str();
+        if let Some(after) = rest.strip_prefix('#').or_else(|| rest.strip_prefix('♯')) {
+            pitch += 1;
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix('b').or_else(|| rest.strip_prefix('♭')) {
+            pitch -= 1;
+            rest = after;
+        }
+        let major = match rest.trim().to_ascii_lowercase().as_str() {
+            "" | "maj" | "major" => true,
+            "m" | "min" | "minor" => false,
+            _ => return None,
+        };
+        // A minor key sits with its relative major, three semitones up
+        let tonic = if major { pitch } else { pitch + 3 };
+        let number = (tonic.rem_euclid(12) * 7 + 8) % 12;
+        Some(Camelot { number: if number == 0 { 12 } else { number as u8 }, major })
+    }
+
+    // Steps around the wheel, plus one for switching between minor and major
+    pub fn distance(self, other: Camelot) -> u8 {
+        self.steps(other) + u8::from(self.major != other.major)
+    }
+
+    // Same key, one step around, or its relative minor/major
+    pub fn is_compatible(self, other: Camelot) -> bool {
+        (self.major == other.major && self.steps(other) <= 1) || self.number == other.number
+    }
+
+    fn steps(self, other: Camelot) -> u8 {
+        let around = (self.number as i32 - other.number as i32).rem_euclid(12) as u8;
+        around.min(12 - around)
+    }
+}
+
+impl fmt::Display for Camelot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{}", self.number, if self.major { 'B' } else { 'A' })
+    }
+}
+
+// Orders `indices` so each track mixes into the next: the closest key on the
+// wheel first, then the nearest tempo. Starts from `after` (the playing track)
+// if given. Tracks without a key go last, in their old order.
+pub fn harmonic_order(songs: &[Song], indices: &[usize], after: Option<&Song>) -> Vec<usize> {
+    let (mut keyed, unkeyed): (Vec<usize>, Vec<usize>) = indices.iter().partition(|&&i| songs[i].key.is_some());
+    let mut ordered = Vec::with_capacity(indices.len());
+    let mut previous = after.filter(|song| song.key.is_some());
+    while !keyed.is_empty() {
+        let next = match previous {
+            Some(previous) => keyed.iter()
+                .enumerate()
+                .min_by_key(|(_, &i)| {
+                    let song = &songs[i];
+                    let tempo = match (previous.bpm, song.bpm) {
+                        (Some(a), Some(b)) => a.abs_diff(b),
+                        _ => u32::MAX,
+                    };
+                    (previous.key.zip(song.key).map(|(a, b)| a.distance(b)), tempo)
+                })
+                .map_or(0, |(position, _)| position),
+            None => 0,
+        };
+        let index = keyed.remove(next);
+        ordered.push(index);
+        previous = Some(&songs[index]);
+    }
+    ordered.extend(unkeyed);
+    ordered
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn key(text: &str) -> Camelot {
+        Camelot::parse(text).unwrap()
+    }
+
+    #[test]
+    fn parses_every_notation() {
+        assert_eq!(key("Am").to_string(), "8A");
+        assert_eq!(key("C").to_string(), "8B");
+        assert_eq!(key("F# minor").to_string(), "11A");
+        assert_eq!(key("Dbmaj").to_string(), "3B");
+        assert_eq!(key("E").to_string(), "12B");
+        assert_eq!(key("10a").to_string(), "10A");
+        assert_eq!(key("1d"), key("C"));
+        assert_eq!(key("1m"), key("Am"));
+        assert_eq!(Camelot::parse("13A"), None);
+        assert_eq!(Camelot::parse("H"), None);
+    }
+
+    #[test]
+    fn neighbours_are_compatible() {
+        assert!(key("8A").is_compatible(key("9A")));
+        assert!(key("12A").is_compatible(key("1A")));
+        assert!(key("8A").is_compatible(key("8B")));
+        assert!(!key("8A").is_compatible(key("9B")));
+        assert!(!key("8A").is_compatible(key("10A")));
+    }
+
+    #[test]
+    fn orders_by_key_then_tempo() {
+        let songs: Vec<Song> = [("3A", 128), ("9A", 124), ("8A", 140), ("8A", 126), ("", 120)]
+            .iter()
+            .enumerate()
+            .map(|(i, (key, bpm))| {
+                let mut song = Song::new(PathBuf::from(format!("/music/{}.mp3", i)));
+                song.key = Camelot::parse(key);
+                song.bpm = Some(*bpm);
+                song
+            })
+            .collect();
+        assert_eq!(harmonic_order(&songs, &[0, 2, 3, 4], Some(&songs[1])), vec![3, 2, 0, 4]);
+    }
+}
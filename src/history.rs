@@ -0,0 +1,145 @@
+use std::collections::{BTreeSet, HashMap};
+
+use serde::Serialize;
+
+use crate::library::Song;
+use crate::stats::Play;
+use crate::streaks;
+
+const TOP: usize = 10;
+
+// A play as the HTTP API's /history gives it, with the tags the track had
+// when it was added; empty for files no longer in the library
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct Entry {
+    // Seconds since the epoch
+    pub at: u64,
+    pub path: String,
+    pub artist: String,
+    pub title: String,
+    pub album: String,
+}
+
+impl Entry {
+    pub fn new(play: &Play, song: Option<&Song>) -> Self {
+        let tag = |field: fn(&Song) -> &String| song.map(field).cloned().unwrap_or_default();
+        Entry {
+            at: play.at,
+            path: play.path.to_string_lossy().to_string(),
+            artist: tag(|song| &song.artist),
+            title: tag(|song| &song.title),
+            album: tag(|song| &song.album),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Count {
+    pub name: String,
+    pub plays: usize,
+}
+
+// Listening habits for /stats. Hours and weekdays are local time; weekday 0
+// is Monday.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Summary {
+    pub plays: usize,
+    pub tracks: usize,
+    pub artists: usize,
+    pub plays_today: usize,
+    pub streak_days: usize,
+    pub longest_streak_days: usize,
+    pub top_artists: Vec<Count>,
+    // "Artist - Title"
+    pub top_tracks: Vec<Count>,
+    pub plays_by_hour: [usize; 24],
+    pub plays_by_weekday: [usize; 7],
+}
+
+// Plays at or after `since`, oldest first
+pub fn since(entries: &[Entry], since: u64) -> &[Entry] {
+    &entries[entries.partition_point(|entry| entry.at < since)..]
+}
+
+pub fn summary(entries: &[Entry], now: u64, offset: i64) -> Summary {
+    let mut artists: HashMap<&str, usize> = HashMap::new();
+    let mut tracks: HashMap<&str, (String, usize)> = HashMap::new();
+    let mut by_hour = [0; 24];
+    let mut by_weekday = [0; 7];
+    let mut days = BTreeSet::new();
+    for entry in entries {
+        if !entry.artist.is_empty() {
+            *artists.entry(&entry.artist).or_default() += 1;
+        }
+        let name = if entry.title.is_empty() { entry.path.clone() } else { format!("{} - {}", entry.artist, entry.title) };
+        tracks.entry(&entry.path).or_insert((name, 0)).1 += 1;
+        let local = entry.at as i64 + offset;
+        by_hour[local.rem_euclid(24 * 3600) as usize / 3600] += 1;
+        let day = streaks::day(entry.at, offset);
+        by_weekday[(day + 3).rem_euclid(7) as usize] += 1;
+        days.insert(day);
+    }
+    let today = streaks::day(now, offset);
+    Summary {
+        plays: entries.len(),
+        tracks: tracks.len(),
+        artists: artists.len(),
+        plays_today: entries.iter().filter(|entry| streaks::day(entry.at, offset) == today).count(),
+        streak_days: streaks::current_streak(&days, today),
+        longest_streak_days: streaks::longest_streak(&days),
+        top_artists: top(artists.into_iter().map(|(name, plays)| (name.to_string(), plays))),
+        top_tracks: top(tracks.into_values()),
+        plays_by_hour: by_hour,
+        plays_by_weekday: by_weekday,
+    }
+}
+
+// Most played first, then by name
+fn top(counts: impl Iterator<Item = (String, usize)>) -> Vec<Count> {
+    let mut counts: Vec<Count> = counts.map(|(name, plays)| Count { name, plays }).collect();
+    counts.sort_by(|a, b| b.plays.cmp(&a.plays).then_with(|| a.name.cmp(&b.name)));
+    counts.truncate(TOP);
+    counts
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    const DAY: u64 = 24 * 3600;
+
+    fn entry(at: u64, artist: &str, title: &str) -> Entry {
+        let mut song = Song::new(PathBuf::from(format!("/music/{} - {}.mp3", artist, title)));
+        song.artist = artist.to_string();
+        song.title = title.to_string();
+        Entry::new(&Play { path: song.path.clone(), at }, Some(&song))
+    }
+
+    #[test]
+    fn since_skips_earlier_plays() {
+        let entries = [entry(10, "A", "x"), entry(20, "A", "y"), entry(30, "B", "z")];
+        assert_eq!(since(&entries, 20), &entries[1..]);
+        assert!(since(&entries, 31).is_empty());
+        let gone = Entry::new(&Play { path: PathBuf::from("/music/gone.mp3"), at: 5 }, None);
+        assert_eq!((gone.path.as_str(), gone.artist.as_str()), ("/music/gone.mp3", ""));
+    }
+
+    #[test]
+    fn sums_up_plays_in_local_time() {
+        // Day 11 is a Monday; 23:30 UTC is already Tuesday an hour east
+        let entries = [
+            entry(11 * DAY + 9 * 3600, "A", "x"),
+            entry(11 * DAY + 23 * 3600 + 1800, "A", "x"),
+            entry(12 * DAY + 3600, "B", "y"),
+        ];
+        let summary = summary(&entries, 12 * DAY + 7200, 3600);
+        assert_eq!((summary.plays, summary.tracks, summary.artists), (3, 2, 2));
+        assert_eq!((summary.plays_today, summary.streak_days, summary.longest_streak_days), (2, 2, 2));
+        assert_eq!(summary.top_artists[0], Count { name: String::from("A"), plays: 2 });
+        assert_eq!(summary.top_tracks[1], Count { name: String::from("B - y"), plays: 1 });
+        assert_eq!((summary.plays_by_hour[10], summary.plays_by_hour[0], summary.plays_by_hour[2]), (1, 1, 1));
+        assert_eq!(summary.plays_by_weekday[..2], [1, 2]);
+    }
+}
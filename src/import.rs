@@ -0,0 +1,236 @@
+use std::{fs, path::Path, time::Duration};
+
+use anyhow::Result;
+use serde_json::Value;
+
+use crate::library::Song;
+
+// Lengths further apart than this are different recordings
+const MAX_DURATION_DIFFERENCE: Duration = Duration::from_secs(10);
+const MIN_TITLE_SIMILARITY: f64 = 0.8;
+const MIN_ARTIST_SIMILARITY: f64 = 0.5;
+
+// One track of a playlist exported from a streaming service
+#[derive(Clone, Debug, PartialEq)]
+pub struct ImportedTrack {
+    pub title: String,
+    pub artist: String,
+    pub duration: Option<Duration>,
+}
+
+impl ImportedTrack {
+    pub fn label(&self) -> String {
+        format!("{} - {}", self.artist, self.title)
+    }
+}
+
+pub struct ImportResult {
+    // Library indices in playlist order
+    pub matched: Vec<usize>,
+    pub unmatched: Vec<ImportedTrack>,
+}
+
+// Exportify CSVs by extension, anything else is taken to be Apple Music JSON
+pub fn read_tracks(path: &Path) -> Result<Vec<ImportedTrack>> {
+    let contents = fs::read_to_string(path)?;
+    let is_csv = path.extension().map(|ext| ext.eq_ignore_ascii_case("csv")).unwrap_or(false);
+    if is_csv { parse_csv(&contents) } else { parse_apple_json(&contents) }
+}
+
+// Exportify: a header row naming "Track Name", "Artist Name(s)" and a
+// duration in milliseconds, with columns in whatever order the version uses
+pub fn parse_csv(contents: &str) -> Result<Vec<ImportedTrack>> {
+    let mut rows = csv_rows(contents).into_iter();
+    let header = rows.next().ok_or_else(|| anyhow::anyhow!("Empty CSV file"))?;
+    let column = |name: &str| header.iter().position(|field| field.to_lowercase().starts_with(name));
+    let title = column("track name").ok_or_else(|| anyhow::anyhow!("No \"Track Name\" column"))?;
+    let artist = column("artist name").ok_or_else(|| anyhow::anyhow!("No \"Artist Name(s)\" column"))?;
+    let duration = column("duration").or_else(|| column("track duration"));
+
+    Ok(rows
+        .filter(|row| row.len() > title.max(artist))
+        .map(|row| ImportedTrack {
+            title: row[title].clone(),
+            // Several artists are comma separated; the first is enough to match on
+            artist: row[artist].split(',').next().unwrap_or_default().trim().to_string(),
+            duration: duration
+                .and_then(|i| row.get(i))
+                .and_then(|ms| ms.trim().parse().ok())
+                .map(Duration::from_millis),
+        })
+        .collect())
+}
+
+// Fields separated by commas, optionally quoted with "" for a literal quote
+fn csv_rows(contents: &str) -> Vec<Vec<String>> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = contents.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, quoted) {
+            ('"', true) if chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            ('"', _) => quoted = !quoted,
+            (',', false) => row.push(std::mem::take(&mut field)),
+            ('\r', false) => {}
+            ('\n', false) => {
+                row.push(std::mem::take(&mut field));
+                rows.push(std::mem::take(&mut row));
+            }
+            _ => field.push(c),
+        }
+    }
+    if !field.is_empty() || !row.is_empty() {
+        row.push(field);
+        rows.push(row);
+    }
+    rows.retain(|row| row.iter().any(|field| !field.is_empty()));
+    rows
+}
+
+// Apple Music library export: an array of objects with "Title"/"Name",
+// "Artist" and "Track Duration"/"Total Time" in milliseconds
+pub fn parse_apple_json(contents: &str) -> Result<Vec<ImportedTrack>> {
+    let value: Value = serde_json::from_str(contents)?;
+    let items = value.as_array().ok_or_else(|| anyhow::anyhow!("Expected a list of tracks"))?;
+    let text = |item: &Value, keys: &[&str]| {
+        keys.iter().find_map(|key| item.get(key).and_then(Value::as_str)).map(str::to_string)
+    };
+    Ok(items
+        .iter()
+        .filter_map(|item| {
+            Some(ImportedTrack {
+                title: text(item, &["Title", "Name", "Song Name"])?,
+                artist: text(item, &["Artist", "Artist Name"]).unwrap_or_default(),
+                duration: ["Track Duration", "Total Time"]
+                    .iter()
+                    .find_map(|key| item.get(key).and_then(Value::as_u64))
+                    .map(Duration::from_millis),
+            })
+        })
+        .collect())
+}
+
+// Lowercase words without punctuation, and without the "(feat. X)" and
+// "- Remastered 2011" decorations that services add to titles
+fn normalize(text: &str) -> Vec<String> {
+    let text = text.to_lowercase();
+    let text = text.split(" - ").next().unwrap_or_default();
+    let text = match text.find(['(', '[']) {
+        Some(start) if start > 0 => &text[..start],
+        _ => text,
+    };
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+// Share of words in common, 1.0 for the same words in any order
+fn similarity(a: &[String], b: &[String]) -> f64 {
+    if a.is_empty() || b.is_empty() {
+        return 0.0;
+    }
+    let common = a.iter().filter(|word| b.contains(word)).count();
+    common as f64 / a.len().max(b.len()) as f64
+}
+
+// Best library match for each track: titles must nearly agree, artists
+// mostly, and lengths (when both are known) within a few seconds
+pub fn match_tracks(tracks: &[ImportedTrack], songs: &[Song]) -> ImportResult {
+    let library: Vec<(Vec<String>, Vec<String>)> = songs.iter()
+        .map(|song| (normalize(&song.title), normalize(&song.artist)))
+        .collect();
+
+    let mut result = ImportResult { matched: Vec::new(), unmatched: Vec::new() };
+    for track in tracks {
+        let title = normalize(&track.title);
+        let artist = normalize(&track.artist);
+        let best = library.iter()
+            .enumerate()
+            .filter_map(|(i, (song_title, song_artist))| {
+                let title_score = similarity(&title, song_title);
+                let artist_score = similarity(&artist, song_artist);
+                if title_score < MIN_TITLE_SIMILARITY || artist_score < MIN_ARTIST_SIMILARITY {
+                    return None;
+                }
+                let duration_score = match (track.duration, songs[i].duration) {
+                    (Some(a), Some(b)) => {
+                        let difference = a.abs_diff(b);
+                        if difference > MAX_DURATION_DIFFERENCE {
+                            return None;
+                        }
+                        1.0 - difference.as_secs_f64() / MAX_DURATION_DIFFERENCE.as_secs_f64()
+                    }
+                    _ => 0.0,
+                };
+                Some((i, title_score * 2.0 + artist_score + duration_score))
+            })
+            .max_by(|a, b| a.1.total_cmp(&b.1));
+        match best {
+            Some((i, _)) => result.matched.push(i),
+            None => result.unmatched.push(track.clone()),
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn song(name: &str, seconds: Option<u64>) -> Song {
+        let mut song = Song::new(PathBuf::from(format!("/music/{}.mp3", name)));
+        song.duration = seconds.map(Duration::from_secs);
+        song
+    }
+
+    fn track(artist: &str, title: &str, seconds: Option<u64>) -> ImportedTrack {
+        ImportedTrack {
+            title: title.to_string(),
+            artist: artist.to_string(),
+            duration: seconds.map(Duration::from_secs),
+        }
+    }
+
+    #[test]
+    fn parses_exportify_csv() {
+        let csv = "\"Track URI\",\"Track Name\",\"Artist Name(s)\",\"Duration (ms)\"\n\
+                   \"spotify:track:1\",\"Hello, \"\"World\"\"\",\"A,B\",\"215000\"\r\n\
+                   \"spotify:track:2\",\"Two\",\"C\",\"\"\n";
+        assert_eq!(parse_csv(csv).unwrap(), vec![
+            track("A", "Hello, \"World\"", Some(215)),
+            track("C", "Two", None),
+        ]);
+        assert!(parse_csv("foo,bar\n1,2\n").is_err());
+    }
+
+    #[test]
+    fn parses_apple_music_json() {
+        let json = r#"[{"Title": "One", "Artist": "X", "Track Duration": 180000}, {"Artist": "no title"}]"#;
+        assert_eq!(parse_apple_json(json).unwrap(), vec![track("X", "One", Some(180))]);
+    }
+
+    #[test]
+    fn matches_despite_decorations_and_rejects_other_lengths() {
+        let songs = vec![
+            song("Daft Punk - One More Time", Some(320)),
+            song("Radiohead - Karma Police", Some(264)),
+            song("Radiohead - Creep", None),
+        ];
+        let tracks = vec![
+            track("Daft Punk", "One More Time - Radio Edit", Some(322)),
+            track("Radiohead", "Karma Police", Some(400)),
+            track("Radiohead", "Creep (Acoustic)", Some(250)),
+            track("Björk", "Hyperballad", None),
+        ];
+        let result = match_tracks(&tracks, &songs);
+        assert_eq!(result.matched, vec![0, 2]);
+        assert_eq!(result.unmatched, vec![tracks[1].clone(), tracks[3].clone()]);
+    }
+}
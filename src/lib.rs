@@ -0,0 +1,48 @@
+// Library, scanning and playback logic shared by the player binary and the benchmarks
+pub mod analysis;
+pub mod archive;
+pub mod art;
+pub mod artists;
+pub mod buffer;
+pub mod bigtext;
+pub mod catalog;
+pub mod channels;
+pub mod clock;
+pub mod columns;
+pub mod compressor;
+pub mod config;
+pub mod cues;
+pub mod decks;
+pub mod decode;
+pub mod dither;
+pub mod fade;
+pub mod folders;
+pub mod genres;
+pub mod grouping;
+pub mod harmony;
+pub mod history;
+pub mod import;
+pub mod library;
+pub mod lyrics;
+pub mod meter;
+pub mod metrics;
+pub mod mojibake;
+pub mod net;
+pub mod persist;
+pub mod playback;
+pub mod playlist;
+pub mod power;
+pub mod queue;
+pub mod queues;
+pub mod replaygain;
+pub mod resample;
+pub mod setlist;
+pub mod state;
+pub mod stats;
+pub mod streaks;
+pub mod systemd;
+pub mod tags;
+pub mod trims;
+pub mod tree;
+pub mod verify;
+pub mod webhooks;
@@ -0,0 +1,604 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::{self, File},
+    io::{BufRead, BufReader, Read, Seek, SeekFrom},
+    ops::Range,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::Result;
+use id3::TagLike;
+use walkdir::WalkDir;
+
+use crate::config::ScanConfig;
+use crate::genres::Genres;
+use crate::grouping::{GroupIndex, Grouping};
+use crate::harmony::Camelot;
+use crate::tree::Node;
+use crate::replaygain::ReplayGain;
+use crate::stats::{self, TagStats};
+
+// Album of songs without an album tag
+pub const UNKNOWN_ALBUM: &str = "Unknown Album";
+
+#[derive(Clone)]
+pub struct Song {
+    pub path: PathBuf,
+    pub title: String,
+    pub artist: String,
+    pub album: String,
+    pub genre: String,
+    // From TPE2, which compilations set to the same artist on every track
+    pub album_artist: Option<String>,
+    pub year: Option<i32>,
+    pub track: Option<u32>,
+    // From the TLEN tag; None until something else measures it
+    pub duration: Option<Duration>,
+    // From TBPM and TKEY, as written by DJ software and key detectors
+    pub bpm: Option<u32>,
+    pub key: Option<Camelot>,
+    // Dynamic range score, once `:analyze` has measured the file
+    pub dr: Option<u8>,
+    // Rating and play count from a POPM tag, for syncing with the player's own
+    pub tag_stats: Option<TagStats>,
+    pub replay_gain: ReplayGain,
+    pub modified: Option<SystemTime>,
+    // When the library first had the file: now for a file just found, kept
+    // from the library database after that
+    pub added: SystemTime,
+    // "Artist - Title", formatted once instead of on every frame
+    pub label: String,
+}
+
+impl Song {
+    pub fn new(path: PathBuf) -> Self {
+        let filename = path.file_stem()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string();
+        
+        // Split filename by " - " to get artist and title
+        let parts: Vec<&str> = filename.splitn(2, " - ").collect();
+        
+        let (mut artist, mut title) = match parts.as_slice() {
+            [artist, title] => {
+                // Add spaces between multiple artists (separated by &, feat., or featuring)
+                let artist = artist
+                    .replace("&", " & ")
+                    .replace("feat.", " feat. ")
+                    .replace("featuring", " featuring ")
+                    .replace("  ", " ") // Remove any double spaces
+                    .trim()
+                    .to_string();
+                (artist, title.to_string())
+            },
+            _ => (String::from("Unknown Artist"), filename),
+        };
+
+        let modified = fs::metadata(&path).and_then(|m| m.modified()).ok();
+
+        let mut album = String::from(UNKNOWN_ALBUM);
+        let mut genre = String::from("Unknown Genre");
+        let mut album_artist = None;
+        let mut year = None;
+        let mut track = None;
+        let mut duration = None;
+        let mut bpm = None;
+        let mut key = None;
+        let mut tag_stats = None;
+        let mut replay_gain = ReplayGain::default();
+
+        // Try to read metadata
+        // Old rips often have only an ID3v1 tag
+        if let Ok(tag) = id3::v1v2::read_from_path(&path) {
+            if let Some(meta_title) = tag.title() {
+                title = meta_title.to_string();
+            }
+            if let Some(meta_artist) = tag.artist() {
+                // Add spaces between multiple artists in metadata too
+                artist = meta_artist
+                    .replace("&", " & ")
+                    .replace("feat.", " feat. ")
+                    .replace("featuring", " featuring ")
+                    .replace("  ", " ")
+                    .trim()
+                    .to_string();
+            }
+            if let Some(meta_album) = tag.album() {
+                album = meta_album.to_string();
+            }
+            if let Some(meta_genre) = tag.genre() {
+                genre = meta_genre.to_string();
+            }
+            album_artist = tag.album_artist().map(str::to_string);
+            // ID3v2.4 moved the year into the recording date
+            year = tag.year().or_else(|| tag.date_recorded().map(|date| date.year));
+            track = tag.track();
+            duration = tag.duration().map(|ms| Duration::from_millis(ms.into()));
+            let text = |id: &str| tag.get(id).and_then(|frame| frame.content().text()).map(str::to_string);
+            // Some tools write fractional tempos like "127.98"
+            bpm = text("TBPM").and_then(|bpm| bpm.trim().parse::<f64>().ok()).map(|bpm| bpm.round() as u32);
+            key = text("TKEY").and_then(|key| Camelot::parse(&key));
+            tag_stats = stats::read_popm(&tag);
+            replay_gain = ReplayGain::from_tag(&tag);
+        }
+
+        let label = format!("{} - {}", artist, title);
+
+        Song {
+            path,
+            title,
+            artist,
+            album,
+            genre,
+            album_artist,
+            year,
+            track,
+            duration,
+            bpm,
+            key,
+            dr: None,
+            tag_stats,
+            replay_gain,
+            modified,
+            added: SystemTime::now(),
+            label,
+        }
+    }
+}
+
+// Sorted artist/album/genre lookups for the browse tabs, kept up to date as the
+// library changes instead of being rebuilt on every frame
+#[derive(Default)]
+pub struct BrowseIndex {
+    pub artists: BTreeMap<String, Vec<usize>>,
+    pub albums: BTreeMap<(String, String), Vec<usize>>,
+    pub genres: BTreeMap<String, Vec<usize>>,
+    // One per browse hierarchy in the config
+    pub groups: Vec<GroupIndex>,
+    // For finding playlist entries in the library
+    pub paths: HashMap<PathBuf, usize>,
+    // Songs are listed under the broader genres of theirs as well
+    pub genre_map: Genres,
+}
+
+impl BrowseIndex {
+    pub fn new(groupings: &[Grouping]) -> Self {
+        BrowseIndex {
+            groups: groupings.iter().cloned().map(GroupIndex::new).collect(),
+            ..BrowseIndex::default()
+        }
+    }
+
+    pub fn groupings(&self) -> Vec<Grouping> {
+        self.groups.iter().map(|group| group.grouping.clone()).collect()
+    }
+
+    // The Artists tab as a tree: artists, pinned ones first, then their
+    // albums, then the tracks
+    pub fn artist_entries(&self, path: &[String], pinned: &[String]) -> Vec<Node<Vec<String>>> {
+        match path {
+            [] => pinned_first(&self.artists, pinned).into_iter()
+                .map(|artist| Node::Branch(vec![artist.clone()], self.artists[artist].len()))
+                .collect(),
+            [artist] => self.albums.iter()
+                .filter(|((_, by), _)| by == artist)
+                .map(|((album, _), songs)| Node::Branch(vec![artist.clone(), album.clone()], songs.len()))
+                .collect(),
+            [artist, album, ..] => self.albums.get(&(album.clone(), artist.clone()))
+                .into_iter()
+                .flatten()
+                .map(|&index| Node::Song(index))
+                .collect(),
+        }
+    }
+
+    pub fn insert(&mut self, index: usize, song: &Song) {
+        self.artists.entry(song.artist.clone()).or_default().push(index);
+        self.albums.entry((song.album.clone(), song.artist.clone())).or_default().push(index);
+        self.genres.entry(song.genre.clone()).or_default().push(index);
+        for parent in self.genre_map.ancestors(&song.genre) {
+            self.genres.entry(parent).or_default().push(index);
+        }
+        for group in &mut self.groups {
+            group.insert(index, song);
+        }
+        self.paths.insert(song.path.clone(), index);
+    }
+
+    // Needed whenever song indices shift (removals, shuffles, tag updates)
+    pub fn rebuild(&mut self, songs: &[Song]) {
+        *self = BrowseIndex {
+            genre_map: std::mem::take(&mut self.genre_map),
+            ..BrowseIndex::new(&self.groupings())
+        };
+        for (i, song) in songs.iter().enumerate() {
+            self.insert(i, song);
+        }
+    }
+}
+
+// The keys of `map`, with those in `pinned` first in the order they were pinned
+pub fn pinned_first<'a, K: Ord>(map: &'a BTreeMap<K, Vec<usize>>, pinned: &'a [K]) -> Vec<&'a K> {
+    pinned.iter()
+        .filter(|key| map.contains_key(key))
+        .chain(map.keys().filter(|key| !pinned.contains(key)))
+        .collect()
+}
+
+// Pins `key`, or unpins it if it was. Returns whether it's pinned now.
+pub fn toggle_pin<K: PartialEq>(pinned: &mut Vec<K>, key: K) -> bool {
+    match pinned.iter().position(|pin| *pin == key) {
+        Some(at) => {
+            pinned.remove(at);
+            false
+        }
+        None => {
+            pinned.push(key);
+            true
+        }
+    }
+}
+
+// Up to `n` songs, the ones the library got most recently first
+pub fn recently_added(songs: &[Song], n: usize) -> Vec<usize> {
+    let mut added: Vec<(usize, SystemTime)> = songs.iter().enumerate().map(|(index, song)| (index, song.added)).collect();
+    added.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    added.into_iter().take(n).map(|(index, _)| index).collect()
+}
+
+// Resolves symlinks and relative components so the same file is always the same path
+pub fn canonical_path(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_owned())
+}
+
+fn walk(dir: &Path, scan: &ScanConfig) -> WalkDir {
+    let mut walker = WalkDir::new(dir).follow_links(scan.follow_links);
+    if let Some(depth) = scan.max_depth {
+        walker = walker.max_depth(depth);
+    }
+    walker
+}
+
+// Tells files apart from copies of ones seen before by their audio, tags
+// aside. Files are only hashed, in full, once another has audio of the same
+// length, so telling most of them apart costs a look at their headers.
+#[derive(Default)]
+pub struct Copies {
+    // Files by the length of their audio, with its hash once it was needed
+    seen: HashMap<u64, Vec<(PathBuf, Option<u64>)>>,
+}
+
+impl Copies {
+    pub fn new<'a>(paths: impl IntoIterator<Item = &'a PathBuf>) -> Self {
+        let mut copies = Copies::default();
+        for path in paths {
+            copies.is_copy(path);
+        }
+        copies
+    }
+
+    // Whether `path` holds the same audio as a file seen before; the first
+    // of them is remembered. Unreadable files are never copies, and neither
+    // are copies of files gone since.
+    pub fn is_copy(&mut self, path: &Path) -> bool {
+        let Some(range) = audio_range(path) else {
+            return false;
+        };
+        let same_length = self.seen.entry(range.end - range.start).or_default();
+        if same_length.is_empty() {
+            same_length.push((path.to_path_buf(), None));
+            return false;
+        }
+        let Some(hash) = audio_hash(path, range) else {
+            return false;
+        };
+        for (other, other_hash) in same_length.iter_mut() {
+            if other_hash.is_none() {
+                *other_hash = audio_range(other).and_then(|range| audio_hash(other, range));
+            }
+            if *other_hash == Some(hash) {
+                return true;
+            }
+        }
+        same_length.push((path.to_path_buf(), Some(hash)));
+        false
+    }
+}
+
+// Where the audio of a file lies, short of the tags taggers rewrite: ID3v2
+// or FLAC metadata before it and ID3v1 after it. Tags inside other
+// containers count as audio, so copies of those have to match them too.
+fn audio_range(path: &Path) -> Option<Range<u64>> {
+    let mut file = File::open(path).ok()?;
+    let len = file.metadata().ok()?.len();
+    let mut header = [0; 10];
+    let mut start = 0;
+    if file.read_exact(&mut header).is_ok() {
+        if header.starts_with(b"ID3") {
+            // Syncsafe: seven bits a byte
+            let size = header[6..10].iter().fold(0u64, |size, &byte| size << 7 | (byte & 0x7f) as u64);
+            start = 10 + size;
+        } else if header.starts_with(b"fLaC") {
+            // Metadata blocks, each with a 4 byte header, until the one marked last
+            start = 4;
+            loop {
+                let mut block = [0; 4];
+                file.seek(SeekFrom::Start(start)).ok()?;
+                file.read_exact(&mut block).ok()?;
+                start += 4 + u64::from_be_bytes([0, 0, 0, 0, 0, block[1], block[2], block[3]]);
+                if block[0] & 0x80 != 0 {
+                    break;
+                }
+            }
+        }
+    }
+    let mut end = len;
+    let mut trailer = [0; 3];
+    if len >= start + 128 && file.seek(SeekFrom::Start(len - 128)).is_ok() && file.read_exact(&mut trailer).is_ok() && &trailer == b"TAG" {
+        end -= 128;
+    }
+    (start < end).then_some(start..end)
+}
+
+// FNV-1a of all of `range` in the file
+fn audio_hash(path: &Path, range: Range<u64>) -> Option<u64> {
+    let mut file = File::open(path).ok()?;
+    file.seek(SeekFrom::Start(range.start)).ok()?;
+    let mut audio = BufReader::with_capacity(64 * 1024, file.take(range.end - range.start));
+    let mut hash: u64 = 0xcbf29ce484222325;
+    loop {
+        let chunk = audio.fill_buf().ok()?;
+        if chunk.is_empty() {
+            return Some(hash);
+        }
+        hash = chunk.iter().fold(hash, |hash, &byte| (hash ^ byte as u64).wrapping_mul(0x100000001b3));
+        let read = chunk.len();
+        audio.consume(read);
+    }
+}
+
+// The hash of a file's audio `Copies` goes by, which retagging leaves alone:
+// what finds a file with play counts again once it's moved or renamed
+pub fn content_hash(path: &Path) -> Option<u64> {
+    audio_hash(path, audio_range(path)?)
+}
+
+// Extensions are compared case-insensitively so SONG.MP3 and track.FlAc are found too
+pub fn is_audio_file(path: &Path, scan: &ScanConfig) -> bool {
+    match path.extension() {
+        Some(ext) => {
+            let ext = ext.to_string_lossy();
+            scan.extensions.iter().any(|allowed| allowed.eq_ignore_ascii_case(&ext))
+        }
+        None => false,
+    }
+}
+
+// Calls `on_file` for every supported audio file below a directory as it is found
+pub fn scan_directory(dir: &Path, scan: &ScanConfig, mut on_file: impl FnMut(PathBuf)) -> Result<()> {
+    for entry in walk(dir, scan) {
+        let entry = match entry {
+            Ok(entry) => entry,
+            // Only a broken root is fatal; symlink loops and unreadable subdirectories are skipped
+            Err(e) if e.depth() == 0 => return Err(e.into()),
+            Err(_) => continue,
+        };
+        let path = entry.path();
+        if is_audio_file(path, scan) {
+            on_file(canonical_path(path));
+        }
+    }
+    Ok(())
+}
+
+// Stops walking as soon as the tree is known to exceed the configured file count or size
+pub fn exceeds_scan_limits(dir: &Path, scan: &ScanConfig) -> Option<String> {
+    let mut files = 0;
+    let mut bytes = 0;
+    for entry in walk(dir, scan).into_iter().flatten() {
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        files += 1;
+        bytes += entry.metadata().map(|m| m.len()).unwrap_or(0);
+        if files > scan.max_files {
+            return Some(format!("more than {} files", scan.max_files));
+        }
+        if bytes > scan.max_total_size_mb * 1024 * 1024 {
+            return Some(format!("more than {} MB", scan.max_total_size_mb));
+        }
+    }
+    None
+}
+
+// What `/` looks through
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SearchScope {
+    Library,
+    Queue,
+    Playlists,
+}
+
+impl SearchScope {
+    pub fn next(self) -> SearchScope {
+        match self {
+            SearchScope::Library => SearchScope::Queue,
+            SearchScope::Queue => SearchScope::Playlists,
+            SearchScope::Playlists => SearchScope::Library,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            SearchScope::Library => "Library",
+            SearchScope::Queue => "Queue",
+            SearchScope::Playlists => "Playlists",
+        }
+    }
+}
+
+// Indices of songs whose title, artist or album contain `query`, ignoring case
+pub fn search(songs: &[Song], query: &str) -> Vec<usize> {
+    if query.is_empty() {
+        return Vec::new();
+    }
+    let query_lower = query.to_lowercase();
+    songs.iter().enumerate()
+        .filter(|(_, song)| {
+            song.title.to_lowercase().contains(&query_lower) ||
+            song.artist.to_lowercase().contains(&query_lower) ||
+            song.album.to_lowercase().contains(&query_lower)
+        })
+        .map(|(i, _)| i)
+        .collect()
+}
+
+// Songs whose file is called the same as `path`, ignoring case, for finding
+// where a moved file went
+pub fn same_file_name(songs: &[Song], path: &Path) -> Vec<usize> {
+    let Some(name) = path.file_name().map(|name| name.to_string_lossy().to_lowercase()) else {
+        return Vec::new();
+    };
+    songs.iter().enumerate()
+        .filter(|(_, song)| {
+            song.path.file_name().is_some_and(|other| other.to_string_lossy().to_lowercase() == name)
+        })
+        .map(|(i, _)| i)
+        .collect()
+}
+
+// 3:07, or 1:02:03 for anything an hour or longer
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    if secs >= 3600 {
+        format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+    } else {
+        format!("{}:{:02}", secs / 60, secs % 60)
+    }
+}
+
+// Subsequence match ignoring case: every character of `query` must appear in
+// order. Higher is better; consecutive and early matches score more.
+pub fn fuzzy_score(candidate: &str, query: &str) -> Option<usize> {
+    let candidate: Vec<char> = candidate.to_lowercase().chars().collect();
+    let mut score = 0;
+    let mut next = 0;
+    let mut previous: Option<usize> = None;
+    for q in query.to_lowercase().chars() {
+        let found = next + candidate[next..].iter().position(|&c| c == q)?;
+        score += if previous.map(|p| p + 1) == Some(found) || found == 0 { 3 } else { 1 };
+        previous = Some(found);
+        next = found + 1;
+    }
+    Some(score)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn copies_are_told_by_their_audio() {
+        let dir = tempfile::tempdir().unwrap();
+        let [first, copy, other] = ["a/song.mp3", "b/song (1).mp3", "a/other.mp3"].map(|name| dir.path().join(name));
+        // The copy was retagged: a longer ID3v2 tag in front of the same audio
+        let retagged = [b"ID3\x03\0\0\0\0\0\x04TIT2".as_slice(), b"same audio"].concat();
+        for (path, audio) in [(&first, b"same audio".as_slice()), (&copy, &retagged), (&other, b"some other")] {
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, audio).unwrap();
+        }
+        let mut copies = Copies::new([&first]);
+        assert!(copies.is_copy(&copy));
+        assert!(!copies.is_copy(&other));
+        assert!(!copies.is_copy(&dir.path().join("missing.mp3")));
+    }
+
+    #[test]
+    fn songs_ending_in_the_same_silence_are_not_copies() {
+        let dir = tempfile::tempdir().unwrap();
+        let [first, second] = ["one.wav", "two.wav"].map(|name| dir.path().join(name));
+        let silence = vec![0; 256 * 1024];
+        fs::write(&first, [b"first song".as_slice(), &silence].concat()).unwrap();
+        fs::write(&second, [b"other song".as_slice(), &silence].concat()).unwrap();
+        let mut copies = Copies::new([&first]);
+        assert!(!copies.is_copy(&second));
+    }
+
+    #[test]
+    fn content_hash_leaves_out_tags() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("song.mp3");
+        fs::write(&path, b"audio").unwrap();
+        let hash = content_hash(&path);
+        let mut tagged = [b"ID3\x03\0\0\0\0\0\x04TIT2".as_slice(), b"audio", b"TAG"].concat();
+        tagged.resize(tagged.len() + 125, 0);
+        fs::write(&path, tagged).unwrap();
+        assert_eq!(content_hash(&path), hash);
+        fs::write(&path, b"other").unwrap();
+        assert_ne!(content_hash(&path), hash);
+        fs::write(&path, b"").unwrap();
+        assert_eq!(content_hash(&path), None);
+    }
+
+    #[test]
+    fn format_duration_switches_to_hours() {
+        assert_eq!(format_duration(Duration::from_secs(0)), "0:00");
+        assert_eq!(format_duration(Duration::from_secs(187)), "3:07");
+        assert_eq!(format_duration(Duration::from_secs(3723)), "1:02:03");
+    }
+
+    #[test]
+    fn same_file_name_ignores_directory_and_case() {
+        let songs: Vec<Song> = ["/music/old/Track.mp3", "/music/new/track.MP3", "/music/other.mp3"]
+            .iter()
+            .map(|path| Song::new(PathBuf::from(path)))
+            .collect();
+        assert_eq!(same_file_name(&songs, Path::new("/gone/TRACK.mp3")), vec![0, 1]);
+        assert!(same_file_name(&songs, Path::new("/gone/missing.mp3")).is_empty());
+    }
+
+    #[test]
+    fn pinned_keys_come_first_in_pin_order() {
+        let map: BTreeMap<String, Vec<usize>> = ["Air", "Björk", "Can", "Daft Punk"]
+            .iter()
+            .map(|artist| (artist.to_string(), Vec::new()))
+            .collect();
+        let mut pinned = Vec::new();
+        assert!(toggle_pin(&mut pinned, String::from("Can")));
+        assert!(toggle_pin(&mut pinned, String::from("Gone")));
+        assert!(toggle_pin(&mut pinned, String::from("Air")));
+        assert_eq!(pinned_first(&map, &pinned), ["Can", "Air", "Björk", "Daft Punk"]);
+
+        assert!(!toggle_pin(&mut pinned, String::from("Can")));
+        assert_eq!(pinned_first(&map, &pinned), ["Air", "Björk", "Can", "Daft Punk"]);
+    }
+
+    #[test]
+    fn recently_added_goes_by_when_the_library_got_them() {
+        let mut songs: Vec<Song> = ["a", "b", "c"].iter().map(|name| Song::new(PathBuf::from(name))).collect();
+        songs[0].added = SystemTime::UNIX_EPOCH + Duration::from_secs(10);
+        songs[1].added = SystemTime::UNIX_EPOCH;
+        songs[2].added = SystemTime::UNIX_EPOCH + Duration::from_secs(20);
+        // A file touched since doesn't count as new
+        songs[1].modified = Some(SystemTime::now());
+        assert_eq!(recently_added(&songs, 5), vec![2, 0, 1]);
+        assert_eq!(recently_added(&songs, 1), vec![2]);
+    }
+
+    #[test]
+    fn fuzzy_score_requires_characters_in_order() {
+        assert!(fuzzy_score("Road Trip", "rdtp").is_some());
+        assert!(fuzzy_score("Road Trip", "ROAD").is_some());
+        assert!(fuzzy_score("Road Trip", "trd").is_none());
+        assert_eq!(fuzzy_score("anything", ""), Some(0));
+    }
+
+    #[test]
+    fn fuzzy_score_prefers_prefixes_and_runs() {
+        let prefix = fuzzy_score("Workout", "work").unwrap();
+        let scattered = fuzzy_score("Wild Orchestra Rock", "work").unwrap();
+        assert!(prefix > scattered);
+    }
+}
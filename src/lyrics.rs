@@ -0,0 +1,233 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::Result;
+use id3::{frame::TimestampFormat, Tag};
+use serde_json::Value;
+
+use crate::library::Song;
+use crate::net::curl;
+use crate::persist;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct LyricLine {
+    // None for plain, unsynced lyrics
+    pub time: Option<Duration>,
+    pub text: String,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Lyrics {
+    pub lines: Vec<LyricLine>,
+    // Where they came from, shown with them: "Embedded", a file name or a provider
+    pub source: String,
+}
+
+impl Lyrics {
+    // LRC: "[mm:ss.xx]text", several stamps per line allowed, plus [by:] and
+    // [offset:] headers. Lines without a stamp are kept as plain lyrics.
+    pub fn parse_lrc(contents: &str, source: &str) -> Self {
+        let mut lines = Vec::new();
+        let mut source = source.to_string();
+        let mut offset_ms = 0i64;
+        for line in contents.lines() {
+            let mut rest = line.trim();
+            let mut times = Vec::new();
+            while let Some((tag, after)) = rest.strip_prefix('[').and_then(|r| r.split_once(']')) {
+                match tag.split_once(':') {
+                    Some(("by", by)) if !by.trim().is_empty() => source = by.trim().to_string(),
+                    Some(("offset", ms)) => offset_ms = ms.trim().parse().unwrap_or(0),
+                    _ => times.extend(parse_timestamp(tag)),
+                }
+                rest = after;
+            }
+            let text = rest.trim().to_string();
+            if times.is_empty() {
+                // Header-only lines leave nothing behind
+                if !line.trim_start().starts_with('[') || !text.is_empty() {
+                    lines.push(LyricLine { time: None, text });
+                }
+            } else {
+                lines.extend(times.into_iter().map(|time| LyricLine { time: Some(time), text: text.clone() }));
+            }
+        }
+        // A positive offset makes lyrics come sooner
+        if offset_ms != 0 {
+            for line in &mut lines {
+                line.time = line.time.map(|time| {
+                    Duration::from_millis((time.as_millis() as i64 - offset_ms).max(0) as u64)
+                });
+            }
+        }
+        // Stray unstamped lines (usually blank) in synced lyrics have no place in time
+        if lines.iter().any(|line| line.time.is_some()) {
+            lines.retain(|line| line.time.is_some());
+        }
+        lines.sort_by_key(|line| line.time);
+        Lyrics { lines, source }
+    }
+
+    pub fn is_synced(&self) -> bool {
+        self.lines.iter().any(|line| line.time.is_some())
+    }
+
+    // The line being sung at `position`, if the lyrics are synced and it has started
+    pub fn current(&self, position: Duration) -> Option<usize> {
+        if !self.is_synced() {
+            return None;
+        }
+        self.lines
+            .partition_point(|line| line.time.is_some_and(|time| time <= position))
+            .checked_sub(1)
+    }
+
+    pub fn to_lrc(&self) -> String {
+        let mut lrc = format!("[by:{}]\n", self.source);
+        for line in &self.lines {
+            if let Some(time) = line.time {
+                let centis = time.as_millis() / 10;
+                lrc.push_str(&format!("[{:02}:{:02}.{:02}]", centis / 6000, centis / 100 % 60, centis % 100));
+            }
+            lrc.push_str(&line.text);
+            lrc.push('\n');
+        }
+        lrc
+    }
+}
+
+// "mm:ss", "mm:ss.xx" or "mm:ss.xxx"
+fn parse_timestamp(stamp: &str) -> Option<Duration> {
+    let (minutes, seconds) = stamp.split_once(':')?;
+    let minutes: u64 = minutes.trim().parse().ok()?;
+    let seconds: f64 = seconds.trim().parse().ok()?;
+    Some(Duration::from_millis(minutes * 60_000) + Duration::from_secs_f64(seconds))
+}
+
+// ~/.cache/music-player/lyrics on Linux, %LOCALAPPDATA%\music-player\lyrics on Windows
+pub fn default_cache_dir() -> Option<PathBuf> {
+    dirs::cache_dir().map(|dir| dir.join("music-player").join("lyrics"))
+}
+
+// Where fetched lyrics for `path` are kept: a .lrc next to it, or in the cache dir
+pub fn store_path(path: &Path, next_to_file: bool, cache_dir: Option<&Path>) -> Option<PathBuf> {
+    if next_to_file {
+        Some(path.with_extension("lrc"))
+    } else {
+        cache_dir.map(|dir| dir.join(format!("{}.lrc", persist::file_key(&path.to_string_lossy()))))
+    }
+}
+
+// Tags first (synced before plain), then a .lrc or .txt next to the file,
+// then anything fetched earlier into the cache dir
+pub fn find(path: &Path, cache_dir: Option<&Path>) -> Option<Lyrics> {
+    if let Ok(tag) = Tag::read_from_path(path) {
+        let synced = tag.synchronised_lyrics()
+            .find(|lyrics| lyrics.timestamp_format == TimestampFormat::Ms);
+        if let Some(synced) = synced {
+            let lines = synced.content.iter()
+                .map(|(ms, text)| LyricLine {
+                    time: Some(Duration::from_millis(*ms as u64)),
+                    text: text.trim().to_string(),
+                })
+                .collect();
+            return Some(Lyrics { lines, source: String::from("Embedded") });
+        }
+        if let Some(plain) = tag.lyrics().next() {
+            // Some taggers put whole LRC files in here
+            return Some(Lyrics::parse_lrc(&plain.text, "Embedded"));
+        }
+    }
+
+    let cached = cache_dir.and_then(|dir| store_path(path, false, Some(dir)));
+    [path.with_extension("lrc"), path.with_extension("txt")]
+        .into_iter()
+        .chain(cached)
+        .find_map(|file| {
+            let contents = fs::read_to_string(&file).ok()?;
+            let name = file.file_name().unwrap_or_default().to_string_lossy().to_string();
+            Some(Lyrics::parse_lrc(&contents, &name))
+        })
+}
+
+// Asks an LRCLIB-style API (GET with artist_name, track_name, album_name and
+// duration) for lyrics, preferring synced ones. Ok(None) when it has none.
+pub fn fetch(song: &Song, provider: &str) -> Result<Option<Lyrics>> {
+    let mut args = vec![
+        String::from("-G"),
+        provider.to_string(),
+        String::from("--data-urlencode"),
+        format!("artist_name={}", song.artist),
+        String::from("--data-urlencode"),
+        format!("track_name={}", song.title),
+        String::from("--data-urlencode"),
+        format!("album_name={}", song.album),
+    ];
+    if let Some(duration) = song.duration {
+        args.push(String::from("-d"));
+        args.push(format!("duration={}", duration.as_secs()));
+    }
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    // A 404 is the usual answer for songs it doesn't know
+    let Ok(body) = curl(&args) else {
+        return Ok(None);
+    };
+    let json: Value = serde_json::from_slice(&body)?;
+
+    // Credit the provider by its host name, e.g. "lrclib.net"
+    let source = provider.split("://").last().unwrap_or(provider).split('/').next().unwrap_or(provider);
+    if json["instrumental"].as_bool() == Some(true) {
+        return Ok(Some(Lyrics::parse_lrc("♪ Instrumental ♪", source)));
+    }
+    Ok(["syncedLyrics", "plainLyrics"]
+        .iter()
+        .find_map(|key| json[key].as_str().filter(|text| !text.trim().is_empty()))
+        .map(|text| Lyrics::parse_lrc(text, source)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(ms: u64, text: &str) -> LyricLine {
+        LyricLine { time: Some(Duration::from_millis(ms)), text: text.to_string() }
+    }
+
+    #[test]
+    fn parses_lrc_with_headers_and_repeated_stamps() {
+        let lyrics = Lyrics::parse_lrc(
+            "[ar:Someone]\n[by:lrclib.net]\n[offset:+500]\n[00:12.00]First\n[00:05.50][01:00.25]Chorus\n",
+            "song.lrc",
+        );
+        assert_eq!(lyrics.source, "lrclib.net");
+        assert_eq!(lyrics.lines, vec![line(5_000, "Chorus"), line(11_500, "First"), line(59_750, "Chorus")]);
+        assert_eq!(Lyrics::parse_lrc(&lyrics.to_lrc(), "x"), lyrics);
+    }
+
+    #[test]
+    fn current_line_follows_position() {
+        let lyrics = Lyrics::parse_lrc("[00:01.00]One\n[00:03.00]Two\n", "x");
+        assert_eq!(lyrics.current(Duration::from_millis(500)), None);
+        assert_eq!(lyrics.current(Duration::from_secs(2)), Some(0));
+        assert_eq!(lyrics.current(Duration::from_secs(60)), Some(1));
+        assert_eq!(Lyrics::parse_lrc("Just words", "x").current(Duration::from_secs(9)), None);
+    }
+
+    #[test]
+    fn finds_sidecar_then_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = tempfile::tempdir().unwrap();
+        let song = dir.path().join("song.mp3");
+        assert_eq!(find(&song, Some(cache.path())), None);
+
+        let stored = store_path(&song, false, Some(cache.path())).unwrap();
+        fs::write(&stored, "[by:lrclib.net]\nCached\n").unwrap();
+        assert_eq!(find(&song, Some(cache.path())).unwrap().source, "lrclib.net");
+
+        fs::write(dir.path().join("song.txt"), "Beside it\n").unwrap();
+        let found = find(&song, Some(cache.path())).unwrap();
+        assert_eq!((found.source.as_str(), found.lines[0].text.as_str()), ("song.txt", "Beside it"));
+    }
+}
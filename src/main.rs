@@ -1,129 +1,700 @@
+mod announce;
+mod remote;
+mod terminal;
+mod ui;
+
 use std::{
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    env, fs,
+    hash::Hash,
     io,
-    path::PathBuf,
-    sync::mpsc::{self, Sender},
+    net::TcpListener,
+    panic,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering},
+        mpsc::{self, RecvTimeoutError, Sender},
+        Arc, Mutex,
+    },
     thread,
     time::Duration,
-    collections::VecDeque,
     time::Instant,
+    time::{SystemTime, UNIX_EPOCH},
 };
 
 use anyhow::Result;
 use crossterm::{
-    event::{self, Event, KeyCode},
+    event::{
+        self, DisableBracketedPaste, DisableMouseCapture, EnableBracketedPaste, EnableMouseCapture, Event,
+        KeyCode, KeyEvent, KeyEventKind, KeyModifiers, KeyboardEnhancementFlags, MouseButton, MouseEvent,
+        MouseEventKind, PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags,
+    },
     execute,
-    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
-};
-use ratatui::{
-    backend::CrosstermBackend,
-    layout::{Constraint, Direction, Layout},
-    style::{Color, Modifier, Style},
-    text::{Span, Line},
-    widgets::{Block, Borders, List, ListItem, Paragraph, ListState, Tabs},
-    Terminal,
-    prelude::Alignment,
+    terminal::{
+        disable_raw_mode, enable_raw_mode, supports_keyboard_enhancement, EnterAlternateScreen,
+        LeaveAlternateScreen,
+    },
 };
-use rodio::{Decoder, OutputStream, Sink};
-use walkdir::WalkDir;
+use ratatui::{backend::CrosstermBackend, layout::Rect, Terminal};
+use rodio::cpal::{traits::{DeviceTrait, HostTrait}, SampleFormat};
+use rodio::{OutputStream, OutputStreamHandle, Sink, Source, SupportedStreamConfig};
 use rand::seq::SliceRandom;
-use id3::{Tag, TagLike};
+
+use announce::{Announcer, Hook, NowPlaying};
+use music_player::art::{ArtCache, CoverBlocks};
+use music_player::artists;
+use music_player::channels::{self, Mix, Mono, MonoSwitch};
+use music_player::columns::{self, Column, LibrarySort};
+use music_player::compressor::{self, Compressor, Night};
+use music_player::buffer::{BufferLevel, ReadAhead};
+use music_player::catalog::{self, Catalog};
+use music_player::config::{
+    ChannelMode, ColumnsConfig, Config, DuplicatePolicy, GoalsConfig, LayoutConfig, LyricsConfig, OutputConfig, PinsConfig, PlayedPolicy, PowerMode, QueueConfig,
+    ScanConfig, SectionsConfig, TagSyncConfig, TimeDisplay,
+};
+use music_player::cues::Cues;
+use music_player::decks::{DeckSide, Decks, Filter};
+use music_player::decode::{self, AudioSource, Diagnostics};
+use music_player::clock;
+use music_player::dither::{self, Dither, FloatSource};
+use music_player::fade::{self, Fade};
+use music_player::folders;
+use music_player::genres::Genres;
+use music_player::history;
+use music_player::lyrics::{self, Lyrics};
+use music_player::mojibake;
+use music_player::meter::{self, Level, Meter, Played, Stall};
+use music_player::library::{self, canonical_path, Copies, exceeds_scan_limits, format_duration, scan_directory, BrowseIndex, SearchScope, Song};
+use music_player::playback::{Effect, Playback, PlaybackState};
+use music_player::analysis::{self, Analysis, TrackAnalysis};
+use music_player::archive::{Archive, PlaylistRecord};
+use music_player::harmony;
+use music_player::import::{self, ImportResult};
+use music_player::persist;
+use music_player::power;
+use music_player::playlist::{self, Playlist};
+use music_player::queue::{self, QueueRow};
+use music_player::queues::Queues;
+use music_player::resample::{self, DeviceFormat, StreamInfo};
+use music_player::setlist;
+use music_player::state::State;
+use music_player::trims::Trims;
+use music_player::tree::{self, Node, Tree, TreeRow};
+use music_player::verify::{self, Verdict};
+use music_player::webhooks;
+use music_player::stats::{self, Play, SongStats, Stats, TagStats};
+use music_player::streaks;
+use music_player::systemd;
+use music_player::tags::{self, TagField};
+
+const SPINNER: [&str; 10] = ["⠋", "⠙", "⠹", "⠸", "⠼", "⠴", "⠦", "⠧", "⠇", "⠏"];
+const TICK_RATE: Duration = Duration::from_millis(100);
+const LOW_POWER_TICK_RATE: Duration = Duration::from_secs(1);
+const POWER_POLL: Duration = Duration::from_secs(30);
+// How often the audio thread says where the playing track is
+const POSITION_INTERVAL: Duration = Duration::from_millis(250);
+// Preloaded tracks are buffered at least this far, and only so many are kept
+const PRELOAD_SECS: f32 = 30.0;
+const MAX_PRELOADS: usize = 3;
+// The next track is appended to the playing one this long before it ends
+const GAPLESS_LEAD: Duration = Duration::from_secs(5);
+// How often an appended track checks whether it has been taken back
+const CANCEL_POLL: Duration = Duration::from_millis(5);
+// A device that takes no samples this long while playing is taken to be gone
+const DEVICE_STALL: Duration = Duration::from_secs(3);
+// How often a new output device is looked for once the old one is gone
+const DEVICE_RETRY: Duration = Duration::from_secs(2);
+const KEY_DELAY: Duration = Duration::from_millis(150); // 150ms delay between key presses
+const SEARCH_DEBOUNCE: Duration = Duration::from_millis(100);
+// How long the last key stays in the corner of the controls
+const SHOWCMD_TIME: Duration = Duration::from_secs(1);
+const SHORT_SEEK: Duration = Duration::from_secs(5);
+const LONG_SEEK: Duration = Duration::from_secs(30);
+const CONFIG_POLL: Duration = Duration::from_secs(1);
+// Bars kept for the screensaver, more than any terminal is wide
+const LEVEL_HISTORY: usize = 400;
+
+// Everything the main loop reacts to arrives through one channel as an Action.
+// The threads feeding it are plain std threads rather than tokio tasks: each
+// spends its time blocked in something with no async API (crossterm's
+// event::read, rodio's output stream, which can't leave its thread, walkdir
+// and the tag readers, SQLite, rustls' blocking streams), so on a runtime
+// they would all be spawn_blocking anyway.
+enum Action {
+    Input(Event),
+    Scan(ScanEvent),
+    Player(PlayerEvent),
+    // The art cache finished looking up the album of this song
+    Cover(PathBuf),
+    // What the lyrics provider had for this song
+    Lyrics(PathBuf, Option<Lyrics>),
+    // One track measured by `:analyze`, with how many are left after it
+    Analyzed(PathBuf, TrackAnalysis, usize),
+    // One file decoded through by `:verify`, with how many are left after it
+    Verified(PathBuf, Verdict, usize),
+    // A directory for `:add`, with what makes it bigger than the scan limits
+    // allow, if anything
+    Measured(PathBuf, Option<String>),
+    // The content hash of a file that got play counts or a rating
+    Hashed(PathBuf, u64),
+    // Something a client of the HTTP API asked for
+    Remote(remote::Command),
+    // SIGTERM, SIGINT, or SIGHUP in a terminal: leave as q does
+    Quit,
+    // SIGHUP under systemd
+    ReloadConfig,
+    Tick,
+}
+
+impl Action {
+    // Ticks and position reports, which come whether or not anything happened
+    fn is_timer(&self) -> bool {
+        matches!(self, Action::Tick | Action::Player(PlayerEvent::Position(_)))
+    }
+}
+
+fn spawn_input_thread(tx: Sender<Action>) {
+    thread::spawn(move || {
+        while let Ok(event) = event::read() {
+            if tx.send(Action::Input(event)).is_err() {
+                break;
+            }
+        }
+    });
+}
+
+// Turns SIGTERM and SIGINT into a quit. SIGHUP reloads the config under a
+// service manager; in a terminal it means the terminal closed, so it quits too.
+#[cfg(unix)]
+fn spawn_signal_thread(tx: Sender<Action>) -> Result<()> {
+    use signal_hook::{consts::{SIGHUP, SIGINT, SIGTERM}, iterator::Signals};
+
+    let mut signals = Signals::new([SIGTERM, SIGINT, SIGHUP])?;
+    let reload_on_hangup = systemd::supervised();
+    thread::spawn(move || {
+        for signal in signals.forever() {
+            let action = if signal == SIGHUP && reload_on_hangup { Action::ReloadConfig } else { Action::Quit };
+            if tx.send(action).is_err() {
+                break;
+            }
+        }
+    });
+    Ok(())
+}
+
+// Ctrl-C, Ctrl-Break, closing the console window, logging off and shutting
+// down all reach a console control handler. Windows calls it on a thread of
+// its own that can't hold the channel, so it sets a flag looked at now and then.
+#[cfg(windows)]
+fn spawn_signal_thread(tx: Sender<Action>) -> Result<()> {
+    static QUIT: AtomicBool = AtomicBool::new(false);
+    const CTRL_CLOSE_EVENT: u32 = 2;
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn SetConsoleCtrlHandler(handler: Option<extern "system" fn(u32) -> i32>, add: i32) -> i32;
+    }
+
+    extern "system" fn handler(event: u32) -> i32 {
+        QUIT.store(true, Ordering::Relaxed);
+        // After a close, logoff or shutdown Windows ends the process as soon
+        // as this returns, or a few seconds later at most. Holding on until
+        // main returns and exits gives it those seconds to fade out and save.
+        if event >= CTRL_CLOSE_EVENT {
+            thread::sleep(Duration::from_secs(10));
+        }
+        1
+    }
+
+    if unsafe { SetConsoleCtrlHandler(Some(handler), 1) } == 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+    thread::spawn(move || {
+        while !QUIT.load(Ordering::Relaxed) {
+            thread::sleep(Duration::from_millis(250));
+        }
+        let _ = tx.send(Action::Quit);
+    });
+    Ok(())
+}
+
+// Ticks every `interval_ms`, which the app changes in low-power mode
+fn spawn_tick_thread(tx: Sender<Action>, interval_ms: Arc<AtomicU64>) {
+    thread::spawn(move || {
+        while tx.send(Action::Tick).is_ok() {
+            thread::sleep(Duration::from_millis(interval_ms.load(Ordering::Relaxed)));
+        }
+    });
+}
+
+// What a timer tick can change on screen: the time, the message, search
+// results and the screensaver clock
+type TickView = (Option<String>, Option<String>, String, Option<String>);
 
 enum PlayerMessage {
-    Play(PathBuf),
+    // Starts the file at the given offset, scaled by its ReplayGain
+    Play(PathBuf, Duration, f32),
     Stop,
+    // Holds and carries on with the playing track, keeping its place
+    Pause,
+    Resume,
     Next,
     Previous,
-    Quit,
+    // Fades out what's playing, then says so and ends the audio thread
+    Quit(Sender<()>),
     AddDirectory(PathBuf),
     RemoveDirectory(usize),
     SetVolume(f32),
+    // Silences the output, keeping the volume to go back to
+    Mute,
+    Unmute,
+    // Sums every channel into all of them, or puts them back, mid-track
+    SetChannelMode(Mix),
     Shuffle,
     AddToQueue(usize),
+    // Deck mode: two more sinks, loaded paused, mixed alongside the main one
+    LoadDeck(DeckSide, PathBuf),
+    PlayDeck(DeckSide, bool),
+    DeckGain(DeckSide, f32),
+    DeckFilter(DeckSide, Filter),
+    StopDecks,
+    Configure(OutputConfig),
+    // Opens and buffers a track ahead of its Play
+    Preload(PathBuf),
+    // What to append to the playing track shortly before it ends, where to
+    // start it and its gain; None when nothing should follow without a gap
+    Gapless(Option<(PathBuf, Duration, f32)>),
 }
 
-#[derive(Clone)]
-struct Song {
+// Reported back by the audio thread once a Play request has been handled
+enum PlayerEvent {
+    // With the track length, if the decoder knows it
+    Started(Option<Duration>, StreamInfo),
+    Failed(String),
+    // A deck could not load its track; the main playback is unaffected
+    DeckFailed(String),
+    // Which decoder opened a file for playback, or why none could
+    Decoded(PathBuf, Diagnostics),
+    // How far into the track the device is, sent every POSITION_INTERVAL while playing
+    Position(Duration),
+    // The device has played the track to its end
+    Finished,
+    // A preloaded track has filled its buffer
+    Preloaded(PathBuf),
+    // The device went straight on into the appended track, which was
+    // started from the given offset
+    Advanced(PathBuf, Duration, Option<Duration>, StreamInfo),
+    // The output device stopped taking samples, or there was none to start
+    // with, and no other could be opened yet
+    DeviceLost,
+    // Output is open again, on whatever is the default device now; the track
+    // was at this position when the old one went
+    DeviceRestored(Duration),
+}
+
+// A preloaded track once open, with the length the decoder gave
+type Buffered = (ReadAhead, StreamInfo, Option<Duration>);
+
+// A track opened and buffered on its own thread; the slot is filled once it
+// is open
+struct Preload {
     path: PathBuf,
-    title: String,
-    artist: String,
-    album: String,
-    genre: String,
+    ready: Arc<Mutex<Option<Buffered>>>,
+    // Converted for the dither stage, so it must be played through it
+    dither: bool,
+    reported: bool,
 }
 
-impl Song {
-    fn new(path: PathBuf) -> Self {
-        let filename = path.file_stem()
-            .unwrap_or_default()
-            .to_string_lossy()
-            .to_string();
-        
-        // Split filename by " - " to get artist and title
-        let parts: Vec<&str> = filename.splitn(2, " - ").collect();
-        
-        let (mut artist, mut title) = match parts.as_slice() {
-            [artist, title] => {
-                // Add spaces between multiple artists (separated by &, feat., or featuring)
-                let artist = artist
-                    .replace("&", " & ")
-                    .replace("feat.", " feat. ")
-                    .replace("featuring", " featuring ")
-                    .replace("  ", " ") // Remove any double spaces
-                    .trim()
-                    .to_string();
-                (artist, title.to_string())
-            },
-            _ => (String::from("Unknown Artist"), filename),
-        };
+// Where the playing track was started from, the samples the device has
+// taken since, how many make a second and how long the track is
+struct Progress {
+    from: Duration,
+    played: Played,
+    per_second: u64,
+    length: Option<Duration>,
+}
+
+impl Progress {
+    fn position(&self) -> Duration {
+        let taken = self.played.load(Ordering::Relaxed) as f64 / self.per_second as f64;
+        self.from + Duration::from_secs_f64(taken)
+    }
+}
 
-        let mut album = String::from("Unknown Album");
-        let mut genre = String::from("Unknown Genre");
+// A track appended to the sink behind the playing one, until the device
+// gets to it
+struct Appended {
+    path: PathBuf,
+    progress: Progress,
+    stream: StreamInfo,
+    buffer: Option<Arc<BufferLevel>>,
+    cancel: Arc<AtomicBool>,
+}
 
-        // Try to read metadata
-        if let Ok(tag) = Tag::read_from_path(&path) {
-            if let Some(meta_title) = tag.title() {
-                title = meta_title.to_string();
-            }
-            if let Some(meta_artist) = tag.artist() {
-                // Add spaces between multiple artists in metadata too
-                artist = meta_artist
-                    .replace("&", " & ")
-                    .replace("feat.", " feat. ")
-                    .replace("featuring", " featuring ")
-                    .replace("  ", " ")
-                    .trim()
-                    .to_string();
-            }
-            if let Some(meta_album) = tag.album() {
-                album = meta_album.to_string();
+// A track ready for the sink: its source, how it reaches the device, its
+// length and its read-ahead buffer if it has one
+type Opened = (AudioSource, StreamInfo, Option<Duration>, Option<Arc<BufferLevel>>);
+
+// Opens `path` for the device from `from`, taking a preload of it if there is
+// one made the same way. The error says why it can't be played.
+fn open_track(
+    path: &Path,
+    from: Duration,
+    dither: bool,
+    preloads: &mut Vec<Preload>,
+    device: Option<DeviceFormat>,
+    output: &OutputConfig,
+    events: &Sender<Action>,
+) -> Result<Opened, String> {
+    // One still opening, or converted otherwise, is let go
+    let preloaded = preloads.iter().position(|preload| preload.path == path)
+        .map(|i| preloads.remove(i))
+        .filter(|preload| preload.dither == dither)
+        .and_then(|preload| {
+            let ready = preload.ready.lock().unwrap().take();
+            ready
+        });
+    if let Some((buffered, stream, length)) = preloaded {
+        let level = buffered.level();
+        return Ok((Box::new(buffered.skip_duration(from)), stream, length, Some(level)));
+    }
+
+    let (source, diagnostics) = decode::open_at(path, from);
+    let error = format!("Cannot play {}: {}", path.display(), diagnostics.first_error());
+    let _ = events.send(Action::Player(PlayerEvent::Decoded(path.to_path_buf(), diagnostics)));
+    let source = source.ok_or(error)?;
+    let length = source.total_duration();
+    let (source, mut stream) = resample::to_device(source, device, output.resample_quality, dither);
+    stream.codec = decode::codec(path);
+    if output.read_ahead_secs > 0.0 {
+        let buffered = ReadAhead::new(source, output.read_ahead_secs);
+        let level = buffered.level();
+        Ok((Box::new(buffered), stream, length, Some(level)))
+    } else {
+        Ok((source, stream, length, None))
+    }
+}
+
+fn fade_length(output: &OutputConfig) -> Duration {
+    Duration::from_millis(output.fade_ms)
+}
+
+// Holds the audio thread while a playing sink fades to silence, ahead of a
+// pause or stop
+fn fade_out(sink: &Sink, target: &fade::Target, output: &OutputConfig) {
+    if !sink.is_paused() && !sink.empty() && output.fade_ms > 0 {
+        fade::set(target, 0.0);
+        thread::sleep(fade_length(output));
+    }
+}
+
+// Counts what the device takes of `source`, for position reports, and feeds
+// the level meter
+fn metered(source: FloatSource, from: Duration, length: Option<Duration>, level: &Level) -> (FloatSource, Progress) {
+    let played = Played::default();
+    let per_second = (source.sample_rate() * source.channels() as u32) as u64;
+    let progress = Progress { from, played: played.clone(), per_second: per_second.max(1), length };
+    (Box::new(Meter::new(source, level.clone(), played)), progress)
+}
+
+// While dithering, the dither stage applies the volume instead of the sink.
+// ReplayGain is applied to floats either way, so it isn't rounded twice.
+fn append(sink: &Sink, source: FloatSource, dithering: bool, gain: &dither::Gain, track_gain: f32) {
+    if dithering {
+        sink.append(Dither::new(source, gain.clone(), track_gain));
+    } else {
+        sink.append(source.amplify(track_gain));
+    }
+}
+
+enum ScanEvent {
+    Found(Box<Song>),
+    // A file left out as a copy of one already in the library
+    Copy,
+    Failed(PathBuf, String),
+    // Files with play counts that no scan found and are gone, with the
+    // content hashes of the files new to the library this scan found
+    Moved(HashSet<PathBuf>, HashMap<PathBuf, u64>),
+    Finished,
+    Rescanned(Box<Rescan>),
+    RescanFailed(String),
+}
+
+// Sees whether `dir` is within the scan limits on a worker thread; it can
+// take a while to find out about a whole disk
+fn spawn_limit_check(dir: PathBuf, scan: ScanConfig, tx: Sender<Action>) {
+    thread::spawn(move || {
+        let exceeded = exceeds_scan_limits(&dir, &scan);
+        let _ = tx.send(Action::Measured(dir, exceeded));
+    });
+}
+
+// Hashes a file with new play counts or a rating on a worker thread
+fn spawn_hash(path: PathBuf, tx: Sender<Action>) {
+    thread::spawn(move || {
+        if let Some(hash) = library::content_hash(&path) {
+            let _ = tx.send(Action::Hashed(path, hash));
+        }
+    });
+}
+
+// Walks directories on a worker thread and streams songs back as their tags
+// are read. Files unchanged since the library database last saw them aren't
+// read again; without the database every file is. `counted` is the files
+// with play counts and whether their content hash is kept, as for a rescan.
+fn spawn_scan(dirs: Vec<PathBuf>, scan: ScanConfig, known: HashSet<PathBuf>, counted: HashMap<PathBuf, bool>, tx: Sender<Action>) {
+    thread::spawn(move || {
+        let mut seen = known.clone();
+        // Files the database hadn't seen, which moved ones turn up as
+        let mut new_paths = Vec::new();
+        let mut catalog = Catalog::open().ok();
+        let cached = catalog.as_ref().and_then(|catalog| catalog.load().ok()).unwrap_or_default();
+        // Built from the library once the first new file turns up
+        let mut copies: Option<Copies> = None;
+        for dir in dirs {
+            let mut walked = HashSet::new();
+            let mut read = Vec::new();
+            let result = scan_directory(&dir, &scan, |path| {
+                walked.insert(path.clone());
+                // Nested or repeated directories yield the same files more than once
+                if seen.insert(path.clone()) {
+                    if scan.skip_copies && copies.get_or_insert_with(|| Copies::new(&known)).is_copy(&path) {
+                        let _ = tx.send(Action::Scan(ScanEvent::Copy));
+                        return;
+                    }
+                    let song = catalog::cached(&cached, &path).unwrap_or_else(|| {
+                        let mut song = Song::new(path);
+                        // Changed since, but not new
+                        match cached.get(&song.path) {
+                            Some(before) => song.added = before.added,
+                            None => new_paths.push(song.path.clone()),
+                        }
+                        read.push(song.clone());
+                        song
+                    });
+                    let _ = tx.send(Action::Scan(ScanEvent::Found(Box::new(song))));
+                }
+            });
+            // Only a full walk tells which files are gone
+            let gone: Vec<PathBuf> = match &result {
+                Ok(()) => cached.keys().filter(|path| path.starts_with(&dir) && !walked.contains(*path)).cloned().collect(),
+                Err(_) => Vec::new(),
+            };
+            if let Some(catalog) = &mut catalog {
+                let _ = catalog.update(&read, &gone);
             }
-            if let Some(meta_genre) = tag.genre() {
-                genre = meta_genre.to_string();
+            if let Err(e) = result {
+                let _ = tx.send(Action::Scan(ScanEvent::Failed(dir, e.to_string())));
             }
         }
+        // Only worth hashing if there are numbers to take along
+        let gone: HashSet<PathBuf> = counted.into_iter()
+            .filter(|(path, hashed)| *hashed && !seen.contains(path) && !path.exists())
+            .map(|(path, _)| path)
+            .collect();
+        if !gone.is_empty() && !new_paths.is_empty() {
+            let hashes = new_paths.into_iter().filter_map(|path| library::content_hash(&path).map(|hash| (path, hash))).collect();
+            let _ = tx.send(Action::Scan(ScanEvent::Moved(gone, hashes)));
+        }
+        let _ = tx.send(Action::Scan(ScanEvent::Finished));
+    });
+}
+
+// What a rescan found on disk, for `MusicPlayer::apply_rescan`
+struct Rescan {
+    on_disk: HashSet<PathBuf>,
+    // Songs read again because their file changed
+    updated: Vec<Song>,
+    // New files, read, short of copies
+    added: Vec<Song>,
+    copies: usize,
+    // Content hashes of files with play counts that changed or had none, and
+    // of new files if some with play counts are gone
+    hashes: HashMap<PathBuf, u64>,
+    quiet: bool,
+}
+
+// Walks every directory again on a worker thread and reads what changed:
+// `known` is the library's files with when they were last changed, `counted`
+// the files with play counts and whether their content hash is kept
+fn spawn_rescan(
+    dirs: Vec<PathBuf>,
+    scan: ScanConfig,
+    known: HashMap<PathBuf, Option<SystemTime>>,
+    counted: HashMap<PathBuf, bool>,
+    quiet: bool,
+    tx: Sender<Action>,
+) {
+    thread::spawn(move || {
+        let event = match rescan_dirs(&dirs, &scan, &known, &counted) {
+            Ok(rescan) => ScanEvent::Rescanned(Box::new(Rescan { quiet, ..rescan })),
+            Err(e) => ScanEvent::RescanFailed(e.to_string()),
+        };
+        let _ = tx.send(Action::Scan(event));
+    });
+}
+
+fn rescan_dirs(
+    dirs: &[PathBuf],
+    scan: &ScanConfig,
+    known: &HashMap<PathBuf, Option<SystemTime>>,
+    counted: &HashMap<PathBuf, bool>,
+) -> Result<Rescan> {
+    let mut on_disk = HashSet::new();
+    for dir in dirs {
+        scan_directory(dir, scan, |path| {
+            on_disk.insert(path);
+        })?;
+    }
+    let gone: Vec<PathBuf> = known.keys().filter(|path| !on_disk.contains(*path)).cloned().collect();
+
+    let mut hashes = HashMap::new();
+    let mut updated = Vec::new();
+    for (path, modified) in known.iter().filter(|(path, _)| on_disk.contains(*path)) {
+        let changed = fs::metadata(path).and_then(|m| m.modified()).ok() != *modified;
+        if changed {
+            updated.push(Song::new(path.clone()));
+        }
+        if counted.get(path).is_some_and(|&hashed| changed || !hashed) {
+            hashes.extend(library::content_hash(path).map(|hash| (path.clone(), hash)));
+        }
+    }
+
+    let mut new_paths: Vec<PathBuf> = on_disk.iter()
+        .filter(|path| !known.contains_key(*path))
+        .cloned()
+        .collect();
+    new_paths.sort();
+    let before_copies = new_paths.len();
+    // The library's files are only looked at if there is something to compare them with
+    if scan.skip_copies && !new_paths.is_empty() {
+        let mut copies = Copies::new(known.keys().filter(|path| on_disk.contains(*path)));
+        new_paths.retain(|path| !copies.is_copy(path));
+    }
+    let copies = before_copies - new_paths.len();
+    // Only worth hashing if there are numbers to take along
+    if counted.iter().any(|(path, &hashed)| hashed && !on_disk.contains(path)) {
+        hashes.extend(new_paths.iter().filter_map(|path| Some((path.clone(), library::content_hash(path)?))));
+    }
+    let added: Vec<Song> = new_paths.into_iter().map(Song::new).collect();
+
+    // Saves reading them again on the next start
+    if let Ok(mut catalog) = Catalog::open() {
+        let read: Vec<Song> = updated.iter().chain(&added).cloned().collect();
+        let _ = catalog.update(&read, &gone);
+    }
+    Ok(Rescan { on_disk, updated, added, copies, hashes, quiet: false })
+}
+
+struct RescanSummary {
+    added: usize,
+    removed: usize,
+    updated: usize,
+    // Moved or renamed files that took their ratings and play counts along
+    relinked: usize,
+    // New files left out as copies, with `skip_copies`
+    copies: usize,
+}
+
+impl RescanSummary {
+    fn has_changes(&self) -> bool {
+        self.added + self.removed + self.updated > 0
+    }
 
-        Song {
-            path,
-            title,
-            artist,
-            album,
-            genre,
+    fn message(&self) -> String {
+        let message = format!("Rescan: added {}, removed {}, updated {}", self.added, self.removed, self.updated);
+        let message = match self.relinked {
+            0 => message,
+            n => format!("{}, relinked {}", message, n),
+        };
+        match self.copies {
+            0 => message,
+            n => format!("{}, skipped {} copies", message, n),
         }
     }
 }
 
 struct MusicPlayer {
     songs: Vec<Song>,
-    current_index: usize,
+    playback: Playback,
+    // Where the current track was started from, or last reported at by the
+    // audio thread, and when that was
+    play_offset: Duration,
+    playing_since: Option<Instant>,
+    // Where the audio thread holds the current track paused, if it does
+    paused_at: Option<Duration>,
     _player_tx: Sender<PlayerMessage>,
-    is_playing: bool,
     music_dirs: Vec<PathBuf>,
     volume: f32,
-    queue: VecDeque<usize>,
+    muted: bool,
+    mono: bool,
+    // Tracks started since the player was opened or the setlist cleared,
+    // with when and what they were
+    setlist: Vec<(Instant, String, String)>,
     view_mode: ViewMode,
+    // Query the cached `search_results` were computed for
     search_query: String,
+    search_scope: SearchScope,
+    search_results: Vec<usize>,
+    search_stale: bool,
+    announcer: Announcer,
+    scan_config: ScanConfig,
+    events: Sender<Action>,
+    scans_running: usize,
+    // A rescan is walking the directories; see `rescan`
+    rescanning: bool,
+    scan_found: usize,
+    // When the running scans began, the first of them if several overlap
+    scan_started: Instant,
+    browse: BrowseIndex,
+    // Re-applied whenever songs are added; see `LibrarySort`
+    library_sort: Option<LibrarySort>,
+    playlists: Vec<Playlist>,
+    playlist_dir: Option<PathBuf>,
+    stats: Stats,
+    tag_sync: TagSyncConfig,
+    // Songs whose POPM tag is behind `stats`, written once nothing is playing them
+    unsynced_tags: HashSet<PathBuf>,
+    analysis: Analysis,
+    // Tracks still to be measured by the running analysis
+    analyzing: usize,
+    output: OutputConfig,
+    // Read-ahead buffer of the playing track, while there is one
+    buffer: Arc<Mutex<Option<Arc<BufferLevel>>>>,
+    // Peak of what is playing right now
+    level: Level,
+    // Night mode's compressor settings, read by the playing track as it goes
+    night: Night,
+    // Artist names shown as another; see `artists::apply`
+    aliases: HashMap<String, String>,
+    // Goes up whenever songs move to other indices (removals, sorts,
+    // shuffles), so an index kept from before can be told to be stale.
+    // Songs found by a scan are added at the end and leave it alone.
+    generation: u64,
+    // The queue as the webhooks were last told it
+    hooked_queue: Vec<PathBuf>,
+    // Songs found by the running scans with tags in the wrong encoding
+    misencoded: usize,
+    // Files the running scans left out as copies
+    copies: usize,
+    // Files with play counts the running scans found moved
+    relinked: usize,
+    // How the playing track reaches the device
+    stream: Option<StreamInfo>,
+    trims: Trims,
+    queue_config: QueueConfig,
+    queues: Queues,
+    // Whether the active queue saved last time has been put back
+    queue_restored: bool,
+    // Tracks the audio thread is holding open ahead of their turn, and
+    // whether each has filled its buffer
+    preloads: Vec<(PathBuf, bool)>,
+    // What the audio thread was last told to append to the playing track
+    gapless: Option<(PathBuf, Duration, f32)>,
 }
 
-#[derive(PartialEq)]
+#[derive(Clone, Copy, PartialEq)]
 enum ViewMode {
     AllSongs,
     Artists,
@@ -131,593 +702,4493 @@ enum ViewMode {
     Genres,
     Queue,
     Search,
+    Playlists,
+    Folders,
+    // One of the browse hierarchies from the config, by its place there
+    Grouped(usize),
 }
 
-impl MusicPlayer {
-    fn new(music_dirs: &[PathBuf]) -> Result<Self> {
-        let mut songs = Vec::new();
-        for dir in music_dirs {
-            for entry in WalkDir::new(dir).follow_links(true) {
-                let entry = entry?;
-                let path = entry.path();
-                if let Some(ext) = path.extension() {
-                    if ext == "mp3" || ext == "ogg" || ext == "flac" {
-                        songs.push(Song::new(path.to_owned()));
-                    }
-                }
-            }
-        }
-
-        let (tx, rx) = mpsc::channel();
-        let _player_tx = tx.clone();
-
-        // Audio playback thread
-        thread::spawn(move || {
-            let (_stream, stream_handle) = OutputStream::try_default().unwrap();
-            let mut sink: Option<Sink> = None;
-            let mut current_volume = 1.0;
+impl ViewMode {
+    // Tab bar order; the configured hierarchies come after these
+    const ALL: [ViewMode; 8] = [
+        ViewMode::AllSongs,
+        ViewMode::Artists,
+        ViewMode::Albums,
+        ViewMode::Genres,
+        ViewMode::Queue,
+        ViewMode::Search,
+        ViewMode::Playlists,
+        ViewMode::Folders,
+    ];
 
-            while let Ok(msg) = rx.recv() {
-                match msg {
-                    PlayerMessage::Play(path) => {
-                        if let Some(s) = sink.take() {
-                            s.stop();
-                        }
-                        if let Ok(file) = std::fs::File::open(&path) {
-                            if let Ok(source) = Decoder::new(file) {
-                                let new_sink = Sink::try_new(&stream_handle).unwrap();
-                                new_sink.set_volume(current_volume);
-                                new_sink.append(source);
-                                new_sink.play();
-                                sink = Some(new_sink);
-                            }
-                        }
-                    }
-                    PlayerMessage::SetVolume(vol) => {
-                        current_volume = vol;
-                        if let Some(s) = &sink {
-                            s.set_volume(vol);
-                        }
-                    }
-                    PlayerMessage::Stop => {
-                        if let Some(s) = &sink {
-                            s.stop();
-                        }
-                    }
-                    PlayerMessage::Quit => break,
-                    _ => {}
-                }
-            }
-        });
+    fn index(self) -> usize {
+        match self {
+            ViewMode::Grouped(group) => ViewMode::ALL.len() + group,
+            _ => ViewMode::ALL.iter().position(|&mode| mode == self).unwrap_or(0),
+        }
+    }
 
-        Ok(MusicPlayer {
-            songs,
-            current_index: 0,
-            _player_tx: tx,
-            is_playing: false,
-            music_dirs: music_dirs.to_vec(),
-            volume: 1.0,
-            queue: VecDeque::new(),
-            view_mode: ViewMode::AllSongs,
-            search_query: String::new(),
-        })
+    fn nth(index: usize) -> ViewMode {
+        ViewMode::ALL.get(index).copied().unwrap_or(ViewMode::Grouped(index.saturating_sub(ViewMode::ALL.len())))
     }
 
-    fn play_current(&mut self) {
-        if let Some(song) = self.songs.get(self.current_index) {
-            self._player_tx
-                .send(PlayerMessage::Play(song.path.clone()))
-                .unwrap();
-            self.is_playing = true;
-        }
+    fn next(self, groups: usize) -> ViewMode {
+        ViewMode::nth((self.index() + 1) % (ViewMode::ALL.len() + groups))
     }
 
-    fn stop(&mut self) {
-        self._player_tx.send(PlayerMessage::Stop).unwrap();
-        self.is_playing = false;
+    fn previous(self, groups: usize) -> ViewMode {
+        let tabs = ViewMode::ALL.len() + groups;
+        ViewMode::nth((self.index() + tabs - 1) % tabs)
     }
+}
 
-    fn next(&mut self) {
-        if let Some(next_index) = self.queue.pop_front() {
-            self.current_index = next_index;
-        } else {
-            self.current_index = (self.current_index + 1) % self.songs.len();
-        }
-        if self.is_playing {
-            self.play_current();
+// Short lists above the full one in the Songs tab, when configured
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+enum Section {
+    RecentlyPlayed,
+    MostPlayed,
+    RecentlyAdded,
+}
+
+impl Section {
+    const ALL: [Section; 3] = [Section::RecentlyPlayed, Section::MostPlayed, Section::RecentlyAdded];
+
+    fn label(self) -> &'static str {
+        match self {
+            Section::RecentlyPlayed => "Recently played",
+            Section::MostPlayed => "Most played",
+            Section::RecentlyAdded => "Recently added",
         }
     }
+}
 
-    fn previous(&mut self) {
-        if self.current_index > 0 {
-            self.current_index -= 1;
-        } else {
-            self.current_index = self.songs.len() - 1;
-        }
-        if self.is_playing {
-            self.play_current();
+// Panes that take keyboard input; keys not handled globally go to the focused one
+#[derive(Clone, Copy, PartialEq)]
+enum Pane {
+    Library,
+    Queue,
+}
+
+// The column a song list was last sorted by. The sidebar queue counts as the Queue view.
+#[derive(Clone, Copy, PartialEq)]
+struct SortOrder {
+    view: ViewMode,
+    column: Column,
+    descending: bool,
+}
+
+// What a browse tab remembers while another is shown: its view, search
+// and place in the list. Playback and the queue are shared by all tabs.
+#[derive(Clone)]
+struct Session {
+    view_mode: ViewMode,
+    search_input: String,
+    artist_tree: Tree<Vec<String>>,
+    selected_playlist: Option<usize>,
+    folder_tree: Tree<PathBuf>,
+    group_trees: HashMap<usize, Tree<Vec<String>>>,
+    scroll_offset: usize,
+    list_offset: usize,
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Session {
+            view_mode: ViewMode::AllSongs,
+            search_input: String::new(),
+            artist_tree: Tree::default(),
+            selected_playlist: None,
+            folder_tree: Tree::default(),
+            group_trees: HashMap::new(),
+            scroll_offset: 0,
+            list_offset: 0,
         }
     }
+}
 
-    fn add_directory(&mut self, new_dir: PathBuf) -> Result<()> {
-        if !new_dir.exists() {
-            return Err(anyhow::anyhow!("Directory does not exist"));
-        }
+// Where a column header was drawn, so a click on it can sort its list
+struct HeaderCell {
+    area: Rect,
+    view: ViewMode,
+    column: Column,
+}
 
-        // Add new songs from the directory
-        for entry in WalkDir::new(&new_dir).follow_links(true) {
-            let entry = entry?;
-            let path = entry.path();
-            if let Some(ext) = path.extension() {
-                if ext == "mp3" || ext == "ogg" || ext == "flac" {
-                    self.songs.push(Song::new(path.to_owned()));
-                }
-            }
-        }
+#[derive(Clone, Copy, PartialEq)]
+enum MenuAction {
+    Play,
+    PlayNext,
+    AddToQueue,
+    AddToPlaylist,
+    EditTags,
+    ShowAlbum,
+    Reveal,
+    RemoveFromLibrary,
+}
 
-        self.music_dirs.push(new_dir);
-        Ok(())
+impl MenuAction {
+    fn label(self) -> &'static str {
+        match self {
+            MenuAction::Play => "Play",
+            MenuAction::PlayNext => "Play next",
+            MenuAction::AddToQueue => "Add to queue",
+            MenuAction::AddToPlaylist => "Add to playlist…",
+            MenuAction::EditTags => "Edit tags…",
+            MenuAction::ShowAlbum => "Show album",
+            MenuAction::Reveal => "Show in file manager",
+            MenuAction::RemoveFromLibrary => "Remove from library",
+        }
     }
+}
 
-    fn remove_directory(&mut self, index: usize) -> Result<()> {
-        if index >= self.music_dirs.len() {
-            return Err(anyhow::anyhow!("Invalid directory index"));
-        }
+// Read-only popup of lines to scroll through, such as tracks an import couldn't find
+struct Report {
+    title: String,
+    lines: Vec<String>,
+    scroll: usize,
+}
 
-        let removed_dir = &self.music_dirs[index];
-        self.songs.retain(|song| !song.path.starts_with(removed_dir));
-        self.music_dirs.remove(index);
+// What the action menu was opened on, which decides what it offers
+#[derive(Clone, Copy, PartialEq)]
+enum MenuTarget {
+    Song,
+    Album,
+    Artist,
+    Playlist,
+}
 
-        // Reset current index if needed
-        if self.current_index >= self.songs.len() {
-            self.current_index = self.songs.len().saturating_sub(1);
+impl MenuTarget {
+    fn actions(self) -> &'static [MenuAction] {
+        use MenuAction::*;
+        match self {
+            MenuTarget::Song => &[Play, PlayNext, AddToQueue, AddToPlaylist, EditTags, ShowAlbum, Reveal, RemoveFromLibrary],
+            MenuTarget::Album | MenuTarget::Artist => &[Play, PlayNext, AddToQueue, AddToPlaylist, EditTags, RemoveFromLibrary],
+            // Its tracks are the playlist's to list, not to retag or remove
+            MenuTarget::Playlist => &[Play, PlayNext, AddToQueue, AddToPlaylist],
         }
-
-        Ok(())
     }
+}
 
-    fn set_volume(&mut self, delta: f32) {
-        self.volume = (self.volume + delta).clamp(0.0, 1.0);
-        self._player_tx.send(PlayerMessage::SetVolume(self.volume)).unwrap();
+// Popup listing what can be done with a song, or with all the songs of an
+// album, artist or playlist, opened with Enter
+struct ActionMenu {
+    target: MenuTarget,
+    title: String,
+    songs: Vec<usize>,
+    selected: usize,
+}
+
+impl ActionMenu {
+    fn new(target: MenuTarget, title: String, songs: Vec<usize>) -> Self {
+        ActionMenu { target, title, songs, selected: 0 }
     }
 
-    fn shuffle(&mut self) {
-        let mut rng = rand::thread_rng();
-        self.songs.shuffle(&mut rng);
-        self.current_index = 0;
-        if self.is_playing {
-            self.play_current();
-        }
+    fn song(index: usize, song: &Song) -> Self {
+        ActionMenu::new(MenuTarget::Song, song.title.clone(), vec![index])
     }
+}
 
-    fn add_to_queue(&mut self, index: usize) {
-        if index < self.songs.len() {
-            self.queue.push_back(index);
-        }
+// Popup for changing the tags of one song, or of many at once. A field the
+// songs don't agree on starts out empty and is left alone unless typed into.
+struct TagEditor {
+    songs: Vec<usize>,
+    // Each field, what the songs have in it if they all have the same, and
+    // what it's been changed to
+    fields: Vec<(TagField, Option<String>, String)>,
+    selected: usize,
+}
+
+impl TagEditor {
+    fn new(songs: Vec<usize>, library: &[Song]) -> Self {
+        let shown: &[TagField] = if songs.len() == 1 { &TagField::ALL } else { &TagField::SHARED };
+        let fields = shown.iter()
+            .map(|&field| {
+                let mut values = songs.iter().map(|&index| field.value(&library[index]));
+                let first = values.next().unwrap_or_default();
+                let shared = values.all(|value| value == first).then_some(first);
+                (field, shared.clone(), shared.unwrap_or_default())
+            })
+            .collect();
+        TagEditor { songs, fields, selected: 0 }
     }
 
-    fn search(&mut self, query: &str) -> Vec<(usize, &Song)> {
-        self.songs.iter().enumerate()
-            .filter(|(_, song)| {
-                song.title.to_lowercase().contains(&query.to_lowercase()) ||
-                song.artist.to_lowercase().contains(&query.to_lowercase()) ||
-                song.album.to_lowercase().contains(&query.to_lowercase())
+    // The fields typed into, with what to write in them
+    fn edits(&self) -> Vec<(TagField, String)> {
+        self.fields.iter()
+            .filter(|(_, shared, value)| match shared {
+                Some(shared) => value.trim() != shared,
+                None => !value.trim().is_empty(),
             })
+            .map(|(field, _, value)| (*field, value.clone()))
             .collect()
     }
 }
 
-struct App {
-    player: MusicPlayer,
-    command_mode: bool,
-    command_input: String,
-    message: Option<String>,
-    search_mode: bool,
-    search_input: String,
-    selected_artist: Option<String>,
+// Goes through the entries of a playlist that aren't in the library, offering
+// songs with the same file name to point each one at, or dropping it
+struct Relink {
+    playlist: usize,
+    // Entry indices still unresolved; the first is the one shown
+    missing: Vec<usize>,
+    candidates: Vec<usize>,
+    selected: usize,
 }
 
-fn main() -> Result<()> {
-    // Setup terminal
-    enable_raw_mode()?;
-    let mut stdout = io::stdout();
-    execute!(stdout, EnterAlternateScreen)?;
-    let backend = CrosstermBackend::new(stdout);
-    let mut terminal = Terminal::new(backend)?;
+// Popup for choosing the playlist songs are added to; typing filters it
+struct PlaylistPicker {
+    songs: Vec<usize>,
+    filter: String,
+    selected: usize,
+}
 
-    let initial_dirs = vec![
-        PathBuf::from("C:/Users/lintr/AppData/Roaming/Python/Python312/Scripts"),
-    ];
+impl PlaylistPicker {
+    fn new(songs: Vec<usize>) -> Self {
+        PlaylistPicker { songs, filter: String::new(), selected: 0 }
+    }
 
-    let mut app = App {
-        player: MusicPlayer::new(&initial_dirs)?,
-        command_mode: false,
-        command_input: String::new(),
-        message: None,
-        search_mode: false,
-        search_input: String::new(),
-        selected_artist: None,
-    };
+    // Matching playlists, best match first, then None for "new playlist"
+    fn choices(&self, playlists: &[Playlist]) -> Vec<Option<usize>> {
+        let mut matches: Vec<(usize, usize)> = playlists.iter().enumerate()
+            .filter_map(|(i, playlist)| library::fuzzy_score(&playlist.name, &self.filter).map(|score| (i, score)))
+            .collect();
+        // Stable sort keeps alphabetical order among equal scores
+        matches.sort_by_key(|&(_, score)| std::cmp::Reverse(score));
+        matches.into_iter().map(|(i, _)| Some(i)).chain([None]).collect()
+    }
+}
 
-    let mut scroll_offset = 0;
-    let mut last_key_time = Instant::now();
-    let key_delay = Duration::from_millis(150); // 150ms delay between key presses
+// What a command palette entry does: press a key, show a view, run a
+// command, or start typing a command that needs an argument
+#[derive(Clone, Copy)]
+enum PaletteAction {
+    Key(KeyCode),
+    View(usize),
+    Command(&'static str),
+    Prompt(&'static str),
+}
 
-    loop {
-        terminal.draw(|f| {
-            // Create a more complex layout
-            let main_chunks = Layout::default()
-                .direction(Direction::Horizontal)
-                .constraints([
-                    Constraint::Percentage(70),
-                    Constraint::Percentage(30),
-                ])
-                .split(f.size());
-
-            let left_chunks = Layout::default()
-                .direction(Direction::Vertical)
-                .constraints([
-                    Constraint::Length(3),  // Title
-                    Constraint::Length(3),  // View mode tabs
-                    Constraint::Min(0),     // Main content
-                    Constraint::Length(3),  // Controls
-                ])
-                .split(main_chunks[0]);
-
-            let right_chunks = Layout::default()
-                .direction(Direction::Vertical)
-                .constraints([
-                    Constraint::Length(10), // Now Playing (increased height)
-                    Constraint::Min(0),     // Queue
-                ])
-                .split(main_chunks[1]);
-
-            // Render title
-            let title = Paragraph::new("Music Player")
-                .style(Style::default().fg(Color::Cyan))
-                .block(Block::default().borders(Borders::ALL));
-            f.render_widget(title, left_chunks[0]);
-
-            // Render view mode tabs
-            let view_modes = vec!["Songs", "Artists", "Albums", "Genres", "Queue", "Search"];
-            let tabs = Tabs::new(view_modes)
-                .select(match app.player.view_mode {
-                    ViewMode::AllSongs => 0,
-                    ViewMode::Artists => 1,
-                    ViewMode::Albums => 2,
-                    ViewMode::Genres => 3,
-                    ViewMode::Queue => 4,
-                    ViewMode::Search => 5,
-                })
-                .block(Block::default().borders(Borders::ALL))
-                .style(Style::default().fg(Color::White))
-                .highlight_style(Style::default().fg(Color::Cyan));
-            f.render_widget(tabs, left_chunks[1]);
-
-            // Render main content based on view mode
-            let content: Vec<ListItem> = match app.player.view_mode {
-                ViewMode::AllSongs => app.player.songs.iter().enumerate()
-                    .map(|(i, song)| {
-                        let style = if i == app.player.current_index {
-                            Style::default().fg(Color::Green).add_modifier(Modifier::BOLD)
-                        } else {
-                            Style::default().fg(Color::White)
-                        };
-                        ListItem::new(song.title.clone()).style(style)
-                    })
-                    .collect(),
-                ViewMode::Artists => {
-                    if let Some(selected_artist) = &app.selected_artist {
-                        // Show songs by selected artist
-                        app.player.songs.iter().enumerate()
-                            .filter(|(_, song)| &song.artist == selected_artist)
-                            .map(|(i, song)| {
-                                let style = if i == app.player.current_index {
-                                    Style::default().fg(Color::Green).add_modifier(Modifier::BOLD)
-                                } else {
-                                    Style::default().fg(Color::White)
-                                };
-                                ListItem::new(song.title.clone()).style(style)
-                            })
-                            .collect()
-                    } else {
-                        // Show list of artists
-                        let mut artists: Vec<_> = app.player.songs.iter()
-                            .map(|song| &song.artist)
-                            .collect();
-                        artists.sort();
-                        artists.dedup();
-                        artists.into_iter()
-                            .map(|artist| ListItem::new(artist.to_string()))
-                            .collect()
-                    }
-                },
-                ViewMode::Albums => {
-                    let mut albums: Vec<_> = app.player.songs.iter()
-                        .map(|song| (song.album.as_str(), song.artist.as_str()))
-                        .collect();
-                    albums.sort();
-                    albums.dedup();
-                    albums.into_iter()
-                        .map(|(album, artist)| {
-                            ListItem::new(format!("{} (by {})", album, artist))
-                        })
-                        .collect()
-                },
-                ViewMode::Genres => {
-                    let mut genres: Vec<_> = app.player.songs.iter()
-                        .map(|song| song.genre.as_str())
-                        .collect();
-                    genres.sort();
-                    genres.dedup();
-                    genres.into_iter()
-                        .map(|genre| ListItem::new(genre.to_string()))
-                        .collect()
-                },
-                ViewMode::Queue => app.player.queue.iter()
-                    .map(|&index| {
-                        let song = &app.player.songs[index];
-                        ListItem::new(format!("{} - {}", song.artist, song.title))
-                    })
-                    .collect(),
-                ViewMode::Search => {
-                    if !app.search_input.is_empty() {
-                        let current_index = app.player.current_index;
-                        app.player.search(&app.search_input)
-                            .into_iter()
-                            .map(|(i, song)| {
-                                let style = if i == current_index {
-                                    Style::default().fg(Color::Green).add_modifier(Modifier::BOLD)
-                                } else {
-                                    Style::default().fg(Color::White)
-                                };
-                                ListItem::new(format!("{} - {}", song.artist, song.title)).style(style)
-                            })
-                            .collect()
-                    } else {
-                        vec![]
-                    }
-                },
-            };
+// Everything the palette offers, in the order shown before anything is typed
+const PALETTE: &[(&str, PaletteAction)] = &[
+    ("Play/pause", PaletteAction::Key(KeyCode::Char('p'))),
+    ("Next track", PaletteAction::Key(KeyCode::Char('l'))),
+    ("Previous track", PaletteAction::Key(KeyCode::Char('h'))),
+    ("Add to queue", PaletteAction::Key(KeyCode::Char('a'))),
+    ("Pin or unpin", PaletteAction::Key(KeyCode::Char('f'))),
+    ("Shuffle", PaletteAction::Key(KeyCode::Char('s'))),
+    ("Cycle repeat", PaletteAction::Key(KeyCode::Char('r'))),
+    ("Seek back", PaletteAction::Key(KeyCode::Left)),
+    ("Seek forward", PaletteAction::Key(KeyCode::Right)),
+    ("Volume up", PaletteAction::Key(KeyCode::Char('+'))),
+    ("Volume down", PaletteAction::Key(KeyCode::Char('-'))),
+    ("Mute or unmute", PaletteAction::Key(KeyCode::Char('m'))),
+    ("Toggle night mode", PaletteAction::Key(KeyCode::Char('N'))),
+    ("Toggle mono", PaletteAction::Key(KeyCode::Char('M'))),
+    ("Search", PaletteAction::Key(KeyCode::Char('/'))),
+    ("Show songs", PaletteAction::View(0)),
+    ("Show artists", PaletteAction::View(1)),
+    ("Show albums", PaletteAction::View(2)),
+    ("Show genres", PaletteAction::View(3)),
+    ("Show queue", PaletteAction::View(4)),
+    ("Show search results", PaletteAction::View(5)),
+    ("Show playlists", PaletteAction::View(6)),
+    ("Show folders", PaletteAction::View(7)),
+    ("Toggle lyrics", PaletteAction::Key(KeyCode::Char('L'))),
+    ("Toggle sidebar", PaletteAction::Key(KeyCode::Char('z'))),
+    ("Cycle time display", PaletteAction::Key(KeyCode::Char('t'))),
+    ("Cycle library sort", PaletteAction::Key(KeyCode::Char('S'))),
+    ("Copy artist and title", PaletteAction::Key(KeyCode::Char('y'))),
+    ("Copy file path", PaletteAction::Key(KeyCode::Char('Y'))),
+    ("Set trim start", PaletteAction::Key(KeyCode::Char('['))),
+    ("Set trim end", PaletteAction::Key(KeyCode::Char(']'))),
+    ("Clear trim", PaletteAction::Command("untrim")),
+    ("Rate track", PaletteAction::Prompt("rate ")),
+    ("Reveal in file manager", PaletteAction::Command("reveal")),
+    ("List queues", PaletteAction::Command("queue")),
+    ("Switch queue", PaletteAction::Prompt("queue ")),
+    ("Export queue tracklist", PaletteAction::Prompt("queue export md ")),
+    ("Copy session setlist", PaletteAction::Command("setlist")),
+    ("Export session setlist", PaletteAction::Prompt("setlist ")),
+    ("Preload next track", PaletteAction::Command("preload")),
+    ("Preload queue entry", PaletteAction::Prompt("preload ")),
+    ("New browse tab", PaletteAction::Command("tabnew")),
+    ("Close browse tab", PaletteAction::Command("tabclose")),
+    ("Add music directory", PaletteAction::Prompt("add ")),
+    ("Rescan library", PaletteAction::Command("rescan")),
+    ("Analyze tracks", PaletteAction::Command("analyze")),
+    ("Find duplicates", PaletteAction::Command("duplicates")),
+    ("Find broken playlist entries", PaletteAction::Command("broken")),
+    ("Verify listed files", PaletteAction::Command("verify")),
+    ("Listening stats", PaletteAction::Command("stats")),
+    ("Find artist name variants", PaletteAction::Command("artists")),
+    ("Merge artist name variants", PaletteAction::Command("artists merge")),
+    ("Find tags in the wrong encoding", PaletteAction::Command("encoding")),
+    ("Fix tags in the wrong encoding", PaletteAction::Command("encoding write")),
+    ("Webhook deliveries", PaletteAction::Command("webhooks")),
+    ("Import playlist", PaletteAction::Prompt("import ")),
+    ("Karaoke", PaletteAction::Command("karaoke")),
+    ("Big now playing", PaletteAction::Command("big")),
+    ("Perform with cue points", PaletteAction::Command("perform")),
+    ("Decks", PaletteAction::Command("decks")),
+    ("Quit", PaletteAction::Key(KeyCode::Char('q'))),
+];
 
-            // Clear the main content area before rendering the list
-            let clear_block = Block::default()
-                .borders(Borders::ALL)
-                .title("Songs");
-            f.render_widget(clear_block, left_chunks[2]);
-
-            // Render the list with proper styling
-            let content_list = List::new(content)
-                .block(Block::default().borders(Borders::ALL))
-                .highlight_style(Style::default().add_modifier(Modifier::BOLD))
-                .highlight_symbol(">> ");
-
-            let mut state = ListState::default();
-            state.select(Some(scroll_offset));
-            f.render_stateful_widget(content_list, left_chunks[2], &mut state);
-
-            // Render Now Playing with proper formatting
-            let now_playing = if let Some(song) = app.player.songs.get(app.player.current_index) {
-                vec![
-                    Line::from(""),
-                    //Line::from(vec![Span::raw("Now Playing:")]),
-                    //Line::from(""),
-                    Line::from(vec![Span::raw(format!("Title: {}", song.title))]),
-                    Line::from(vec![Span::raw(format!("Artist: {}", song.artist))]),
-                    Line::from(vec![Span::raw(format!("Album: {}", song.album))]),
-                    Line::from(vec![Span::raw(format!("Genre: {}", song.genre))]),
-                    Line::from(""),
-                    Line::from(vec![Span::raw(format!("Status: {}", 
-                        if app.player.is_playing { "Playing" } else { "Paused" }
-                    ))]),
-                ]
-            } else {
-                vec![
-                    Line::from(""),
-                    Line::from(vec![Span::raw("Nothing playing")]),
-                ]
-            };
+// Ctrl-P popup listing every action with its key; typing filters it
+#[derive(Default)]
+struct Palette {
+    filter: String,
+    selected: usize,
+}
 
-            let now_playing_widget = Paragraph::new(now_playing)
-                .block(Block::default().borders(Borders::ALL).title("Now Playing"))
-                .style(Style::default().fg(Color::Green))
-                .alignment(Alignment::Left);
-            f.render_widget(now_playing_widget, right_chunks[0]);
-
-            // Render Queue
-            let queue_items: Vec<ListItem> = app.player.queue.iter()
-                .map(|&index| {
-                    let song = &app.player.songs[index];
-                    ListItem::new(format!("{} - {}", song.artist, song.title))
-                })
-                .collect();
+impl Palette {
+    // Indices into PALETTE that match, best match first
+    fn choices(&self) -> Vec<usize> {
+        let mut matches: Vec<(usize, usize)> = PALETTE.iter().enumerate()
+            .filter_map(|(i, (label, _))| library::fuzzy_score(label, &self.filter).map(|score| (i, score)))
+            .collect();
+        matches.sort_by_key(|&(_, score)| std::cmp::Reverse(score));
+        matches.into_iter().map(|(i, _)| i).collect()
+    }
+}
 
-            let queue_list = List::new(queue_items)
-                .block(Block::default().borders(Borders::ALL).title("Queue"));
-            f.render_widget(queue_list, right_chunks[1]);
+// Config key names: a single character or F1-F12
+fn parse_key(name: &str) -> Option<KeyCode> {
+    let mut chars = name.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Some(KeyCode::Char(c)),
+        _ => {
+            let n = name.strip_prefix('F').or_else(|| name.strip_prefix('f'))?.parse().ok()?;
+            (1..=12).contains(&n).then_some(KeyCode::F(n))
+        }
+    }
+}
 
-            // Render controls
-            let controls = if app.search_mode {
-                Paragraph::new(format!("Search: {} (ESC to stop typing)", app.search_input))
-            } else {
-                Paragraph::new(vec![
-                    Line::from(vec![
-                        Span::raw("p: Play/Pause | "),
-                        Span::raw("h/l: Prev/Next | "),
-                        Span::raw("j/k: Move | "),
-                        Span::raw("-/+: Volume | "),
-                        Span::raw("s: Shuffle | "),
-                        Span::raw("a: Add to Queue | "),
-                        Span::raw("/: Search | "),
-                        Span::raw("Space: Select | "),
-                        Span::raw("Tab: Change View | "),
-                        Span::raw("q: Quit"),
-                    ])
-                ])
-            };
-            f.render_widget(controls.block(Block::default().borders(Borders::ALL)), left_chunks[3]);
-        })?;
+// The other way round, for showing bound keys
+fn key_name(key: KeyCode) -> String {
+    match key {
+        KeyCode::Char(' ') => String::from("Space"),
+        KeyCode::Char(c) => c.to_string(),
+        KeyCode::F(n) => format!("F{}", n),
+        KeyCode::Tab => String::from("Tab"),
+        KeyCode::BackTab => String::from("S-Tab"),
+        KeyCode::Enter => String::from("Enter"),
+        KeyCode::Esc => String::from("Esc"),
+        KeyCode::Backspace => String::from("Bksp"),
+        KeyCode::Up => String::from("↑"),
+        KeyCode::Down => String::from("↓"),
+        KeyCode::Left => String::from("←"),
+        KeyCode::Right => String::from("→"),
+        _ => String::from("?"),
+    }
+}
 
-        if event::poll(Duration::from_millis(100))? {
-            if let Event::Key(key) = event::read()? {
-                let now = Instant::now();
-                if now.duration_since(last_key_time) < key_delay {
-                    continue;
+// A deck's track through its EQ, which follows `filter` while it plays
+fn filtered(source: AudioSource, filter: Arc<AtomicU8>) -> impl Source<Item = f32> + Send {
+    let mut applied = Filter::Flat;
+    source
+        .convert_samples::<f32>()
+        .low_pass(20_000)
+        .periodic_access(Duration::from_millis(50), move |source| {
+            let wanted = Filter::from_u8(filter.load(Ordering::Relaxed));
+            if wanted != applied {
+                match wanted {
+                    Filter::Flat => source.to_low_pass(20_000),
+                    Filter::LowPass => source.to_low_pass(300),
+                    Filter::HighPass => source.to_high_pass(1_000),
                 }
-                last_key_time = now;
-
-                if app.command_mode {
-                    match key.code {
-                        KeyCode::Enter => {
-                            let cmd = app.command_input.trim();
-                            if cmd.starts_with("add ") {
-                                let path = PathBuf::from(cmd.trim_start_matches("add "));
-                                match app.player.add_directory(path) {
-                                    Ok(_) => app.message = Some("Directory added successfully".to_string()),
-                                    Err(e) => app.message = Some(format!("Error: {}", e)),
+                applied = wanted;
+            }
+        })
+}
+
+// Opens the default device in stereo, or for passthrough with the most
+// speakers it offers, at its usual rate and sample format. Falls back to
+// rodio's choice, which is also what is used if the device can't say. None
+// if there is no device at all.
+fn open_output(mode: ChannelMode) -> Option<(OutputStream, OutputStreamHandle, Option<SupportedStreamConfig>)> {
+    let opened = rodio::cpal::default_host().default_output_device().and_then(|device| {
+        let default = device.default_output_config().ok()?;
+        let rate = default.sample_rate();
+        let config = device.supported_output_configs().ok()?
+            .filter(|range| range.sample_format() == default.sample_format() && range.channels() <= 8)
+            .filter(|range| range.min_sample_rate() <= rate && rate <= range.max_sample_rate())
+            .map(|range| range.with_sample_rate(rate))
+            .filter(|config| mode == ChannelMode::Passthrough || config.channels() == 2)
+            .max_by_key(|config| config.channels())
+            .unwrap_or(default);
+        let (stream, handle) = OutputStream::try_from_device_config(&device, config.clone()).ok()?;
+        Some((stream, handle, Some(config)))
+    });
+    opened.or_else(|| {
+        let (stream, handle) = OutputStream::try_default().ok()?;
+        let config = rodio::cpal::default_host()
+            .default_output_device()
+            .and_then(|device| device.default_output_config().ok());
+        Some((stream, handle, config))
+    })
+}
+
+// A sink on the device, if there is one that takes it
+fn new_sink(handle: Option<&OutputStreamHandle>) -> Result<Sink, String> {
+    let handle = handle.ok_or_else(|| String::from("No output device; waiting for one"))?;
+    Sink::try_new(handle).map_err(|e| format!("Output device: {}", e))
+}
+
+// Tracks are converted to what the stream was opened with beforehand. Float
+// and 24/32-bit devices round far below anything audible, so only 16-bit
+// ones are dithered for.
+fn device_format(config: Option<&SupportedStreamConfig>) -> (Option<DeviceFormat>, bool) {
+    let device = config.map(|config| DeviceFormat { rate: config.sample_rate().0, channels: config.channels() });
+    let sixteen_bit = config.is_some_and(|config| matches!(config.sample_format(), SampleFormat::I16 | SampleFormat::U16));
+    (device, sixteen_bit)
+}
+
+impl MusicPlayer {
+    // `listeners` are sockets systemd passed for the HTTP API; see `systemd::listeners`
+    fn new(music_dirs: &[PathBuf], config: &Config, listeners: Vec<TcpListener>, events: Sender<Action>) -> Result<Self> {
+        let music_dirs: Vec<PathBuf> = music_dirs.iter().map(|dir| canonical_path(dir)).collect();
+        let playlist_dir = config.playlist_dir();
+
+        let (tx, rx) = mpsc::channel();
+        let _player_tx = tx.clone();
+
+        // Audio playback thread
+        let audio_events = events.clone();
+        let buffer = Arc::new(Mutex::new(None));
+        let audio_buffer = Arc::clone(&buffer);
+        let level = meter::level();
+        let audio_level = level.clone();
+        let night = compressor::night(config.output.night.threshold_db, config.output.night.ratio);
+        let audio_night = night.clone();
+        let channel_mode = config.output.channels;
+        thread::spawn(move || {
+            // None until a device turns up
+            let (mut _stream, mut stream_handle, device_config) = match open_output(channel_mode) {
+                Some((stream, handle, config)) => (Some(stream), Some(handle), config),
+                None => (None, None, None),
+            };
+            let mut sink: Option<Sink> = None;
+            let mut current_volume = 1.0;
+            let mut muted = false;
+            // Polled by whatever the main sink plays, like the fade target
+            let mono = MonoSwitch::default();
+            // Shared by whatever the main sink plays
+            let fade_target = fade::target(1.0);
+            // Applied by the dither stage instead of the sink while dithering
+            let gain = dither::gain(1.0);
+            let mut dithering = false;
+            // Deck A and B, each with the filter setting its source polls
+            let mut decks: [Option<(Sink, Arc<AtomicU8>)>; 2] = [None, None];
+            let mut output = OutputConfig::default();
+            let (mut device, mut sixteen_bit) = device_format(device_config.as_ref());
+            let mut stall = Stall::default();
+            // Where the track was when the device went, and when another was
+            // last looked for
+            let mut lost: Option<(Duration, Option<Instant>)> = None;
+            // Without one at the start, another is looked for as if it had gone
+            if stream_handle.is_none() {
+                let _ = audio_events.send(Action::Player(PlayerEvent::DeviceLost));
+                lost = Some((Duration::ZERO, Some(Instant::now())));
+            }
+            let mut progress: Option<Progress> = None;
+            let mut preloads: Vec<Preload> = Vec::new();
+            // What follows the playing track, until it's appended
+            let mut following: Option<(PathBuf, Duration, f32)> = None;
+            let mut appended: Option<Appended> = None;
+
+            let mut next_report = Instant::now() + POSITION_INTERVAL;
+            loop {
+                let msg = match rx.recv_timeout(next_report.saturating_duration_since(Instant::now())) {
+                    Ok(msg) => Some(msg),
+                    Err(RecvTimeoutError::Timeout) => None,
+                    Err(RecvTimeoutError::Disconnected) => break,
+                };
+                // Due whether or not a message came in, so a steady stream of
+                // them (a held volume key, the crossfader) can't hold it off
+                if Instant::now() >= next_report {
+                    next_report = Instant::now() + POSITION_INTERVAL;
+                    // The device has gone on into the appended track
+                    if let Some(next) = appended.take_if(|next| next.progress.played.load(Ordering::Relaxed) > 0) {
+                        *audio_buffer.lock().unwrap() = next.buffer;
+                        let event = PlayerEvent::Advanced(next.path, next.progress.from, next.progress.length, next.stream);
+                        progress = Some(next.progress);
+                        let _ = audio_events.send(Action::Player(event));
+                    }
+                    let playing = sink.as_ref().is_some_and(|s| !s.is_paused() && !s.empty());
+                    if !playing {
+                        stall.reset();
+                    }
+                    let stalled = progress.as_ref()
+                        .filter(|current| playing && stall.check(current.played.load(Ordering::Relaxed), Instant::now(), DEVICE_STALL))
+                        .map(Progress::position);
+                    if let Some(position) = stalled {
+                        // Whatever was on the old stream goes with it
+                        if let Some(s) = sink.take() {
+                            s.stop();
+                        }
+                        decks = [None, None];
+                        *audio_buffer.lock().unwrap() = None;
+                        meter::reset(&audio_level);
+                        stall.reset();
+                        progress = None;
+                        following = None;
+                        appended = None;
+                        lost = Some((position, None));
+                    }
+                    if let Some((position, _)) = lost.filter(|(_, tried)| tried.is_none_or(|tried| tried.elapsed() >= DEVICE_RETRY)) {
+                        match open_output(channel_mode) {
+                            Some((new_stream, new_handle, config)) => {
+                                _stream = Some(new_stream);
+                                stream_handle = Some(new_handle);
+                                (device, sixteen_bit) = device_format(config.as_ref());
+                                lost = None;
+                                let _ = audio_events.send(Action::Player(PlayerEvent::DeviceRestored(position)));
+                            }
+                            None => {
+                                // Said the first time round only
+                                if stalled.is_some() {
+                                    let _ = audio_events.send(Action::Player(PlayerEvent::DeviceLost));
                                 }
-                            } else if cmd.starts_with("remove ") {
-                                if let Ok(index) = cmd.trim_start_matches("remove ").parse::<usize>() {
-                                    match app.player.remove_directory(index) {
-                                        Ok(_) => app.message = Some("Directory removed successfully".to_string()),
-                                        Err(e) => app.message = Some(format!("Error: {}", e)),
+                                lost = Some((position, Some(Instant::now())));
+                            }
+                        }
+                    }
+                    if let Some(current) = progress.as_ref().filter(|_| playing) {
+                        let position = current.position();
+                        let _ = audio_events.send(Action::Player(PlayerEvent::Position(position)));
+                        let ending = current.length.is_some_and(|length| position + GAPLESS_LEAD >= length);
+                        if let (Some(s), Some((path, from, track_gain)), true) = (&sink, following.as_ref(), ending) {
+                            // One that can't be opened is left to its Play, which says why
+                            if let Ok((source, stream, length, buffer)) =
+                                open_track(path, *from, dithering, &mut preloads, device, &output, &audio_events)
+                            {
+                                let source = Compressor::new(Mono::new(source, mono.clone()), audio_night.clone());
+                                // Straight on from the last one, so not faded in
+                                let source = Box::new(Fade::new(source, fade_target.clone(), 1.0, fade_length(&output)));
+                                let (source, progress) = metered(source, *from, length, &audio_level);
+                                let cancel = Arc::new(AtomicBool::new(false));
+                                let cancelled = cancel.clone();
+                                let source = source.stoppable().periodic_access(CANCEL_POLL, move |source| {
+                                    if cancelled.load(Ordering::Relaxed) {
+                                        source.stop();
                                     }
-                                }
+                                });
+                                append(s, Box::new(source), dithering, &gain, *track_gain);
+                                appended = Some(Appended { path: path.clone(), progress, stream, buffer, cancel });
                             }
-                            app.command_mode = false;
-                            app.command_input.clear();
+                            following = None;
                         }
-                        KeyCode::Esc => {
-                            app.command_mode = false;
-                            app.command_input.clear();
+                    } else if progress.is_some() && sink.as_ref().is_some_and(Sink::empty) {
+                        // Said once; a stop in between means it was never heard to the end
+                        progress = None;
+                        let _ = audio_events.send(Action::Player(PlayerEvent::Finished));
+                    }
+                    for preload in preloads.iter_mut().filter(|preload| !preload.reported) {
+                        let ready = preload.ready.lock().unwrap();
+                        if ready.as_ref().is_some_and(|(buffered, ..)| buffered.level().complete()) {
+                            drop(ready);
+                            preload.reported = true;
+                            let _ = audio_events.send(Action::Player(PlayerEvent::Preloaded(preload.path.clone())));
                         }
-                        KeyCode::Char(c) => {
-                            app.command_input.push(c);
+                    }
+                }
+                let Some(msg) = msg else {
+                    continue;
+                };
+                match msg {
+                    PlayerMessage::Play(path, from, track_gain) => {
+                        if let Some(s) = sink.take() {
+                            s.stop();
                         }
-                        KeyCode::Backspace => {
-                            app.command_input.pop();
+                        stall.reset();
+                        following = None;
+                        appended = None;
+                        dithering = output.dither && sixteen_bit;
+                        let opened = open_track(&path, from, dithering, &mut preloads, device, &output, &audio_events)
+                            .and_then(|track| Ok((track, new_sink(stream_handle.as_ref())?)));
+                        let event = match opened {
+                            Ok(((source, stream, length, buffer), new_sink)) => {
+                                *audio_buffer.lock().unwrap() = buffer;
+                                new_sink.set_volume(match (muted, dithering) {
+                                    (true, _) => 0.0,
+                                    (false, true) => 1.0,
+                                    (false, false) => current_volume,
+                                });
+                                let source = Compressor::new(Mono::new(source, mono.clone()), audio_night.clone());
+                                fade::set(&fade_target, 1.0);
+                                let source = Box::new(Fade::new(source, fade_target.clone(), 0.0, fade_length(&output)));
+                                let (source, started) = metered(source, from, length, &audio_level);
+                                progress = Some(started);
+                                append(&new_sink, source, dithering, &gain, track_gain);
+                                new_sink.play();
+                                sink = Some(new_sink);
+                                PlayerEvent::Started(length, stream)
+                            }
+                            Err(error) => PlayerEvent::Failed(error),
+                        };
+                        let _ = audio_events.send(Action::Player(event));
+                    }
+                    PlayerMessage::Gapless(next) => {
+                        // Appended already, but no longer what comes next
+                        if let Some(old) = appended.take_if(|old| next.as_ref().map(|(path, ..)| path) != Some(&old.path)) {
+                            old.cancel.store(true, Ordering::Relaxed);
                         }
-                        _ => {}
+                        following = if appended.is_some() { None } else { next };
                     }
-                } else {
-                    match key.code {
-                        KeyCode::Char('q') if !app.search_mode => {
-                            app.player._player_tx.send(PlayerMessage::Quit)?;
-                            break;
-                        },
-                        KeyCode::Char('s') if !app.search_mode => {
-                            app.player.shuffle();
-                        },
-                        KeyCode::Char('a') if !app.search_mode => {
-                            app.player.add_to_queue(scroll_offset);
-                            app.message = Some("Added to queue".to_string());
-                        },
-                        KeyCode::Char('p') if !app.search_mode => {
-                            if app.player.is_playing {
-                                app.player.stop();
-                            } else {
-                                app.player.play_current();
-                            }
-                        },
-                        KeyCode::Char('j') if !app.search_mode => {
-                            if scroll_offset < app.player.songs.len().saturating_sub(1) {
-                                scroll_offset += 1;
-                            }
-                        },
-                        KeyCode::Char('k') if !app.search_mode => {
-                            if scroll_offset > 0 {
-                                scroll_offset -= 1;
+                    PlayerMessage::Preload(path) => {
+                        if preloads.iter().any(|preload| preload.path == path) {
+                            continue;
+                        }
+                        let ready = Arc::new(Mutex::new(None));
+                        let slot = Arc::clone(&ready);
+                        let file = path.clone();
+                        let quality = output.resample_quality;
+                        let dither = output.dither && sixteen_bit;
+                        let seconds = output.read_ahead_secs.max(PRELOAD_SECS);
+                        // Opening may itself wait on a slow share, which
+                        // mustn't hold up the playing track
+                        thread::spawn(move || {
+                            if let (Some(source), _) = decode::open(&file) {
+                                let length = source.total_duration();
+                                let (source, mut stream) = resample::to_device(source, device, quality, dither);
+                                stream.codec = decode::codec(&file);
+                                *slot.lock().unwrap() = Some((ReadAhead::new(source, seconds), stream, length));
                             }
-                        },
-                        KeyCode::Char('h') if !app.search_mode => {
-                            app.player.previous();
-                            if app.player.current_index < scroll_offset {
-                                scroll_offset = app.player.current_index;
+                        });
+                        preloads.push(Preload { path, ready, dither, reported: false });
+                        if preloads.len() > MAX_PRELOADS {
+                            preloads.remove(0);
+                        }
+                    }
+                    PlayerMessage::SetVolume(vol) => {
+                        current_volume = vol;
+                        if muted {
+                            continue;
+                        }
+                        dither::set_gain(&gain, vol);
+                        if let Some(s) = sink.as_ref().filter(|_| !dithering) {
+                            s.set_volume(vol);
+                        }
+                    }
+                    PlayerMessage::Mute | PlayerMessage::Unmute => {
+                        muted = matches!(msg, PlayerMessage::Mute);
+                        let vol = if muted { 0.0 } else { current_volume };
+                        dither::set_gain(&gain, vol);
+                        if let Some(s) = &sink {
+                            s.set_volume(if dithering && !muted { 1.0 } else { vol });
+                        }
+                    }
+                    PlayerMessage::SetChannelMode(mix) => channels::set_mix(&mono, mix),
+                    PlayerMessage::Stop => {
+                        if let Some(s) = &sink {
+                            fade_out(s, &fade_target, &output);
+                            s.stop();
+                        }
+                        *audio_buffer.lock().unwrap() = None;
+                        meter::reset(&audio_level);
+                        progress = None;
+                        following = None;
+                        appended = None;
+                    }
+                    PlayerMessage::Pause => {
+                        if let Some(s) = &sink {
+                            fade_out(s, &fade_target, &output);
+                            s.pause();
+                        }
+                    }
+                    // Faded out when paused, so this fades back in
+                    PlayerMessage::Resume => {
+                        if let Some(s) = &sink {
+                            fade::set(&fade_target, 1.0);
+                            s.play();
+                        }
+                    }
+                    PlayerMessage::Configure(config) => output = config,
+                    PlayerMessage::LoadDeck(side, path) => {
+                        let (source, diagnostics) = decode::open(&path);
+                        match source.map(|source| (source, new_sink(stream_handle.as_ref()))) {
+                            Some((_, Err(error))) => {
+                                let _ = audio_events.send(Action::Player(PlayerEvent::DeckFailed(error)));
                             }
-                        },
-                        KeyCode::Char('l') if !app.search_mode => {
-                            app.player.next();
-                            if app.player.current_index > scroll_offset {
-                                scroll_offset = app.player.current_index;
+                            Some((source, Ok(new_sink))) => {
+                                let (source, _) = resample::to_device(source, device, output.resample_quality, false);
+                                let filter = Arc::new(AtomicU8::new(Filter::Flat.as_u8()));
+                                new_sink.pause();
+                                new_sink.append(filtered(source, filter.clone()));
+                                decks[side as usize] = Some((new_sink, filter));
                             }
-                        },
-                        KeyCode::Char(' ') if !app.search_mode => {
-                            match app.player.view_mode {
-                                ViewMode::Artists => {
-                                    if app.selected_artist.is_none() {
-                                        // Select artist
-                                        if let Some(artist) = app.player.songs.iter()
-                                            .map(|song| &song.artist)
-                                            .collect::<Vec<_>>()
-                                            .into_iter()
-                                            .nth(scroll_offset) {
-                                            app.selected_artist = Some(artist.to_string());
-                                            scroll_offset = 0;  // Reset scroll position for song list
-                                        }
-                                    } else {
-                                        // Select song from artist's songs
-                                        if let Some(selected_artist) = &app.selected_artist {
-                                            if let Some((index, _)) = app.player.songs.iter().enumerate()
-                                                .filter(|(_, song)| &song.artist == selected_artist)
-                                                .nth(scroll_offset) {
-                                                app.player.current_index = index;
-                                                app.player.play_current();
-                                            }
-                                        }
-                                    }
-                                },
-                                _ => {
-                                    app.player.current_index = scroll_offset;
-                                    app.player.play_current();
-                                }
+                            None => {
+                                let error = format!("Cannot load {}: {}", path.display(), diagnostics.first_error());
+                                let _ = audio_events.send(Action::Player(PlayerEvent::DeckFailed(error)));
                             }
-                        },
-                        KeyCode::Tab if !app.search_mode => {
-                            app.player.view_mode = match app.player.view_mode {
-                                ViewMode::AllSongs => ViewMode::Artists,
-                                ViewMode::Artists => ViewMode::Albums,
-                                ViewMode::Albums => ViewMode::Genres,
-                                ViewMode::Genres => ViewMode::Queue,
-                                ViewMode::Queue => ViewMode::Search,
-                                ViewMode::Search => ViewMode::AllSongs,
-                            };
-                        },
-                        KeyCode::Char('/') if !app.search_mode => {
-                            app.search_mode = true;
-                            app.player.view_mode = ViewMode::Search;
-                        },
-                        KeyCode::Esc => {
-                            if app.search_mode {
-                                app.search_mode = false;
-                            } else if app.player.view_mode == ViewMode::Search {
-                                app.search_input.clear();
-                                app.player.view_mode = ViewMode::AllSongs;
-                            } else if app.player.view_mode == ViewMode::Artists && app.selected_artist.is_some() {
-                                app.selected_artist = None;
-                                scroll_offset = 0;
+                        }
+                    }
+                    PlayerMessage::PlayDeck(side, play) => {
+                        if let Some((deck, _)) = &decks[side as usize] {
+                            if play {
+                                deck.play();
+                            } else {
+                                deck.pause();
                             }
-                        },
-                        KeyCode::Char(c) if app.search_mode => {
-                            app.search_input.push(c);
-                        },
-                        KeyCode::Backspace if app.search_mode => {
-                            app.search_input.pop();
-                        },
-                        KeyCode::Char('+') | KeyCode::Char('=') => app.player.set_volume(0.05),
-                        KeyCode::Char('-') => app.player.set_volume(-0.05),
-                        KeyCode::Char(':') => {
-                            app.command_mode = true;
-                            app.message = None;
                         }
-                        _ => {}
+                    }
+                    PlayerMessage::DeckGain(side, gain) => {
+                        if let Some((deck, _)) = &decks[side as usize] {
+                            deck.set_volume(gain);
+                        }
+                    }
+                    PlayerMessage::DeckFilter(side, setting) => {
+                        if let Some((_, filter)) = &decks[side as usize] {
+                            filter.store(setting.as_u8(), Ordering::Relaxed);
+                        }
+                    }
+                    PlayerMessage::StopDecks => decks = [None, None],
+                    PlayerMessage::Quit(done) => {
+                        if let Some(s) = &sink {
+                            fade_out(s, &fade_target, &output);
+                        }
+                        let _ = done.send(());
+                        break;
+                    }
+                    _ => {}
+                }
+            }
+        });
+
+        let mut player = MusicPlayer {
+            songs: Vec::new(),
+            playback: Playback::new(),
+            play_offset: Duration::ZERO,
+            playing_since: None,
+            paused_at: None,
+            _player_tx: tx,
+            music_dirs: music_dirs.clone(),
+            volume: 1.0,
+            muted: false,
+            mono: false,
+            setlist: Vec::new(),
+            view_mode: ViewMode::AllSongs,
+            search_query: String::new(),
+            search_results: Vec::new(),
+            search_scope: SearchScope::Library,
+            search_stale: false,
+            announcer: Announcer::new(config, listeners, events.clone())?,
+            scan_config: config.scan.clone(),
+            events,
+            scans_running: 0,
+            rescanning: false,
+            scan_found: 0,
+            scan_started: Instant::now(),
+            browse: BrowseIndex { genre_map: Genres::new(&config.genres), ..BrowseIndex::new(&config.browse) },
+            library_sort: None,
+            // See load_playlists
+            playlists: Vec::new(),
+            playlist_dir,
+            stats: Stats::load(),
+            tag_sync: config.tag_sync,
+            unsynced_tags: HashSet::new(),
+            analysis: Analysis::load(),
+            analyzing: 0,
+            output: config.output.clone(),
+            buffer,
+            level,
+            night,
+            aliases: HashMap::new(),
+            generation: 0,
+            hooked_queue: Vec::new(),
+            misencoded: 0,
+            copies: 0,
+            relinked: 0,
+            stream: None,
+            trims: Trims::load(),
+            queue_config: config.queue,
+            queues: Queues::load(),
+            queue_restored: false,
+            preloads: Vec::new(),
+            gapless: None,
+        };
+        player.playback.keep_played = config.queue.played == PlayedPolicy::Keep;
+        let _ = player._player_tx.send(PlayerMessage::Configure(config.output.clone()));
+        player.start_scan(music_dirs);
+        Ok(player)
+    }
+
+    fn start_scan(&mut self, dirs: Vec<PathBuf>) {
+        if dirs.is_empty() {
+            return;
+        }
+        if self.scans_running == 0 {
+            self.scan_found = 0;
+            self.scan_started = Instant::now();
+        }
+        self.scans_running += 1;
+        let known = self.songs.iter().map(|song| song.path.clone()).collect();
+        spawn_scan(dirs, self.scan_config.clone(), known, self.counted(), self.events.clone());
+    }
+
+    // Answered with Action::Measured
+    fn check_scan_limits(&self, dir: PathBuf) {
+        spawn_limit_check(dir, self.scan_config.clone(), self.events.clone());
+    }
+
+    // Moves songs found by background scans into the library, returning any scan error
+    fn handle_scan_event(&mut self, event: ScanEvent) -> Option<String> {
+        match event {
+            // A rescan that ran meanwhile may have added it already
+            ScanEvent::Found(song) if self.find_song(&song.path).is_some() => {}
+            ScanEvent::Found(mut song) => {
+                artists::apply(&mut song, &self.aliases);
+                self.browse.genre_map.apply(&mut song);
+                if mojibake::needs_repair(&song) {
+                    self.misencoded += 1;
+                }
+                self.browse.insert(self.songs.len(), &song);
+                self.songs.push(*song);
+                self.sync_song_stats(self.songs.len() - 1);
+                self.load_analysis(self.songs.len() - 1);
+                self.search_stale = true;
+                self.scan_found += 1;
+            }
+            ScanEvent::Copy => self.copies += 1,
+            ScanEvent::Moved(gone, hashes) => {
+                let mut found: Vec<PathBuf> = hashes.keys().cloned().collect();
+                found.sort();
+                self.relinked += self.stats.relink(&found, |path| !gone.contains(path), |path| hashes.get(path).copied());
+            }
+            ScanEvent::Rescanned(rescan) => return self.apply_rescan(*rescan),
+            ScanEvent::RescanFailed(e) => {
+                self.rescanning = false;
+                return Some(format!("Error: {}", e));
+            }
+            ScanEvent::Failed(dir, e) => {
+                return Some(format!("Error scanning {}: {}", dir.display(), e));
+            }
+            ScanEvent::Finished => {
+                self.scans_running -= 1;
+                if !self.is_scanning() {
+                    self.apply_library_sort();
+                }
+                // The saved queue can be found in the library once it's all there
+                if !self.is_scanning() && !self.queue_restored {
+                    self.queue_restored = true;
+                    let saved = self.queue_indices(&self.queues.active);
+                    self.playback.queue = saved.into_iter().chain(self.playback.queue.drain(..)).collect();
+                }
+                if !self.is_scanning() {
+                    let (took, songs) = (self.scan_started.elapsed(), self.songs.len());
+                    self.announcer.update_metrics(|metrics| {
+                        metrics.last_scan = Some(took);
+                        metrics.songs = songs;
+                    });
+                }
+                if let Err(e) = self.save_stats() {
+                    return Some(format!("Error saving play counts: {}", e));
+                }
+                // Tags for the plays can be looked up now the library is all there
+                if !self.is_scanning() && self.announcer.serves_history() {
+                    let entries = self.stats.history.iter()
+                        .map(|play| history::Entry::new(play, self.find_song(&play.path).map(|index| &self.songs[index])))
+                        .collect();
+                    self.announcer.set_history(entries);
+                }
+                if !self.is_scanning() {
+                    let notes: Vec<String> = [
+                        (std::mem::take(&mut self.copies), "copies of files already in the library left out"),
+                        (std::mem::take(&mut self.relinked), "moved files took their ratings and play counts along"),
+                        (std::mem::take(&mut self.misencoded), "songs have tags in the wrong encoding, see :encoding"),
+                    ].into_iter()
+                        .filter(|&(count, _)| count > 0)
+                        .map(|(count, note)| format!("{} {}", count, note))
+                        .collect();
+                    if !notes.is_empty() {
+                        return Some(notes.join("; "));
                     }
                 }
             }
         }
+        None
     }
 
-    // Cleanup
-    disable_raw_mode()?;
-    execute!(terminal.backend_mut(), LeaveAlternateScreen)?;
-    Ok(())
+    // Renames songs already in the library as well as those still to come.
+    // Names aliased away stay so until the player restarts.
+    fn add_aliases(&mut self, aliases: impl IntoIterator<Item = (String, String)>) {
+        let before = self.aliases.len();
+        for (variant, name) in aliases {
+            if variant != name {
+                self.aliases.insert(variant, name);
+            }
+        }
+        if self.aliases.len() == before {
+            return;
+        }
+        let mut changed = false;
+        for song in &mut self.songs {
+            changed |= artists::apply(song, &self.aliases);
+        }
+        if changed {
+            self.browse.rebuild(&self.songs);
+            self.search_stale = true;
+            self.apply_library_sort();
+        }
+    }
+
+    // Like `add_aliases`, genres renamed by the old config stay so until the
+    // player restarts
+    fn set_genres(&mut self, genres: Genres) {
+        if genres == self.browse.genre_map {
+            return;
+        }
+        for song in &mut self.songs {
+            genres.apply(song);
+        }
+        self.browse.genre_map = genres;
+        self.browse.rebuild(&self.songs);
+        self.search_stale = true;
+    }
+
+    // Shows the tags of every song as `mojibake::repair` reads them, for
+    // this session; returns how many changed
+    fn repair_encodings(&mut self) -> usize {
+        let mut repaired = 0;
+        for song in &mut self.songs {
+            if mojibake::repair_song(song) {
+                artists::apply(song, &self.aliases);
+                self.browse.genre_map.apply(song);
+                repaired += 1;
+            }
+        }
+        if repaired > 0 {
+            self.browse.rebuild(&self.songs);
+            self.search_stale = true;
+            self.apply_library_sort();
+        }
+        repaired
+    }
+
+    fn is_scanning(&self) -> bool {
+        self.scans_running > 0
+    }
+
+    fn play_index(&mut self, index: usize) {
+        let effect = self.playback.play(index, self.songs.len());
+        self.apply(effect);
+    }
+
+    fn toggle_playback(&mut self) {
+        let effect = self.playback.toggle(self.songs.len(), self.position());
+        self.apply(effect);
+    }
+
+    fn seek(&mut self, position: Duration) {
+        let effect = self.playback.seek(self.songs.len(), position);
+        self.apply(effect);
+    }
+
+    // Jumps back or forward within the track, playing or paused. The audio
+    // side reopens it at the new place; past the end moves on to the next.
+    fn seek_by(&mut self, delta: Duration, forward: bool) {
+        let position = match self.playback.state {
+            PlaybackState::Paused { position } => position,
+            PlaybackState::Playing | PlaybackState::Transitioning => self.position(),
+            PlaybackState::Stopped => return,
+        };
+        let target = if forward { position + delta } else { position.saturating_sub(delta) };
+        let length = self.songs.get(self.playback.current).and_then(|song| song.duration);
+        if length.is_some_and(|length| target >= length) {
+            self.next();
+        } else {
+            self.seek(target);
+        }
+    }
+
+    fn next(&mut self) {
+        self.hook_skip();
+        self.advance();
+    }
+
+    fn advance(&mut self) {
+        let effect = self.playback.next(self.songs.len());
+        self.apply(effect);
+    }
+
+    fn previous(&mut self) {
+        self.hook_skip();
+        let effect = self.playback.previous(self.songs.len());
+        self.apply(effect);
+    }
+
+    fn hook_skip(&self) {
+        if self.playback.is_active() {
+            self.hook(webhooks::Event::Skip);
+        }
+    }
+
+    // Moves on once the playing track reaches where it was trimmed to end
+    fn skip_outro(&mut self) {
+        if self.playback.state != PlaybackState::Playing {
+            return;
+        }
+        let Some(song) = self.songs.get(self.playback.current) else {
+            return;
+        };
+        if self.trims.get(&song.path).end().is_some_and(|end| self.position() >= end) {
+            self.advance();
+        }
+    }
+
+    // Tells the audio thread what to append to the playing track when that
+    // changes. One that ends at a trim point is moved on from by `skip_outro`.
+    fn sync_gapless(&mut self) {
+        let trimmed = self.songs.get(self.playback.current)
+            .is_some_and(|song| self.trims.get(&song.path).end().is_some());
+        let next = self.playback.upcoming(self.songs.len())
+            .filter(|_| self.output.gapless && self.playback.is_active() && !trimmed)
+            .map(|index| {
+                let path = self.songs[index].path.clone();
+                let from = self.trims.get(&path).start().unwrap_or_default();
+                (path, from, self.songs[index].replay_gain.factor(self.output.replay_gain))
+            });
+        if next != self.gapless {
+            let _ = self._player_tx.send(PlayerMessage::Gapless(next.clone()));
+            self.gapless = next;
+        }
+    }
+
+    // Carries out what the state machine decided
+    fn apply(&mut self, effect: Option<Effect>) {
+        match effect {
+            Some(Effect::Pause) => {
+                let _ = self._player_tx.send(PlayerMessage::Pause);
+                if let PlaybackState::Paused { position } = self.playback.state {
+                    self.play_offset = position;
+                    self.paused_at = Some(position);
+                }
+                self.playing_since = None;
+                self.announce();
+            }
+            // Still paused where it was left; a seek since means opening it again
+            Some(Effect::Resume { from, .. }) if self.paused_at == Some(from) => {
+                let _ = self._player_tx.send(PlayerMessage::Resume);
+                self.paused_at = None;
+                self.playback.started();
+                self.playing_since = Some(Instant::now());
+                self.announce();
+            }
+            Some(Effect::Play { index, from }) | Some(Effect::Resume { index, from }) => {
+                // Played from the top means from where it was trimmed to start
+                let trim = self.trims.get(&self.songs[index].path);
+                let from = if from.is_zero() { trim.start().unwrap_or(from) } else { from };
+                let path = self.songs[index].path.clone();
+                // Taken by the audio thread whether or not it was ready
+                self.preloads.retain(|(preloaded, _)| *preloaded != path);
+                let gain = self.songs[index].replay_gain.factor(self.output.replay_gain);
+                let _ = self._player_tx.send(PlayerMessage::Play(path, from, gain));
+                // A new Play forgets what was to follow
+                self.gapless = None;
+                self.play_offset = from;
+                self.playing_since = None;
+                self.paused_at = None;
+            }
+            Some(Effect::Stop) => {
+                let _ = self._player_tx.send(PlayerMessage::Stop);
+                self.gapless = None;
+                self.playing_since = None;
+                self.paused_at = None;
+                self.stream = None;
+                self.announce();
+                self.hook(webhooks::Event::Stop);
+            }
+            None => {}
+        }
+    }
+
+    // Returns an error message when the track could not be played
+    fn handle_player_event(&mut self, event: PlayerEvent) -> Option<String> {
+        match event {
+            PlayerEvent::Started(length, stream) => {
+                self.playback.started();
+                self.track_started(length, stream)
+            }
+            PlayerEvent::Advanced(path, from, length, stream) => {
+                self.gapless = None;
+                match self.browse.paths.get(&path).copied() {
+                    Some(index) if self.playback.advanced(self.songs.len(), index) => {
+                        self.play_offset = from;
+                        self.track_started(length, stream)
+                    }
+                    // Not what comes next any more; played as if the last one ended
+                    _ => {
+                        let effect = self.playback.finished(self.songs.len());
+                        self.apply(effect);
+                        None
+                    }
+                }
+            }
+            PlayerEvent::Failed(e) => {
+                self.announcer.update_metrics(|metrics| metrics.decode_errors += 1);
+                self.playback.failed();
+                self.stream = None;
+                self.announce();
+                Some(e)
+            }
+            PlayerEvent::DeckFailed(e) => Some(e),
+            PlayerEvent::DeviceLost => Some(String::from("No output device; waiting for one")),
+            // Picked up where the old device left off, unless stopped since
+            PlayerEvent::DeviceRestored(_) if self.playback.state == PlaybackState::Stopped => {
+                Some(String::from("Output device found"))
+            }
+            PlayerEvent::DeviceRestored(position) => {
+                self.seek(position);
+                Some(format!("Output device changed; resumed at {}", format_duration(position)))
+            }
+            // Timed from here until the next report. One that crosses a pause
+            // or a newly started track is out of date.
+            PlayerEvent::Position(position) => {
+                if self.playback.state == PlaybackState::Playing {
+                    self.play_offset = position;
+                    self.playing_since = Some(Instant::now());
+                }
+                None
+            }
+            PlayerEvent::Finished => {
+                let effect = self.playback.finished(self.songs.len());
+                self.apply(effect);
+                None
+            }
+            PlayerEvent::Preloaded(path) => {
+                if let Some((_, ready)) = self.preloads.iter_mut().find(|(preloaded, _)| *preloaded == path) {
+                    *ready = true;
+                }
+                None
+            }
+            PlayerEvent::Decoded(path, diagnostics) => {
+                let song = self.songs.get(*self.browse.paths.get(&path)?)?;
+                if self.analysis.record_decode(song, diagnostics) {
+                    self.analysis.save().err().map(|e| format!("Error saving analysis: {}", e))
+                } else {
+                    None
+                }
+            }
+        }
+    }
+
+    // The current track has reached the device
+    fn track_started(&mut self, length: Option<Duration>, stream: StreamInfo) -> Option<String> {
+        // Tags don't always say how long a track is
+        if let Some(song) = self.songs.get_mut(self.playback.current) {
+            song.duration = song.duration.or(length);
+        }
+        self.playing_since = Some(Instant::now());
+        self.stream = Some(stream);
+        self.announce();
+        self.hook(webhooks::Event::Start);
+        // Resuming from a position isn't another play
+        let start = self.songs.get(self.playback.current).and_then(|song| self.trims.get(&song.path).start());
+        if self.play_offset == start.unwrap_or_default() {
+            self.count_play();
+            if let Some(song) = self.songs.get(self.playback.current) {
+                self.setlist.push((Instant::now(), song.artist.clone(), song.title.clone()));
+            }
+        }
+        if self.output.preload_next {
+            if let Some(next) = self.playback.upcoming(self.songs.len()) {
+                self.preload(next);
+            }
+        }
+        self.save_stats().err().map(|e| format!("Error saving play counts: {}", e))
+    }
+
+    // Has the audio thread open and buffer a song before it's played, dropping
+    // the oldest preload past MAX_PRELOADS as the audio thread does
+    fn preload(&mut self, index: usize) {
+        let Some(path) = self.songs.get(index).map(|song| song.path.clone()) else {
+            return;
+        };
+        if self.preloads.iter().any(|(preloaded, _)| *preloaded == path) {
+            return;
+        }
+        let _ = self._player_tx.send(PlayerMessage::Preload(path.clone()));
+        self.preloads.push((path, false));
+        if self.preloads.len() > MAX_PRELOADS {
+            self.preloads.remove(0);
+        }
+    }
+
+    // Whether what plays next has been buffered, if it's being preloaded
+    fn next_buffered(&self) -> Option<bool> {
+        let song = self.songs.get(self.playback.upcoming(self.songs.len())?)?;
+        self.preloads.iter().find(|(path, _)| *path == song.path).map(|&(_, ready)| ready)
+    }
+
+    fn count_play(&mut self) {
+        let Some(path) = self.songs.get(self.playback.current).map(|song| song.path.clone()) else {
+            return;
+        };
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs());
+        let stats = self.stats.songs.entry(path.clone()).or_default();
+        stats.plays += 1;
+        stats.last_played = Some(now);
+        let play = Play { path: path.clone(), at: now };
+        self.announcer.update_metrics(|metrics| metrics.tracks_played += 1);
+        if self.announcer.serves_history() {
+            self.announcer.record_play(history::Entry::new(&play, self.songs.get(self.playback.current)));
+        }
+        self.stats.history.push(play);
+        self.fingerprint(&path);
+        self.mark_unsynced(path);
+    }
+
+    // `None` clears the rating
+    fn rate(&mut self, index: usize, rating: Option<u8>) -> Result<()> {
+        let path = self.songs.get(index).ok_or_else(|| anyhow::anyhow!("No such song"))?.path.clone();
+        self.stats.songs.entry(path.clone()).or_default().rating = rating;
+        self.fingerprint(&path);
+        self.mark_unsynced(path);
+        self.save_stats()
+    }
+
+    // Hashed once it has numbers worth keeping, so a scan can find the file
+    // again if it's moved. The whole file is read, so on a worker thread;
+    // the hash comes back as Action::Hashed.
+    fn fingerprint(&mut self, path: &Path) {
+        if !self.stats.hashes.contains_key(path) {
+            spawn_hash(path.to_path_buf(), self.events.clone());
+        }
+    }
+
+    fn mark_unsynced(&mut self, path: PathBuf) {
+        if self.tag_sync.enabled && stats::has_id3(&path) {
+            self.unsynced_tags.insert(path);
+        }
+    }
+
+    // Brings the player's numbers for a freshly read song together with its tag's
+    fn sync_song_stats(&mut self, index: usize) {
+        let song = &self.songs[index];
+        if !self.tag_sync.enabled || !stats::has_id3(&song.path) {
+            return;
+        }
+        let tag = song.tag_stats.unwrap_or_default();
+        let mut entry = self.stats.get(&song.path);
+        // Nothing on either side; not worth an entry
+        if entry == SongStats::default() && tag == TagStats::default() {
+            return;
+        }
+        let path = song.path.clone();
+        if stats::reconcile(&mut entry, tag, self.tag_sync.conflicts).is_some() {
+            self.unsynced_tags.insert(path.clone());
+        }
+        self.stats.songs.insert(path, entry);
+    }
+
+    fn load_analysis(&mut self, index: usize) {
+        let song = &mut self.songs[index];
+        song.dr = self.analysis.get(song).and_then(|analysis| analysis.dr);
+    }
+
+    // Decodes every track not measured yet on a background thread, one at a time
+    fn start_analysis(&mut self) -> String {
+        if self.analyzing > 0 {
+            return format!("Already analyzing, {} tracks left", self.analyzing);
+        }
+        let pending: Vec<(PathBuf, Option<SystemTime>)> = self.songs.iter()
+            .filter(|song| self.analysis.get(song).is_none())
+            .map(|song| (song.path.clone(), song.modified))
+            .collect();
+        if pending.is_empty() {
+            return "Every track has been analyzed".to_string();
+        }
+        self.analyzing = pending.len();
+        let events = self.events.clone();
+        thread::spawn(move || {
+            let mut remaining = pending.len();
+            for (path, modified) in pending {
+                remaining -= 1;
+                let result = analysis::analyze(&path, modified);
+                if events.send(Action::Analyzed(path, result, remaining)).is_err() {
+                    break;
+                }
+            }
+        });
+        format!("Analyzing {} tracks", self.analyzing)
+    }
+
+    // Returns the message to show
+    fn handle_analyzed(&mut self, path: PathBuf, mut result: TrackAnalysis, remaining: usize) -> String {
+        if let Some(&index) = self.browse.paths.get(&path) {
+            self.songs[index].dr = result.dr;
+            // Playback failures stay counted while the file is unchanged
+            if let Some(known) = self.analysis.get(&self.songs[index]) {
+                result.failures = known.failures;
+            }
+        }
+        self.analysis.songs.insert(path, result);
+        self.analyzing = remaining;
+        if remaining > 0 {
+            return format!("Analyzing: {} tracks left", remaining);
+        }
+        match self.analysis.save() {
+            Ok(()) => "Analysis finished".to_string(),
+            Err(e) => format!("Error saving analysis: {}", e),
+        }
+    }
+
+    // Writes the tags that are behind, except the open file's, then the stats file
+    fn save_stats(&mut self) -> Result<()> {
+        let playing = (self.playback.state != PlaybackState::Stopped)
+            .then(|| self.songs.get(self.playback.current).map(|song| song.path.clone()))
+            .flatten();
+        let ready: Vec<PathBuf> = self.unsynced_tags.iter()
+            .filter(|path| Some(*path) != playing.as_ref())
+            .cloned()
+            .collect();
+        let mut result = Ok(());
+        for path in ready {
+            self.unsynced_tags.remove(&path);
+            let Some(entry) = self.stats.songs.get_mut(&path) else {
+                continue;
+            };
+            match stats::write_popm(&path, entry.values()) {
+                Ok(()) => entry.synced = Some(entry.values()),
+                Err(e) => result = Err(anyhow::anyhow!("{}: {}", path.display(), e)),
+            }
+        }
+        self.stats.save()?;
+        result
+    }
+
+    // Approximate, measured from when the audio thread reported the track started
+    fn position(&self) -> Duration {
+        self.play_offset + self.playing_since.map(|since| since.elapsed()).unwrap_or_default()
+    }
+
+    fn now_playing(&self) -> Option<NowPlaying> {
+        let song = self.songs.get(self.playback.current)?;
+        Some(NowPlaying {
+            state: match self.playback.state {
+                PlaybackState::Playing | PlaybackState::Transitioning => "playing",
+                PlaybackState::Paused { .. } => "paused",
+                PlaybackState::Stopped => "stopped",
+            },
+            title: song.title.clone(),
+            artist: song.artist.clone(),
+            album: song.album.clone(),
+            genre: song.genre.clone(),
+            path: song.path.to_string_lossy().to_string(),
+        })
+    }
+
+    fn announce(&self) {
+        if let Some(now_playing) = self.now_playing() {
+            self.announcer.announce(now_playing);
+        }
+    }
+
+    fn hook(&self, event: webhooks::Event) {
+        if !self.announcer.hooks(event) {
+            return;
+        }
+        self.announcer.hook(&Hook {
+            event,
+            track: if event == webhooks::Event::Queue { None } else { self.now_playing() },
+            queue: self.queued_paths().map(|path| path.to_string_lossy().to_string()).collect(),
+        });
+    }
+
+    fn queued_paths(&self) -> impl Iterator<Item = &PathBuf> {
+        self.playback.queue.iter().filter_map(|&index| self.songs.get(index)).map(|song| &song.path)
+    }
+
+    // Tells the webhooks when what's queued has changed since they last heard
+    fn hook_queue_change(&mut self) {
+        if !self.announcer.hooks(webhooks::Event::Queue) || self.queued_paths().eq(self.hooked_queue.iter()) {
+            return;
+        }
+        self.hooked_queue = self.queued_paths().cloned().collect();
+        self.hook(webhooks::Event::Queue);
+    }
+
+    // Returns a warning when the directory overlaps one that is already in the library
+    fn add_directory(&mut self, new_dir: PathBuf) -> Result<Option<String>> {
+        if !new_dir.exists() {
+            return Err(anyhow::anyhow!("Directory does not exist"));
+        }
+        let new_dir = canonical_path(&new_dir);
+        if self.music_dirs.contains(&new_dir) {
+            return Err(anyhow::anyhow!("Directory already added"));
+        }
+
+        let warning = if let Some(parent) = self.music_dirs.iter().find(|dir| new_dir.starts_with(dir)) {
+            Some(format!("Warning: directory is already inside {}", parent.display()))
+        } else {
+            self.music_dirs.iter()
+                .find(|dir| dir.starts_with(&new_dir))
+                .map(|child| format!("Warning: directory contains {}", child.display()))
+        };
+
+        // Songs show up as the background scan finds them
+        self.start_scan(vec![new_dir.clone()]);
+        self.music_dirs.push(new_dir);
+        Ok(warning)
+    }
+
+    fn remove_directory(&mut self, index: usize) -> Result<()> {
+        if index >= self.music_dirs.len() {
+            return Err(anyhow::anyhow!("Invalid directory index"));
+        }
+
+        let removed_dir = self.music_dirs.remove(index);
+        // Songs also covered by another directory stay in the library
+        let remaining_dirs = self.music_dirs.clone();
+        self.retain_songs(|song| {
+            !song.path.starts_with(&removed_dir)
+                || remaining_dirs.iter().any(|dir| song.path.starts_with(dir))
+        });
+
+        Ok(())
+    }
+
+    // Removes songs while keeping queue entries and the current index pointing at the same tracks
+    fn retain_songs(&mut self, keep: impl Fn(&Song) -> bool) {
+        let mut new_indices = Vec::with_capacity(self.songs.len());
+        let mut next = 0;
+        for song in &self.songs {
+            if keep(song) {
+                new_indices.push(Some(next));
+                next += 1;
+            } else {
+                new_indices.push(None);
+            }
+        }
+
+        self.playback.remap(&new_indices, next);
+        if next < self.songs.len() {
+            self.generation += 1;
+        }
+
+        self.songs.retain(keep);
+        self.browse.rebuild(&self.songs);
+        self.search_stale = true;
+    }
+
+    // Files with play counts, and whether their content hash is kept
+    fn counted(&self) -> HashMap<PathBuf, bool> {
+        self.stats.songs.keys().map(|path| (path.clone(), self.stats.hashes.contains_key(path))).collect()
+    }
+
+    // Re-walks all directories on a worker thread; what it finds comes back
+    // as `ScanEvent::Rescanned`. A `quiet` one only reports changes.
+    fn rescan(&mut self, quiet: bool) -> Result<()> {
+        // A running scan would deliver the same new files a second time
+        if self.is_scanning() || self.rescanning {
+            return Err(anyhow::anyhow!("Scan already in progress"));
+        }
+        self.rescanning = true;
+        let known = self.songs.iter().map(|song| (song.path.clone(), song.modified)).collect();
+        spawn_rescan(self.music_dirs.clone(), self.scan_config.clone(), known, self.counted(), quiet, self.events.clone());
+        Ok(())
+    }
+
+    // Syncs the library with what a rescan found on disk. Songs are matched
+    // by path, so anything that changed while it ran is left as it is now.
+    fn apply_rescan(&mut self, rescan: Rescan) -> Option<String> {
+        self.rescanning = false;
+        let before = self.songs.len();
+        self.retain_songs(|song| rescan.on_disk.contains(&song.path));
+        let removed = before - self.songs.len();
+
+        let updated = rescan.updated.len();
+        for mut song in rescan.updated {
+            // Hashed again on the worker if it has numbers, in case more than the tags changed
+            self.stats.hashes.remove(&song.path);
+            artists::apply(&mut song, &self.aliases);
+            self.browse.genre_map.apply(&mut song);
+            if let Some(index) = self.find_song(&song.path) {
+                song.added = self.songs[index].added;
+                self.songs[index] = song;
+            }
+        }
+
+        // A scan started meanwhile may have found some of them already
+        let added: Vec<Song> = rescan.added.into_iter().filter(|song| self.find_song(&song.path).is_none()).collect();
+        let added_paths: Vec<PathBuf> = added.iter().map(|song| song.path.clone()).collect();
+        let relinked = self.stats.relink(&added_paths, |path| rescan.on_disk.contains(path), |path| rescan.hashes.get(path).copied());
+        // Files with numbers from before hashes were kept
+        for (path, hash) in rescan.hashes {
+            if self.stats.songs.contains_key(&path) {
+                self.stats.hashes.entry(path).or_insert(hash);
+            }
+        }
+        let kept = self.songs.len();
+        self.songs.extend(added);
+        // Named as a scan would have them; the database keeps the tags as they are
+        for song in &mut self.songs[kept..] {
+            artists::apply(song, &self.aliases);
+            self.browse.genre_map.apply(song);
+        }
+        self.browse.rebuild(&self.songs);
+        self.search_stale = true;
+        self.apply_library_sort();
+        for index in 0..self.songs.len() {
+            self.sync_song_stats(index);
+            self.load_analysis(index);
+        }
+        let songs = self.songs.len();
+        self.announcer.update_metrics(|metrics| metrics.songs = songs);
+        if let Err(e) = self.save_stats() {
+            return Some(format!("Error saving play counts: {}", e));
+        }
+
+        let summary = RescanSummary { added: added_paths.len(), removed, updated, relinked, copies: rescan.copies };
+        (!rescan.quiet || summary.has_changes()).then(|| summary.message())
+    }
+
+    // Waits for the playing track to fade out, though not forever: an audio
+    // thread stuck on a device mustn't stop the player from exiting
+    fn quit_audio(&self) {
+        let (done_tx, done_rx) = mpsc::channel();
+        if self._player_tx.send(PlayerMessage::Quit(done_tx)).is_ok() {
+            let _ = done_rx.recv_timeout(Duration::from_millis(self.output.fade_ms) + Duration::from_secs(1));
+        }
+    }
+
+    fn set_volume(&mut self, delta: f32) {
+        self.volume = (self.volume + delta).clamp(0.0, 1.0);
+        let _ = self._player_tx.send(PlayerMessage::SetVolume(self.volume));
+    }
+
+    // The volume is left as it was, so unmuting goes back to exactly that
+    fn toggle_mute(&mut self) {
+        self.muted = !self.muted;
+        let _ = self._player_tx.send(if self.muted { PlayerMessage::Mute } else { PlayerMessage::Unmute });
+    }
+
+    fn set_mono(&mut self, mono: bool) {
+        self.mono = mono;
+        let _ = self._player_tx.send(PlayerMessage::SetChannelMode(if mono { Mix::Mono } else { Mix::Channels }));
+    }
+
+    // Through `reorder`, so the queue keeps its songs. Drops the library sort
+    // too, or the next scan would put the songs back in order.
+    fn shuffle(&mut self) {
+        let mut order: Vec<usize> = (0..self.songs.len()).collect();
+        order.shuffle(&mut rand::thread_rng());
+        self.library_sort = None;
+        self.reorder(&order);
+        self.playback.current = 0;
+        if self.playback.is_active() {
+            self.play_index(0);
+        }
+    }
+
+    fn add_to_queue(&mut self, index: usize) -> Result<()> {
+        self.check_queueable(index)?;
+        self.playback.queue.push_back(index);
+        Ok(())
+    }
+
+    fn play_next(&mut self, index: usize) -> Result<()> {
+        self.check_queueable(index)?;
+        self.playback.queue.push_front(index);
+        Ok(())
+    }
+
+    // Queues `songs` in their order, next up or at the end. The ones the
+    // queue policies turn away are left out; returns how many went in and
+    // why the last one left out was.
+    fn queue_songs(&mut self, songs: &[usize], next: bool) -> (usize, Option<anyhow::Error>) {
+        // Each goes in front of the ones after it
+        let order: Vec<usize> = if next { songs.iter().rev().copied().collect() } else { songs.to_vec() };
+        let (mut queued, mut error) = (0, None);
+        for index in order {
+            match if next { self.play_next(index) } else { self.add_to_queue(index) } {
+                Ok(()) => queued += 1,
+                Err(e) => error = Some(e),
+            }
+        }
+        (queued, error)
+    }
+
+    // Songs of a saved queue that are in the library
+    fn queue_indices(&self, name: &str) -> Vec<usize> {
+        self.queues.entries(name).iter().filter_map(|path| self.browse.paths.get(path).copied()).collect()
+    }
+
+    // Puts the active queue away and brings out `name`, new if there's no such queue
+    fn switch_queue(&mut self, name: &str) -> Result<()> {
+        self.store_queue();
+        self.playback.queue = self.queue_indices(name).into();
+        self.playback.played.clear();
+        self.queues.active = name.to_string();
+        self.queue_restored = true;
+        self.queues.save()
+    }
+
+    // Writes the active queue back to `queues`. Before the saved one has
+    // been restored, what was queued since goes after it.
+    fn store_queue(&mut self) {
+        let name = self.queues.active.clone();
+        let mut entries = if self.queue_restored { Vec::new() } else { self.queues.entries(&name).to_vec() };
+        entries.extend(self.playback.queue.iter().map(|&index| self.songs[index].path.clone()));
+        self.queues.store(&name, entries);
+    }
+
+    fn save_queues(&mut self) -> Result<()> {
+        self.store_queue();
+        self.queues.save()
+    }
+
+    // Whether the queue policies let `index` be queued
+    fn check_queueable(&self, index: usize) -> Result<()> {
+        if index >= self.songs.len() {
+            return Err(anyhow::anyhow!("No such song"));
+        }
+        let queue = &self.playback.queue;
+        if self.queue_config.duplicates == DuplicatePolicy::Reject && queue.contains(&index) {
+            return Err(anyhow::anyhow!("Already in the queue"));
+        }
+        if let Some(max) = self.queue_config.max_length.filter(|&max| queue.len() >= max) {
+            return Err(anyhow::anyhow!("The queue is full ({} tracks)", max));
+        }
+        Ok(())
+    }
+
+    fn add_to_playlist(&mut self, playlist: usize, songs: &[usize]) -> Result<()> {
+        let paths = songs.iter()
+            .map(|&song| self.songs.get(song).map(|song| song.path.clone()).ok_or_else(|| anyhow::anyhow!("No such song")))
+            .collect::<Result<Vec<PathBuf>>>()?;
+        let playlist = self.playlists.get_mut(playlist).ok_or_else(|| anyhow::anyhow!("No such playlist"))?;
+        let before = playlist.entries.len();
+        playlist.entries.extend(paths);
+        if let Err(e) = playlist.save() {
+            playlist.entries.truncate(before);
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    // Returns the index of the new, empty playlist
+    fn create_playlist(&mut self, name: &str) -> Result<usize> {
+        let dir = self.playlist_dir.as_ref().ok_or_else(|| anyhow::anyhow!("No playlist directory"))?;
+        let playlist = Playlist::create(dir, name)?;
+        Ok(self.insert_playlist(playlist))
+    }
+
+    // Keeps the list sorted by name; returns where the playlist went
+    fn insert_playlist(&mut self, playlist: Playlist) -> usize {
+        let index = self.playlists
+            .partition_point(|existing| existing.name.to_lowercase() < playlist.name.to_lowercase());
+        self.playlists.insert(index, playlist);
+        index
+    }
+
+    // Also brings back a deleted playlist, as long as its backups are there
+    fn restore_playlist(&mut self, name: &str, backup: usize) -> Result<usize> {
+        let existing = self.playlists.iter().position(|p| p.name.eq_ignore_ascii_case(name));
+        let path = match existing {
+            Some(index) => self.playlists[index].path.clone(),
+            None => {
+                let dir = self.playlist_dir.as_ref().ok_or_else(|| anyhow::anyhow!("No playlist directory"))?;
+                dir.join(format!("{}.m3u", name))
+            }
+        };
+        persist::restore(&path, backup)?;
+        let restored = Playlist::load(&path)?;
+        match existing {
+            Some(index) => {
+                self.playlists[index] = restored;
+                Ok(index)
+            }
+            None => Ok(self.insert_playlist(restored)),
+        }
+    }
+
+    fn playlist_records(&self) -> Vec<PlaylistRecord> {
+        self.playlists.iter()
+            .map(|playlist| PlaylistRecord { name: playlist.name.clone(), entries: playlist.entries.clone() })
+            .collect()
+    }
+
+    // Creates or overwrites each playlist in the archive. Entries that aren't
+    // in the library are pointed at songs with the same tags where possible;
+    // returns how many still aren't.
+    fn restore_archived_playlists(&mut self, archive: &Archive) -> Result<usize> {
+        let dir = self.playlist_dir.clone().ok_or_else(|| anyhow::anyhow!("No playlist directory"))?;
+        fs::create_dir_all(&dir)?;
+        let mut missing = 0;
+        for record in &archive.playlists {
+            let entries = record.entries.iter()
+                .map(|entry| match self.find_song(entry).or_else(|| archive.relink(entry, &self.songs)) {
+                    Some(song) => self.songs[song].path.clone(),
+                    None => {
+                        missing += 1;
+                        entry.clone()
+                    }
+                })
+                .collect();
+            let existing = self.playlists.iter().position(|p| p.name.eq_ignore_ascii_case(&record.name));
+            let path = match existing {
+                Some(index) => self.playlists[index].path.clone(),
+                None => dir.join(format!("{}.m3u", record.name)),
+            };
+            let playlist = Playlist { name: record.name.clone(), path, entries };
+            playlist.save()?;
+            match existing {
+                Some(index) => self.playlists[index] = playlist,
+                None => {
+                    self.insert_playlist(playlist);
+                }
+            }
+        }
+        Ok(missing)
+    }
+
+    // Returns the playlist's new index, which moves with its name
+    fn rename_playlist(&mut self, index: usize, name: &str) -> Result<usize> {
+        let playlist = self.playlists.get_mut(index).ok_or_else(|| anyhow::anyhow!("No such playlist"))?;
+        playlist.rename(name)?;
+        let playlist = self.playlists.remove(index);
+        Ok(self.insert_playlist(playlist))
+    }
+
+    // `base`, or "base 2" and so on if that is taken
+    fn unused_playlist_name(&self, base: &str) -> String {
+        let taken = |name: &str| self.playlists.iter().any(|p| p.name.eq_ignore_ascii_case(name));
+        (1..)
+            .map(|n| match n {
+                1 => base.to_string(),
+                n => format!("{} {}", base, n),
+            })
+            .find(|name| !taken(name))
+            .unwrap_or_default()
+    }
+
+    fn duplicate_playlist(&mut self, index: usize) -> Result<usize> {
+        let playlist = self.playlists.get(index).ok_or_else(|| anyhow::anyhow!("No such playlist"))?;
+        let copy = playlist.duplicate(&self.unused_playlist_name(&format!("{} copy", playlist.name)))?;
+        Ok(self.insert_playlist(copy))
+    }
+
+    // Makes a playlist, named after the export file, of the tracks found in the library
+    fn import_playlist(&mut self, path: &Path) -> Result<(usize, ImportResult)> {
+        let tracks = import::read_tracks(path)?;
+        let result = import::match_tracks(&tracks, &self.songs);
+        let dir = self.playlist_dir.clone().ok_or_else(|| anyhow::anyhow!("No playlist directory"))?;
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy().to_string();
+        let mut playlist = Playlist::create(&dir, &self.unused_playlist_name(&stem))?;
+        playlist.entries = result.matched.iter().map(|&i| self.songs[i].path.clone()).collect();
+        playlist.save()?;
+        Ok((self.insert_playlist(playlist), result))
+    }
+
+    fn delete_playlist(&mut self, index: usize) -> Result<()> {
+        if index >= self.playlists.len() {
+            return Err(anyhow::anyhow!("No such playlist"));
+        }
+        self.playlists.remove(index).delete()
+    }
+
+    // Swaps two entries and saves; the file is left as it was on failure
+    fn swap_playlist_entries(&mut self, index: usize, a: usize, b: usize) -> Result<()> {
+        let playlist = self.playlists.get_mut(index).ok_or_else(|| anyhow::anyhow!("No such playlist"))?;
+        if a >= playlist.entries.len() || b >= playlist.entries.len() {
+            return Ok(());
+        }
+        playlist.entries.swap(a, b);
+        if let Err(e) = playlist.save() {
+            playlist.entries.swap(a, b);
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    // Entries of a playlist that aren't in the library, in playlist order
+    fn missing_entries(&self, index: usize) -> Vec<usize> {
+        self.playlists.get(index).map_or_else(Vec::new, |playlist| {
+            (0..playlist.entries.len())
+                .filter(|&entry| self.find_song(&playlist.entries[entry]).is_none())
+                .collect()
+        })
+    }
+
+    // Points an entry at a library song, or drops it with `song` None
+    fn relink_playlist_entry(&mut self, index: usize, entry: usize, song: Option<usize>) -> Result<()> {
+        let path = song.map(|song| self.songs[song].path.clone());
+        let playlist = self.playlists.get_mut(index).ok_or_else(|| anyhow::anyhow!("No such playlist"))?;
+        if entry >= playlist.entries.len() {
+            return Ok(());
+        }
+        let old = match path {
+            Some(path) => std::mem::replace(&mut playlist.entries[entry], path),
+            None => playlist.entries.remove(entry),
+        };
+        if let Err(e) = playlist.save() {
+            match song {
+                Some(_) => playlist.entries[entry] = old,
+                None => playlist.entries.insert(entry, old),
+            }
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    // Playlist entries may be spelled differently from the scanned path
+    fn find_song(&self, path: &Path) -> Option<usize> {
+        self.browse.paths.get(path)
+            .or_else(|| self.browse.paths.get(&canonical_path(path)))
+            .copied()
+    }
+
+    // Only forgets the songs; the files stay on disk and come back on rescan
+    fn remove_songs(&mut self, songs: &[usize]) {
+        let paths: HashSet<PathBuf> = songs.iter().filter_map(|&index| self.songs.get(index)).map(|song| song.path.clone()).collect();
+        self.retain_songs(|song| !paths.contains(&song.path));
+    }
+
+    // Writes `edits` to the files of `songs`, then reads them again. The
+    // playing file is left alone, as with play counts. Returns how many were
+    // written and what went wrong with the rest.
+    fn edit_tags(&mut self, songs: &[usize], edits: &[(TagField, String)]) -> (usize, Vec<String>) {
+        let playing = (self.playback.state != PlaybackState::Stopped).then_some(self.playback.current);
+        let (mut written, mut failed) = (0, Vec::new());
+        for &index in songs {
+            let path = self.songs[index].path.clone();
+            if Some(index) == playing {
+                failed.push(format!("{}: playing; its tags can be changed once it stops", path.display()));
+                continue;
+            }
+            if let Err(e) = tags::write(&path, edits) {
+                failed.push(format!("{}: {}", path.display(), e));
+                continue;
+            }
+            let mut song = Song::new(path);
+            artists::apply(&mut song, &self.aliases);
+            self.browse.genre_map.apply(&mut song);
+            // What wasn't read from the file
+            song.added = self.songs[index].added;
+            song.dr = self.songs[index].dr;
+            self.songs[index] = song;
+            written += 1;
+        }
+        if written > 0 {
+            self.browse.rebuild(&self.songs);
+            self.search_stale = true;
+            self.apply_library_sort();
+        }
+        (written, failed)
+    }
+
+    fn search(&mut self, query: &str) {
+        let matches = library::search(&self.songs, query);
+        self.search_results = match self.search_scope {
+            SearchScope::Library => matches,
+            // In queue order, each song once
+            SearchScope::Queue => {
+                let matches: HashSet<usize> = matches.into_iter().collect();
+                let mut seen = HashSet::new();
+                self.playback.queue.iter()
+                    .copied()
+                    .filter(|index| matches.contains(index) && seen.insert(*index))
+                    .collect()
+            }
+            SearchScope::Playlists => {
+                let listed: HashSet<usize> = self.playlists.iter()
+                    .flat_map(|playlist| &playlist.entries)
+                    .filter_map(|path| self.find_song(path))
+                    .collect();
+                matches.into_iter().filter(|index| listed.contains(index)).collect()
+            }
+        };
+        self.search_query = query.to_string();
+        self.search_stale = false;
+    }
+
+    fn sort_songs(&mut self, column: Column, descending: bool) {
+        let mut order: Vec<usize> = (0..self.songs.len()).collect();
+        columns::sort(&mut order, &self.songs, column, descending);
+        self.reorder(&order);
+    }
+
+    // Keeps the library sorted by `sort` from now on, songs found later included
+    fn set_library_sort(&mut self, sort: Option<LibrarySort>) {
+        if sort != self.library_sort {
+            self.library_sort = sort;
+            self.apply_library_sort();
+        }
+    }
+
+    // Scans add songs at the end; they're put in their place once done
+    fn apply_library_sort(&mut self) {
+        if let Some(sort) = self.library_sort {
+            let mut order: Vec<usize> = (0..self.songs.len()).collect();
+            order.sort_by(|&a, &b| sort.compare(&self.songs[a], &self.songs[b]));
+            // Already in order: keep indices, and popups holding them, valid
+            if order.iter().enumerate().any(|(new, &old)| new != old) {
+                self.reorder(&order);
+            }
+        }
+    }
+
+    // Reorders the library itself, song `order[i]` moving to `i`; playback
+    // and the queue follow their songs
+    fn reorder(&mut self, order: &[usize]) {
+        let mut new_indices = vec![None; order.len()];
+        for (new, &old) in order.iter().enumerate() {
+            new_indices[old] = Some(new);
+        }
+        self.playback.remap(&new_indices, order.len());
+        self.songs = order.iter().map(|&i| self.songs[i].clone()).collect();
+        self.generation += 1;
+        self.browse.rebuild(&self.songs);
+        self.search_stale = true;
+    }
+
+    fn search_needs_update(&self, query: &str) -> bool {
+        self.search_stale || self.search_query != query
+    }
+
+    // Returns which playlists couldn't be read, if the directory changed
+    fn apply_config(&mut self, config: &Config) -> Result<Option<String>> {
+        self.scan_config = config.scan.clone();
+        self.tag_sync = config.tag_sync;
+        self.queue_config = config.queue;
+        self.playback.keep_played = config.queue.played == PlayedPolicy::Keep;
+        if !self.playback.keep_played {
+            self.playback.played.clear();
+        }
+        compressor::configure(&self.night, config.output.night.threshold_db, config.output.night.ratio);
+        // Takes effect from the next track
+        self.output = config.output.clone();
+        let _ = self._player_tx.send(PlayerMessage::Configure(config.output.clone()));
+        self.set_genres(Genres::new(&config.genres));
+        if self.browse.groupings() != config.browse {
+            let genre_map = std::mem::take(&mut self.browse.genre_map);
+            self.browse = BrowseIndex { genre_map, ..BrowseIndex::new(&config.browse) };
+            self.browse.rebuild(&self.songs);
+            if matches!(self.view_mode, ViewMode::Grouped(group) if group >= config.browse.len()) {
+                self.view_mode = ViewMode::AllSongs;
+            }
+        }
+        let playlist_dir = config.playlist_dir();
+        let mut skipped = None;
+        if playlist_dir != self.playlist_dir {
+            self.playlist_dir = playlist_dir;
+            skipped = self.load_playlists();
+        }
+        self.announcer.reconfigure(config)?;
+        Ok(skipped)
+    }
+
+    // Those in the playlist directory; returns which files couldn't be read
+    fn load_playlists(&mut self) -> Option<String> {
+        let (playlists, skipped) = self.playlist_dir.as_deref().map(playlist::load_dir).unwrap_or_default();
+        self.playlists = playlists;
+        let (path, e) = skipped.first()?;
+        Some(match skipped.len() {
+            1 => format!("Playlist {} couldn't be read: {}", path.display(), e),
+            n => format!("{} playlists couldn't be read, {} for one: {}", n, path.display(), e),
+        })
+    }
+}
+
+fn config_modified() -> Option<SystemTime> {
+    let path = Config::path()?;
+    fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+struct App {
+    player: MusicPlayer,
+    command_mode: bool,
+    command_input: String,
+    message: Option<String>,
+    search_mode: bool,
+    search_input: String,
+    // Artists and albums opened in the Artists tab
+    artist_tree: Tree<Vec<String>>,
+    // Playlist opened in the Playlists tab
+    selected_playlist: Option<usize>,
+    // Folders opened in the Folders tab
+    folder_tree: Tree<PathBuf>,
+    // Groups opened in each configured browse tab
+    group_trees: HashMap<usize, Tree<Vec<String>>>,
+    // Sections of the Songs tab left open; all of them to start with
+    section_tree: Tree<Section>,
+    sections: SectionsConfig,
+    goals: GoalsConfig,
+    pending_add: Option<PathBuf>,
+    // Playlist waiting for y/n before its file is deleted
+    pending_delete: Option<usize>,
+    menu: Option<ActionMenu>,
+    tag_editor: Option<TagEditor>,
+    picker: Option<PlaylistPicker>,
+    palette: Option<Palette>,
+    relink: Option<Relink>,
+    report: Option<Report>,
+    // Files `:verify` has still to decode, and what it made of the rest
+    verifying: usize,
+    verified: Vec<(PathBuf, Verdict)>,
+    focus: Pane,
+    // Row of the queue pane, counting album headers
+    queue_cursor: usize,
+    // (album, artist) of album runs listed track by track in the queue pane
+    expanded_albums: HashSet<(String, String)>,
+    layout: LayoutConfig,
+    columns: ColumnsConfig,
+    pins: PinsConfig,
+    time_display: TimeDisplay,
+    sort: Option<SortOrder>,
+    // Filled in by every draw
+    header_cells: Vec<HeaderCell>,
+    state: State,
+    // Created on first use and kept: on X11 the clipboard contents vanish with it
+    clipboard: Option<arboard::Clipboard>,
+    // Cover art of the track shown in Now Playing, None if it has none
+    cover: Option<(PathBuf, Option<CoverBlocks>)>,
+    art: ArtCache,
+    // Song whose album was last handed to the art cache, so it is asked once
+    cover_requested: Option<PathBuf>,
+    // Lyrics replace the song list while shown
+    show_lyrics: bool,
+    // Full-screen big lyrics, opened with :karaoke
+    karaoke: bool,
+    // Full-screen big title and artist, for reading across the room; :big
+    big_now: bool,
+    // Keys 1-8 jump to cue points, opened with :perform
+    perform: bool,
+    // Two decks fed from the queue, opened with :decks
+    decks: Option<Decks>,
+    cues: Cues,
+    // x was pressed; the next pad is cleared instead
+    clearing_cue: bool,
+    lyrics: Option<(PathBuf, Option<Lyrics>)>,
+    lyrics_config: LyricsConfig,
+    // Song whose lyrics are being fetched
+    lyrics_requested: Option<PathBuf>,
+    scroll_offset: usize,
+    // First row of the main list that is currently on screen
+    list_offset: usize,
+    last_key_time: Instant,
+    rescan_interval: Option<Duration>,
+    last_rescan: Instant,
+    started: Instant,
+    last_search_edit: Instant,
+    // Modification time of the config file when it was last read
+    config_modified: Option<SystemTime>,
+    last_config_check: Instant,
+    should_quit: bool,
+    // tab_keys[i] jumps to ViewMode::nth(i)
+    tab_keys: Vec<KeyCode>,
+    // Browse tabs, switched with gt/gT. The shown one's entry is out of date;
+    // its state lives in the fields above.
+    sessions: Vec<Session>,
+    session: usize,
+    // g was pressed and waits for t or T
+    pending_g: bool,
+    // Shown in the corner so a keystroke can be seen to have registered
+    last_key: Option<(String, Instant)>,
+    // Clock, track and level bars instead of everything else, after a
+    // while without input
+    screensaver: bool,
+    screensaver_after: Option<Duration>,
+    last_input: Instant,
+    // Peaks seen on each tick while the screensaver shows, in percent
+    levels: VecDeque<u64>,
+    utc_offset: i64,
+    power_mode: PowerMode,
+    // Fewer ticks, no animations, and ticks only redraw if they change something
+    low_power: bool,
+    last_power_check: Instant,
+    tick_ms: Arc<AtomicU64>,
+    // What the screen showed of `TickView` when last drawn for a tick
+    tick_view: Option<TickView>,
+}
+
+impl App {
+    fn new(player: MusicPlayer, config: &Config, state: State) -> Self {
+        let mut app = App {
+            player,
+            command_mode: false,
+            command_input: String::new(),
+            message: None,
+            search_mode: false,
+            search_input: String::new(),
+            artist_tree: Tree::default(),
+            selected_playlist: None,
+            folder_tree: Tree::default(),
+            group_trees: HashMap::new(),
+            section_tree: Tree::default(),
+            sections: config.sections,
+            goals: config.goals,
+            pending_add: None,
+            pending_delete: None,
+            menu: None,
+            tag_editor: None,
+            picker: None,
+            palette: None,
+            relink: None,
+            report: None,
+            verifying: 0,
+            verified: Vec::new(),
+            focus: Pane::Library,
+            queue_cursor: 0,
+            expanded_albums: HashSet::new(),
+            layout: config.layout,
+            columns: config.columns.clone(),
+            pins: config.pins.clone(),
+            time_display: config.time_display,
+            sort: None,
+            header_cells: Vec::new(),
+            state,
+            clipboard: None,
+            cover: None,
+            art: ArtCache::new(None, false),
+            cover_requested: None,
+            show_lyrics: false,
+            karaoke: false,
+            big_now: false,
+            perform: false,
+            decks: None,
+            cues: Cues::load(),
+            clearing_cue: false,
+            lyrics: None,
+            lyrics_config: LyricsConfig::default(),
+            lyrics_requested: None,
+            scroll_offset: 0,
+            list_offset: 0,
+            last_key_time: Instant::now(),
+            rescan_interval: None,
+            last_rescan: Instant::now(),
+            started: Instant::now(),
+            last_search_edit: Instant::now(),
+            config_modified: config_modified(),
+            last_config_check: Instant::now(),
+            should_quit: false,
+            tab_keys: Vec::new(),
+            sessions: vec![Session::default()],
+            session: 0,
+            pending_g: false,
+            last_key: None,
+            screensaver: false,
+            screensaver_after: None,
+            last_input: Instant::now(),
+            levels: VecDeque::new(),
+            utc_offset: clock::utc_offset(),
+            power_mode: PowerMode::Auto,
+            low_power: false,
+            last_power_check: Instant::now(),
+            tick_ms: Arc::new(AtomicU64::new(TICK_RATE.as_millis() as u64)),
+            tick_view: None,
+        };
+        for section in Section::ALL {
+            app.section_tree.expand(section);
+        }
+        app.apply_config(config);
+        app
+    }
+
+    // The UI side of the config; also used when the file changes while running
+    fn apply_config(&mut self, config: &Config) {
+        self.tab_keys.clear();
+        for name in &config.keys.tabs {
+            match parse_key(name) {
+                Some(key) => self.tab_keys.push(key),
+                None => {
+                    self.message = Some(format!("Unknown key in config: {}", name));
+                    break;
+                }
+            }
+        }
+
+        // A layout saved from the keyboard wins over the config file
+        let mut layout = self.state.layout.unwrap_or(config.layout);
+        layout.sidebar_width = layout.sidebar_width
+            .clamp(LayoutConfig::MIN_SIDEBAR_WIDTH, LayoutConfig::MAX_SIDEBAR_WIDTH);
+        self.layout = layout;
+        if !self.layout.sidebar {
+            self.focus = Pane::Library;
+        }
+
+        self.rescan_interval = config.rescan_interval_secs.map(Duration::from_secs);
+        self.screensaver_after = config.screensaver_after_mins.map(|mins| Duration::from_secs(mins * 60));
+        self.power_mode = config.power;
+        self.update_power();
+        self.art = ArtCache::new(config.art.cache_dir.clone().or_else(ArtCache::default_dir), config.art.fetch);
+        self.lyrics_config = config.lyrics.clone();
+        self.columns = config.columns.clone();
+        self.pins = self.state.pins.clone().unwrap_or_else(|| config.pins.clone());
+        self.sections = config.sections;
+        self.goals = config.goals;
+        self.time_display = self.state.time_display.unwrap_or(config.time_display);
+        self.player.set_library_sort(self.state.library_sort.or(config.library_sort));
+        compressor::set_enabled(&self.player.night, self.state.night.unwrap_or(config.output.night.enabled));
+        self.player.set_mono(self.state.mono.unwrap_or(config.output.mono));
+        self.player.add_aliases(config.artist_aliases.clone());
+        self.player.add_aliases(self.state.artist_aliases.clone().unwrap_or_default());
+    }
+
+    // Playback is left alone; a broken file keeps the old settings
+    fn reload_config(&mut self) {
+        match Config::load() {
+            Ok(config) => {
+                self.message = Some(match self.player.apply_config(&config) {
+                    Ok(Some(skipped)) => skipped,
+                    Ok(None) => "Config reloaded".to_string(),
+                    Err(e) => format!("Config error: {}", e),
+                });
+                self.apply_config(&config);
+            }
+            Err(e) => self.message = Some(format!("Config error: {}", e)),
+        }
+    }
+
+    // Single reducer for everything that happens: input, scanner progress and timers
+    fn update(&mut self, action: Action) -> Result<()> {
+        let generation = self.player.generation;
+        // Any input wakes the screen; whatever woke it does nothing else
+        if matches!(action, Action::Input(_)) {
+            self.last_input = Instant::now();
+            if std::mem::take(&mut self.screensaver) {
+                self.levels.clear();
+                return Ok(());
+            }
+        }
+        match action {
+            // Windows reports releases too; only presses should do anything
+            Action::Input(Event::Key(key)) if key.kind != KeyEventKind::Release => self.handle_key(key)?,
+            Action::Input(Event::Paste(text)) => self.handle_paste(&text),
+            Action::Input(Event::Mouse(mouse)) => self.handle_mouse(mouse),
+            Action::Input(_) => {}
+            Action::Scan(event) => {
+                if let Some(error) = self.player.handle_scan_event(event) {
+                    self.message = Some(error);
+                }
+            }
+            Action::Player(event) => {
+                if let Some(error) = self.player.handle_player_event(event) {
+                    self.message = Some(error);
+                }
+            }
+            Action::Cover(path) => {
+                // Picked up from the cache on the next draw
+                if self.cover.as_ref().is_some_and(|(current, _)| *current == path) {
+                    self.cover = None;
+                }
+            }
+            Action::Analyzed(path, result, remaining) => {
+                self.message = Some(self.player.handle_analyzed(path, result, remaining));
+            }
+            Action::Verified(path, verdict, remaining) => self.handle_verified(path, verdict, remaining),
+            Action::Lyrics(path, lyrics) => {
+                if self.lyrics_requested.as_ref() == Some(&path) {
+                    self.lyrics_requested = None;
+                }
+                if self.lyrics.as_ref().is_some_and(|(current, _)| *current == path) {
+                    self.lyrics = Some((path, lyrics));
+                }
+            }
+            // Asks before indexing something that looks like a whole disk
+            Action::Measured(path, None) => self.add_directory(path),
+            Action::Measured(path, Some(reason)) => {
+                self.message = Some(format!("{} has {}. Add anyway? (y/n)", path.display(), reason));
+                self.pending_add = Some(path);
+            }
+            // Moved meanwhile, or its numbers cleared, there's nothing to keep it for
+            Action::Hashed(path, hash) => {
+                if self.player.stats.songs.contains_key(&path) {
+                    self.player.stats.hashes.entry(path).or_insert(hash);
+                }
+            }
+            Action::Remote(command) => self.handle_remote(command),
+            Action::Quit => self.should_quit = true,
+            Action::ReloadConfig => self.reload_config(),
+            Action::Tick => self.tick(),
+        }
+        if self.player.generation != generation {
+            self.close_stale_popups();
+        }
+        self.player.hook_queue_change();
+        Ok(())
+    }
+
+    fn handle_remote(&mut self, command: remote::Command) {
+        match command {
+            remote::Command::PlayPause => self.player.toggle_playback(),
+            remote::Command::Next => self.player.next(),
+            remote::Command::Previous => self.player.previous(),
+            remote::Command::Queue(path) => match self.player.find_song(&path) {
+                Some(index) => self.queue_song(index),
+                None => self.message = Some(format!("Asked over HTTP to queue {}, which isn't in the library", path.display())),
+            },
+            remote::Command::ClearQueue => {
+                let count = self.player.playback.queue.len();
+                self.player.playback.queue.clear();
+                self.message = Some(format!("Queue of {} tracks cleared over HTTP", count));
+            }
+        }
+    }
+
+    // The action menu, tag editor, playlist picker and relink popup hold song
+    // indices from when they opened, which may now be other songs; a timed
+    // rescan can remove files while one is open
+    fn close_stale_popups(&mut self) {
+        let menu = self.menu.take().is_some();
+        let editor = self.tag_editor.take().is_some();
+        let picker = self.picker.take().is_some();
+        let relink = self.relink.take().is_some();
+        if menu || editor || picker || relink {
+            self.message = Some(String::from("The library changed, so the popup was closed"));
+        }
+    }
+
+    // Looks up the song's album art on a worker thread
+    fn request_cover(&mut self, index: usize) {
+        let Some(song) = self.player.songs.get(index).cloned() else {
+            return;
+        };
+        if self.cover_requested.as_ref() == Some(&song.path) {
+            return;
+        }
+        self.cover_requested = Some(song.path.clone());
+        let art = self.art.clone();
+        let events = self.player.events.clone();
+        thread::spawn(move || {
+            art.lookup(&song);
+            let _ = events.send(Action::Cover(song.path));
+        });
+    }
+
+    // Lyrics on disk are read straight away; otherwise the provider is asked
+    // on a worker thread, if fetching is on
+    fn load_lyrics(&mut self, index: usize) {
+        let Some(song) = self.player.songs.get(index).cloned() else {
+            return;
+        };
+        let cache_dir = lyrics::default_cache_dir();
+        let found = lyrics::find(&song.path, cache_dir.as_deref());
+        let missing = found.is_none();
+        self.lyrics = Some((song.path.clone(), found));
+        if missing && self.lyrics_config.fetch {
+            self.lyrics_requested = Some(song.path.clone());
+            let config = self.lyrics_config.clone();
+            let events = self.player.events.clone();
+            thread::spawn(move || {
+                let fetched = lyrics::fetch(&song, &config.provider).ok().flatten();
+                if let Some(fetched) = &fetched {
+                    let store = lyrics::store_path(&song.path, config.save_next_to_files, cache_dir.as_deref());
+                    if let Some(store) = store {
+                        if let Some(dir) = store.parent() {
+                            let _ = fs::create_dir_all(dir);
+                        }
+                        let _ = fs::write(store, fetched.to_lrc());
+                    }
+                }
+                let _ = events.send(Action::Lyrics(song.path, fetched));
+            });
+        }
+    }
+
+    fn tick(&mut self) {
+        self.player.skip_outro();
+        self.player.sync_gapless();
+
+        if self.screensaver_after.is_some_and(|after| self.last_input.elapsed() >= after) {
+            self.screensaver = true;
+        }
+        if self.power_mode == PowerMode::Auto && self.last_power_check.elapsed() >= POWER_POLL {
+            self.last_power_check = Instant::now();
+            self.update_power();
+        }
+
+        // No level bars in low-power mode
+        if self.screensaver && !self.low_power {
+            let playing = self.player.playback.state == PlaybackState::Playing;
+            let peak = if playing { meter::read(&self.player.level) } else { 0.0 };
+            self.levels.push_back((peak * 100.0).round() as u64);
+            if self.levels.len() > LEVEL_HISTORY {
+                self.levels.pop_front();
+            }
+        }
+
+        if self.last_config_check.elapsed() >= CONFIG_POLL {
+            self.last_config_check = Instant::now();
+            let modified = config_modified();
+            if modified != self.config_modified {
+                self.config_modified = modified;
+                self.reload_config();
+            }
+        }
+
+        // Search once typing pauses instead of on every frame
+        if self.player.search_needs_update(&self.search_input)
+            && self.last_search_edit.elapsed() >= SEARCH_DEBOUNCE
+        {
+            self.player.search(&self.search_input);
+            // Fresh results come in relevance order
+            if let Some(sort) = self.sort.filter(|sort| sort.view == ViewMode::Search) {
+                columns::sort(&mut self.player.search_results, &self.player.songs, sort.column, sort.descending);
+            }
+        }
+
+        // Periodic background rescan only reports when something changed
+        if let Some(interval) = self.rescan_interval {
+            if self.last_rescan.elapsed() >= interval && !self.player.is_scanning() && !self.player.rescanning {
+                self.last_rescan = Instant::now();
+                if let Err(e) = self.player.rescan(true) {
+                    self.message = Some(format!("Error: {}", e));
+                }
+            }
+        }
+    }
+
+    fn handle_key(&mut self, key: KeyEvent) -> Result<()> {
+        let now = Instant::now();
+        if now.duration_since(self.last_key_time) < KEY_DELAY {
+            return Ok(());
+        }
+        self.last_key_time = now;
+        // Typed text shows up where it is typed
+        if !self.command_mode && !self.search_mode {
+            let name = key_name(key.code);
+            let name = if key.modifiers.contains(KeyModifiers::CONTROL) { format!("Ctrl-{}", name) } else { name };
+            self.last_key = Some((name, now));
+        }
+
+        if let Some(path) = self.pending_add.take() {
+            if key.code == KeyCode::Char('y') {
+                self.add_directory(path);
+            } else {
+                self.message = Some("Directory not added".to_string());
+            }
+        } else if let Some(index) = self.pending_delete.take() {
+            if key.code == KeyCode::Char('y') {
+                self.delete_playlist(index);
+            } else {
+                self.message = Some("Playlist kept".to_string());
+            }
+        } else if let Some(report) = self.report.as_mut() {
+            match key.code {
+                KeyCode::Char('j') | KeyCode::Down => {
+                    report.scroll = (report.scroll + 1).min(report.lines.len().saturating_sub(1));
+                }
+                KeyCode::Char('k') | KeyCode::Up => report.scroll = report.scroll.saturating_sub(1),
+                KeyCode::Esc | KeyCode::Enter | KeyCode::Char('q') => self.report = None,
+                _ => {}
+            }
+        } else if self.relink.is_some() {
+            self.handle_relink_key(key);
+        } else if self.karaoke {
+            self.handle_karaoke_key(key);
+        } else if self.big_now {
+            self.handle_big_now_key(key);
+        } else if self.perform {
+            self.handle_perform_key(key);
+        } else if self.decks.is_some() {
+            self.handle_deck_key(key);
+        } else if self.palette.is_some() {
+            self.handle_palette_key(key)?;
+        } else if self.picker.is_some() {
+            self.handle_picker_key(key);
+        } else if self.tag_editor.is_some() {
+            self.handle_tag_editor_key(key);
+        } else if self.menu.is_some() {
+            self.handle_menu_key(key);
+        } else if self.command_mode {
+            match key.code {
+                KeyCode::Enter => {
+                    let cmd = self.command_input.trim().to_string();
+                    self.run_command(&cmd);
+                    self.command_mode = false;
+                    self.command_input.clear();
+                }
+                KeyCode::Esc => {
+                    self.command_mode = false;
+                    self.command_input.clear();
+                }
+                KeyCode::Char(c) => {
+                    self.command_input.push(c);
+                }
+                KeyCode::Backspace => {
+                    self.command_input.pop();
+                }
+                _ => {}
+            }
+        } else if let Some(index) = self.tab_key_index(key.code) {
+            self.player.view_mode = ViewMode::nth(index);
+        } else if self.search_mode {
+            self.handle_search_key(key);
+        } else if self.focus == Pane::Library && self.handle_tree_view_key(key) {
+            // h and l open and close branches in trees instead of changing tracks
+        } else if !self.handle_global_key(key)? {
+            match self.focus {
+                Pane::Library => self.handle_library_key(key),
+                Pane::Queue => self.handle_queue_key(key),
+            }
+        }
+        Ok(())
+    }
+
+    // Stashes the shown tab and brings out tab `index`, wrapping around
+    fn switch_session(&mut self, index: usize) {
+        let index = index % self.sessions.len();
+        self.sessions[self.session] = Session {
+            view_mode: self.player.view_mode,
+            search_input: std::mem::take(&mut self.search_input),
+            artist_tree: std::mem::take(&mut self.artist_tree),
+            selected_playlist: self.selected_playlist.take(),
+            folder_tree: std::mem::take(&mut self.folder_tree),
+            group_trees: std::mem::take(&mut self.group_trees),
+            scroll_offset: self.scroll_offset,
+            list_offset: self.list_offset,
+        };
+        let session = self.sessions[index].clone();
+        self.player.view_mode = session.view_mode;
+        self.search_input = session.search_input;
+        self.artist_tree = session.artist_tree;
+        self.selected_playlist = session.selected_playlist;
+        self.folder_tree = session.folder_tree;
+        self.group_trees = session.group_trees;
+        self.scroll_offset = session.scroll_offset;
+        self.list_offset = session.list_offset;
+        self.search_mode = false;
+        self.session = index;
+    }
+
+    // A new tab starts on the song list, after the shown one
+    fn open_session(&mut self) {
+        self.sessions.insert(self.session + 1, Session::default());
+        self.switch_session(self.session + 1);
+    }
+
+    fn close_session(&mut self) {
+        if self.sessions.len() == 1 {
+            self.message = Some("Can't close the last tab".to_string());
+            return;
+        }
+        let closing = self.session;
+        // Moving off stores the closing tab, which is then dropped
+        self.switch_session(if closing == 0 { 1 } else { closing - 1 });
+        self.sessions.remove(closing);
+        if self.session > closing {
+            self.session -= 1;
+        }
+    }
+
+    // Runs what was typed after `:`
+    fn run_command(&mut self, cmd: &str) {
+        if cmd.starts_with("add ") {
+            let path = PathBuf::from(cmd.trim_start_matches("add "));
+            self.message = Some(format!("Looking through {}", path.display()));
+            self.player.check_scan_limits(path);
+        } else if cmd.starts_with("remove ") {
+            if let Ok(index) = cmd.trim_start_matches("remove ").parse::<usize>() {
+                match self.player.remove_directory(index) {
+                    Ok(_) => self.message = Some("Directory removed successfully".to_string()),
+                    Err(e) => self.message = Some(format!("Error: {}", e)),
+                }
+            }
+        } else if cmd == "rescan" {
+            match self.player.rescan(false) {
+                Ok(()) => self.message = Some("Rescanning the library".to_string()),
+                Err(e) => self.message = Some(format!("Error: {}", e)),
+            }
+            self.last_rescan = Instant::now();
+        } else if cmd == "karaoke" {
+            self.karaoke = true;
+        } else if cmd == "big" {
+            self.big_now = true;
+        } else if cmd == "perform" {
+            self.perform = true;
+        } else if cmd == "decks" {
+            self.open_decks();
+        } else if cmd == "analyze" {
+            self.message = Some(self.player.start_analysis());
+        } else if cmd == "duplicates" {
+            self.show_duplicates();
+        } else if cmd == "broken" {
+            self.show_broken();
+        } else if cmd == "stats" {
+            self.show_stats();
+        } else if cmd == "artists" {
+            self.show_artist_variants();
+        } else if cmd == "artists merge" {
+            self.merge_artist_variants();
+        } else if cmd == "encoding" {
+            self.show_misencoded();
+        } else if cmd == "encoding repair" {
+            self.repair_encodings(false);
+        } else if cmd == "encoding write" {
+            self.repair_encodings(true);
+        } else if cmd == "webhooks" {
+            self.show_deliveries();
+        } else if cmd == "verify" {
+            self.start_verify();
+        } else if cmd == "untrim" {
+            self.clear_trim();
+        } else if cmd == "queue" {
+            self.message = Some(format!("Queues: {}", self.player.queues.names().join(", ")));
+        } else if cmd == "setlist clear" {
+            self.player.setlist.clear();
+            self.message = Some(String::from("Setlist cleared; it starts again with the next track"));
+        } else if let Some(path) = cmd.strip_prefix("setlist") {
+            self.export_setlist(path.trim());
+        } else if let Some(args) = cmd.strip_prefix("queue export") {
+            self.export_queue(args.trim());
+        } else if let Some(name) = cmd.strip_prefix("queue ") {
+            let name = name.trim().to_string();
+            self.message = Some(match self.player.switch_queue(&name) {
+                Ok(()) => format!("Queue: {} ({} tracks)", name, self.player.playback.queue.len()),
+                Err(e) => format!("Error saving queues: {}", e),
+            });
+        } else if cmd == "tabnew" {
+            self.open_session();
+        } else if cmd == "tabclose" {
+            self.close_session();
+        } else if cmd == "reveal" {
+            let index = self.selected_song().unwrap_or(self.player.playback.current);
+            self.reveal(index);
+        } else if cmd == "preload" || cmd.starts_with("preload ") {
+            self.preload_queued(cmd.trim_start_matches("preload").trim());
+        } else if let Some(stars) = cmd.strip_prefix("rate ") {
+            let stars = stars.trim().to_string();
+            self.rate(&stars);
+        } else if let Some(name) = cmd.strip_prefix("rename ") {
+            let name = name.to_string();
+            self.rename_playlist(&name);
+        } else if let Some(path) = cmd.strip_prefix("export state ") {
+            let path = PathBuf::from(path.trim());
+            self.export_state(&path);
+        } else if let Some(path) = cmd.strip_prefix("import state ") {
+            let path = PathBuf::from(path.trim());
+            self.message = Some(match self.import_state(&path) {
+                Ok(summary) => summary,
+                Err(e) => format!("Import failed: {}", e),
+            });
+        } else if let Some(path) = cmd.strip_prefix("import ") {
+            let path = PathBuf::from(path.trim());
+            self.import_playlist(&path);
+        } else if let Some(what) = cmd.strip_prefix("restore ") {
+            let what = what.to_string();
+            self.restore(&what);
+        }
+    }
+
+    // `:preload` buffers what plays next, `:preload 3` the third queue entry
+    fn preload_queued(&mut self, position: &str) {
+        let index = if position.is_empty() {
+            self.player.playback.upcoming(self.player.songs.len())
+        } else {
+            match position.parse::<usize>() {
+                Ok(position) if position > 0 => self.player.playback.queue.get(position - 1).copied(),
+                _ => {
+                    self.message = Some(format!("Not a queue position: {}", position));
+                    return;
+                }
+            }
+        };
+        let Some(index) = index else {
+            self.message = Some("Nothing to preload".to_string());
+            return;
+        };
+        self.player.preload(index);
+        self.message = Some(format!("Preloading {}", self.player.songs[index].title));
+    }
+
+    fn update_power(&mut self) {
+        self.low_power = match self.power_mode {
+            PowerMode::Auto => power::on_battery(),
+            PowerMode::Normal => false,
+            PowerMode::Low => true,
+        };
+        let rate = if self.low_power { LOW_POWER_TICK_RATE } else { TICK_RATE };
+        self.tick_ms.store(rate.as_millis() as u64, Ordering::Relaxed);
+    }
+
+    // Whether a timer tick should redraw: always, except in low-power mode
+    // when nothing it could have changed did
+    fn tick_needs_draw(&mut self) -> bool {
+        let clock = self.screensaver.then(|| clock::hours_minutes(SystemTime::now(), self.utc_offset));
+        let view = (self.time_readout(), self.message.clone(), self.player.search_query.clone(), clock);
+        let changed = self.tick_view.as_ref() != Some(&view);
+        self.tick_view = Some(view);
+        changed || !self.low_power
+    }
+
+    // The same playback keys as on the karaoke screen
+    fn handle_big_now_key(&mut self, key: KeyEvent) {
+        match key.code {
+            KeyCode::Esc | KeyCode::Char('q') => self.big_now = false,
+            _ => self.handle_karaoke_key(key),
+        }
+    }
+
+    // Only playback keys work on the karaoke screen
+    fn handle_karaoke_key(&mut self, key: KeyEvent) {
+        match key.code {
+            KeyCode::Esc | KeyCode::Char('q') => self.karaoke = false,
+            KeyCode::Char('p') | KeyCode::Char(' ') => self.player.toggle_playback(),
+            KeyCode::Char('h') => self.player.previous(),
+            KeyCode::Char('l') => self.player.next(),
+            KeyCode::Char('+') | KeyCode::Char('=') => self.player.set_volume(0.05),
+            KeyCode::Char('-') => self.player.set_volume(-0.05),
+            KeyCode::Char('m') => self.player.toggle_mute(),
+            _ => {}
+        }
+    }
+
+    // Every recording found more than once, best mastered version first
+    fn show_duplicates(&mut self) {
+        let songs = &self.player.songs;
+        let groups = analysis::duplicates(songs);
+        if groups.is_empty() {
+            self.message = Some("No duplicates in the library".to_string());
+            return;
+        }
+        let mut lines = Vec::new();
+        for group in &groups {
+            lines.push(songs[group[0]].label.clone());
+            for &i in group {
+                let dr = songs[i].dr.map_or(String::from("DR?"), |dr| format!("DR{}", dr));
+                lines.push(format!("  {:>4}  {}", dr, songs[i].path.display()));
+            }
+        }
+        if groups.iter().flatten().any(|&i| songs[i].dr.is_none()) {
+            lines.push(String::new());
+            lines.push("DR? tracks haven't been measured; run :analyze".to_string());
+        }
+        self.report = Some(Report {
+            title: format!("Duplicates ({})", groups.len()),
+            lines,
+            scroll: 0,
+        });
+    }
+
+    // Files the decoders struggled with, the ones that keep failing first
+    fn show_broken(&mut self) {
+        let problems = self.player.analysis.problems();
+        if problems.is_empty() {
+            self.message = Some("No decoding problems recorded".to_string());
+            return;
+        }
+        let mut lines = Vec::new();
+        for (path, analysis) in &problems {
+            lines.push(path.display().to_string());
+            let outcome = match &analysis.diagnostics.decoder {
+                Some(decoder) => format!("  Plays with the {} decoder", decoder),
+                None => String::from("  No decoder can read it"),
+            };
+            lines.push(match analysis.failures {
+                0 => outcome,
+                n => format!("{}, failed {} times", outcome, n),
+            });
+            lines.extend(analysis.diagnostics.errors.iter().map(|e| format!("    {}", e)));
+        }
+        self.report = Some(Report {
+            title: format!("Decoding problems ({})", problems.len()),
+            lines,
+            scroll: 0,
+        });
+    }
+
+    // Artist names that look like spellings of one another, as `:artists
+    // merge` would merge them
+    fn show_artist_variants(&mut self) {
+        let suggested = artists::suggest(&self.player.songs);
+        if suggested.is_empty() {
+            self.message = Some(String::from("No artist names to merge"));
+            return;
+        }
+        let mut lines = Vec::new();
+        for (name, variants) in &suggested {
+            lines.push(name.clone());
+            lines.extend(variants.iter().map(|variant| format!("  {}", variant)));
+        }
+        lines.push(String::new());
+        lines.push(String::from(":artists merge to show each group as its first name"));
+        self.report = Some(Report {
+            title: format!("Artist names to merge ({})", suggested.len()),
+            lines,
+            scroll: 0,
+        });
+    }
+
+    // The tags are left alone: the merge is kept in the state file
+    fn merge_artist_variants(&mut self) {
+        let suggested = artists::suggest(&self.player.songs);
+        let aliases: Vec<(String, String)> = suggested.iter()
+            .flat_map(|(name, variants)| variants.iter().map(move |variant| (variant.clone(), name.clone())))
+            .collect();
+        if aliases.is_empty() {
+            self.message = Some(String::from("No artist names to merge"));
+            return;
+        }
+        self.state.artist_aliases.get_or_insert_with(BTreeMap::new).extend(aliases.iter().cloned());
+        self.player.add_aliases(aliases);
+        self.message = Some(format!("Merged {} artist names", suggested.len()));
+        self.save_state();
+    }
+
+    fn show_misencoded(&mut self) {
+        let lines: Vec<String> = self.player.songs.iter()
+            .filter_map(|song| {
+                let mut fixed = song.clone();
+                mojibake::repair_song(&mut fixed).then(|| format!("{}  →  {}", song.label, fixed.label))
+            })
+            .collect();
+        if lines.is_empty() {
+            self.message = Some(String::from("No tags in the wrong encoding"));
+            return;
+        }
+        self.report = Some(Report {
+            title: format!("Tags in the wrong encoding ({})", lines.len()),
+            lines: lines.into_iter()
+                .chain([String::new(), String::from(":encoding repair to show them fixed, :encoding write to fix the files too")])
+                .collect(),
+            scroll: 0,
+        });
+    }
+
+    fn show_deliveries(&mut self) {
+        let deliveries = self.player.announcer.deliveries();
+        if deliveries.is_empty() {
+            self.message = Some(String::from("No webhook deliveries yet"));
+            return;
+        }
+        let failed = deliveries.iter().filter(|delivery| delivery.error.is_some()).count();
+        self.report = Some(Report {
+            title: format!("Webhook deliveries ({} failed)", failed),
+            lines: deliveries.iter().map(|delivery| delivery.line(self.utc_offset)).collect(),
+            scroll: 0,
+        });
+    }
+
+    // `write` saves the fixed tags to the files as UTF-8, otherwise they're
+    // only shown fixed until the next scan
+    fn repair_encodings(&mut self, write: bool) {
+        let mut failed = Vec::new();
+        if write {
+            // The playing file is left alone, as with tag edits
+            let player = &self.player;
+            let playing = (player.playback.state != PlaybackState::Stopped).then_some(player.playback.current);
+            for (index, song) in player.songs.iter().enumerate().filter(|(_, song)| mojibake::needs_repair(song)) {
+                if Some(index) == playing {
+                    failed.push(format!("{}: playing; its tags can be written once it stops", song.path.display()));
+                    continue;
+                }
+                if let Err(e) = mojibake::rewrite(&song.path) {
+                    failed.push(format!("{}: {}", song.path.display(), e));
+                }
+            }
+        }
+        let repaired = self.player.repair_encodings();
+        self.message = Some(match (repaired, failed.last()) {
+            (0, _) => String::from("No tags in the wrong encoding"),
+            (_, None) if write => format!("Fixed the tags of {} songs", repaired),
+            (_, None) => format!("Showing the tags of {} songs fixed; :encoding write saves them", repaired),
+            (_, Some(e)) => format!("Fixed the tags of {} songs, {} files couldn't be written ({})", repaired, failed.len(), e),
+        });
+    }
+
+    // Streak and how the configured goals are going
+    fn show_stats(&mut self) {
+        let songs = &self.player.songs;
+        let album = |path: &Path| self.player.find_song(path).map(|index| (songs[index].album.clone(), songs[index].artist.clone()));
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs());
+        let progress = streaks::progress(&self.player.stats.history, album, now, self.utc_offset);
+        let mut lines = vec![
+            format!("Streak: {} days (longest {})", progress.streak, progress.longest),
+            format!("Played today: {}", progress.plays_today),
+            format!("New albums this week: {}", progress.new_albums),
+        ];
+        if let Some(goal) = self.goals.daily_plays {
+            lines.push(format!("Daily plays:  {}", streaks::bar(progress.plays_today, goal)));
+        }
+        if let Some(goal) = self.goals.weekly_new_albums {
+            lines.push(format!("Weekly albums: {}", streaks::bar(progress.new_albums, goal)));
+        }
+        self.report = Some(Report {
+            title: String::from("Listening stats"),
+            lines,
+            scroll: 0,
+        });
+    }
+
+    // Decodes every song the view lists on a worker thread, one after another
+    fn start_verify(&mut self) {
+        if self.verifying > 0 {
+            self.message = Some(format!("Already verifying, {} files left", self.verifying));
+            return;
+        }
+        let paths: Vec<PathBuf> = self.listed_songs().into_iter()
+            .map(|index| self.player.songs[index].path.clone())
+            .collect();
+        if paths.is_empty() {
+            self.message = Some("No songs listed to verify".to_string());
+            return;
+        }
+        self.verifying = paths.len();
+        self.verified.clear();
+        self.message = Some(format!("Verifying {} files", paths.len()));
+        let events = self.player.events.clone();
+        thread::spawn(move || {
+            let mut remaining = paths.len();
+            for path in paths {
+                remaining -= 1;
+                let verdict = verify::verify(&path);
+                if events.send(Action::Verified(path, verdict, remaining)).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+
+    // Once the last file is in, the ones with problems are listed
+    fn handle_verified(&mut self, path: PathBuf, verdict: Verdict, remaining: usize) {
+        self.verified.push((path, verdict));
+        self.verifying = remaining;
+        if remaining > 0 {
+            self.message = Some(format!("Verifying: {} files left", remaining));
+            return;
+        }
+        let problems: Vec<&(PathBuf, Verdict)> = self.verified.iter().filter(|(_, verdict)| verdict.is_problem()).collect();
+        if problems.is_empty() {
+            let checked = self.verified.iter().filter(|(_, verdict)| *verdict == Verdict::Intact { md5_checked: true }).count();
+            self.message = Some(format!("All {} files intact, {} checked against their MD5", self.verified.len(), checked));
+            return;
+        }
+        let mut lines = Vec::new();
+        for (path, verdict) in &problems {
+            lines.push(path.display().to_string());
+            lines.push(format!("  {}", verdict.describe()));
+        }
+        self.report = Some(Report {
+            title: format!("Verification: {} of {} files damaged", problems.len(), self.verified.len()),
+            lines,
+            scroll: 0,
+        });
+    }
+
+    // The main player pauses so only the decks are heard
+    fn open_decks(&mut self) {
+        if self.player.playback.is_active() {
+            self.player.toggle_playback();
+        }
+        self.decks = Some(Decks::default());
+        self.message = Some("Decks: a/l load the next queued track into deck A/B".to_string());
+    }
+
+    // Deck A on the left hand, deck B on the right, crossfader on the arrows
+    fn handle_deck_key(&mut self, key: KeyEvent) {
+        match key.code {
+            KeyCode::Esc | KeyCode::Char('q') => {
+                let _ = self.player._player_tx.send(PlayerMessage::StopDecks);
+                self.decks = None;
+            }
+            KeyCode::Char('a') => self.load_deck(DeckSide::A),
+            KeyCode::Char('l') => self.load_deck(DeckSide::B),
+            KeyCode::Char('s') => self.toggle_deck(DeckSide::A),
+            KeyCode::Char('k') => self.toggle_deck(DeckSide::B),
+            KeyCode::Char('d') => self.cycle_deck_filter(DeckSide::A),
+            KeyCode::Char('j') => self.cycle_deck_filter(DeckSide::B),
+            KeyCode::Char('w') => self.adjust_decks(|decks| decks.a.change_volume(0.05)),
+            KeyCode::Char('x') => self.adjust_decks(|decks| decks.a.change_volume(-0.05)),
+            KeyCode::Char('o') => self.adjust_decks(|decks| decks.b.change_volume(0.05)),
+            KeyCode::Char(',') => self.adjust_decks(|decks| decks.b.change_volume(-0.05)),
+            KeyCode::Left => self.adjust_decks(|decks| decks.move_crossfader(-0.1)),
+            KeyCode::Right => self.adjust_decks(|decks| decks.move_crossfader(0.1)),
+            KeyCode::Char('c') => self.adjust_decks(|decks| decks.crossfader = 0.0),
+            _ => {}
+        }
+    }
+
+    // The queue is the crate: each load takes the track at its front
+    fn load_deck(&mut self, side: DeckSide) {
+        let Some(decks) = self.decks.as_mut() else {
+            return;
+        };
+        let Some(index) = self.player.playback.queue.pop_front() else {
+            self.message = Some("Queue is empty; queue tracks to load them onto the decks".to_string());
+            return;
+        };
+        let song = self.player.songs[index].clone();
+        let _ = self.player._player_tx.send(PlayerMessage::LoadDeck(side, song.path.clone()));
+        self.message = Some(format!("Deck {:?}: {}", side, song.title));
+        let deck = decks.deck(side);
+        let filter = deck.filter;
+        deck.load(song);
+        let _ = self.player._player_tx.send(PlayerMessage::DeckFilter(side, filter));
+        self.send_deck_gains();
+    }
+
+    fn toggle_deck(&mut self, side: DeckSide) {
+        if let Some(decks) = self.decks.as_mut() {
+            let playing = decks.deck(side).toggle();
+            let _ = self.player._player_tx.send(PlayerMessage::PlayDeck(side, playing));
+        }
+    }
+
+    fn cycle_deck_filter(&mut self, side: DeckSide) {
+        if let Some(decks) = self.decks.as_mut() {
+            let deck = decks.deck(side);
+            deck.filter = deck.filter.next();
+            let _ = self.player._player_tx.send(PlayerMessage::DeckFilter(side, deck.filter));
+        }
+    }
+
+    // Volume and crossfader changes, passed on to the audio thread as gains
+    fn adjust_decks(&mut self, change: impl FnOnce(&mut Decks)) {
+        if let Some(decks) = self.decks.as_mut() {
+            change(decks);
+            self.send_deck_gains();
+        }
+    }
+
+    fn send_deck_gains(&self) {
+        if let Some(decks) = &self.decks {
+            for side in [DeckSide::A, DeckSide::B] {
+                let _ = self.player._player_tx.send(PlayerMessage::DeckGain(side, decks.gain(side)));
+            }
+        }
+    }
+
+    // An empty pad takes the current position, a set one jumps to it
+    fn handle_perform_key(&mut self, key: KeyEvent) {
+        let clearing = std::mem::take(&mut self.clearing_cue);
+        match key.code {
+            KeyCode::Esc | KeyCode::Char('q') => self.perform = false,
+            KeyCode::Char('x') => {
+                self.clearing_cue = true;
+                self.message = Some("Press the pad to clear".to_string());
+            }
+            KeyCode::Char(c @ '1'..='8') => {
+                let pad = c as usize - '1' as usize;
+                self.press_pad(pad, clearing);
+            }
+            KeyCode::Char('p') | KeyCode::Char(' ') => self.player.toggle_playback(),
+            KeyCode::Char('h') => self.player.previous(),
+            KeyCode::Char('l') => self.player.next(),
+            _ => {}
+        }
+    }
+
+    fn press_pad(&mut self, pad: usize, clearing: bool) {
+        let Some(path) = self.player.songs.get(self.player.playback.current).map(|song| song.path.clone()) else {
+            return;
+        };
+        let changed = if clearing {
+            self.cues.clear(&path, pad);
+            self.message = Some(format!("Cue {} cleared", pad + 1));
+            true
+        } else if let Some(at) = self.cues.get(&path, pad) {
+            self.player.seek(at);
+            false
+        } else if let Some((position, _)) = self.track_position() {
+            self.cues.set(&path, pad, position);
+            self.message = Some(format!("Cue {} set at {}", pad + 1, format_duration(position)));
+            true
+        } else {
+            self.message = Some("Play the track to set cues".to_string());
+            false
+        };
+        if changed {
+            if let Err(e) = self.cues.save() {
+                self.message = Some(format!("Error saving cues: {}", e));
+            }
+        }
+    }
+
+    // Marks the playing position as where the track starts (`[`) or stops
+    // (`]`) on every play from now on
+    fn set_trim(&mut self, start: bool) {
+        let Some(path) = self.player.songs.get(self.player.playback.current).map(|song| song.path.clone()) else {
+            return;
+        };
+        let Some((position, _)) = self.track_position() else {
+            self.message = Some("Play the track to trim it".to_string());
+            return;
+        };
+        let trims = &mut self.player.trims;
+        let result = if start { trims.set_start(&path, position) } else { trims.set_end(&path, position) };
+        self.message = Some(match result.and_then(|()| trims.save()) {
+            Ok(()) if start => format!("Starts at {} from now on", format_duration(position)),
+            Ok(()) => format!("Stops at {} from now on", format_duration(position)),
+            Err(e) => format!("Error: {}", e),
+        });
+    }
+
+    fn clear_trim(&mut self) {
+        let Some(path) = self.player.songs.get(self.player.playback.current).map(|song| song.path.clone()) else {
+            return;
+        };
+        if !self.player.trims.clear(&path) {
+            self.message = Some("This track isn't trimmed".to_string());
+            return;
+        }
+        self.message = Some(match self.player.trims.save() {
+            Ok(()) => "Plays in full again".to_string(),
+            Err(e) => format!("Error saving trims: {}", e),
+        });
+    }
+
+    // Typing into the search box
+    fn handle_search_key(&mut self, key: KeyEvent) {
+        match key.code {
+            KeyCode::Esc => self.search_mode = false,
+            KeyCode::Tab => {
+                self.player.search_scope = self.player.search_scope.next();
+                self.player.search_stale = true;
+            }
+            KeyCode::Char(c) => {
+                self.search_input.push(c);
+                self.last_search_edit = Instant::now();
+            }
+            KeyCode::Backspace => {
+                self.search_input.pop();
+                self.last_search_edit = Instant::now();
+            }
+            _ => {}
+        }
+    }
+
+    // Keys that work the same whichever pane has focus; returns false if not handled
+    fn handle_global_key(&mut self, key: KeyEvent) -> Result<bool> {
+        if key.modifiers.contains(KeyModifiers::CONTROL) {
+            match key.code {
+                KeyCode::Char('h') | KeyCode::Left => self.focus = Pane::Library,
+                KeyCode::Char('l') | KeyCode::Right if self.layout.sidebar => self.focus = Pane::Queue,
+                KeyCode::Char('p') => self.palette = Some(Palette::default()),
+                _ => return Ok(false),
+            }
+            return Ok(true);
+        }
+
+        if std::mem::take(&mut self.pending_g) {
+            match key.code {
+                KeyCode::Char('t') => self.switch_session(self.session + 1),
+                KeyCode::Char('T') => self.switch_session(self.session + self.sessions.len() - 1),
+                _ => return Ok(false),
+            }
+            return Ok(true);
+        }
+
+        match key.code {
+            KeyCode::Char('g') => self.pending_g = true,
+            KeyCode::Char('q') => self.should_quit = true,
+            KeyCode::Char('s') => self.player.shuffle(),
+            KeyCode::Char('p') => self.player.toggle_playback(),
+            KeyCode::Char('h') => {
+                self.player.previous();
+                if self.player.playback.current < self.scroll_offset {
+                    self.scroll_offset = self.player.playback.current;
+                }
+            }
+            KeyCode::Char('l') => {
+                self.player.next();
+                if self.player.playback.current > self.scroll_offset {
+                    self.scroll_offset = self.player.playback.current;
+                }
+            }
+            KeyCode::Tab => self.player.view_mode = self.player.view_mode.next(self.player.browse.groups.len()),
+            KeyCode::BackTab => self.player.view_mode = self.player.view_mode.previous(self.player.browse.groups.len()),
+            // Shift for the long jump
+            KeyCode::Left | KeyCode::Right => {
+                let delta = if key.modifiers.contains(KeyModifiers::SHIFT) { LONG_SEEK } else { SHORT_SEEK };
+                self.player.seek_by(delta, key.code == KeyCode::Right);
+            }
+            KeyCode::Char('/') => {
+                self.search_mode = true;
+                self.focus = Pane::Library;
+                self.player.view_mode = ViewMode::Search;
+            }
+            KeyCode::Char('+') | KeyCode::Char('=') => self.player.set_volume(0.05),
+            KeyCode::Char('-') => self.player.set_volume(-0.05),
+            // In the Playlists tab m relinks missing entries
+            KeyCode::Char('m') if !(self.focus == Pane::Library && self.player.view_mode == ViewMode::Playlists) => {
+                self.player.toggle_mute();
+            }
+            KeyCode::Char('r') => self.player.playback.cycle_repeat(),
+            KeyCode::Char('N') => self.toggle_night(),
+            KeyCode::Char('M') => self.toggle_mono(),
+            KeyCode::Char('L') => self.show_lyrics = !self.show_lyrics,
+            KeyCode::Char('S') => self.cycle_library_sort(),
+            KeyCode::Char('t') => {
+                self.time_display = self.time_display.next();
+                self.state.time_display = Some(self.time_display);
+                self.save_state();
+            }
+            KeyCode::Char('y') => self.yank(|song| format!("{} – {}", song.artist, song.title), "track"),
+            KeyCode::Char('Y') => self.yank(|song| song.path.to_string_lossy().to_string(), "path"),
+            KeyCode::Char('z') => {
+                self.layout.sidebar = !self.layout.sidebar;
+                if !self.layout.sidebar {
+                    self.focus = Pane::Library;
+                }
+                self.save_layout();
+            }
+            KeyCode::Char('[') => self.set_trim(true),
+            KeyCode::Char(']') => self.set_trim(false),
+            KeyCode::Char('<') => self.resize_sidebar(5),
+            KeyCode::Char('>') => self.resize_sidebar(-5),
+            KeyCode::Char(':') => {
+                self.command_mode = true;
+                self.message = None;
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    fn handle_library_key(&mut self, key: KeyEvent) {
+        if self.player.view_mode == ViewMode::Playlists && self.handle_playlists_key(key) {
+            return;
+        }
+        match key.code {
+            KeyCode::Char('a') => self.queue_song(self.scroll_offset),
+            KeyCode::Char('j') if self.scroll_offset < self.player.songs.len().saturating_sub(1) => {
+                self.scroll_offset += 1;
+            }
+            KeyCode::Char('k') => self.scroll_offset = self.scroll_offset.saturating_sub(1),
+            KeyCode::Char(' ') => {
+                match self.player.view_mode {
+                    ViewMode::Search => {
+                        if let Some(&index) = self.player.search_results.get(self.scroll_offset) {
+                            self.player.play_index(index);
+                        }
+                    },
+                    _ => {
+                        self.player.play_index(self.scroll_offset);
+                    }
+                }
+            }
+            KeyCode::Esc if self.player.view_mode == ViewMode::Search => {
+                self.search_input.clear();
+                self.player.view_mode = ViewMode::AllSongs;
+            }
+            KeyCode::Enter => {
+                self.menu = self.menu_under_cursor();
+            }
+            KeyCode::Char('P') => {
+                if let Some(index) = self.selected_song() {
+                    self.picker = Some(PlaylistPicker::new(vec![index]));
+                }
+            }
+            KeyCode::Char('f') => self.toggle_pin(),
+            _ => {}
+        }
+    }
+
+    // The Songs tab's sections, those with anything in them, then every song
+    fn song_rows(&self) -> Vec<TreeRow<Section>> {
+        let roots = Section::ALL.into_iter()
+            .filter_map(|section| match self.section_songs(section).len() {
+                0 => None,
+                songs => Some(Node::Branch(section, songs)),
+            })
+            .chain((0..self.player.songs.len()).map(Node::Song))
+            .collect();
+        self.section_tree.rows(roots, |&section| self.section_songs(section).into_iter().map(Node::Song).collect())
+    }
+
+    fn section_songs(&self, section: Section) -> Vec<usize> {
+        let songs = &self.player.songs;
+        match section {
+            Section::RecentlyPlayed => self.player.stats.recently_played(songs, self.sections.recently_played),
+            Section::MostPlayed => self.player.stats.most_played(songs, self.sections.most_played),
+            Section::RecentlyAdded => library::recently_added(songs, self.sections.recently_added),
+        }
+    }
+
+    // Without sections the Songs tab is the plain list, which needs no rows
+    // worked out
+    fn has_sections(&self) -> bool {
+        self.sections != SectionsConfig::default()
+    }
+
+    fn folder_rows(&self) -> Vec<TreeRow<PathBuf>> {
+        let (songs, roots) = (&self.player.songs, &self.player.music_dirs);
+        self.folder_tree.rows(folders::entries(songs, roots, None), |dir| folders::entries(songs, roots, Some(dir)))
+    }
+
+    fn artist_rows(&self) -> Vec<TreeRow<Vec<String>>> {
+        let browse = &self.player.browse;
+        let pinned = &self.pins.artists;
+        self.artist_tree.rows(browse.artist_entries(&[], pinned), |path| browse.artist_entries(path, pinned))
+    }
+
+    // The Albums tab, pinned albums first
+    fn album_list(&self) -> Vec<&(String, String)> {
+        library::pinned_first(&self.player.browse.albums, &self.pins.albums)
+    }
+
+    // `f` in the Artists and Albums tabs; albums can be pinned from either
+    fn toggle_pin(&mut self) {
+        let (pinned, name) = match self.player.view_mode {
+            ViewMode::Artists => match self.artist_rows().get(self.scroll_offset).map(|row| &row.node) {
+                Some(Node::Branch(path, _)) => match path.as_slice() {
+                    [artist] => (library::toggle_pin(&mut self.pins.artists, artist.clone()), artist.clone()),
+                    [artist, album] => {
+                        (library::toggle_pin(&mut self.pins.albums, (album.clone(), artist.clone())), album.clone())
+                    }
+                    _ => return,
+                },
+                _ => return,
+            },
+            ViewMode::Albums => match self.album_list().get(self.scroll_offset).map(|&album| album.clone()) {
+                Some(album) => {
+                    // The cursor follows the album to or from the top
+                    let name = album.0.clone();
+                    let pinned = library::toggle_pin(&mut self.pins.albums, album.clone());
+                    self.scroll_offset = self.album_list().iter().position(|&other| *other == album).unwrap_or(0);
+                    (pinned, name)
+                }
+                None => return,
+            },
+            _ => return,
+        };
+        self.message = Some(format!("{} {}", if pinned { "Pinned" } else { "Unpinned" }, name));
+        self.state.pins = Some(self.pins.clone());
+        self.save_state();
+    }
+
+    fn group_rows(&self) -> Vec<TreeRow<Vec<String>>> {
+        let ViewMode::Grouped(group) = self.player.view_mode else {
+            return Vec::new();
+        };
+        let Some(index) = self.player.browse.groups.get(group) else {
+            return Vec::new();
+        };
+        let closed = Tree::default();
+        let tree = self.group_trees.get(&group).unwrap_or(&closed);
+        tree.rows(index.entries(&[]), |path| index.entries(path))
+    }
+
+    // Folders, Artists and the configured browse tabs are trees
+    fn handle_tree_view_key(&mut self, key: KeyEvent) -> bool {
+        match self.player.view_mode {
+            ViewMode::AllSongs if self.has_sections() => self.handle_tree_key(key, self.song_rows(), |app| &mut app.section_tree),
+            ViewMode::Folders => self.handle_tree_key(key, self.folder_rows(), |app| &mut app.folder_tree),
+            ViewMode::Artists => self.handle_tree_key(key, self.artist_rows(), |app| &mut app.artist_tree),
+            ViewMode::Grouped(group) => {
+                self.handle_tree_key(key, self.group_rows(), move |app| app.group_trees.entry(group).or_default())
+            }
+            _ => false,
+        }
+    }
+
+    // Space opens or closes a branch and plays a song, l opens a branch or
+    // steps into it, h closes it or steps out. Returns false for keys that
+    // mean the same here as in other views.
+    fn handle_tree_key<K: Clone + Eq + Hash>(
+        &mut self,
+        key: KeyEvent,
+        rows: Vec<TreeRow<K>>,
+        tree: impl FnOnce(&mut App) -> &mut Tree<K>,
+    ) -> bool {
+        let at = self.scroll_offset;
+        match (key.code, rows.get(at).map(|row| &row.node)) {
+            (KeyCode::Char('j'), _) => self.scroll_offset = (at + 1).min(rows.len().saturating_sub(1)),
+            (KeyCode::Char('k'), _) => self.scroll_offset = at.saturating_sub(1),
+            (KeyCode::Char(' '), Some(Node::Branch(branch, _))) => tree(self).toggle(branch.clone()),
+            (KeyCode::Char(' '), Some(&Node::Song(index))) => self.player.play_index(index),
+            (KeyCode::Char('a'), Some(&Node::Song(index))) => self.queue_song(index),
+            (KeyCode::Char(' ' | 'a'), _) => {}
+            (KeyCode::Char('l'), _) => self.scroll_offset = tree(self).open(&rows, at),
+            (KeyCode::Char('h') | KeyCode::Esc, _) => self.scroll_offset = tree(self).close(&rows, at),
+            _ => return false,
+        }
+        true
+    }
+
+    // Playlists tab: the list of playlists, or the tracks of the open one.
+    // Returns false for keys that mean the same here as in other views.
+    fn handle_playlists_key(&mut self, key: KeyEvent) -> bool {
+        let len = match self.selected_playlist {
+            Some(index) => self.player.playlists.get(index).map_or(0, |playlist| playlist.entries.len()),
+            None => self.player.playlists.len(),
+        };
+        match key.code {
+            KeyCode::Char('j') => self.scroll_offset = (self.scroll_offset + 1).min(len.saturating_sub(1)),
+            KeyCode::Char('k') => self.scroll_offset = self.scroll_offset.saturating_sub(1),
+            KeyCode::Char(' ') if self.selected_playlist.is_none() => {
+                if self.scroll_offset < len {
+                    let missing = self.player.missing_entries(self.scroll_offset).len();
+                    if missing > 0 {
+                        self.message = Some(format!("{} tracks not found; m to relink or drop them", missing));
+                    }
+                    self.selected_playlist = Some(self.scroll_offset);
+                    self.scroll_offset = 0;
+                }
+            }
+            KeyCode::Char('m') => {
+                if let Some(index) = self.playlist_under_cursor() {
+                    self.start_relink(index);
+                }
+            }
+            KeyCode::Char(' ') => match self.selected_song() {
+                Some(index) => self.player.play_index(index),
+                None => self.message = Some("Not in the library".to_string()),
+            },
+            KeyCode::Char('a') => {
+                if let Some(index) = self.selected_song() {
+                    self.queue_song(index);
+                }
+            }
+            KeyCode::Esc => {
+                if let Some(index) = self.selected_playlist.take() {
+                    self.scroll_offset = index;
+                }
+            }
+            // Move the track under the cursor down or up
+            KeyCode::Char('J') | KeyCode::Char('K') => {
+                let Some(playlist) = self.selected_playlist else {
+                    return true;
+                };
+                let target = if key.code == KeyCode::Char('J') {
+                    self.scroll_offset + 1
+                } else {
+                    self.scroll_offset.wrapping_sub(1)
+                };
+                if target < len {
+                    match self.player.swap_playlist_entries(playlist, self.scroll_offset, target) {
+                        Ok(()) => self.scroll_offset = target,
+                        Err(e) => self.message = Some(format!("Error saving playlist: {}", e)),
+                    }
+                }
+            }
+            KeyCode::Char('R') => {
+                if let Some(playlist) = self.playlist_under_cursor().and_then(|i| self.player.playlists.get(i)) {
+                    self.command_mode = true;
+                    self.command_input = format!("rename {}", playlist.name);
+                    self.message = None;
+                }
+            }
+            KeyCode::Char('D') => {
+                if let Some(index) = self.playlist_under_cursor() {
+                    let name = &self.player.playlists[index].name;
+                    self.message = Some(format!("Delete playlist {}? (y/n)", name));
+                    self.pending_delete = Some(index);
+                }
+            }
+            KeyCode::Char('c') => {
+                if let Some(index) = self.playlist_under_cursor() {
+                    match self.player.duplicate_playlist(index) {
+                        Ok(copy) => {
+                            self.message = Some(format!("Created {}", self.player.playlists[copy].name));
+                            // Indices after the copy shifted by one
+                            if let Some(open) = self.selected_playlist.as_mut().filter(|open| **open >= copy) {
+                                *open += 1;
+                            }
+                        }
+                        Err(e) => self.message = Some(format!("Error: {}", e)),
+                    }
+                }
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    // The open playlist, or the one the cursor is on in the list
+    fn playlist_under_cursor(&self) -> Option<usize> {
+        self.selected_playlist
+            .or(Some(self.scroll_offset))
+            .filter(|&index| index < self.player.playlists.len())
+    }
+
+    fn rename_playlist(&mut self, name: &str) {
+        let Some(index) = self.playlist_under_cursor() else {
+            self.message = Some("No playlist selected".to_string());
+            return;
+        };
+        match self.player.rename_playlist(index, name) {
+            Ok(new_index) => {
+                match &mut self.selected_playlist {
+                    Some(open) => *open = new_index,
+                    None => self.scroll_offset = new_index,
+                }
+                self.message = Some("Playlist renamed".to_string());
+            }
+            Err(e) => self.message = Some(format!("Error: {}", e)),
+        }
+    }
+
+    fn start_relink(&mut self, playlist: usize) {
+        let missing = self.player.missing_entries(playlist);
+        if missing.is_empty() {
+            self.message = Some("No missing tracks".to_string());
+            return;
+        }
+        self.relink = Some(Relink { playlist, missing, candidates: Vec::new(), selected: 0 });
+        self.next_missing();
+    }
+
+    // Looks up candidates for the entry now at the front, or finishes
+    fn next_missing(&mut self) {
+        let Some(relink) = self.relink.as_mut() else {
+            return;
+        };
+        let Some(&entry) = relink.missing.first() else {
+            self.relink = None;
+            self.message = Some("Done with missing tracks".to_string());
+            return;
+        };
+        let path = &self.player.playlists[relink.playlist].entries[entry];
+        relink.candidates = library::same_file_name(&self.player.songs, path);
+        relink.selected = 0;
+    }
+
+    fn handle_relink_key(&mut self, key: KeyEvent) {
+        let Some(relink) = self.relink.as_mut() else {
+            return;
+        };
+        let entry = relink.missing[0];
+        let result = match key.code {
+            KeyCode::Char('j') | KeyCode::Down => {
+                relink.selected = (relink.selected + 1).min(relink.candidates.len().saturating_sub(1));
+                return;
+            }
+            KeyCode::Char('k') | KeyCode::Up => {
+                relink.selected = relink.selected.saturating_sub(1);
+                return;
+            }
+            KeyCode::Enter => match relink.candidates.get(relink.selected) {
+                Some(&song) => self.player.relink_playlist_entry(relink.playlist, entry, Some(song)),
+                None => return,
+            },
+            KeyCode::Char('d') => {
+                let result = self.player.relink_playlist_entry(relink.playlist, entry, None);
+                if result.is_ok() {
+                    // Later entries moved up one
+                    for later in &mut relink.missing[1..] {
+                        *later -= 1;
+                    }
+                }
+                result
+            }
+            // Leave this one as it is
+            KeyCode::Char('n') => Ok(()),
+            KeyCode::Esc | KeyCode::Char('q') => {
+                self.relink = None;
+                return;
+            }
+            _ => return,
+        };
+        match result {
+            Ok(()) => {
+                relink.missing.remove(0);
+                self.next_missing();
+            }
+            Err(e) => self.message = Some(format!("Error saving playlist: {}", e)),
+        }
+    }
+
+    // "state" or a playlist name, optionally followed by which backup (1 is the newest)
+    fn restore(&mut self, what: &str) {
+        let (name, backup) = match what.trim().rsplit_once(' ') {
+            Some((name, n)) if n.parse::<usize>().is_ok() => (name.trim(), n.parse().unwrap_or(1)),
+            _ => (what.trim(), 1),
+        };
+        if !(1..=persist::BACKUPS).contains(&backup) {
+            self.message = Some(format!("Backups go from 1 (newest) to {}", persist::BACKUPS));
+            return;
+        }
+        let result = if name == "state" {
+            self.restore_state(backup)
+        } else {
+            self.player.restore_playlist(name, backup).map(|_| ())
+        };
+        self.message = Some(match result {
+            Ok(()) => format!("Restored {} from backup {}", name, backup),
+            Err(e) => format!("Restore failed: {}", e),
+        });
+    }
+
+    fn restore_state(&mut self, backup: usize) -> Result<()> {
+        let path = State::path().ok_or_else(|| anyhow::anyhow!("No data directory"))?;
+        persist::restore(&path, backup)?;
+        self.state = State::load();
+        self.apply_config(&Config::load()?);
+        Ok(())
+    }
+
+    // 1-5 stars for the selected song, or the playing one; 0 clears the rating
+    fn rate(&mut self, stars: &str) {
+        let rating = match stars.parse::<u8>() {
+            Ok(0) => None,
+            Ok(stars @ 1..=5) => Some(stars),
+            _ => {
+                self.message = Some("Ratings go from 1 to 5 stars, or 0 for none".to_string());
+                return;
+            }
+        };
+        let index = self.selected_song().unwrap_or(self.player.playback.current);
+        self.message = Some(match self.player.rate(index, rating) {
+            Ok(()) if rating.is_some() => format!("Rated {}", stats::stars_label(rating)),
+            Ok(()) => "Rating cleared".to_string(),
+            Err(e) => format!("Error saving rating: {}", e),
+        });
+    }
+
+    // `:queue export md|txt [file]`: the queue as a numbered tracklist, to the
+    // clipboard unless a file is given
+    fn export_queue(&mut self, args: &str) {
+        let (format, path) = args.split_once(' ').map_or((args, ""), |(format, path)| (format, path.trim()));
+        let markdown = match format {
+            "md" | "markdown" => true,
+            "txt" | "text" => false,
+            _ => {
+                self.message = Some(String::from("Usage: queue export md|txt [file]"));
+                return;
+            }
+        };
+        let songs = &self.player.songs;
+        let tracklist = queue::tracklist(self.player.playback.queue.iter().map(|&index| &songs[index]), markdown);
+        if path.is_empty() {
+            self.copy(tracklist, "tracklist");
+            return;
+        }
+        self.message = Some(match fs::write(path, tracklist) {
+            Ok(()) => format!("Tracklist of {} tracks written to {}", self.player.playback.queue.len(), path),
+            Err(e) => format!("Export failed: {}", e),
+        });
+    }
+
+    // `:setlist [file]`: what has played this session with start times from
+    // the first track, to the clipboard unless a file is given. A .cue file
+    // gets a cue sheet for a recording of the set.
+    fn export_setlist(&mut self, path: &str) {
+        let Some(&(first, ..)) = self.player.setlist.first() else {
+            self.message = Some(String::from("Nothing played yet this session"));
+            return;
+        };
+        let entries: Vec<setlist::Entry> = self.player.setlist.iter()
+            .map(|(at, artist, title)| setlist::Entry { at: at.duration_since(first), artist: artist.clone(), title: title.clone() })
+            .collect();
+        if path.is_empty() {
+            self.copy(setlist::text(&entries), "setlist");
+            return;
+        }
+        let path = Path::new(path);
+        let contents = match path.extension().and_then(|extension| extension.to_str()) {
+            Some(extension) if extension.eq_ignore_ascii_case("cue") => {
+                let recording = path.with_extension("wav");
+                setlist::cue(&entries, &recording.file_name().unwrap_or_default().to_string_lossy())
+            }
+            _ => setlist::text(&entries),
+        };
+        self.message = Some(match fs::write(path, contents) {
+            Ok(()) => format!("Setlist of {} tracks written to {}", entries.len(), path.display()),
+            Err(e) => format!("Export failed: {}", e),
+        });
+    }
+
+    fn export_state(&mut self, path: &Path) {
+        let config = Config::path().and_then(|path| fs::read_to_string(path).ok());
+        let archive = Archive::new(
+            config,
+            self.state.clone(),
+            self.player.music_dirs.clone(),
+            &self.player.songs,
+            self.player.playlist_records(),
+        );
+        self.message = Some(match archive.save(path) {
+            Ok(()) => format!(
+                "Exported {} songs and {} playlists to {}",
+                archive.songs.len(),
+                archive.playlists.len(),
+                path.display()
+            ),
+            Err(e) => format!("Export failed: {}", e),
+        });
+    }
+
+    // Replaces the config and state (the old ones are kept as backups), adds
+    // the music directories that exist here and brings in the playlists
+    fn import_state(&mut self, path: &Path) -> Result<String> {
+        let archive = Archive::load(path)?;
+        if let (Some(config), Some(config_path)) = (&archive.config, Config::path()) {
+            if let Some(dir) = config_path.parent() {
+                fs::create_dir_all(dir)?;
+            }
+            persist::write_atomic(&config_path, config)?;
+        }
+        archive.state.save()?;
+        self.state = archive.state.clone();
+        let config = Config::load()?;
+        // Only HTTP changes fail here, and those just wait for a restart
+        let _ = self.player.apply_config(&config);
+        self.apply_config(&config);
+        self.config_modified = config_modified();
+
+        let mut dirs = 0;
+        for dir in &archive.music_dirs {
+            if self.player.add_directory(dir.clone()).is_ok() {
+                dirs += 1;
+            }
+        }
+        let missing = self.player.restore_archived_playlists(&archive)?;
+        let mut summary = format!(
+            "Imported {} playlists and {} of {} music directories",
+            archive.playlists.len(),
+            dirs,
+            archive.music_dirs.len()
+        );
+        if missing > 0 {
+            summary.push_str(&format!("; {} playlist tracks not found (m in Playlists to relink)", missing));
+        }
+        Ok(summary)
+    }
+
+    fn import_playlist(&mut self, path: &Path) {
+        match self.player.import_playlist(path) {
+            Ok((index, result)) => {
+                let total = result.matched.len() + result.unmatched.len();
+                self.message = Some(format!(
+                    "Imported {} of {} tracks into {}",
+                    result.matched.len(),
+                    total,
+                    self.player.playlists[index].name
+                ));
+                if !result.unmatched.is_empty() {
+                    self.report = Some(Report {
+                        title: format!("Not in the library ({})", result.unmatched.len()),
+                        lines: result.unmatched.iter().map(|track| track.label()).collect(),
+                        scroll: 0,
+                    });
+                }
+            }
+            Err(e) => self.message = Some(format!("Import failed: {}", e)),
+        }
+    }
+
+    fn delete_playlist(&mut self, index: usize) {
+        match self.player.delete_playlist(index) {
+            Ok(()) => {
+                self.selected_playlist = None;
+                self.scroll_offset = index.min(self.player.playlists.len().saturating_sub(1));
+                self.message = Some("Playlist deleted".to_string());
+            }
+            Err(e) => self.message = Some(format!("Error: {}", e)),
+        }
+    }
+
+    // The Queue sidebar: move through upcoming songs, play or drop them. The
+    // cursor moves over rows, which may be album headers as well as tracks.
+    fn handle_queue_key(&mut self, key: KeyEvent) {
+        let rows = self.queue_rows();
+        self.queue_cursor = self.queue_cursor.min(rows.len().saturating_sub(1));
+        let row = rows.get(self.queue_cursor).cloned();
+        let queue = &mut self.player.playback.queue;
+        match (key.code, row) {
+            (KeyCode::Char('j'), _) if self.queue_cursor + 1 < rows.len() => self.queue_cursor += 1,
+            (KeyCode::Char('k'), _) => self.queue_cursor = self.queue_cursor.saturating_sub(1),
+            (KeyCode::Char(' '), Some(QueueRow::Album { positions, .. })) => self.toggle_album(positions.start),
+            (KeyCode::Char(' '), Some(QueueRow::Track(position))) => {
+                if let Some(index) = self.player.playback.take_queued(position) {
+                    self.player.play_index(index);
+                }
+            }
+            (KeyCode::Char('c'), _) => {
+                let count = self.player.playback.played.len();
+                self.player.playback.played.clear();
+                self.message = Some(format!("Cleared {} played tracks", count));
+            }
+            (KeyCode::Char('d') | KeyCode::Delete, Some(QueueRow::Album { positions, .. })) => {
+                let count = positions.len();
+                queue.drain(positions);
+                self.message = Some(format!("Removed {} tracks from queue", count));
+            }
+            (KeyCode::Char('d') | KeyCode::Delete, Some(QueueRow::Track(position))) => {
+                queue.remove(position);
+                self.message = Some("Removed from queue".to_string());
+            }
+            (KeyCode::Enter, Some(QueueRow::Track(position))) => {
+                self.menu = Some(ActionMenu::song(queue[position], &self.player.songs[queue[position]]));
+            }
+            (KeyCode::Char('P'), Some(QueueRow::Track(position))) => {
+                self.picker = Some(PlaylistPicker::new(vec![queue[position]]));
+            }
+            (KeyCode::Char('H'), _) => self.harmonic_sort_queue(),
+            (KeyCode::Esc, _) => self.focus = Pane::Library,
+            _ => {}
+        }
+    }
+
+    // Reorders the queue so each track is in a key that mixes into the next,
+    // carrying on from the playing track
+    fn harmonic_sort_queue(&mut self) {
+        let queue: Vec<usize> = self.player.playback.queue.iter().copied().collect();
+        let playing = (self.player.playback.state != PlaybackState::Stopped)
+            .then(|| self.player.songs.get(self.player.playback.current))
+            .flatten();
+        let ordered = harmony::harmonic_order(&self.player.songs, &queue, playing);
+        let clashes = ordered.windows(2)
+            .filter_map(|pair| self.player.songs[pair[0]].key.zip(self.player.songs[pair[1]].key))
+            .filter(|(a, b)| !a.is_compatible(*b))
+            .count();
+        let unkeyed = ordered.iter().filter(|&&i| self.player.songs[i].key.is_none()).count();
+        self.player.playback.queue = ordered.into();
+        self.message = Some(match (clashes, unkeyed) {
+            (0, 0) => "Queue ordered for harmonic mixing".to_string(),
+            _ => format!("Queue ordered for harmonic mixing: {} key clashes, {} tracks without a key", clashes, unkeyed),
+        });
+    }
+
+    fn queue_rows(&self) -> Vec<QueueRow> {
+        queue::rows(&self.player.playback.queue, &self.player.songs, |song| {
+            self.expanded_albums.contains(&(song.album.clone(), song.artist.clone()))
+        })
+    }
+
+    // Expands or collapses the album of the run starting at queue `position`
+    fn toggle_album(&mut self, position: usize) {
+        let Some(song) = self.player.playback.queue.get(position).and_then(|&i| self.player.songs.get(i)) else {
+            return;
+        };
+        let key = (song.album.clone(), song.artist.clone());
+        if !self.expanded_albums.remove(&key) {
+            self.expanded_albums.insert(key);
+        }
+    }
+
+    fn reveal(&mut self, index: usize) {
+        let Some(song) = self.player.songs.get(index) else {
+            return;
+        };
+        if let Err(e) = reveal_in_file_manager(&song.path) {
+            self.message = Some(format!("Error opening file manager: {}", e));
+        }
+    }
+
+    // Shown in the terminal title bar and, inside tmux, the status line
+    fn window_title(&self) -> String {
+        let playback = &self.player.playback;
+        let icon = if let PlaybackState::Paused { .. } = playback.state { "⏸" } else { "▶" };
+        match (self.player.songs.get(playback.current), self.time_readout()) {
+            (Some(song), Some(time)) => format!("{} {} [{}]", icon, song.label, time),
+            _ => String::from("Music Player"),
+        }
+    }
+
+    // Where playback is in the current track and how long that is; None when stopped
+    fn track_position(&self) -> Option<(Duration, Option<Duration>)> {
+        let position = match self.player.playback.state {
+            PlaybackState::Stopped => return None,
+            PlaybackState::Paused { position } => position,
+            PlaybackState::Playing | PlaybackState::Transitioning => self.player.position(),
+        };
+        let song = self.player.songs.get(self.player.playback.current)?;
+        Some((position, song.duration))
+    }
+
+    // Position in the current track as elapsed, remaining or percent
+    fn time_readout(&self) -> Option<String> {
+        let (position, length) = self.track_position()?;
+        Some(self.time_display.format(position, length))
+    }
+
+    // Read-ahead fill level, when asked for in the config, with any dropouts
+    fn buffer_readout(&self) -> Option<String> {
+        if !self.player.output.show_buffer {
+            return None;
+        }
+        let level = self.player.buffer.lock().unwrap().clone()?;
+        let percent = (level.fraction() * 100.0).round();
+        Some(match level.underruns() {
+            0 => format!("Buf {}%", percent),
+            n => format!("Buf {}% ({} dropouts)", percent, n),
+        })
+    }
+
+    // Copies something about the current track to the system clipboard
+    fn yank(&mut self, text: impl Fn(&Song) -> String, what: &str) {
+        let Some(song) = self.player.songs.get(self.player.playback.current) else {
+            return;
+        };
+        let text = text(song);
+        self.copy(text, what);
+    }
+
+    fn copy(&mut self, text: String, what: &str) {
+        let result = match &mut self.clipboard {
+            Some(clipboard) => clipboard.set_text(text),
+            None => arboard::Clipboard::new().and_then(|clipboard| {
+                self.clipboard.insert(clipboard).set_text(text)
+            }),
+        };
+        self.message = Some(match result {
+            Ok(()) => format!("Copied {} to clipboard", what),
+            Err(e) => format!("Clipboard error: {}", e),
+        });
+    }
+
+    // Positive grows the sidebar, negative gives the song list more room
+    fn resize_sidebar(&mut self, delta: i16) {
+        if !self.layout.sidebar {
+            return;
+        }
+        self.layout.sidebar_width = self.layout.sidebar_width
+            .saturating_add_signed(delta)
+            .clamp(LayoutConfig::MIN_SIDEBAR_WIDTH, LayoutConfig::MAX_SIDEBAR_WIDTH);
+        self.save_layout();
+    }
+
+    fn save_layout(&mut self) {
+        self.state.layout = Some(self.layout);
+        self.save_state();
+    }
+
+    // Also kept in the state file. Header sorts of the lists it orders are
+    // undone by it, so their arrows go.
+    fn cycle_library_sort(&mut self) {
+        let sort = self.player.library_sort.map_or(LibrarySort::Title, LibrarySort::next);
+        self.player.set_library_sort(Some(sort));
+        self.sort = self.sort.filter(|sort| !matches!(sort.view, ViewMode::AllSongs | ViewMode::Artists | ViewMode::Search));
+        self.state.library_sort = Some(sort);
+        self.save_state();
+        self.message = Some(format!("Library sorted by {}", sort.name()));
+    }
+
+    // Kept in the state file, so it stays as left until toggled again
+    fn toggle_night(&mut self) {
+        let enabled = !compressor::enabled(&self.player.night);
+        compressor::set_enabled(&self.player.night, enabled);
+        self.state.night = Some(enabled);
+        self.save_state();
+        self.message = Some(format!("Night mode {}", if enabled { "on" } else { "off" }));
+    }
+
+    fn toggle_mono(&mut self) {
+        let mono = !self.player.mono;
+        self.player.set_mono(mono);
+        self.state.mono = Some(mono);
+        self.save_state();
+        self.message = Some(format!("Mono {}", if mono { "on" } else { "off" }));
+    }
+
+    fn save_state(&mut self) {
+        if let Err(e) = self.state.save() {
+            self.message = Some(format!("Error saving state: {}", e));
+        }
+    }
+
+    // Clicking a column header sorts that list by it; clicking again reverses it
+    fn handle_mouse(&mut self, mouse: MouseEvent) {
+        if mouse.kind != MouseEventKind::Down(MouseButton::Left) {
+            return;
+        }
+        let clicked = self.header_cells.iter().find(|cell| {
+            mouse.row == cell.area.y && mouse.column >= cell.area.x && mouse.column < cell.area.right()
+        });
+        if let Some(&HeaderCell { view, column, .. }) = clicked {
+            self.sort_list(view, column);
+        }
+    }
+
+    fn sort_list(&mut self, view: ViewMode, column: Column) {
+        let descending = self.sort == Some(SortOrder { view, column, descending: false });
+        let player = &mut self.player;
+        match view {
+            ViewMode::AllSongs => player.sort_songs(column, descending),
+            // Each album's tracks; artists and albums stay in name order
+            ViewMode::Artists => {
+                for songs in player.browse.albums.values_mut() {
+                    columns::sort(songs, &player.songs, column, descending);
+                }
+            }
+            ViewMode::Queue => {
+                columns::sort(player.playback.queue.make_contiguous(), &player.songs, column, descending);
+            }
+            ViewMode::Search => columns::sort(&mut player.search_results, &player.songs, column, descending),
+            ViewMode::Albums | ViewMode::Genres | ViewMode::Playlists | ViewMode::Folders | ViewMode::Grouped(_) => return,
+        }
+        self.sort = Some(SortOrder { view, column, descending });
+    }
+
+    // Every song the current view lists, in its order; none for lists of groups
+    fn listed_songs(&self) -> Vec<usize> {
+        match self.player.view_mode {
+            ViewMode::AllSongs => (0..self.player.songs.len()).collect(),
+            ViewMode::Artists => (0..self.player.songs.len()).collect(),
+            ViewMode::Queue => self.player.playback.queue.iter().copied().collect(),
+            ViewMode::Search => self.player.search_results.clone(),
+            ViewMode::Playlists => self.selected_playlist
+                .and_then(|index| self.player.playlists.get(index))
+                .map(|playlist| playlist.entries.iter().filter_map(|path| self.player.find_song(path)).collect())
+                .unwrap_or_default(),
+            // Everything in the tree, whichever branches are open
+            ViewMode::Folders => folders::songs_under(&self.player.songs, &self.player.music_dirs),
+            ViewMode::Grouped(group) => self.player.browse.groups.get(group).map(|index| index.songs(&[])).unwrap_or_default(),
+            ViewMode::Albums | ViewMode::Genres => Vec::new(),
+        }
+    }
+
+    // Song under the cursor, if the current view lists songs rather than groups
+    fn selected_song(&self) -> Option<usize> {
+        match self.player.view_mode {
+            ViewMode::AllSongs if self.has_sections() => tree::song_at(&self.song_rows(), self.scroll_offset),
+            ViewMode::AllSongs => Some(self.scroll_offset).filter(|&i| i < self.player.songs.len()),
+            ViewMode::Artists => tree::song_at(&self.artist_rows(), self.scroll_offset),
+            ViewMode::Queue => self.player.playback.queue.get(self.scroll_offset).copied(),
+            ViewMode::Search => self.player.search_results.get(self.scroll_offset).copied(),
+            ViewMode::Playlists => self.selected_playlist
+                .and_then(|index| self.player.playlists.get(index))
+                .and_then(|playlist| playlist.entries.get(self.scroll_offset))
+                .and_then(|path| self.player.find_song(path)),
+            ViewMode::Folders => tree::song_at(&self.folder_rows(), self.scroll_offset),
+            ViewMode::Grouped(_) => tree::song_at(&self.group_rows(), self.scroll_offset),
+            ViewMode::Albums | ViewMode::Genres => None,
+        }
+    }
+
+    fn handle_menu_key(&mut self, key: KeyEvent) {
+        let Some(menu) = self.menu.as_mut() else {
+            return;
+        };
+        let actions = menu.target.actions();
+        match key.code {
+            KeyCode::Char('j') | KeyCode::Down => {
+                menu.selected = (menu.selected + 1).min(actions.len() - 1);
+            }
+            KeyCode::Char('k') | KeyCode::Up => {
+                menu.selected = menu.selected.saturating_sub(1);
+            }
+            KeyCode::Enter => {
+                let action = actions[menu.selected];
+                if let Some(menu) = self.menu.take() {
+                    self.run_menu_action(menu.songs, action);
+                }
+            }
+            KeyCode::Esc | KeyCode::Char('q') => self.menu = None,
+            _ => {}
+        }
+    }
+
+    // The action menu for what the cursor is on: a song, or an album, artist
+    // or playlist to do the same with all of its songs
+    fn menu_under_cursor(&self) -> Option<ActionMenu> {
+        if let Some(index) = self.selected_song() {
+            return Some(ActionMenu::song(index, &self.player.songs[index]));
+        }
+        let browse = &self.player.browse;
+        let album = |album: &String, artist: &String| {
+            let songs = browse.albums.get(&(album.clone(), artist.clone()))?.clone();
+            Some(ActionMenu::new(MenuTarget::Album, album.clone(), songs))
+        };
+        match self.player.view_mode {
+            ViewMode::Albums => self.album_list().get(self.scroll_offset).and_then(|(name, artist)| album(name, artist)),
+            ViewMode::Artists => match &self.artist_rows().get(self.scroll_offset)?.node {
+                Node::Branch(path, _) => match path.as_slice() {
+                    [artist] => Some(ActionMenu::new(MenuTarget::Artist, artist.clone(), browse.artists.get(artist)?.clone())),
+                    [artist, name] => album(name, artist),
+                    _ => None,
+                },
+                Node::Song(_) => None,
+            },
+            ViewMode::Playlists if self.selected_playlist.is_none() => {
+                let playlist = self.player.playlists.get(self.scroll_offset)?;
+                let songs = playlist.entries.iter().filter_map(|path| self.player.find_song(path)).collect();
+                Some(ActionMenu::new(MenuTarget::Playlist, playlist.name.clone(), songs))
+            }
+            _ => None,
+        }
+    }
+
+    fn queue_song(&mut self, index: usize) {
+        self.message = Some(match self.player.add_to_queue(index) {
+            Ok(()) => "Added to queue".to_string(),
+            Err(e) => e.to_string(),
+        });
+    }
+
+    // `done` for one song, or with how many of `songs` it was done to
+    fn queued_message(done: &str, songs: usize, (queued, error): (usize, Option<anyhow::Error>)) -> String {
+        match (songs, error) {
+            (1, Some(e)) => e.to_string(),
+            (_, None) if songs == 1 => done.to_string(),
+            (_, None) => format!("{} ({} tracks)", done, queued),
+            (_, Some(e)) => format!("{} ({} of {} tracks; {})", done, queued, songs, e),
+        }
+    }
+
+    fn run_menu_action(&mut self, songs: Vec<usize>, action: MenuAction) {
+        let Some(&index) = songs.first() else {
+            self.message = Some("None of its tracks are in the library".to_string());
+            return;
+        };
+        match action {
+            // The rest of them follow, ahead of what was queued
+            MenuAction::Play => {
+                let queued = self.player.queue_songs(&songs[1..], true);
+                self.player.play_index(index);
+                if let (_, Some(e)) = queued {
+                    self.message = Some(format!("Queued {} of {} tracks; {}", queued.0, songs.len() - 1, e));
+                }
+            }
+            MenuAction::PlayNext => {
+                let queued = self.player.queue_songs(&songs, true);
+                self.message = Some(Self::queued_message("Playing next", songs.len(), queued));
+            }
+            MenuAction::AddToQueue => {
+                let queued = self.player.queue_songs(&songs, false);
+                self.message = Some(Self::queued_message("Added to queue", songs.len(), queued));
+            }
+            MenuAction::AddToPlaylist => self.picker = Some(PlaylistPicker::new(songs)),
+            MenuAction::EditTags => self.tag_editor = Some(TagEditor::new(songs, &self.player.songs)),
+            MenuAction::ShowAlbum => {
+                let Some(song) = self.player.songs.get(index) else {
+                    return;
+                };
+                let key = (song.album.clone(), song.artist.clone());
+                if let Some(row) = self.album_list().iter().position(|&album| *album == key) {
+                    self.player.view_mode = ViewMode::Albums;
+                    self.search_mode = false;
+                    self.scroll_offset = row;
+                }
+            }
+            MenuAction::Reveal => self.reveal(index),
+            MenuAction::RemoveFromLibrary => {
+                self.player.remove_songs(&songs);
+                let len = self.player.songs.len();
+                self.scroll_offset = self.scroll_offset.min(len.saturating_sub(1));
+                self.message = Some(match songs.len() {
+                    1 => "Removed from library".to_string(),
+                    n => format!("Removed {} tracks from library", n),
+                });
+            }
+        }
+    }
+
+    // Up and down go through the fields, typing changes the one selected
+    fn handle_tag_editor_key(&mut self, key: KeyEvent) {
+        let Some(editor) = self.tag_editor.as_mut() else {
+            return;
+        };
+        match key.code {
+            KeyCode::Down | KeyCode::Tab => editor.selected = (editor.selected + 1).min(editor.fields.len() - 1),
+            KeyCode::Up | KeyCode::BackTab => editor.selected = editor.selected.saturating_sub(1),
+            KeyCode::Char(c) => editor.fields[editor.selected].2.push(c),
+            KeyCode::Backspace => {
+                editor.fields[editor.selected].2.pop();
+            }
+            KeyCode::Esc => self.tag_editor = None,
+            KeyCode::Enter => self.save_tags(),
+            _ => {}
+        }
+    }
+
+    fn save_tags(&mut self) {
+        let Some(editor) = self.tag_editor.as_ref() else {
+            return;
+        };
+        let edits = editor.edits();
+        if edits.is_empty() {
+            self.tag_editor = None;
+            self.message = Some("No tags changed".to_string());
+            return;
+        }
+        // Stays open to be put right
+        if let Err(e) = tags::check(&edits) {
+            self.message = Some(e.to_string());
+            return;
+        }
+        let songs = std::mem::take(&mut self.tag_editor).map(|editor| editor.songs).unwrap_or_default();
+        let (written, failed) = self.player.edit_tags(&songs, &edits);
+        self.message = Some(match (written, failed.first()) {
+            (1, None) => "Tags saved".to_string(),
+            (_, None) => format!("Saved the tags of {} songs", written),
+            (0, Some(e)) if failed.len() == 1 => format!("Error: {}", e),
+            (_, Some(_)) => {
+                self.report = Some(Report {
+                    title: format!("Tags not saved ({})", failed.len()),
+                    lines: failed.clone(),
+                    scroll: 0,
+                });
+                format!("Saved the tags of {} songs, {} couldn't be", written, failed.len())
+            }
+        });
+    }
+
+    fn handle_palette_key(&mut self, key: KeyEvent) -> Result<()> {
+        let Some(palette) = self.palette.as_mut() else {
+            return Ok(());
+        };
+        let choices = palette.choices();
+        match key.code {
+            KeyCode::Down => palette.selected = (palette.selected + 1).min(choices.len().saturating_sub(1)),
+            KeyCode::Up => palette.selected = palette.selected.saturating_sub(1),
+            KeyCode::Char(c) => {
+                palette.filter.push(c);
+                palette.selected = 0;
+            }
+            KeyCode::Backspace => {
+                palette.filter.pop();
+                palette.selected = 0;
+            }
+            KeyCode::Esc => self.palette = None,
+            KeyCode::Enter => {
+                let Some(&choice) = choices.get(palette.selected) else {
+                    return Ok(());
+                };
+                self.palette = None;
+                match PALETTE[choice].1 {
+                    PaletteAction::Key(code) => {
+                        let key = KeyEvent::new(code, KeyModifiers::NONE);
+                        if !self.handle_global_key(key)? {
+                            self.handle_library_key(key);
+                        }
+                    }
+                    PaletteAction::View(index) => self.player.view_mode = ViewMode::ALL[index],
+                    PaletteAction::Command(cmd) => self.run_command(cmd),
+                    PaletteAction::Prompt(cmd) => {
+                        self.command_mode = true;
+                        self.command_input = cmd.to_string();
+                    }
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    // The chord waiting for its second key, or else the last key pressed,
+    // for a moment, like vim's showcmd
+    fn showcmd(&self) -> Option<String> {
+        if self.pending_g {
+            return Some(String::from("g…"));
+        }
+        self.last_key.as_ref()
+            .filter(|(_, at)| at.elapsed() < SHOWCMD_TIME)
+            .map(|(name, _)| name.clone())
+    }
+
+    // How an action is reached without the palette
+    fn palette_key(&self, action: PaletteAction) -> String {
+        match action {
+            PaletteAction::Key(code) => key_name(code),
+            PaletteAction::View(index) => self.tab_keys.get(index).map_or_else(|| String::from("Tab"), |&key| key_name(key)),
+            PaletteAction::Command(cmd) => format!(":{}", cmd),
+            PaletteAction::Prompt(cmd) => format!(":{}…", cmd.trim_end()),
+        }
+    }
+
+    fn handle_picker_key(&mut self, key: KeyEvent) {
+        let Some(picker) = self.picker.as_mut() else {
+            return;
+        };
+        let choices = picker.choices(&self.player.playlists);
+        match key.code {
+            KeyCode::Down => picker.selected = (picker.selected + 1).min(choices.len() - 1),
+            KeyCode::Up => picker.selected = picker.selected.saturating_sub(1),
+            KeyCode::Char(c) => {
+                picker.filter.push(c);
+                picker.selected = 0;
+            }
+            KeyCode::Backspace => {
+                picker.filter.pop();
+                picker.selected = 0;
+            }
+            KeyCode::Esc => self.picker = None,
+            KeyCode::Enter => {
+                let (songs, name) = (picker.songs.clone(), picker.filter.clone());
+                let playlist = match choices[picker.selected] {
+                    Some(index) => Ok(index),
+                    None if name.trim().is_empty() => {
+                        self.message = Some("Type a name for the new playlist".to_string());
+                        return;
+                    }
+                    None => self.player.create_playlist(&name),
+                };
+                self.picker = None;
+                let result = playlist.and_then(|index| {
+                    self.player.add_to_playlist(index, &songs)?;
+                    Ok(index)
+                });
+                self.message = Some(match result {
+                    Ok(index) if songs.len() == 1 => format!("Added to {}", self.player.playlists[index].name),
+                    Ok(index) => format!("Added {} tracks to {}", songs.len(), self.player.playlists[index].name),
+                    Err(e) => format!("Error: {}", e),
+                });
+            }
+            _ => {}
+        }
+    }
+
+    // Character keys type into the search box instead while searching
+    fn tab_key_index(&self, code: KeyCode) -> Option<usize> {
+        if self.search_mode && matches!(code, KeyCode::Char(_)) {
+            return None;
+        }
+        self.tab_keys.iter()
+            .position(|&key| key == code)
+            .filter(|&index| index < ViewMode::ALL.len() + self.player.browse.groups.len())
+    }
+
+    // Pasted text goes into whatever is being typed; it bypasses the key throttle
+    fn handle_paste(&mut self, text: &str) {
+        let text: String = text.chars().filter(|c| *c != '\n' && *c != '\r').collect();
+        if self.command_mode {
+            self.command_input.push_str(&text);
+        } else if self.search_mode {
+            self.search_input.push_str(&text);
+            self.last_search_edit = Instant::now();
+        }
+    }
+
+    fn add_directory(&mut self, path: PathBuf) {
+        match self.player.add_directory(path) {
+            Ok(Some(warning)) => self.message = Some(warning),
+            Ok(None) => self.message = Some("Directory added successfully".to_string()),
+            Err(e) => self.message = Some(format!("Error: {}", e)),
+        }
+    }
+}
+
+// Set once the terminal accepted the kitty keyboard protocol, so it is only popped if pushed
+static KEYBOARD_ENHANCED: AtomicBool = AtomicBool::new(false);
+
+// Opens the folder containing `path`, selecting the file where the platform supports it
+fn reveal_in_file_manager(path: &Path) -> Result<()> {
+    let mut command = if cfg!(target_os = "windows") {
+        let mut command = Command::new("explorer");
+        command.arg(format!("/select,{}", path.display()));
+        command
+    } else if cfg!(target_os = "macos") {
+        let mut command = Command::new("open");
+        command.arg("-R").arg(path);
+        command
+    } else {
+        let mut command = Command::new("xdg-open");
+        command.arg(path.parent().unwrap_or(path));
+        command
+    };
+    // Keep the file manager's output from drawing over the UI
+    command.stdin(Stdio::null()).stdout(Stdio::null()).stderr(Stdio::null()).spawn()?;
+    Ok(())
+}
+
+fn restore_terminal() {
+    let mut stdout = io::stdout();
+    if KEYBOARD_ENHANCED.swap(false, Ordering::SeqCst) {
+        let _ = execute!(stdout, PopKeyboardEnhancementFlags);
+    }
+    let _ = execute!(stdout, DisableBracketedPaste, DisableMouseCapture);
+    terminal::pop_title();
+    let _ = disable_raw_mode();
+    let _ = execute!(stdout, LeaveAlternateScreen);
+}
+
+// Puts the terminal back when dropped, so early returns and panics don't leave
+// the shell in raw mode on the alternate screen
+struct TerminalGuard;
+
+impl TerminalGuard {
+    fn new() -> Result<Self> {
+        enable_raw_mode()?;
+        let mut stdout = io::stdout();
+        execute!(stdout, EnterAlternateScreen)?;
+        terminal::push_title();
+        // Pastes arrive as one event instead of a burst of keys the throttle would drop.
+        // Not every terminal supports it (the legacy Windows console doesn't), which is fine.
+        let _ = execute!(stdout, EnableBracketedPaste);
+        // For clicks on column headers
+        let _ = execute!(stdout, EnableMouseCapture);
+        // Lets terminals that speak the kitty protocol report combinations like
+        // Ctrl-Enter and Shift-Tab unambiguously
+        if supports_keyboard_enhancement().unwrap_or(false)
+            && execute!(stdout, PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::DISAMBIGUATE_ESCAPE_CODES)).is_ok()
+        {
+            KEYBOARD_ENHANCED.store(true, Ordering::SeqCst);
+        }
+        Ok(TerminalGuard)
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        restore_terminal();
+    }
+}
+
+// Restores the terminal before the default hook prints, otherwise the panic
+// message is drawn over the UI and lost when the alternate screen is left
+fn install_panic_hook() {
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        // Worker threads panicking don't end the UI, so only the main thread restores
+        if thread::current().name() == Some("main") {
+            restore_terminal();
+        }
+        default_hook(info);
+    }));
+}
+
+fn main() -> Result<()> {
+    // Load config before touching the terminal so errors are readable
+    let config = Config::load()?;
+    if env::args().skip(1).any(|arg| arg == "--generate-systemd-unit") {
+        let bind = config.http.as_ref().map(|http| http.bind.as_str());
+        print!("{}", systemd::units(&env::current_exe()?, bind));
+        return Ok(());
+    }
+
+    // Before any thread starts; see `systemd::listeners`
+    let listeners = systemd::listeners();
+
+    // Setup terminal
+    install_panic_hook();
+    let _guard = TerminalGuard::new()?;
+    let backend = CrosstermBackend::new(io::stdout());
+    let mut terminal = Terminal::new(backend)?;
+
+    let initial_dirs = vec![
+        PathBuf::from("C:/Users/lintr/AppData/Roaming/Python/Python312/Scripts"),
+    ];
+
+    let (action_tx, action_rx) = mpsc::channel();
+    spawn_input_thread(action_tx.clone());
+    spawn_signal_thread(action_tx.clone())?;
+    let tick_tx = action_tx.clone();
+
+    let player = MusicPlayer::new(&initial_dirs, &config, listeners, action_tx)?;
+    let mut app = App::new(player, &config, State::load());
+    app.message = app.player.load_playlists();
+    spawn_tick_thread(tick_tx, app.tick_ms.clone());
+    // Under a Type=notify unit, starting is done once the library is being scanned and the HTTP API is up
+    systemd::notify("READY=1");
+
+    // However the loop ends, even on a terminal gone away, the music fades
+    // out and everything is saved
+    let result = run(&mut terminal, &mut app, &action_rx);
+    systemd::notify("STOPPING=1");
+    app.player.quit_audio();
+    // Tags that waited for their track to stop playing
+    app.player.playback.state = PlaybackState::Stopped;
+    let saved = app.player.save_stats().and(app.player.save_queues()).and(app.state.save());
+    result.and(saved)
+}
+
+fn run(terminal: &mut Terminal<CrosstermBackend<io::Stdout>>, app: &mut App, actions: &mpsc::Receiver<Action>) -> Result<()> {
+    let mut last_title = String::new();
+    let mut redraw = true;
+    loop {
+        if redraw {
+            terminal.draw(|f| ui::draw(f, app))?;
+        }
+        let title = app.window_title();
+        if title != last_title {
+            terminal::set_title(&title);
+            last_title = title;
+        }
+
+        // Block until something happens, then apply everything that queued up meanwhile
+        let action = actions.recv()?;
+        let mut ticks_only = action.is_timer();
+        app.update(action)?;
+        for action in actions.try_iter() {
+            ticks_only &= action.is_timer();
+            app.update(action)?;
+        }
+        if app.should_quit {
+            return Ok(());
+        }
+        redraw = !ticks_only || app.tick_needs_draw();
+    }
 }
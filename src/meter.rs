@@ -0,0 +1,149 @@
+use std::{
+    sync::{
+        atomic::{AtomicU32, AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use rodio::Source;
+
+// Loudest sample of the last window of what is playing, from 0 to 1, shared
+// with the UI as f32 bits for the screensaver's bars
+pub type Level = Arc<AtomicU32>;
+
+// Samples of the track the device has taken so far, as of the last window
+pub type Played = Arc<AtomicU64>;
+
+// Windows a second is cut into, about one per UI tick or faster
+const WINDOWS_PER_SECOND: u32 = 20;
+
+pub fn level() -> Level {
+    Arc::new(AtomicU32::new(0))
+}
+
+pub fn read(level: &Level) -> f32 {
+    f32::from_bits(level.load(Ordering::Relaxed))
+}
+
+pub fn reset(level: &Level) {
+    level.store(0f32.to_bits(), Ordering::Relaxed);
+}
+
+// Passes samples through unchanged, publishing the peak of each window and
+// how many samples have gone through
+pub struct Meter<S> {
+    source: S,
+    level: Level,
+    played: Played,
+    peak: f32,
+    counted: u32,
+    total: u64,
+}
+
+impl<S: Source<Item = f32>> Meter<S> {
+    pub fn new(source: S, level: Level, played: Played) -> Self {
+        Meter { source, level, played, peak: 0.0, counted: 0, total: 0 }
+    }
+
+    fn window(&self) -> u32 {
+        (self.source.sample_rate() * self.source.channels() as u32 / WINDOWS_PER_SECOND).max(1)
+    }
+}
+
+impl<S: Source<Item = f32>> Iterator for Meter<S> {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let sample = self.source.next()?;
+        self.peak = self.peak.max(sample.abs());
+        self.counted += 1;
+        self.total += 1;
+        if self.counted >= self.window() {
+            self.level.store(self.peak.to_bits(), Ordering::Relaxed);
+            self.played.store(self.total, Ordering::Relaxed);
+            self.peak = 0.0;
+            self.counted = 0;
+        }
+        Some(sample)
+    }
+}
+
+impl<S: Source<Item = f32>> Source for Meter<S> {
+    fn current_frame_len(&self) -> Option<usize> {
+        self.source.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.source.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.source.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.source.total_duration()
+    }
+}
+
+// Notices the device has stopped taking samples while it should be playing,
+// as when it's unplugged. rodio keeps stream errors to itself.
+#[derive(Default)]
+pub struct Stall {
+    // Samples taken when last looked at, and since when that has stood
+    seen: Option<(u64, Instant)>,
+}
+
+impl Stall {
+    // Looked at while playing with what `played` holds; true once it hasn't
+    // moved for `limit`
+    pub fn check(&mut self, taken: u64, now: Instant, limit: Duration) -> bool {
+        match self.seen {
+            Some((seen, since)) if seen == taken => now.duration_since(since) >= limit,
+            _ => {
+                self.seen = Some((taken, now));
+                false
+            }
+        }
+    }
+
+    // Paused, stopped or started over: nothing was expected to move
+    pub fn reset(&mut self) {
+        self.seen = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rodio::buffer::SamplesBuffer;
+
+    #[test]
+    fn publishes_the_peak_of_each_window() {
+        // 100 Hz mono: windows of 5 samples
+        let samples = vec![0.0, 0.01, -0.5, 0.001, 0.0, 0.25, 0.0, 0.0, 0.0, 0.0];
+        let (level, played) = (level(), Played::default());
+        let mut meter = Meter::new(SamplesBuffer::new(1, 100, samples.clone()), level.clone(), played.clone());
+        let first: Vec<f32> = meter.by_ref().take(5).collect();
+        assert_eq!(read(&level), 0.5);
+        assert_eq!(played.load(Ordering::Relaxed), 5);
+        let rest: Vec<f32> = meter.collect();
+        assert_eq!(read(&level), 0.25);
+        assert_eq!(played.load(Ordering::Relaxed), 10);
+        assert_eq!([first, rest].concat(), samples);
+    }
+
+    #[test]
+    fn stalls_once_nothing_is_taken_for_long_enough() {
+        let (mut stall, start, limit) = (Stall::default(), Instant::now(), Duration::from_secs(3));
+        assert!(!stall.check(100, start, limit));
+        assert!(!stall.check(100, start + Duration::from_secs(2), limit));
+        assert!(stall.check(100, start + Duration::from_secs(3), limit));
+        // Moving again starts the wait over
+        assert!(!stall.check(200, start + Duration::from_secs(4), limit));
+        assert!(!stall.check(200, start + Duration::from_secs(6), limit));
+        stall.reset();
+        assert!(!stall.check(200, start + Duration::from_secs(10), limit));
+    }
+}
@@ -0,0 +1,50 @@
+use std::{fmt::Write, time::Duration};
+
+// Counters and gauges for the HTTP API's /metrics, in the Prometheus text
+// format. Counters start from zero with each run of the player.
+#[derive(Default, Clone, Debug, PartialEq)]
+pub struct Metrics {
+    pub tracks_played: u64,
+    // Tracks that couldn't be opened or decoded
+    pub decode_errors: u64,
+    // How long the last scan of the library took, once one has finished
+    pub last_scan: Option<Duration>,
+    pub songs: usize,
+}
+
+impl Metrics {
+    // `underruns` comes straight from the audio side; see `buffer::total_underruns`
+    pub fn render(&self, uptime: Duration, underruns: u64) -> String {
+        let mut text = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, value: String| {
+            let _ = write!(text, "# HELP {name} {help}\n# TYPE {name} {kind}\n{name} {value}\n");
+        };
+        metric("music_player_tracks_played_total", "counter", "Tracks started from the top.", self.tracks_played.to_string());
+        metric("music_player_decode_errors_total", "counter", "Tracks that failed to open or decode.", self.decode_errors.to_string());
+        metric("music_player_buffer_underruns_total", "counter", "Frames of silence played because the read-ahead buffer ran dry.", underruns.to_string());
+        if let Some(scan) = self.last_scan {
+            metric("music_player_scan_duration_seconds", "gauge", "How long the last library scan took.", format!("{:.3}", scan.as_secs_f64()));
+        }
+        metric("music_player_library_songs", "gauge", "Songs in the library.", self.songs.to_string());
+        metric("music_player_uptime_seconds", "gauge", "Seconds since the player started.", uptime.as_secs().to_string());
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_the_prometheus_text_format() {
+        let metrics = Metrics { tracks_played: 3, decode_errors: 1, last_scan: None, songs: 1200 };
+        let text = metrics.render(Duration::from_secs(90), 7);
+        assert!(text.starts_with("# HELP music_player_tracks_played_total Tracks started from the top.\n# TYPE music_player_tracks_played_total counter\nmusic_player_tracks_played_total 3\n"));
+        assert!(text.contains("\nmusic_player_buffer_underruns_total 7\n"));
+        assert!(text.ends_with("\nmusic_player_uptime_seconds 90\n"));
+        // No scan finished yet, so no duration to give
+        assert!(!text.contains("scan_duration"));
+        let scanned = Metrics { last_scan: Some(Duration::from_millis(2500)), ..metrics };
+        assert!(scanned.render(Duration::ZERO, 0).contains("\nmusic_player_scan_duration_seconds 2.500\n"));
+    }
+}
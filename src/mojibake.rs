@@ -0,0 +1,127 @@
+use std::path::Path;
+
+use anyhow::Result;
+use encoding_rs::WINDOWS_1251;
+use id3::{TagLike, Version};
+
+use crate::library::Song;
+
+// Old taggers wrote tags in whatever the system's code page was and marked
+// them Latin-1, so they read as "Ã©" or "Ïðèâåò". Gives the text decoded as
+// what it most likely was, or None if it looks right as it is.
+pub fn repair(text: &str) -> Option<String> {
+    // Anything past Latin-1 didn't come from a Latin-1 frame
+    if text.is_ascii() || text.chars().any(|c| c as u32 > 0xff) {
+        return None;
+    }
+    let bytes: Vec<u8> = text.chars().map(|c| c as u8).collect();
+    // High bytes that happen to make valid UTF-8 almost never do by chance
+    if let Ok(utf8) = String::from_utf8(bytes.clone()) {
+        return Some(utf8);
+    }
+    let (cyrillic, _, errors) = WINDOWS_1251.decode(&bytes);
+    (!errors && looks_cyrillic(&cyrillic)).then(|| cyrillic.into_owned())
+}
+
+// Latin-1 text read as Windows-1251 comes out as words mixing Latin and
+// Cyrillic letters ("Motцrhead"); real Cyrillic text doesn't
+fn looks_cyrillic(text: &str) -> bool {
+    let is_cyrillic = |c: char| ('\u{400}'..='\u{4ff}').contains(&c);
+    let mut letters = 0;
+    for word in text.split(|c: char| !c.is_alphabetic()) {
+        let cyrillic = word.chars().filter(|&c| is_cyrillic(c)).count();
+        if cyrillic > 0 && cyrillic < word.chars().count() {
+            return false;
+        }
+        letters += cyrillic;
+    }
+    letters > 1
+}
+
+// Whether any of the tags of `song` look like they need `repair_song`
+pub fn needs_repair(song: &Song) -> bool {
+    [&song.title, &song.artist, &song.album, &song.genre].into_iter()
+        .map(String::as_str)
+        .chain(song.album_artist.as_deref())
+        .any(|text| repair(text).is_some())
+}
+
+// Re-decodes the tags of `song` as read at scan, returning whether any changed
+pub fn repair_song(song: &mut Song) -> bool {
+    let mut changed = false;
+    for field in [&mut song.title, &mut song.artist, &mut song.album, &mut song.genre] {
+        if let Some(fixed) = repair(field) {
+            *field = fixed;
+            changed = true;
+        }
+    }
+    if let Some(fixed) = song.album_artist.as_deref().and_then(repair) {
+        song.album_artist = Some(fixed);
+        changed = true;
+    }
+    if changed {
+        song.label = format!("{} - {}", song.artist, song.title);
+    }
+    changed
+}
+
+// Re-decodes every text frame in the file's tag and saves it back as UTF-8
+// ID3v2.4, replacing any ID3v1 tag. Returns whether there was anything to fix.
+pub fn rewrite(path: &Path) -> Result<bool> {
+    let mut tag = id3::v1v2::read_from_path(path)?;
+    let fixed: Vec<(String, String)> = tag.frames()
+        .filter_map(|frame| Some((frame.id().to_string(), repair(frame.content().text()?)?)))
+        .collect();
+    if fixed.is_empty() {
+        return Ok(false);
+    }
+    for (id, text) in fixed {
+        tag.set_text(id, text);
+    }
+    id3::v1v2::write_to_path(path, &tag, Version::Id3v24)?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use id3::Tag;
+
+    use super::*;
+
+    #[test]
+    fn utf8_read_as_latin1_decodes_again() {
+        assert_eq!(repair("BjÃ¶rk").as_deref(), Some("Björk"));
+        assert_eq!(repair("\u{d0}\u{9f}\u{d1}\u{80}\u{d0}\u{b8}\u{d0}\u{b2}\u{d0}\u{b5}\u{d1}\u{82}").as_deref(), Some("Привет"));
+    }
+
+    #[test]
+    fn windows_1251_read_as_latin1_decodes_again() {
+        assert_eq!(repair("Êèíî - Ãðóïïà êðîâè").as_deref(), Some("Кино - Группа крови"));
+        assert_eq!(repair("DJ Ñìýø").as_deref(), Some("DJ Смэш"));
+    }
+
+    #[test]
+    fn real_latin1_is_left_alone() {
+        for text in ["Motörhead", "Größe", "Sigur Rós - Ágætis byrjun", "Café à Paris", "Plain", "Кино"] {
+            assert_eq!(repair(text), None, "{}", text);
+        }
+    }
+
+    #[test]
+    fn rewrites_tags_as_utf8() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("song.mp3");
+        fs::write(&path, b"").unwrap();
+        let mut tag = Tag::new();
+        tag.set_artist("Êèíî");
+        tag.set_album("Motörhead");
+        tag.write_to_path(&path, Version::Id3v23).unwrap();
+
+        assert!(rewrite(&path).unwrap());
+        let tag = Tag::read_from_path(&path).unwrap();
+        assert_eq!((tag.version(), tag.artist(), tag.album()), (Version::Id3v24, Some("Кино"), Some("Motörhead")));
+        assert!(!rewrite(&path).unwrap());
+    }
+}
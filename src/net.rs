@@ -0,0 +1,45 @@
+use std::{
+    io::Write,
+    process::{Command, Output, Stdio},
+};
+
+use anyhow::Result;
+
+// MusicBrainz and LRCLIB ask clients to say who they are
+pub const USER_AGENT: &str = "music-player/0.1 (https://github.com/harryytran/music-player)";
+
+// Online lookups go through curl rather than pulling an HTTPS stack into the
+// build. Fails on HTTP errors (404 included) and after 15 seconds.
+pub fn curl(args: &[&str]) -> Result<Vec<u8>> {
+    let output = Command::new("curl")
+        .args(["-fsSL", "--max-time", "15", "-A", USER_AGENT])
+        .args(args)
+        .output()?;
+    finished(output)
+}
+
+// POSTs `body` through curl's stdin, which keeps it out of the process list
+// and off the length limit of a command line. Redirects aren't followed, as
+// curl would send them a GET.
+pub fn post(url: &str, content_type: &str, body: &[u8]) -> Result<Vec<u8>> {
+    let mut child = Command::new("curl")
+        .args(["-fsS", "--max-time", "15", "-A", USER_AGENT, "--data-binary", "@-", "-H"])
+        .arg(format!("Content-Type: {}", content_type))
+        .arg(url)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()?;
+    // Dropped once written, so curl sees the end of it
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(body)?;
+    }
+    finished(child.wait_with_output()?)
+}
+
+fn finished(output: Output) -> Result<Vec<u8>> {
+    if !output.status.success() {
+        return Err(anyhow::anyhow!("curl exited with {}", output.status));
+    }
+    Ok(output.stdout)
+}
@@ -0,0 +1,118 @@
+use std::{
+    fs::{self, File},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+
+// Earlier versions kept of every file written through here
+pub const BACKUPS: usize = 3;
+
+// Writes a temporary file next to `path`, flushes it to disk and renames it
+// over the old one, so a crash leaves either the old or the new contents.
+// The old contents become backup 1, pushing older backups up by one.
+pub fn write_atomic(path: &Path, contents: &str) -> Result<()> {
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    let temp = path.with_file_name(format!(".{}.tmp", file_name));
+    let mut file = File::create(&temp)?;
+    file.write_all(contents.as_bytes())?;
+    file.sync_all()?;
+    rotate_backups(path)?;
+    fs::rename(&temp, path)?;
+    Ok(())
+}
+
+// Removes `path`, keeping what it held as backup 1 so it can be restored
+pub fn remove(path: &Path) -> Result<()> {
+    rotate_backups(path)?;
+    fs::remove_file(path)?;
+    Ok(())
+}
+
+// Moves the backups along with the file
+pub fn rename(from: &Path, to: &Path) -> Result<()> {
+    fs::rename(from, to)?;
+    for n in 1..=BACKUPS {
+        let backup = backup_path(from, n);
+        if backup.exists() {
+            fs::rename(&backup, backup_path(to, n))?;
+        }
+    }
+    Ok(())
+}
+
+// Hidden, and with an extension nothing else picks up as a playlist
+pub fn backup_path(path: &Path, n: usize) -> PathBuf {
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!(".{}.{}.bak", file_name, n))
+}
+
+// Puts backup `n` (1 is the newest) back in place. The current contents
+// become backup 1, so restoring the wrong one can be undone the same way.
+pub fn restore(path: &Path, n: usize) -> Result<()> {
+    let backup = backup_path(path, n);
+    let contents = fs::read_to_string(&backup)
+        .map_err(|_| anyhow::anyhow!("No backup {} of {}", n, path.display()))?;
+    write_atomic(path, &contents)
+}
+
+// A short file name standing for `text`, the same from one build to the next (FNV-1a)
+pub fn file_key(text: &str) -> String {
+    let hash = text.bytes().fold(0xcbf29ce484222325u64, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    });
+    format!("{:016x}", hash)
+}
+
+fn rotate_backups(path: &Path) -> Result<()> {
+    if !path.exists() {
+        return Ok(());
+    }
+    for n in (1..BACKUPS).rev() {
+        let older = backup_path(path, n);
+        if older.exists() {
+            fs::rename(&older, backup_path(path, n + 1))?;
+        }
+    }
+    // Copied rather than moved so `path` never goes missing
+    fs::copy(path, backup_path(path, 1))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_rolling_backups() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.toml");
+        for n in 1..=5 {
+            write_atomic(&path, &n.to_string()).unwrap();
+        }
+        assert_eq!(fs::read_to_string(&path).unwrap(), "5");
+        assert_eq!(fs::read_to_string(backup_path(&path, 1)).unwrap(), "4");
+        assert_eq!(fs::read_to_string(backup_path(&path, BACKUPS)).unwrap(), "2");
+        assert!(!backup_path(&path, BACKUPS + 1).exists());
+        assert!(!dir.path().join(".state.toml.tmp").exists());
+    }
+
+    #[test]
+    fn restore_swaps_with_current_and_brings_back_removed_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("Mix.m3u");
+        write_atomic(&path, "old").unwrap();
+        write_atomic(&path, "new").unwrap();
+
+        restore(&path, 1).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "old");
+        assert_eq!(fs::read_to_string(backup_path(&path, 1)).unwrap(), "new");
+
+        remove(&path).unwrap();
+        assert!(!path.exists());
+        restore(&path, 1).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "old");
+        assert!(restore(&path, BACKUPS + 1).is_err());
+    }
+}
@@ -0,0 +1,548 @@
+use std::{collections::VecDeque, time::Duration};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PlaybackState {
+    Stopped,
+    // A track was requested but the audio thread has not confirmed it started yet
+    Transitioning,
+    Playing,
+    Paused { position: Duration },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RepeatMode {
+    Off,
+    All,
+}
+
+// What the audio side has to do after a state change
+#[derive(Debug, PartialEq)]
+pub enum Effect {
+    Play { index: usize, from: Duration },
+    Stop,
+    // Holds the track where it is, ready to go on
+    Pause,
+    // Carries on with a paused track, or plays it from `from` if it can't
+    Resume { index: usize, from: Duration },
+}
+
+// Pure playback logic: which track is current, what is queued and what state we
+// are in. It never touches audio itself, so every transition can be unit tested.
+pub struct Playback {
+    pub state: PlaybackState,
+    pub current: usize,
+    pub queue: VecDeque<usize>,
+    // Queued tracks that have played, kept when `keep_played` is set
+    pub played: Vec<usize>,
+    pub keep_played: bool,
+    pub repeat: RepeatMode,
+}
+
+impl Default for Playback {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Playback {
+    pub fn new() -> Self {
+        Playback {
+            state: PlaybackState::Stopped,
+            current: 0,
+            queue: VecDeque::new(),
+            played: Vec::new(),
+            keep_played: false,
+            repeat: RepeatMode::All,
+        }
+    }
+
+    // Playing, or about to be
+    pub fn is_active(&self) -> bool {
+        matches!(self.state, PlaybackState::Playing | PlaybackState::Transitioning)
+    }
+
+    pub fn status(&self) -> &'static str {
+        match self.state {
+            PlaybackState::Stopped => "Stopped",
+            PlaybackState::Transitioning => "Loading",
+            PlaybackState::Playing => "Playing",
+            PlaybackState::Paused { .. } => "Paused",
+        }
+    }
+
+    pub fn play(&mut self, index: usize, len: usize) -> Option<Effect> {
+        if index >= len {
+            return None;
+        }
+        self.current = index;
+        self.state = PlaybackState::Transitioning;
+        Some(Effect::Play { index, from: Duration::ZERO })
+    }
+
+    // Play/pause key: pauses at `position`, resumes from where it paused
+    pub fn toggle(&mut self, len: usize, position: Duration) -> Option<Effect> {
+        match self.state {
+            PlaybackState::Playing | PlaybackState::Transitioning => {
+                self.state = PlaybackState::Paused { position };
+                Some(Effect::Pause)
+            }
+            PlaybackState::Paused { position } if self.current < len => {
+                self.state = PlaybackState::Transitioning;
+                Some(Effect::Resume { index: self.current, from: position })
+            }
+            PlaybackState::Paused { .. } | PlaybackState::Stopped => self.play(self.current, len),
+        }
+    }
+
+    // Queued tracks come first, then the following library entry
+    pub fn next(&mut self, len: usize) -> Option<Effect> {
+        if len == 0 {
+            return None;
+        }
+        self.current = match self.take_queued(0) {
+            Some(index) => index,
+            None if self.current + 1 < len => self.current + 1,
+            None => match self.repeat {
+                RepeatMode::All => 0,
+                RepeatMode::Off => return self.stop_at_end(),
+            },
+        };
+        self.follow(len)
+    }
+
+    // The playing track was heard to the end: moves on as `next` would, queue
+    // first. Ignored if another track has been asked for meanwhile.
+    pub fn finished(&mut self, len: usize) -> Option<Effect> {
+        if self.state != PlaybackState::Playing {
+            return None;
+        }
+        self.next(len)
+    }
+
+    // What `next` would play, without taking it off the queue
+    pub fn upcoming(&self, len: usize) -> Option<usize> {
+        match self.queue.front() {
+            Some(&index) => Some(index),
+            None if self.current + 1 < len => Some(self.current + 1),
+            None if self.repeat == RepeatMode::All && len > 0 => Some(0),
+            None => None,
+        }
+    }
+
+    // The device went on into `index`, appended to the playing track ahead of
+    // time: moves to it as `next` would, with nothing left to play. False if
+    // it isn't what `next` would play by now.
+    pub fn advanced(&mut self, len: usize, index: usize) -> bool {
+        if self.state != PlaybackState::Playing || self.upcoming(len) != Some(index) {
+            return false;
+        }
+        if self.queue.front() == Some(&index) {
+            self.take_queued(0);
+        }
+        self.current = index;
+        true
+    }
+
+    // Takes a track off the queue to be played
+    pub fn take_queued(&mut self, position: usize) -> Option<usize> {
+        let index = self.queue.remove(position)?;
+        if self.keep_played {
+            self.played.push(index);
+        }
+        Some(index)
+    }
+
+    pub fn previous(&mut self, len: usize) -> Option<Effect> {
+        if len == 0 {
+            return None;
+        }
+        if self.current > 0 {
+            self.current -= 1;
+        } else if self.repeat == RepeatMode::All {
+            self.current = len - 1;
+        }
+        self.follow(len)
+    }
+
+    // Moves within the current track; a paused track stays paused there
+    pub fn seek(&mut self, len: usize, position: Duration) -> Option<Effect> {
+        if self.current >= len {
+            return None;
+        }
+        if let PlaybackState::Paused { .. } = self.state {
+            self.state = PlaybackState::Paused { position };
+            return None;
+        }
+        self.state = PlaybackState::Transitioning;
+        Some(Effect::Play { index: self.current, from: position })
+    }
+
+    pub fn started(&mut self) {
+        if self.state == PlaybackState::Transitioning {
+            self.state = PlaybackState::Playing;
+        }
+    }
+
+    pub fn failed(&mut self) {
+        self.state = PlaybackState::Stopped;
+    }
+
+    pub fn cycle_repeat(&mut self) {
+        self.repeat = match self.repeat {
+            RepeatMode::Off => RepeatMode::All,
+            RepeatMode::All => RepeatMode::Off,
+        };
+    }
+
+    // Called when songs are removed or reordered; `new_indices[old]` is the new index or None if removed
+    pub fn remap(&mut self, new_indices: &[Option<usize>], len: usize) {
+        self.queue = self.queue.iter()
+            .filter_map(|&i| new_indices.get(i).copied().flatten())
+            .collect();
+        self.played = self.played.iter()
+            .filter_map(|&i| new_indices.get(i).copied().flatten())
+            .collect();
+        self.current = match new_indices.get(self.current).copied().flatten() {
+            Some(index) => index,
+            None => {
+                // A paused position belongs to the removed track
+                if let PlaybackState::Paused { .. } = self.state {
+                    self.state = PlaybackState::Stopped;
+                }
+                self.current.min(len.saturating_sub(1))
+            }
+        };
+    }
+
+    // After moving to another track: keep playing if we were, otherwise a paused
+    // position no longer applies and the paused track is let go
+    fn follow(&mut self, len: usize) -> Option<Effect> {
+        if self.is_active() {
+            self.play(self.current, len)
+        } else if let PlaybackState::Paused { .. } = self.state {
+            self.state = PlaybackState::Stopped;
+            Some(Effect::Stop)
+        } else {
+            None
+        }
+    }
+
+    fn stop_at_end(&mut self) -> Option<Effect> {
+        let was_active = self.is_active();
+        self.state = PlaybackState::Stopped;
+        if was_active {
+            Some(Effect::Stop)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn playing(current: usize, len: usize) -> Playback {
+        let mut playback = Playback::new();
+        playback.play(current, len);
+        playback.started();
+        playback
+    }
+
+    fn play_effect(index: usize) -> Option<Effect> {
+        Some(Effect::Play { index, from: Duration::ZERO })
+    }
+
+    #[test]
+    fn starts_stopped() {
+        let playback = Playback::new();
+        assert_eq!(playback.state, PlaybackState::Stopped);
+        assert!(!playback.is_active());
+        assert_eq!(playback.status(), "Stopped");
+    }
+
+    #[test]
+    fn play_transitions_until_started() {
+        let mut playback = Playback::new();
+        assert_eq!(playback.play(2, 5), play_effect(2));
+        assert_eq!(playback.state, PlaybackState::Transitioning);
+        assert!(playback.is_active());
+        playback.started();
+        assert_eq!(playback.state, PlaybackState::Playing);
+        assert_eq!(playback.current, 2);
+    }
+
+    #[test]
+    fn play_out_of_range_is_ignored() {
+        let mut playback = Playback::new();
+        assert_eq!(playback.play(5, 5), None);
+        assert_eq!(playback.play(0, 0), None);
+        assert_eq!(playback.state, PlaybackState::Stopped);
+    }
+
+    #[test]
+    fn started_only_confirms_a_pending_track() {
+        let mut playback = Playback::new();
+        playback.started();
+        assert_eq!(playback.state, PlaybackState::Stopped);
+
+        let mut playback = playing(0, 3);
+        playback.toggle(3, Duration::from_secs(4));
+        playback.started();
+        assert_eq!(playback.state, PlaybackState::Paused { position: Duration::from_secs(4) });
+    }
+
+    #[test]
+    fn failed_stops() {
+        let mut playback = Playback::new();
+        playback.play(1, 3);
+        playback.failed();
+        assert_eq!(playback.state, PlaybackState::Stopped);
+    }
+
+    #[test]
+    fn toggle_pauses_and_resumes_from_position() {
+        let mut playback = playing(1, 3);
+        let position = Duration::from_secs(42);
+        assert_eq!(playback.toggle(3, position), Some(Effect::Pause));
+        assert_eq!(playback.state, PlaybackState::Paused { position });
+        assert_eq!(playback.status(), "Paused");
+
+        assert_eq!(playback.toggle(3, Duration::ZERO), Some(Effect::Resume { index: 1, from: position }));
+        assert_eq!(playback.state, PlaybackState::Transitioning);
+    }
+
+    #[test]
+    fn toggle_while_transitioning_pauses() {
+        let mut playback = Playback::new();
+        playback.play(0, 3);
+        assert_eq!(playback.toggle(3, Duration::ZERO), Some(Effect::Pause));
+        assert_eq!(playback.state, PlaybackState::Paused { position: Duration::ZERO });
+    }
+
+    #[test]
+    fn toggle_from_stopped_plays_current() {
+        let mut playback = Playback::new();
+        playback.current = 2;
+        assert_eq!(playback.toggle(3, Duration::ZERO), play_effect(2));
+    }
+
+    #[test]
+    fn toggle_with_empty_library_does_nothing() {
+        let mut playback = Playback::new();
+        assert_eq!(playback.toggle(0, Duration::ZERO), None);
+        assert_eq!(playback.state, PlaybackState::Stopped);
+    }
+
+    #[test]
+    fn resume_after_track_was_removed_starts_over() {
+        let mut playback = playing(2, 3);
+        playback.toggle(3, Duration::from_secs(10));
+        playback.remap(&[Some(0), None, None], 1);
+        assert_eq!(playback.toggle(1, Duration::ZERO), play_effect(0));
+    }
+
+    #[test]
+    fn next_advances_and_keeps_playing() {
+        let mut playback = playing(0, 3);
+        assert_eq!(playback.next(3), play_effect(1));
+        assert_eq!(playback.current, 1);
+        assert_eq!(playback.state, PlaybackState::Transitioning);
+    }
+
+    #[test]
+    fn next_while_stopped_only_moves_selection() {
+        let mut playback = Playback::new();
+        assert_eq!(playback.next(3), None);
+        assert_eq!(playback.current, 1);
+        assert_eq!(playback.state, PlaybackState::Stopped);
+    }
+
+    #[test]
+    fn next_while_paused_forgets_position() {
+        let mut playback = playing(0, 3);
+        playback.toggle(3, Duration::from_secs(30));
+        assert_eq!(playback.next(3), Some(Effect::Stop));
+        assert_eq!(playback.current, 1);
+        assert_eq!(playback.state, PlaybackState::Stopped);
+    }
+
+    #[test]
+    fn finished_track_moves_on_unless_already_moving() {
+        let mut playback = playing(0, 3);
+        playback.queue.push_back(2);
+        assert_eq!(playback.finished(3), play_effect(2));
+        assert!(playback.queue.is_empty());
+        assert_eq!(playback.finished(3), None);
+
+        let mut playback = playing(2, 3);
+        playback.repeat = RepeatMode::Off;
+        assert_eq!(playback.finished(3), Some(Effect::Stop));
+        assert_eq!(playback.state, PlaybackState::Stopped);
+    }
+
+    #[test]
+    fn next_prefers_queue_in_order() {
+        let mut playback = playing(0, 5);
+        playback.queue.extend([3, 1]);
+        assert_eq!(playback.next(5), play_effect(3));
+        assert_eq!(playback.next(5), play_effect(1));
+        assert_eq!(playback.next(5), play_effect(2));
+        assert!(playback.queue.is_empty());
+    }
+
+    #[test]
+    fn played_queue_entries_can_be_kept() {
+        let mut playback = playing(0, 5);
+        playback.keep_played = true;
+        playback.queue.extend([3, 1, 4]);
+        playback.next(5);
+        assert_eq!(playback.take_queued(1), Some(4));
+        assert_eq!(playback.played, vec![3, 4]);
+        assert_eq!(playback.queue, VecDeque::from([1]));
+
+        playback.remap(&[Some(0), Some(1), Some(2), None, Some(3)], 4);
+        assert_eq!(playback.played, vec![3]);
+    }
+
+    #[test]
+    fn next_uses_queue_even_at_end_with_repeat_off() {
+        let mut playback = playing(2, 3);
+        playback.repeat = RepeatMode::Off;
+        playback.queue.push_back(0);
+        assert_eq!(playback.next(3), play_effect(0));
+    }
+
+    #[test]
+    fn upcoming_matches_next() {
+        let mut playback = playing(1, 3);
+        playback.queue.push_back(0);
+        assert_eq!(playback.upcoming(3), Some(0));
+        playback.queue.clear();
+        assert_eq!(playback.upcoming(3), Some(2));
+
+        let mut playback = playing(2, 3);
+        assert_eq!(playback.upcoming(3), Some(0));
+        playback.repeat = RepeatMode::Off;
+        assert_eq!(playback.upcoming(3), None);
+        assert_eq!(playback.queue.len(), 0);
+    }
+
+    #[test]
+    fn advancing_takes_the_queue_first() {
+        let mut playback = playing(0, 3);
+        playback.queue.extend([2, 1]);
+        assert!(!playback.advanced(3, 1));
+        assert!(playback.advanced(3, 2));
+        assert_eq!(playback.current, 2);
+        assert_eq!(playback.queue, VecDeque::from([1]));
+        assert_eq!(playback.state, PlaybackState::Playing);
+
+        playback.state = PlaybackState::Paused { position: Duration::ZERO };
+        assert!(!playback.advanced(3, 1));
+    }
+
+    #[test]
+    fn next_wraps_with_repeat_all() {
+        let mut playback = playing(2, 3);
+        assert_eq!(playback.next(3), play_effect(0));
+    }
+
+    #[test]
+    fn next_stops_at_end_with_repeat_off() {
+        let mut playback = playing(2, 3);
+        playback.repeat = RepeatMode::Off;
+        assert_eq!(playback.next(3), Some(Effect::Stop));
+        assert_eq!(playback.current, 2);
+        assert_eq!(playback.state, PlaybackState::Stopped);
+
+        // Already stopped: nothing to tell the audio thread
+        assert_eq!(playback.next(3), None);
+    }
+
+    #[test]
+    fn next_with_empty_library_does_nothing() {
+        let mut playback = Playback::new();
+        playback.queue.push_back(0);
+        assert_eq!(playback.next(0), None);
+        assert_eq!(playback.queue.len(), 1);
+    }
+
+    #[test]
+    fn previous_steps_back() {
+        let mut playback = playing(2, 3);
+        assert_eq!(playback.previous(3), play_effect(1));
+    }
+
+    #[test]
+    fn previous_wraps_with_repeat_all() {
+        let mut playback = playing(0, 3);
+        assert_eq!(playback.previous(3), play_effect(2));
+    }
+
+    #[test]
+    fn previous_restarts_first_track_with_repeat_off() {
+        let mut playback = playing(0, 3);
+        playback.repeat = RepeatMode::Off;
+        assert_eq!(playback.previous(3), play_effect(0));
+    }
+
+    #[test]
+    fn previous_ignores_queue() {
+        let mut playback = playing(1, 3);
+        playback.queue.push_back(2);
+        assert_eq!(playback.previous(3), play_effect(0));
+        assert_eq!(playback.queue.len(), 1);
+    }
+
+    #[test]
+    fn previous_with_empty_library_does_nothing() {
+        let mut playback = Playback::new();
+        assert_eq!(playback.previous(0), None);
+    }
+
+    #[test]
+    fn cycle_repeat_alternates() {
+        let mut playback = Playback::new();
+        assert_eq!(playback.repeat, RepeatMode::All);
+        playback.cycle_repeat();
+        assert_eq!(playback.repeat, RepeatMode::Off);
+        playback.cycle_repeat();
+        assert_eq!(playback.repeat, RepeatMode::All);
+    }
+
+    #[test]
+    fn remap_drops_removed_queue_entries() {
+        let mut playback = playing(3, 4);
+        playback.queue.extend([0, 1, 3]);
+        playback.remap(&[Some(0), None, Some(1), Some(2)], 3);
+        assert_eq!(playback.queue, VecDeque::from([0, 2]));
+        assert_eq!(playback.current, 2);
+    }
+
+    #[test]
+    fn remap_clamps_removed_current() {
+        let mut playback = playing(3, 4);
+        playback.remap(&[Some(0), Some(1), None, None], 2);
+        assert_eq!(playback.current, 1);
+
+        playback.remap(&[None, None], 0);
+        assert_eq!(playback.current, 0);
+    }
+
+    #[test]
+    fn seek_restarts_or_moves_the_paused_position() {
+        let at = Duration::from_secs(42);
+        let mut playback = playing(1, 3);
+        assert_eq!(playback.seek(3, at), Some(Effect::Play { index: 1, from: at }));
+        assert_eq!(playback.state, PlaybackState::Transitioning);
+
+        playback.state = PlaybackState::Paused { position: Duration::from_secs(5) };
+        assert_eq!(playback.seek(3, at), None);
+        assert_eq!(playback.state, PlaybackState::Paused { position: at });
+
+        let mut stopped = Playback::new();
+        assert_eq!(stopped.seek(0, at), None);
+        assert_eq!(stopped.seek(3, at), Some(Effect::Play { index: 0, from: at }));
+    }
+}
@@ -0,0 +1,212 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+use encoding_rs::WINDOWS_1252;
+
+use crate::persist;
+
+// A playlist is an M3U file; the file stem is its name
+pub struct Playlist {
+    pub name: String,
+    pub path: PathBuf,
+    pub entries: Vec<PathBuf>,
+}
+
+impl Playlist {
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = decode(path, fs::read(path)?)?;
+        let base = path.parent().unwrap_or(Path::new(""));
+        let entries = contents.lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            // Relative entries are relative to the playlist file
+            .map(|line| base.join(line))
+            .collect();
+        Ok(Playlist {
+            name: path.file_stem().unwrap_or_default().to_string_lossy().to_string(),
+            path: path.to_owned(),
+            entries,
+        })
+    }
+
+    pub fn create(dir: &Path, name: &str) -> Result<Self> {
+        let name = name.trim();
+        let path = new_path(dir, name, "m3u")?;
+        fs::create_dir_all(dir)?;
+        let playlist = Playlist { name: name.to_string(), path, entries: Vec::new() };
+        playlist.save()?;
+        Ok(playlist)
+    }
+
+    // Never leaves half a playlist behind; earlier versions are kept as backups
+    pub fn save(&self) -> Result<()> {
+        let mut contents = String::from("#EXTM3U\n");
+        for entry in &self.entries {
+            contents.push_str(&entry.to_string_lossy());
+            contents.push('\n');
+        }
+        persist::write_atomic(&self.path, &contents)
+    }
+
+    // Renames the file too, keeping its extension
+    pub fn rename(&mut self, name: &str) -> Result<()> {
+        let name = name.trim();
+        let dir = self.path.parent().unwrap_or(Path::new(""));
+        let ext = self.path.extension().unwrap_or_default().to_string_lossy().to_string();
+        let path = new_path(dir, name, &ext)?;
+        persist::rename(&self.path, &path)?;
+        self.name = name.to_string();
+        self.path = path;
+        Ok(())
+    }
+
+    pub fn duplicate(&self, name: &str) -> Result<Playlist> {
+        let dir = self.path.parent().unwrap_or(Path::new(""));
+        let mut copy = Playlist::create(dir, name)?;
+        copy.entries = self.entries.clone();
+        copy.save()?;
+        Ok(copy)
+    }
+
+    // Its backups stay behind, so `:restore NAME` brings it back
+    pub fn delete(self) -> Result<()> {
+        persist::remove(&self.path)
+    }
+}
+
+// .m3u8 is UTF-8 by definition; a plain .m3u from an older player is often
+// in the Windows code page for Western languages, a superset of Latin-1
+fn decode(path: &Path, bytes: Vec<u8>) -> Result<String> {
+    let text = match String::from_utf8(bytes) {
+        Ok(text) => text,
+        Err(e) if path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("m3u")) => {
+            WINDOWS_1252.decode_without_bom_handling(e.as_bytes()).0.into_owned()
+        }
+        Err(_) => return Err(anyhow::anyhow!("not UTF-8")),
+    };
+    // Notepad starts UTF-8 files with a byte order mark
+    Ok(text.strip_prefix('\u{feff}').map(str::to_string).unwrap_or(text))
+}
+
+// Path for a playlist called `name`, which must be a usable and unused file name
+fn new_path(dir: &Path, name: &str, ext: &str) -> Result<PathBuf> {
+    if name.is_empty() || name.contains(['/', '\\']) {
+        return Err(anyhow::anyhow!("Invalid playlist name"));
+    }
+    let path = dir.join(format!("{}.{}", name, ext));
+    if path.exists() {
+        return Err(anyhow::anyhow!("Playlist {} already exists", name));
+    }
+    Ok(path)
+}
+
+// Every .m3u/.m3u8 file in `dir`, sorted by name, and the files that
+// couldn't be read, with why
+pub fn load_dir(dir: &Path) -> (Vec<Playlist>, Vec<(PathBuf, String)>) {
+    let (mut playlists, mut skipped) = (Vec::new(), Vec::new());
+    let paths = fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.extension()
+                .map(|ext| ext.eq_ignore_ascii_case("m3u") || ext.eq_ignore_ascii_case("m3u8"))
+                .unwrap_or(false)
+        });
+    for path in paths {
+        match Playlist::load(&path) {
+            Ok(playlist) => playlists.push(playlist),
+            Err(e) => skipped.push((path, e.to_string())),
+        }
+    }
+    playlists.sort_by_key(|playlist| playlist.name.to_lowercase());
+    skipped.sort();
+    (playlists, skipped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn save_and_load_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut playlist = Playlist::create(dir.path(), "Road Trip").unwrap();
+        playlist.entries.push(PathBuf::from("/music/a.mp3"));
+        playlist.entries.push(PathBuf::from("/music/b.flac"));
+        playlist.save().unwrap();
+
+        let loaded = Playlist::load(&dir.path().join("Road Trip.m3u")).unwrap();
+        assert_eq!(loaded.name, "Road Trip");
+        assert_eq!(loaded.entries, playlist.entries);
+    }
+
+    #[test]
+    fn relative_entries_resolve_against_playlist_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("mix.m3u8");
+        fs::write(&path, "#EXTM3U\n#EXTINF:123,Artist - Title\nsongs/a.mp3\n\n/abs/b.mp3\n").unwrap();
+
+        let playlist = Playlist::load(&path).unwrap();
+        assert_eq!(playlist.entries, vec![dir.path().join("songs/a.mp3"), PathBuf::from("/abs/b.mp3")]);
+    }
+
+    #[test]
+    fn create_rejects_bad_and_duplicate_names() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(Playlist::create(dir.path(), "  ").is_err());
+        assert!(Playlist::create(dir.path(), "a/b").is_err());
+        Playlist::create(dir.path(), "Mix").unwrap();
+        assert!(Playlist::create(dir.path(), "Mix").is_err());
+    }
+
+    #[test]
+    fn rename_duplicate_and_delete() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut playlist = Playlist::create(dir.path(), "Mix").unwrap();
+        playlist.entries.push(PathBuf::from("/music/a.mp3"));
+        playlist.save().unwrap();
+
+        playlist.rename("Party").unwrap();
+        assert_eq!(playlist.path, dir.path().join("Party.m3u"));
+        assert!(!dir.path().join("Mix.m3u").exists());
+
+        let copy = playlist.duplicate("Party copy").unwrap();
+        assert_eq!(Playlist::load(&copy.path).unwrap().entries, playlist.entries);
+        assert!(playlist.duplicate("Party copy").is_err());
+
+        playlist.delete().unwrap();
+        let names: Vec<String> = load_dir(dir.path()).0.into_iter().map(|p| p.name).collect();
+        assert_eq!(names, vec!["Party copy"]);
+    }
+
+    #[test]
+    fn load_dir_sorts_and_skips_other_files() {
+        let dir = tempfile::tempdir().unwrap();
+        Playlist::create(dir.path(), "beta").unwrap();
+        Playlist::create(dir.path(), "Alpha").unwrap();
+        fs::write(dir.path().join("notes.txt"), "x").unwrap();
+
+        let names: Vec<String> = load_dir(dir.path()).0.into_iter().map(|p| p.name).collect();
+        assert_eq!(names, vec!["Alpha", "beta"]);
+        assert!(load_dir(&dir.path().join("missing")).0.is_empty());
+    }
+
+    #[test]
+    fn old_m3u_files_are_read_as_latin1_and_the_rest_reported() {
+        let dir = tempfile::tempdir().unwrap();
+        // "Motörhead" with ö as the single byte 0xf6
+        fs::write(dir.path().join("old.m3u"), b"/music/Mot\xf6rhead - Ace of Spades.mp3\n").unwrap();
+        fs::write(dir.path().join("broken.m3u8"), b"/music/Mot\xf6rhead - Ace of Spades.mp3\n").unwrap();
+        fs::write(dir.path().join("notepad.m3u8"), "\u{feff}#EXTM3U\n/music/Björk - Hyperballad.mp3\n").unwrap();
+
+        let (playlists, skipped) = load_dir(dir.path());
+        let entries: Vec<&Path> = playlists.iter().flat_map(|p| &p.entries).map(PathBuf::as_path).collect();
+        assert_eq!(entries, vec![Path::new("/music/Björk - Hyperballad.mp3"), Path::new("/music/Motörhead - Ace of Spades.mp3")]);
+        assert_eq!(skipped, vec![(dir.path().join("broken.m3u8"), String::from("not UTF-8"))]);
+    }
+}
@@ -0,0 +1,56 @@
+use std::{fs, path::Path, process::Command};
+
+// Whether the machine is running off its battery right now. Unknown, as on
+// desktops and on Windows, counts as plugged in.
+pub fn on_battery() -> bool {
+    if cfg!(target_os = "linux") {
+        on_battery_in(Path::new("/sys/class/power_supply")).unwrap_or(false)
+    } else if cfg!(target_os = "macos") {
+        Command::new("pmset")
+            .args(["-g", "batt"])
+            .output()
+            .is_ok_and(|output| String::from_utf8_lossy(&output.stdout).contains("'Battery Power'"))
+    } else {
+        false
+    }
+}
+
+// Reads Linux power supplies: a mains adapter that is online means plugged
+// in, otherwise a discharging battery means on battery
+fn on_battery_in(dir: &Path) -> Option<bool> {
+    let read = |supply: &Path, file: &str| fs::read_to_string(supply.join(file)).map(|s| s.trim().to_string()).ok();
+    let mut discharging = false;
+    for entry in fs::read_dir(dir).ok()?.flatten() {
+        let supply = entry.path();
+        match read(&supply, "type").as_deref() {
+            Some("Mains") if read(&supply, "online").as_deref() == Some("1") => return Some(false),
+            Some("Battery") => discharging |= read(&supply, "status").as_deref() == Some("Discharging"),
+            _ => {}
+        }
+    }
+    Some(discharging)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn supply(dir: &Path, name: &str, files: &[(&str, &str)]) {
+        let supply = dir.join(name);
+        fs::create_dir(&supply).unwrap();
+        for (file, contents) in files {
+            fs::write(supply.join(file), format!("{}\n", contents)).unwrap();
+        }
+    }
+
+    #[test]
+    fn discharging_battery_without_mains() {
+        let dir = tempfile::tempdir().unwrap();
+        supply(dir.path(), "BAT0", &[("type", "Battery"), ("status", "Discharging")]);
+        assert_eq!(on_battery_in(dir.path()), Some(true));
+
+        supply(dir.path(), "AC", &[("type", "Mains"), ("online", "1")]);
+        assert_eq!(on_battery_in(dir.path()), Some(false));
+        assert_eq!(on_battery_in(&dir.path().join("missing")), None);
+    }
+}
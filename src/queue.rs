@@ -0,0 +1,115 @@
+use std::{collections::VecDeque, ops::Range};
+
+use crate::library::{format_duration, Song, UNKNOWN_ALBUM};
+
+// A line of the queue pane: a queued track, or the header of a run of
+// consecutive tracks from one album, whose tracks only show while expanded
+#[derive(Clone, Debug, PartialEq)]
+pub enum QueueRow {
+    Album { positions: Range<usize>, expanded: bool },
+    Track(usize),
+}
+
+// Runs of two or more tracks from the same album get a header. Untagged
+// songs all share the unknown album, so they are never grouped.
+pub fn rows(queue: &VecDeque<usize>, songs: &[Song], expanded: impl Fn(&Song) -> bool) -> Vec<QueueRow> {
+    let mut rows = Vec::new();
+    let mut start = 0;
+    while start < queue.len() {
+        let first = &songs[queue[start]];
+        let mut end = start + 1;
+        if first.album != UNKNOWN_ALBUM {
+            while end < queue.len() && same_album(first, &songs[queue[end]]) {
+                end += 1;
+            }
+        }
+        if end - start >= 2 {
+            let expanded = expanded(first);
+            rows.push(QueueRow::Album { positions: start..end, expanded });
+            if expanded {
+                rows.extend((start..end).map(QueueRow::Track));
+            }
+        } else {
+            rows.push(QueueRow::Track(start));
+        }
+        start = end;
+    }
+    rows
+}
+
+fn same_album(a: &Song, b: &Song) -> bool {
+    a.album == b.album && a.artist == b.artist
+}
+
+// "1. Artist – Title [3:07]" a line, for posting a mix. Markdown escapes
+// what would otherwise be read as formatting.
+pub fn tracklist<'a>(songs: impl Iterator<Item = &'a Song>, markdown: bool) -> String {
+    let escape = |text: &str| {
+        let mut escaped = String::new();
+        for c in text.chars() {
+            if markdown && "\\`*_[]<>#|".contains(c) {
+                escaped.push('\\');
+            }
+            escaped.push(c);
+        }
+        escaped
+    };
+    songs.enumerate()
+        .map(|(n, song)| {
+            let line = format!("{}. {} – {}", n + 1, escape(&song.artist), escape(&song.title));
+            match song.duration {
+                Some(duration) => format!("{} [{}]\n", line, format_duration(duration)),
+                None => format!("{}\n", line),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{path::PathBuf, time::Duration};
+
+    fn song(artist: &str, album: &str) -> Song {
+        let mut song = Song::new(PathBuf::from(format!("/music/{} - x.mp3", artist)));
+        song.album = album.to_string();
+        song
+    }
+
+    #[test]
+    fn groups_consecutive_tracks_of_an_album() {
+        let songs = vec![song("A", "One"), song("A", "One"), song("B", "Two"), song("A", "One")];
+        let queue = VecDeque::from(vec![0, 1, 2, 3]);
+
+        let collapsed = rows(&queue, &songs, |_| false);
+        assert_eq!(collapsed, vec![
+            QueueRow::Album { positions: 0..2, expanded: false },
+            QueueRow::Track(2),
+            QueueRow::Track(3),
+        ]);
+
+        let expanded = rows(&queue, &songs, |_| true);
+        assert_eq!(expanded[..3], [
+            QueueRow::Album { positions: 0..2, expanded: true },
+            QueueRow::Track(0),
+            QueueRow::Track(1),
+        ]);
+    }
+
+    #[test]
+    fn tracklist_numbers_tracks_and_escapes_markdown() {
+        let mut songs = [song("Daft Punk", "Discovery"), song("*NSYNC", "No Strings Attached")];
+        songs[0].title = String::from("One More Time");
+        songs[0].duration = Some(Duration::from_secs(320));
+        songs[1].title = String::from("Bye_Bye");
+        assert_eq!(tracklist(songs.iter(), false), "1. Daft Punk – One More Time [5:20]\n2. *NSYNC – Bye_Bye\n");
+        assert_eq!(tracklist(songs.iter(), true), "1. Daft Punk – One More Time [5:20]\n2. \\*NSYNC – Bye\\_Bye\n");
+    }
+
+    #[test]
+    fn untagged_songs_are_not_grouped() {
+        let songs = vec![song("A", UNKNOWN_ALBUM), song("A", UNKNOWN_ALBUM)];
+        let queue = VecDeque::from(vec![0, 1]);
+        assert_eq!(rows(&queue, &songs, |_| false), vec![QueueRow::Track(0), QueueRow::Track(1)]);
+    }
+}
@@ -0,0 +1,99 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::PathBuf,
+};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::persist;
+
+// Name of the queue used until another is picked; not shown in the title
+pub const DEFAULT: &str = "default";
+
+// Named queues, such as "work" and "evening", switched between with
+// `:queue <name>`. They are kept as file paths, as song indices change
+// from one run to the next. The active queue lives in the player while it
+// is active and is written back here when switching away or quitting.
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+pub struct Queues {
+    pub active: String,
+    pub queues: BTreeMap<String, Vec<PathBuf>>,
+}
+
+impl Default for Queues {
+    fn default() -> Self {
+        Queues { active: String::from(DEFAULT), queues: BTreeMap::new() }
+    }
+}
+
+impl Queues {
+    // ~/.local/share/music-player/queues.json on Linux, %APPDATA%\music-player\queues.json on Windows
+    pub fn path() -> Option<PathBuf> {
+        dirs::data_dir().map(|dir| dir.join("music-player").join("queues.json"))
+    }
+
+    // A missing or unreadable file means just the default queue, empty
+    pub fn load() -> Self {
+        Self::path()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let path = Self::path().ok_or_else(|| anyhow::anyhow!("No data directory"))?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        persist::write_atomic(&path, &serde_json::to_string(self)?)
+    }
+
+    // Empty queues aren't kept, which is how a queue is deleted
+    pub fn store(&mut self, name: &str, entries: Vec<PathBuf>) {
+        if entries.is_empty() {
+            self.queues.remove(name);
+        } else {
+            self.queues.insert(name.to_string(), entries);
+        }
+    }
+
+    pub fn entries(&self, name: &str) -> &[PathBuf] {
+        self.queues.get(name).map_or(&[], |entries| entries.as_slice())
+    }
+
+    // Every queue with something in it, and the active one even if empty
+    pub fn names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.queues.keys().map(String::as_str).collect();
+        if !self.queues.contains_key(&self.active) {
+            names.push(&self.active);
+            names.sort();
+        }
+        names
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_queues_are_dropped() {
+        let mut queues = Queues::default();
+        queues.store("work", vec![PathBuf::from("/music/a.mp3")]);
+        queues.store("evening", vec![PathBuf::from("/music/b.mp3")]);
+        assert_eq!(queues.names(), vec!["default", "evening", "work"]);
+
+        queues.store("evening", Vec::new());
+        queues.active = String::from("work");
+        assert_eq!(queues.names(), vec!["work"]);
+        assert!(queues.entries("evening").is_empty());
+
+        let json = serde_json::to_string(&queues).unwrap();
+        let queues: Queues = serde_json::from_str(&json).unwrap();
+        assert_eq!(queues.active, "work");
+        assert_eq!(queues.entries("work"), [PathBuf::from("/music/a.mp3")]);
+    }
+}
@@ -0,0 +1,391 @@
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, Read, Write},
+    net::TcpListener,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{self, Sender, SyncSender},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Result;
+use rustls::{ServerConfig, ServerConnection, StreamOwned};
+use music_player::art::cover_art;
+use music_player::clock;
+use music_player::config::{Access, HttpConfig, TlsConfig};
+use music_player::buffer;
+use music_player::history::{self, Entry};
+use music_player::metrics::Metrics;
+
+use crate::announce::NowPlaying;
+use crate::Action;
+
+// A connection from a client, plain or over TLS
+trait Connection: Read + Write + Send {}
+
+impl<T: Read + Write + Send> Connection for T {}
+
+type Client = Box<dyn Connection>;
+
+// Track changes waiting for an /events client; one this far behind is let go
+const EVENTS_BACKLOG: usize = 8;
+
+// A client that stops sending or taking what is sent is given up on after this long
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+// Open connections per listener, /events clients included; more are closed
+// straight away
+const MAX_CONNECTIONS: usize = 64;
+
+// The request line and headers together, and how many headers
+const MAX_HEADER_BYTES: u64 = 8 * 1024;
+const MAX_HEADERS: usize = 64;
+
+// What a client with enough access can ask the player to do
+pub enum Command {
+    PlayPause,
+    Next,
+    Previous,
+    // The song with this path, if it's in the library
+    Queue(PathBuf),
+    ClearQueue,
+}
+
+#[derive(Default)]
+struct Shared {
+    now_playing: Option<NowPlaying>,
+    // Each /events client is written to by its own connection thread
+    event_clients: Vec<SyncSender<String>>,
+    history: Vec<Entry>,
+    utc_offset: i64,
+    metrics: Metrics,
+    started: Option<Instant>,
+}
+
+// Small HTTP API for overlays, stream widgets, dashboards and remotes:
+//   GET /metadata         current track as JSON
+//   GET /art              cover image of the current track
+//   GET /events           server-sent events, one `data:` line per track change
+//   GET /stats            listening habits as JSON; see `history::Summary`
+//   GET /history?since=T  plays since T, in seconds since the epoch
+//   GET /metrics          counters and gauges for Prometheus
+//   POST /queue?path=P    queues the song at P (queue access)
+//   POST /play-pause, /next, /previous, /queue/clear  (full access)
+pub struct RemoteServer {
+    shared: Arc<Mutex<Shared>>,
+}
+
+impl RemoteServer {
+    // Sockets in `listeners` stand in for `bind` and then `listen`, in order
+    pub fn start(config: &HttpConfig, listeners: Vec<TcpListener>, commands: Sender<Action>) -> Result<Self> {
+        let shared = Arc::new(Mutex::new(Shared {
+            utc_offset: clock::utc_offset(),
+            started: Some(Instant::now()),
+            ..Shared::default()
+        }));
+        let tls = config.tls.as_ref().map(tls_config).transpose()?;
+        let addresses = [(config.bind.clone(), config.access)].into_iter()
+            .chain(config.listen.iter().map(|listen| (listen.bind.clone(), listen.access)));
+        let mut passed = listeners.into_iter();
+        for (bind, access) in addresses {
+            let listener = match passed.next() {
+                Some(listener) => listener,
+                None => TcpListener::bind(&bind)?,
+            };
+            let server_shared = Arc::clone(&shared);
+            let (token, commands, tls) = (config.token.clone(), commands.clone(), tls.clone());
+            thread::spawn(move || {
+                let open = Arc::new(AtomicUsize::new(0));
+                for stream in listener.incoming().flatten() {
+                    if open.load(Ordering::Relaxed) >= MAX_CONNECTIONS {
+                        continue;
+                    }
+                    let slot = Slot::take(&open);
+                    let shared = Arc::clone(&server_shared);
+                    let (token, commands, tls) = (token.clone(), commands.clone(), tls.clone());
+                    thread::spawn(move || {
+                        let _slot = slot;
+                        if stream.set_read_timeout(Some(TIMEOUT)).and(stream.set_write_timeout(Some(TIMEOUT))).is_err() {
+                            return;
+                        }
+                        // The handshake happens on the first read, here rather than on the listener
+                        let client: Client = match tls {
+                            Some(tls) => match ServerConnection::new(tls) {
+                                Ok(connection) => Box::new(StreamOwned::new(connection, stream)),
+                                Err(_) => return,
+                            },
+                            None => Box::new(stream),
+                        };
+                        let _ = handle_connection(client, &shared, access, token.as_deref(), &commands);
+                    });
+                }
+            });
+        }
+
+        Ok(RemoteServer { shared })
+    }
+
+    pub fn set_history(&self, history: Vec<Entry>) {
+        self.shared.lock().unwrap().history = history;
+    }
+
+    pub fn record_play(&self, entry: Entry) {
+        self.shared.lock().unwrap().history.push(entry);
+    }
+
+    pub fn update_metrics(&self, update: impl FnOnce(&mut Metrics)) {
+        update(&mut self.shared.lock().unwrap().metrics);
+    }
+
+    pub fn update(&self, now_playing: &NowPlaying) {
+        let mut shared = self.shared.lock().unwrap();
+        shared.now_playing = Some(now_playing.clone());
+
+        let event = match serde_json::to_string(now_playing) {
+            Ok(json) => format!("event: track\ndata: {}\n\n", json),
+            Err(_) => return,
+        };
+        // Never written to from here, so a stalled client can't hold up the
+        // player; those gone away or too far behind are dropped
+        shared.event_clients.retain(|client| client.try_send(event.clone()).is_ok());
+    }
+}
+
+// One of a listener's open connections, given back when the connection ends
+struct Slot(Arc<AtomicUsize>);
+
+impl Slot {
+    fn take(open: &Arc<AtomicUsize>) -> Self {
+        open.fetch_add(1, Ordering::Relaxed);
+        Slot(Arc::clone(open))
+    }
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+fn tls_config(config: &TlsConfig) -> Result<Arc<ServerConfig>> {
+    let certs = rustls_pemfile::certs(&mut BufReader::new(File::open(&config.cert)?)).collect::<Result<Vec<_>, _>>()?;
+    let key = rustls_pemfile::private_key(&mut BufReader::new(File::open(&config.key)?))?
+        .ok_or_else(|| anyhow::anyhow!("No private key in {}", config.key.display()))?;
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let tls = ServerConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()?
+        .with_no_client_auth()
+        .with_single_cert(certs, key)?;
+    Ok(Arc::new(tls))
+}
+
+fn handle_connection(mut stream: Client, shared: &Mutex<Shared>, access: Access, token: Option<&str>, commands: &Sender<Action>) -> Result<()> {
+    let mut reader = BufReader::new((&mut stream).take(MAX_HEADER_BYTES));
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let mut bearer = None;
+    let mut headers = 0;
+    loop {
+        let mut header = String::new();
+        reader.read_line(&mut header)?;
+        // Cut off by the limit, or the client stopped partway
+        if !header.ends_with('\n') || headers == MAX_HEADERS {
+            drop(reader);
+            return respond(&mut stream, "431 Request Header Fields Too Large", "text/plain", b"Request too large");
+        }
+        if header.trim().is_empty() {
+            break;
+        }
+        headers += 1;
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("authorization") {
+                bearer = value.trim().strip_prefix("Bearer ").map(|token| token.trim().to_string());
+            }
+        }
+    }
+    drop(reader);
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or("");
+    let path = parts.next().unwrap_or("");
+    let (path, query) = path.split_once('?').unwrap_or((path, ""));
+
+    let access = match (bearer.as_deref(), token) {
+        (Some(sent), Some(token)) if same_token(sent, token) => Access::Full,
+        (Some(_), Some(_)) => return respond(&mut stream, "401 Unauthorized", "text/plain", b"Wrong token"),
+        _ => access,
+    };
+    match method {
+        "GET" if access < Access::ReadOnly => {
+            return respond(&mut stream, "401 Unauthorized", "text/plain", b"A token is needed");
+        }
+        "GET" => {}
+        "POST" => return handle_command(&mut stream, path, query, access, commands),
+        _ => return respond(&mut stream, "405 Method Not Allowed", "text/plain", b"Method not allowed"),
+    }
+
+    match path {
+        "/metadata" => {
+            let body = serde_json::to_vec(&shared.lock().unwrap().now_playing)?;
+            respond(&mut stream, "200 OK", "application/json", &body)
+        }
+        "/art" => {
+            let song_path = shared.lock().unwrap().now_playing.as_ref().map(|np| np.path.clone());
+            match song_path.and_then(|path| cover_art(Path::new(&path))) {
+                Some((mime, data)) => respond(&mut stream, "200 OK", &mime, &data),
+                None => respond(&mut stream, "404 Not Found", "text/plain", b"No cover art"),
+            }
+        }
+        "/events" => {
+            stream.write_all(
+                b"HTTP/1.1 200 OK\r\n\
+                  Content-Type: text/event-stream\r\n\
+                  Cache-Control: no-cache\r\n\
+                  Access-Control-Allow-Origin: *\r\n\r\n",
+            )?;
+            let (tx, rx) = mpsc::sync_channel(EVENTS_BACKLOG);
+            let current = {
+                let mut shared = shared.lock().unwrap();
+                shared.event_clients.push(tx);
+                shared.now_playing.as_ref().and_then(|np| serde_json::to_string(np).ok())
+            };
+            if let Some(json) = current {
+                write!(stream, "event: track\ndata: {}\n\n", json)?;
+                stream.flush()?;
+            }
+            // Until the client goes away or falls behind and `update` drops it
+            for event in rx {
+                stream.write_all(event.as_bytes())?;
+                stream.flush()?;
+            }
+            Ok(())
+        }
+        "/stats" => {
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs());
+            let shared = shared.lock().unwrap();
+            let body = serde_json::to_vec(&history::summary(&shared.history, now, shared.utc_offset))?;
+            drop(shared);
+            respond(&mut stream, "200 OK", "application/json", &body)
+        }
+        "/history" => {
+            let since = match param(query, "since").map(str::parse::<u64>) {
+                None => 0,
+                Some(Ok(since)) => since,
+                Some(Err(_)) => return respond(&mut stream, "400 Bad Request", "text/plain", b"since must be seconds since the epoch"),
+            };
+            let body = serde_json::to_vec(history::since(&shared.lock().unwrap().history, since))?;
+            respond(&mut stream, "200 OK", "application/json", &body)
+        }
+        "/metrics" => {
+            let shared = shared.lock().unwrap();
+            let uptime = shared.started.map(|started| started.elapsed()).unwrap_or_default();
+            let body = shared.metrics.render(uptime, buffer::total_underruns());
+            drop(shared);
+            respond(&mut stream, "200 OK", "text/plain; version=0.0.4", body.as_bytes())
+        }
+        _ => respond(&mut stream, "404 Not Found", "text/plain", b"Not found"),
+    }
+}
+
+fn handle_command(stream: &mut Client, path: &str, query: &str, access: Access, commands: &Sender<Action>) -> Result<()> {
+    let command = match path {
+        "/play-pause" => Command::PlayPause,
+        "/next" => Command::Next,
+        "/previous" => Command::Previous,
+        "/queue/clear" => Command::ClearQueue,
+        "/queue" => match param(query, "path") {
+            Some(path) => Command::Queue(PathBuf::from(percent_decode(path))),
+            None => return respond(stream, "400 Bad Request", "text/plain", b"path is required"),
+        },
+        _ => return respond(stream, "404 Not Found", "text/plain", b"Not found"),
+    };
+    if access < command.needs() {
+        return respond(stream, "403 Forbidden", "text/plain", b"Not allowed from this address without the token");
+    }
+    // Carried out on the main thread; there's nothing to wait for
+    let _ = commands.send(Action::Remote(command));
+    respond(stream, "202 Accepted", "text/plain", b"OK")
+}
+
+impl Command {
+    fn needs(&self) -> Access {
+        match self {
+            Command::Queue(_) => Access::Queue,
+            _ => Access::Full,
+        }
+    }
+}
+
+// "%2Fmusic%2FA+B.mp3" → "/music/A B.mp3"; malformed escapes are kept as they are
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'%').then(|| text.get(i + 1..i + 3)).flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], escaped) {
+            (_, Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+                continue;
+            }
+            (b'+', None) => decoded.push(b' '),
+            (byte, None) => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+// Takes as long whichever byte differs, so the token can't be guessed a byte
+// at a time from how quickly wrong ones are turned away
+fn same_token(sent: &str, token: &str) -> bool {
+    sent.len() == token.len() && sent.bytes().zip(token.bytes()).fold(0, |differ, (a, b)| differ | (a ^ b)) == 0
+}
+
+// The value of `name` in a query string such as "since=123&limit=5"
+fn param<'a>(query: &'a str, name: &str) -> Option<&'a str> {
+    query.split('&').find_map(|pair| pair.strip_prefix(name)?.strip_prefix('='))
+}
+
+fn respond(stream: &mut Client, status: &str, content_type: &str, body: &[u8]) -> Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nAccess-Control-Allow-Origin: *\r\nConnection: close\r\n\r\n",
+        status,
+        content_type,
+        body.len()
+    )?;
+    stream.write_all(body)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_query_values() {
+        assert_eq!(percent_decode("%2Fmusic%2FSigur%20R%C3%B3s+-+Hopp%C3%ADpolla.mp3"), "/music/Sigur Rós - Hoppípolla.mp3");
+        assert_eq!(percent_decode("100%"), "100%");
+        assert_eq!(param("token=x&path=%2Fa.mp3", "path"), Some("%2Fa.mp3"));
+    }
+
+    #[test]
+    fn tokens_match_only_in_full() {
+        assert!(same_token("s3cret", "s3cret"));
+        assert!(!same_token("s3creT", "s3cret"));
+        assert!(!same_token("s3cre", "s3cret"));
+    }
+
+    #[test]
+    fn queueing_needs_less_than_control() {
+        assert!(Access::Queue >= Command::Queue(PathBuf::new()).needs());
+        assert!(Access::Queue < Command::ClearQueue.needs());
+        assert!(Access::ReadOnly < Command::Queue(PathBuf::new()).needs());
+    }
+}
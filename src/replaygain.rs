@@ -0,0 +1,107 @@
+use id3::Tag;
+
+use crate::config::ReplayGainMode;
+
+// R128 gains are relative to -23 LUFS, ReplayGain's to about -18
+const R128_OFFSET_DB: f32 = 5.0;
+
+// Loudness adjustments in dB and peaks (1.0 is full scale) from a file's
+// REPLAYGAIN_* or R128_* tags
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ReplayGain {
+    pub track: Option<f32>,
+    pub album: Option<f32>,
+    pub track_peak: Option<f32>,
+    pub album_peak: Option<f32>,
+}
+
+impl ReplayGain {
+    // From the TXXX frames taggers write them to
+    pub fn from_tag(tag: &Tag) -> Self {
+        let text = |name: &str| {
+            tag.extended_texts()
+                .find(|text| text.description.eq_ignore_ascii_case(name))
+                .map(|text| text.value.as_str())
+        };
+        ReplayGain {
+            track: text("REPLAYGAIN_TRACK_GAIN").and_then(parse_db)
+                .or_else(|| text("R128_TRACK_GAIN").and_then(parse_r128)),
+            album: text("REPLAYGAIN_ALBUM_GAIN").and_then(parse_db)
+                .or_else(|| text("R128_ALBUM_GAIN").and_then(parse_r128)),
+            track_peak: text("REPLAYGAIN_TRACK_PEAK").and_then(|peak| peak.trim().parse().ok()),
+            album_peak: text("REPLAYGAIN_ALBUM_PEAK").and_then(|peak| peak.trim().parse().ok()),
+        }
+    }
+
+    // What to multiply samples by: 1.0 without a gain, and never so much that
+    // the peak would clip
+    pub fn factor(&self, mode: ReplayGainMode) -> f32 {
+        let track = self.track.map(|gain| (gain, self.track_peak));
+        let album = self.album.map(|gain| (gain, self.album_peak));
+        let chosen = match mode {
+            ReplayGainMode::Off => None,
+            ReplayGainMode::Track => track.or(album),
+            ReplayGainMode::Album => album.or(track),
+        };
+        let Some((gain, peak)) = chosen else {
+            return 1.0;
+        };
+        let factor = 10f32.powf(gain / 20.0);
+        match peak.filter(|&peak| peak > 0.0) {
+            Some(peak) => factor.min(1.0 / peak),
+            None => factor,
+        }
+    }
+}
+
+// "-6.54 dB", or just the number
+fn parse_db(text: &str) -> Option<f32> {
+    let number = text.trim();
+    let number = number.strip_suffix("dB").or_else(|| number.strip_suffix("db")).unwrap_or(number);
+    number.trim().parse().ok()
+}
+
+// Whole 1/256ths of a dB, as Opus writes them
+fn parse_r128(text: &str) -> Option<f32> {
+    let steps: i32 = text.trim().parse().ok()?;
+    Some(steps as f32 / 256.0 + R128_OFFSET_DB)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use id3::{frame::ExtendedText, TagLike};
+
+    fn tag(frames: &[(&str, &str)]) -> Tag {
+        let mut tag = Tag::new();
+        for (description, value) in frames {
+            tag.add_frame(ExtendedText { description: description.to_string(), value: value.to_string() });
+        }
+        tag
+    }
+
+    #[test]
+    fn reads_replaygain_and_r128_tags() {
+        let gain = ReplayGain::from_tag(&tag(&[
+            ("replaygain_track_gain", "-6.54 dB"),
+            ("REPLAYGAIN_TRACK_PEAK", "0.988"),
+            ("R128_ALBUM_GAIN", "-512"),
+        ]));
+        assert_eq!(gain.track, Some(-6.54));
+        assert_eq!(gain.track_peak, Some(0.988));
+        assert_eq!(gain.album, Some(3.0));
+        assert_eq!(ReplayGain::from_tag(&Tag::new()), ReplayGain::default());
+    }
+
+    #[test]
+    fn factor_falls_back_and_keeps_peaks_from_clipping() {
+        let gain = ReplayGain { track: Some(-6.0), album: Some(6.0), track_peak: None, album_peak: Some(0.8) };
+        assert!((gain.factor(ReplayGainMode::Track) - 0.501).abs() < 0.001);
+        assert_eq!(gain.factor(ReplayGainMode::Album), 1.25);
+        assert_eq!(gain.factor(ReplayGainMode::Off), 1.0);
+
+        let album_only = ReplayGain { album: Some(-6.0), ..ReplayGain::default() };
+        assert_eq!(album_only.factor(ReplayGainMode::Track), album_only.factor(ReplayGainMode::Album));
+        assert_eq!(ReplayGain::default().factor(ReplayGainMode::Track), 1.0);
+    }
+}
@@ -0,0 +1,303 @@
+use std::{collections::VecDeque, f64::consts::PI, time::Duration};
+
+use rand::{rngs::StdRng, SeedableRng};
+use rodio::Source;
+
+use crate::channels;
+use crate::config::ResampleQuality;
+use crate::decode::{AudioSource, Codec};
+use crate::dither;
+
+// What the output stream was opened with
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DeviceFormat {
+    pub rate: u32,
+    pub channels: u16,
+}
+
+// What the playing track's audio goes through on its way to the device
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct StreamInfo {
+    pub file_rate: u32,
+    pub device_rate: u32,
+    // Set when the file's rate is converted to the device's
+    pub conversion: Option<ResampleQuality>,
+    pub file_channels: u16,
+    pub device_channels: u16,
+    // Where the decoder could tell
+    pub codec: Option<Codec>,
+}
+
+impl StreamInfo {
+    pub fn rate(&self) -> String {
+        match self.conversion {
+            Some(quality) => format!("{} → {} kHz ({})", khz(self.file_rate), khz(self.device_rate), quality.label()),
+            None => format!("{} kHz", khz(self.file_rate)),
+        }
+    }
+
+    pub fn layout(&self) -> String {
+        if self.file_channels == self.device_channels {
+            channels::layout_name(self.file_channels)
+        } else {
+            format!("{} → {}", channels::layout_name(self.file_channels), channels::layout_name(self.device_channels))
+        }
+    }
+}
+
+// 44100 as "44.1", 48000 as "48"
+fn khz(rate: u32) -> f64 {
+    rate as f64 / 1000.0
+}
+
+// Converts `source` to the device's channel layout and sample rate where they
+// differ, rather than leaving it to rodio, which drops or repeats channels and
+// interpolates linearly. With `dither` the results are dithered back down to
+// 16 bits.
+pub fn to_device(
+    source: AudioSource,
+    device: Option<DeviceFormat>,
+    quality: ResampleQuality,
+    dither: bool,
+) -> (AudioSource, StreamInfo) {
+    let (file_rate, file_channels) = (source.sample_rate(), source.channels());
+    let device = device.unwrap_or(DeviceFormat { rate: file_rate, channels: file_channels });
+    let mut info = StreamInfo {
+        file_rate,
+        device_rate: device.rate,
+        conversion: None,
+        file_channels,
+        device_channels: device.channels,
+        codec: None,
+    };
+    let source = channels::remix(source, device.channels, dither);
+    if device.rate == file_rate {
+        return (source, info);
+    }
+    info.conversion = Some(quality);
+    let mut resampler = Resampler::new(source, device.rate, quality);
+    if dither {
+        resampler.dither = Some(StdRng::from_entropy());
+    }
+    (Box::new(resampler), info)
+}
+
+// Input frames either side of the output position each kernel looks at
+fn reach(quality: ResampleQuality) -> usize {
+    match quality {
+        ResampleQuality::Fast => 1,
+        ResampleQuality::Medium => 2,
+        ResampleQuality::High => 16,
+    }
+}
+
+// Weight of the input frame `x` frames away from the output position.
+// `cutoff` is the output's share of the input bandwidth, below 1 when
+// downsampling so the sinc also filters out what can't be represented.
+fn kernel(quality: ResampleQuality, x: f64, cutoff: f64) -> f64 {
+    let d = x.abs();
+    match quality {
+        // Linear interpolation
+        ResampleQuality::Fast => (1.0 - d).max(0.0),
+        // Catmull-Rom spline
+        ResampleQuality::Medium if d < 1.0 => 1.5 * d.powi(3) - 2.5 * d.powi(2) + 1.0,
+        ResampleQuality::Medium if d < 2.0 => -0.5 * d.powi(3) + 2.5 * d.powi(2) - 4.0 * d + 2.0,
+        ResampleQuality::Medium => 0.0,
+        // Blackman-windowed sinc
+        ResampleQuality::High => {
+            let reach = reach(quality) as f64;
+            if d >= reach {
+                return 0.0;
+            }
+            let sinc = if d < 1e-9 { 1.0 } else { (PI * cutoff * x).sin() / (PI * cutoff * x) };
+            let u = x / reach;
+            cutoff * sinc * (0.42 + 0.5 * (PI * u).cos() + 0.08 * (2.0 * PI * u).cos())
+        }
+    }
+}
+
+pub struct Resampler {
+    source: AudioSource,
+    quality: ResampleQuality,
+    channels: usize,
+    from: u32,
+    to: u32,
+    // Input frames the kernel covers, the first of them being frame `first`;
+    // frames before the start and past the end are silence
+    window: VecDeque<Vec<f32>>,
+    first: i64,
+    read: i64,
+    ended: bool,
+    // Output frames produced so far
+    produced: u64,
+    frame: Vec<i16>,
+    position: usize,
+    total_duration: Option<Duration>,
+    dither: Option<StdRng>,
+}
+
+impl Resampler {
+    pub fn new(source: AudioSource, to: u32, quality: ResampleQuality) -> Self {
+        let channels = source.channels().max(1) as usize;
+        let from = source.sample_rate();
+        let total_duration = source.total_duration();
+        let before = reach(quality) - 1;
+        Resampler {
+            source,
+            quality,
+            channels,
+            from,
+            to,
+            window: vec![vec![0.0; channels]; before].into(),
+            first: -(before as i64),
+            read: 0,
+            ended: false,
+            produced: 0,
+            frame: Vec::new(),
+            position: 0,
+            total_duration,
+            dither: None,
+        }
+    }
+
+    fn read_frame(&mut self) -> Vec<f32> {
+        if !self.ended {
+            let frame: Vec<f32> = self.source.by_ref()
+                .take(self.channels)
+                .map(|sample| sample as f32 / 32768.0)
+                .collect();
+            // A partial frame at the end is dropped
+            if frame.len() == self.channels {
+                self.read += 1;
+                return frame;
+            }
+            self.ended = true;
+        }
+        vec![0.0; self.channels]
+    }
+
+    fn next_frame(&mut self) -> Option<Vec<i16>> {
+        let reach = reach(self.quality) as i64;
+        let position = self.produced * self.from as u64;
+        let index = (position / self.to as u64) as i64;
+        let fraction = (position % self.to as u64) as f64 / self.to as f64;
+
+        while self.first < index + 1 - reach {
+            self.window.pop_front();
+            self.first += 1;
+        }
+        while self.first + (self.window.len() as i64) <= index + reach {
+            let frame = self.read_frame();
+            self.window.push_back(frame);
+        }
+        if self.ended && index >= self.read {
+            return None;
+        }
+
+        let cutoff = (self.to as f64 / self.from as f64).min(1.0);
+        let mut frame = vec![0.0; self.channels];
+        let mut total = 0.0;
+        for (k, input) in self.window.iter().enumerate() {
+            let weight = kernel(self.quality, (self.first + k as i64 - index) as f64 - fraction, cutoff);
+            total += weight;
+            for (out, sample) in frame.iter_mut().zip(input) {
+                *out += weight * *sample as f64;
+            }
+        }
+        self.produced += 1;
+        // Normalised, so the window's truncation doesn't change the level
+        let scale = if total.abs() > 1e-9 { 32768.0 / total } else { 0.0 };
+        Some(frame.into_iter()
+            .map(|sample| {
+                let noise = self.dither.as_mut().map_or(0.0, |rng| dither::noise(rng) as f64);
+                (sample * scale + noise).round().clamp(i16::MIN as f64, i16::MAX as f64) as i16
+            })
+            .collect())
+    }
+}
+
+impl Iterator for Resampler {
+    type Item = i16;
+
+    fn next(&mut self) -> Option<i16> {
+        if self.position >= self.frame.len() {
+            self.frame = self.next_frame()?;
+            self.position = 0;
+        }
+        self.position += 1;
+        Some(self.frame[self.position - 1])
+    }
+}
+
+impl Source for Resampler {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels as u16
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.to
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.total_duration
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rodio::buffer::SamplesBuffer;
+
+    fn sine(rate: u32, frames: usize) -> Vec<i16> {
+        (0..frames)
+            .map(|i| (10_000.0 * (2.0 * PI * 6_000.0 * i as f64 / rate as f64).sin()).round() as i16)
+            .collect()
+    }
+
+    // Largest difference from the ideal sine, away from the edges
+    fn worst_error(quality: ResampleQuality) -> i32 {
+        let source: AudioSource = Box::new(SamplesBuffer::new(1, 44_100, sine(44_100, 4_410)));
+        let (resampled, info) = to_device(source, Some(DeviceFormat { rate: 48_000, channels: 1 }), quality, false);
+        assert_eq!(info.conversion, Some(quality));
+        let resampled: Vec<i16> = resampled.collect();
+        assert_eq!(resampled.len(), 4_800);
+        let ideal = sine(48_000, 4_800);
+        resampled[100..4_700].iter()
+            .zip(&ideal[100..4_700])
+            .map(|(a, b)| (*a as i32 - *b as i32).abs())
+            .max()
+            .unwrap()
+    }
+
+    #[test]
+    fn better_quality_tracks_the_signal_closer() {
+        let (fast, medium, high) =
+            (worst_error(ResampleQuality::Fast), worst_error(ResampleQuality::Medium), worst_error(ResampleQuality::High));
+        assert!(high < medium && medium < fast, "{} {} {}", fast, medium, high);
+        // Within rounding of the ideal
+        assert!(high <= 2, "{}", high);
+    }
+
+    #[test]
+    fn matching_formats_pass_through() {
+        let source: AudioSource = Box::new(SamplesBuffer::new(2, 48_000, vec![1i16, 2, 3, 4]));
+        let device = DeviceFormat { rate: 48_000, channels: 2 };
+        let (source, info) = to_device(source, Some(device), ResampleQuality::High, true);
+        assert_eq!((info.rate().as_str(), info.layout().as_str()), ("48 kHz", "stereo"));
+        assert_eq!(source.collect::<Vec<_>>(), vec![1, 2, 3, 4]);
+
+        let info = StreamInfo {
+            file_rate: 44_100,
+            device_rate: 48_000,
+            conversion: Some(ResampleQuality::Medium),
+            file_channels: 6,
+            device_channels: 2,
+            codec: None,
+        };
+        assert_eq!((info.rate().as_str(), info.layout().as_str()), ("44.1 → 48 kHz (medium)", "5.1 → stereo"));
+    }
+}
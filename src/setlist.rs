@@ -0,0 +1,68 @@
+use std::time::Duration;
+
+// Cue sheet positions count 75 frames a second
+const CUE_FRAMES: u128 = 75;
+
+// A track as it was heard during the session, `at` after the first one started
+#[derive(Clone, Debug, PartialEq)]
+pub struct Entry {
+    pub at: Duration,
+    pub artist: String,
+    pub title: String,
+}
+
+// "03:07", or "1:02:03" once past the hour
+pub fn timestamp(at: Duration) -> String {
+    let secs = at.as_secs();
+    match secs / 3600 {
+        0 => format!("{:02}:{:02}", secs / 60, secs % 60),
+        hours => format!("{}:{:02}:{:02}", hours, secs % 3600 / 60, secs % 60),
+    }
+}
+
+// "00:00 Artist – Title" a line, for a mix upload's description
+pub fn text(entries: &[Entry]) -> String {
+    entries.iter().map(|entry| format!("{} {} – {}\n", timestamp(entry.at), entry.artist, entry.title)).collect()
+}
+
+// A cue sheet for a recording of the session in `file`
+pub fn cue(entries: &[Entry], file: &str) -> String {
+    let quoted = |text: &str| text.replace('"', "'");
+    let mut sheet = format!("FILE \"{}\" WAVE\n", quoted(file));
+    for (n, entry) in entries.iter().enumerate() {
+        let frames = entry.at.as_millis() * CUE_FRAMES / 1000;
+        let (minutes, seconds, frames) = (frames / CUE_FRAMES / 60, frames / CUE_FRAMES % 60, frames % CUE_FRAMES);
+        sheet.push_str(&format!("  TRACK {:02} AUDIO\n", n + 1));
+        sheet.push_str(&format!("    TITLE \"{}\"\n", quoted(&entry.title)));
+        sheet.push_str(&format!("    PERFORMER \"{}\"\n", quoted(&entry.artist)));
+        sheet.push_str(&format!("    INDEX 01 {:02}:{:02}:{:02}\n", minutes, seconds, frames));
+    }
+    sheet
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entries() -> Vec<Entry> {
+        vec![
+            Entry { at: Duration::ZERO, artist: String::from("Daft Punk"), title: String::from("One More Time") },
+            Entry { at: Duration::from_millis(3_787_500), artist: String::from("Björk"), title: String::from("\"Hyperballad\"") },
+        ]
+    }
+
+    #[test]
+    fn text_lists_start_times() {
+        assert_eq!(timestamp(Duration::from_secs(187)), "03:07");
+        assert_eq!(text(&entries()), "00:00 Daft Punk – One More Time\n1:03:07 Björk – \"Hyperballad\"\n");
+    }
+
+    #[test]
+    fn cue_indexes_in_frames() {
+        let sheet = cue(&entries(), "set.wav");
+        assert!(sheet.starts_with("FILE \"set.wav\" WAVE\n  TRACK 01 AUDIO\n"));
+        assert!(sheet.contains("    INDEX 01 00:00:00\n"));
+        // Minutes go past 99 rather than into hours
+        assert!(sheet.contains("  TRACK 02 AUDIO\n    TITLE \"'Hyperballad'\"\n    PERFORMER \"Björk\"\n    INDEX 01 63:07:37\n"));
+    }
+}
@@ -0,0 +1,46 @@
+use std::{collections::BTreeMap, fs, path::PathBuf};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::columns::LibrarySort;
+use crate::config::{LayoutConfig, PinsConfig, TimeDisplay};
+use crate::persist;
+
+// Preferences changed from inside the player, as opposed to the hand-edited
+// config file. Anything set here wins over the config.
+#[derive(Serialize, Deserialize, Default, Clone)]
+#[serde(default)]
+pub struct State {
+    pub layout: Option<LayoutConfig>,
+    pub time_display: Option<TimeDisplay>,
+    pub library_sort: Option<LibrarySort>,
+    pub pins: Option<PinsConfig>,
+    pub night: Option<bool>,
+    pub mono: Option<bool>,
+    // Merged by `:artists merge`, on top of the config's aliases
+    pub artist_aliases: Option<BTreeMap<String, String>>,
+}
+
+impl State {
+    // ~/.local/share/music-player/state.toml on Linux, %APPDATA%\music-player\state.toml on Windows
+    pub fn path() -> Option<PathBuf> {
+        dirs::data_dir().map(|dir| dir.join("music-player").join("state.toml"))
+    }
+
+    // A missing or unreadable state file just means defaults
+    pub fn load() -> Self {
+        Self::path()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|contents| toml::from_str(&contents).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let path = Self::path().ok_or_else(|| anyhow::anyhow!("No data directory"))?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        persist::write_atomic(&path, &toml::to_string(self)?)
+    }
+}
@@ -0,0 +1,343 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+use id3::{frame::Popularimeter, Tag, TagLike};
+use serde::{Deserialize, Serialize};
+
+use crate::config::TagConflict;
+use crate::library::Song;
+use crate::persist;
+use crate::tags;
+
+// POPM frames are per user; other players' frames are read but left alone
+const POPM_USER: &str = "music-player";
+
+// Rating (1-5 stars) and play count, as kept by the player or found in tags
+#[derive(Serialize, Deserialize, Default, Clone, Copy, PartialEq, Debug)]
+#[serde(default)]
+pub struct TagStats {
+    pub rating: Option<u8>,
+    pub plays: u64,
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, Copy, PartialEq, Debug)]
+#[serde(default)]
+pub struct SongStats {
+    pub rating: Option<u8>,
+    pub plays: u64,
+    // What the tags held after the last sync, to tell which side changed since
+    pub synced: Option<TagStats>,
+    // When it last played through, in seconds since the epoch
+    pub last_played: Option<u64>,
+}
+
+impl SongStats {
+    pub fn values(&self) -> TagStats {
+        TagStats { rating: self.rating, plays: self.plays }
+    }
+}
+
+// One track played through, for streaks and goals
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct Play {
+    pub path: PathBuf,
+    // Seconds since the epoch
+    pub at: u64,
+}
+
+// Ratings and play counts by file path
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct Stats {
+    pub songs: HashMap<PathBuf, SongStats>,
+    // Content hashes of those files, to find them again once moved
+    pub hashes: HashMap<PathBuf, u64>,
+    // Every play, oldest first
+    pub history: Vec<Play>,
+}
+
+impl Stats {
+    // ~/.local/share/music-player/stats.json on Linux, %APPDATA%\music-player\stats.json on Windows
+    pub fn path() -> Option<PathBuf> {
+        dirs::data_dir().map(|dir| dir.join("music-player").join("stats.json"))
+    }
+
+    // A missing or unreadable file starts everything from zero
+    pub fn load() -> Self {
+        Self::path()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let path = Self::path().ok_or_else(|| anyhow::anyhow!("No data directory"))?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        persist::write_atomic(&path, &serde_json::to_string(self)?)
+    }
+
+    pub fn get(&self, path: &Path) -> SongStats {
+        self.songs.get(path).copied().unwrap_or_default()
+    }
+
+    // Up to `n` of `songs` that have played, the latest first
+    pub fn recently_played(&self, songs: &[Song], n: usize) -> Vec<usize> {
+        self.top(songs, n, |stats| stats.last_played.map(|at| (at, 0)))
+    }
+
+    // Up to `n` of `songs` that have played, the most played first, ties going
+    // to whichever played last
+    pub fn most_played(&self, songs: &[Song], n: usize) -> Vec<usize> {
+        self.top(songs, n, |stats| (stats.plays > 0).then_some((stats.plays, stats.last_played.unwrap_or(0))))
+    }
+
+    fn top(&self, songs: &[Song], n: usize, key: impl Fn(&SongStats) -> Option<(u64, u64)>) -> Vec<usize> {
+        let mut ranked: Vec<(usize, (u64, u64))> = songs.iter().enumerate()
+            .filter_map(|(index, song)| Some((index, key(self.songs.get(&song.path)?)?)))
+            .collect();
+        ranked.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        ranked.into_iter().take(n).map(|(index, _)| index).collect()
+    }
+
+    // Moves what is kept for files that no longer `exist` over to the new
+    // paths with the same content hash. One shared by two gone files or two
+    // new ones moves nothing, as there is no telling which went where. Only
+    // new paths are hashed, and only if there is something to move. Returns
+    // how many moved.
+    pub fn relink(
+        &mut self,
+        new_paths: &[PathBuf],
+        exists: impl Fn(&Path) -> bool,
+        hash: impl Fn(&Path) -> Option<u64>,
+    ) -> usize {
+        let mut gone: HashMap<u64, Option<PathBuf>> = HashMap::new();
+        for (path, &hash) in self.hashes.iter().filter(|(path, _)| self.songs.contains_key(*path) && !exists(path)) {
+            gone.entry(hash).and_modify(|old| *old = None).or_insert_with(|| Some(path.clone()));
+        }
+        if gone.is_empty() {
+            return 0;
+        }
+        let mut found: HashMap<u64, Option<&PathBuf>> = HashMap::new();
+        let mut order = Vec::new();
+        for path in new_paths.iter().filter(|path| !self.songs.contains_key(*path)) {
+            let Some(hash) = hash(path).filter(|hash| gone.contains_key(hash)) else {
+                continue;
+            };
+            found.entry(hash).and_modify(|new| *new = None).or_insert_with(|| {
+                order.push(hash);
+                Some(path)
+            });
+        }
+
+        let mut relinked = 0;
+        for hash in order {
+            let (Some(Some(old)), Some(Some(path))) = (gone.get(&hash), found.get(&hash)) else {
+                continue;
+            };
+            let path = (*path).clone();
+            if let Some(stats) = self.songs.remove(old) {
+                self.songs.insert(path.clone(), stats);
+            }
+            if let Some(hash) = self.hashes.remove(old) {
+                self.hashes.insert(path.clone(), hash);
+            }
+            for play in self.history.iter_mut().filter(|play| play.path == *old) {
+                play.path = path.clone();
+            }
+            relinked += 1;
+        }
+        relinked
+    }
+}
+
+// ★★★☆☆, or all hollow when unrated
+pub fn stars_label(rating: Option<u8>) -> String {
+    let filled = rating.unwrap_or(0).min(5) as usize;
+    format!("{}{}", "★".repeat(filled), "☆".repeat(5 - filled))
+}
+
+// Only ID3 tags can be written, which in practice means MP3 files
+pub fn has_id3(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("mp3"))
+}
+
+// Our own POPM frame, or else the first one another player wrote
+pub fn read_popm(tag: &Tag) -> Option<TagStats> {
+    let frames: Vec<&Popularimeter> = tag.frames()
+        .filter_map(|frame| frame.content().popularimeter())
+        .collect();
+    let popm = frames.iter().find(|popm| popm.user == POPM_USER).or(frames.first())?;
+    Some(TagStats { rating: stars(popm.rating), plays: popm.counter })
+}
+
+pub fn write_popm(path: &Path, stats: TagStats) -> Result<()> {
+    let mut tag = tags::load_or_new(path)?;
+    // Replaces only the frame with our user name
+    tag.add_frame(Popularimeter {
+        user: POPM_USER.to_string(),
+        rating: stats.rating.map_or(0, popm_rating),
+        counter: stats.plays,
+    });
+    tags::save(&tag, path)
+}
+
+// The 0-255 POPM scale in the steps other players use for 1-5 stars
+fn popm_rating(stars: u8) -> u8 {
+    match stars {
+        0 | 1 => 1,
+        2 => 64,
+        3 => 128,
+        4 => 196,
+        _ => 255,
+    }
+}
+
+fn stars(rating: u8) -> Option<u8> {
+    match rating {
+        0 => None,
+        1..=31 => Some(1),
+        32..=95 => Some(2),
+        96..=159 => Some(3),
+        160..=223 => Some(4),
+        _ => Some(5),
+    }
+}
+
+// Brings the player's numbers and the tag's together. Whichever side changed
+// since the last sync wins; when both did, plays made on either side are
+// added up and the rating is taken from the side `conflict` prefers.
+// Returns what the tag should be rewritten to, if anything.
+pub fn reconcile(stats: &mut SongStats, tag: TagStats, conflict: TagConflict) -> Option<TagStats> {
+    let synced = stats.synced.unwrap_or_default();
+    let ours = stats.values();
+    let merged = match (ours != synced, tag != synced) {
+        (_, false) => ours,
+        (false, true) => tag,
+        (true, true) => TagStats {
+            rating: match conflict {
+                TagConflict::Player => ours.rating,
+                TagConflict::Tags => tag.rating,
+            },
+            plays: (ours.plays + tag.plays).saturating_sub(synced.plays),
+        },
+    };
+    stats.rating = merged.rating;
+    stats.plays = merged.plays;
+    if merged == tag {
+        stats.synced = Some(tag);
+        None
+    } else {
+        Some(merged)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use id3::Version;
+
+    use super::*;
+
+    fn values(rating: Option<u8>, plays: u64) -> TagStats {
+        TagStats { rating, plays }
+    }
+
+    #[test]
+    fn whichever_side_changed_wins() {
+        let mut stats = SongStats { rating: Some(4), plays: 12, synced: Some(values(Some(4), 10)), last_played: None };
+        assert_eq!(reconcile(&mut stats, values(Some(4), 10), TagConflict::Player), Some(values(Some(4), 12)));
+
+        let mut stats = SongStats { rating: Some(4), plays: 10, synced: Some(values(Some(4), 10)), last_played: None };
+        assert_eq!(reconcile(&mut stats, values(Some(2), 11), TagConflict::Player), None);
+        assert_eq!(stats, SongStats { rating: Some(2), plays: 11, synced: Some(values(Some(2), 11)), last_played: None });
+    }
+
+    #[test]
+    fn conflicts_add_up_plays_and_pick_a_rating() {
+        let synced = Some(values(Some(3), 10));
+        let mut stats = SongStats { rating: Some(5), plays: 12, synced, last_played: None };
+        assert_eq!(reconcile(&mut stats, values(Some(1), 13), TagConflict::Player), Some(values(Some(5), 15)));
+        let mut stats = SongStats { rating: Some(5), plays: 12, synced, last_played: None };
+        assert_eq!(reconcile(&mut stats, values(Some(1), 13), TagConflict::Tags), Some(values(Some(1), 15)));
+        // Written back later; until then the old sync point stays
+        assert_eq!(stats.synced, synced);
+    }
+
+    #[test]
+    fn moved_files_keep_their_numbers() {
+        let mut stats = Stats::default();
+        let (old, new, other) = (PathBuf::from("/music/old.mp3"), PathBuf::from("/music/new.mp3"), PathBuf::from("/music/other.mp3"));
+        stats.songs.insert(old.clone(), SongStats { rating: Some(5), plays: 3, synced: None, last_played: None });
+        stats.hashes.insert(old.clone(), 42);
+        stats.history.push(Play { path: old.clone(), at: 100 });
+        let hash = |path: &Path| Some(if path.ends_with("new.mp3") { 42 } else { 7 });
+
+        // Nothing moves while the old file is still there
+        assert_eq!(stats.relink(&[other.clone(), new.clone()], |_| true, hash), 0);
+        assert_eq!(stats.relink(&[other.clone(), new.clone()], |path| path != old, hash), 1);
+        assert_eq!(stats.get(&new).rating, Some(5));
+        assert_eq!(stats.hashes.get(&new), Some(&42));
+        assert_eq!(stats.history, vec![Play { path: new.clone(), at: 100 }]);
+        assert!(!stats.songs.contains_key(&old));
+        assert!(!stats.songs.contains_key(&other));
+    }
+
+    #[test]
+    fn files_that_cannot_be_told_apart_keep_their_numbers_where_they_were() {
+        let mut stats = Stats::default();
+        let (first, second) = (PathBuf::from("/music/old/1.mp3"), PathBuf::from("/music/old/2.mp3"));
+        for (path, plays) in [(&first, 3), (&second, 8)] {
+            stats.songs.insert(path.clone(), SongStats { plays, ..SongStats::default() });
+            stats.hashes.insert(path.clone(), 42);
+        }
+        let moved = [PathBuf::from("/music/new/1.mp3"), PathBuf::from("/music/new/2.mp3")];
+        // Both gone, ending alike: whichever new file came first would get either's numbers
+        assert_eq!(stats.relink(&moved, |_| false, |_| Some(42)), 0);
+        assert_eq!(stats.get(&first).plays, 3);
+
+        // One gone, but two new files that end like it
+        stats.hashes.insert(second.clone(), 7);
+        assert_eq!(stats.relink(&moved, |path| path == second, |_| Some(42)), 0);
+        assert_eq!(stats.relink(&moved[..1], |path| path == second, |_| Some(42)), 1);
+        assert_eq!(stats.get(&moved[0]).plays, 3);
+    }
+
+    #[test]
+    fn recent_and_most_played() {
+        let songs: Vec<Song> = ["a", "b", "c", "d"].iter().map(|name| Song::new(PathBuf::from(format!("/music/{}.mp3", name)))).collect();
+        let mut stats = Stats::default();
+        let mut played = |index: usize, plays: u64, at: u64| {
+            stats.songs.insert(songs[index].path.clone(), SongStats { plays, last_played: Some(at), ..SongStats::default() });
+        };
+        played(0, 5, 100);
+        played(1, 2, 300);
+        played(3, 5, 200);
+        // Rated but never played
+        stats.songs.insert(songs[2].path.clone(), SongStats { rating: Some(4), ..SongStats::default() });
+
+        assert_eq!(stats.recently_played(&songs, 10), vec![1, 3, 0]);
+        assert_eq!(stats.most_played(&songs, 10), vec![3, 0, 1]);
+        assert_eq!(stats.most_played(&songs, 1), vec![3]);
+    }
+
+    #[test]
+    fn popm_round_trip_keeps_other_players_frames() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("song.mp3");
+        fs::write(&path, b"").unwrap();
+        let mut tag = Tag::new();
+        tag.add_frame(Popularimeter { user: String::from("other@example.com"), rating: 255, counter: 99 });
+        tag.write_to_path(&path, Version::Id3v24).unwrap();
+        assert_eq!(read_popm(&Tag::read_from_path(&path).unwrap()), Some(values(Some(5), 99)));
+
+        write_popm(&path, values(Some(3), 7)).unwrap();
+        let tag = Tag::read_from_path(&path).unwrap();
+        assert_eq!(read_popm(&tag), Some(values(Some(3), 7)));
+        assert_eq!(tag.frames().filter(|frame| frame.id() == "POPM").count(), 2);
+    }
+}
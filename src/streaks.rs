@@ -0,0 +1,134 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    path::Path,
+};
+
+use crate::stats::Play;
+
+const DAY_SECS: i64 = 24 * 3600;
+const BAR_CELLS: usize = 10;
+
+// Days since the epoch in a zone `offset` seconds from UTC
+pub fn day(at: u64, offset: i64) -> i64 {
+    (at as i64 + offset).div_euclid(DAY_SECS)
+}
+
+// Weeks start on Monday; the epoch was a Thursday
+pub fn week(day: i64) -> i64 {
+    (day + 3).div_euclid(7)
+}
+
+// Days in a row with something played, up to today. Today isn't over yet,
+// so a streak that reached yesterday still counts.
+pub fn current_streak(days: &BTreeSet<i64>, today: i64) -> usize {
+    let last = if days.contains(&today) { today } else { today - 1 };
+    (0..).take_while(|&back| days.contains(&(last - back))).count()
+}
+
+pub fn longest_streak(days: &BTreeSet<i64>) -> usize {
+    let mut longest = 0;
+    let mut run = 0;
+    let mut previous = None;
+    for &day in days {
+        run = if previous == Some(day - 1) { run + 1 } else { 1 };
+        longest = longest.max(run);
+        previous = Some(day);
+    }
+    longest
+}
+
+// Where the listening history stands as of `now`
+#[derive(Debug, PartialEq)]
+pub struct Progress {
+    pub streak: usize,
+    pub longest: usize,
+    pub plays_today: usize,
+    // Albums first heard this week
+    pub new_albums: usize,
+}
+
+// `album` names the album a file is on, for those still in the library
+pub fn progress(history: &[Play], album: impl Fn(&Path) -> Option<(String, String)>, now: u64, offset: i64) -> Progress {
+    let today = day(now, offset);
+    let days: BTreeSet<i64> = history.iter().map(|play| day(play.at, offset)).collect();
+    let mut first_heard: HashMap<(String, String), i64> = HashMap::new();
+    for play in history {
+        if let Some(key) = album(&play.path) {
+            let first = first_heard.entry(key).or_insert(i64::MAX);
+            *first = (*first).min(day(play.at, offset));
+        }
+    }
+    Progress {
+        streak: current_streak(&days, today),
+        longest: longest_streak(&days),
+        plays_today: history.iter().filter(|play| day(play.at, offset) == today).count(),
+        new_albums: first_heard.values().filter(|&&first| week(first) == week(today)).count(),
+    }
+}
+
+// "███████░░░ 7/10", with a tick once the goal is met
+pub fn bar(done: usize, goal: usize) -> String {
+    let filled = (done * BAR_CELLS).checked_div(goal).unwrap_or(BAR_CELLS).min(BAR_CELLS);
+    let mut bar = format!("{}{} {}/{}", "█".repeat(filled), "░".repeat(BAR_CELLS - filled), done, goal);
+    if done >= goal {
+        bar.push_str(" ✓");
+    }
+    bar
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    fn play(path: &str, day: i64) -> Play {
+        Play { path: PathBuf::from(path), at: (day * DAY_SECS + 3600) as u64 }
+    }
+
+    #[test]
+    fn streaks_survive_until_the_day_is_over() {
+        let days: BTreeSet<i64> = [1, 2, 3, 7, 8].into_iter().collect();
+        assert_eq!(current_streak(&days, 8), 2);
+        assert_eq!(current_streak(&days, 9), 2);
+        assert_eq!(current_streak(&days, 10), 0);
+        assert_eq!(longest_streak(&days), 3);
+        assert_eq!(longest_streak(&BTreeSet::new()), 0);
+    }
+
+    #[test]
+    fn days_follow_the_local_zone() {
+        // 23:30 UTC is the next day an hour east
+        assert_eq!(day(DAY_SECS as u64 - 1800, 0), 0);
+        assert_eq!(day(DAY_SECS as u64 - 1800, 3600), 1);
+        // 1970-01-05 was a Monday
+        assert_eq!(week(3), 0);
+        assert_eq!(week(4), 1);
+    }
+
+    #[test]
+    fn counts_plays_today_and_albums_new_this_week() {
+        // Day 11 is a Monday, day 13 the Wednesday after
+        let history = [
+            play("/music/old.mp3", 5),
+            play("/music/old.mp3", 12),
+            play("/music/new.mp3", 12),
+            play("/music/new.mp3", 13),
+            play("/music/gone.mp3", 13),
+        ];
+        let album = |path: &Path| match path.to_str()? {
+            "/music/old.mp3" => Some((String::from("Old"), String::from("Band"))),
+            "/music/new.mp3" => Some((String::from("New"), String::from("Band"))),
+            _ => None,
+        };
+        let now = (13 * DAY_SECS + 7200) as u64;
+        assert_eq!(progress(&history, album, now, 0), Progress { streak: 2, longest: 2, plays_today: 2, new_albums: 1 });
+    }
+
+    #[test]
+    fn bars_fill_up_to_the_goal() {
+        assert_eq!(bar(7, 10), "███████░░░ 7/10");
+        assert_eq!(bar(3, 2), "██████████ 3/2 ✓");
+        assert_eq!(bar(0, 0), "██████████ 0/0 ✓");
+    }
+}
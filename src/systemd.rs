@@ -0,0 +1,154 @@
+use std::{env, net::TcpListener, ops::Range, path::Path};
+
+// What `--generate-systemd-unit` prints. The player has no headless mode, so
+// `script` gives it a terminal nobody looks at; it's then driven through the
+// HTTP API, which the socket unit starts it for when `bind` is given.
+pub fn units(exe: &Path, bind: Option<&str>) -> String {
+    let mut units = format!(
+        "# ~/.config/systemd/user/music-player.service\n\
+         [Unit]\n\
+         Description=Music player\n\
+         After=sound.target\n\
+         \n\
+         [Service]\n\
+         Type=notify\n\
+         # Readiness comes from the player, a child of script\n\
+         NotifyAccess=all\n\
+         ExecStart=/usr/bin/script --quiet --flush --command {} /dev/null\n\
+         # $MAINPID is script; the player is its child\n\
+         ExecReload=/usr/bin/pkill -HUP --parent $MAINPID\n\
+         Restart=on-failure\n\
+         \n\
+         [Install]\n\
+         WantedBy=default.target\n",
+        command(exe)
+    );
+    if let Some(bind) = bind {
+        units.push_str(&format!(
+            "\n# ~/.config/systemd/user/music-player.socket\n\
+             [Unit]\n\
+             Description=Music player HTTP API\n\
+             \n\
+             [Socket]\n\
+             ListenStream={}\n\
+             \n\
+             [Install]\n\
+             WantedBy=sockets.target\n",
+            bind
+        ));
+    }
+    units
+}
+
+// `exe` as one argument to script's --command: quoted for the shell that
+// script runs it with, then for systemd, which also expands $ and %
+fn command(exe: &Path) -> String {
+    let shell = format!("'{}'", exe.display().to_string().replace('\'', "'\\''"));
+    let mut quoted = String::from("\"");
+    for c in shell.chars() {
+        match c {
+            '\\' | '"' => quoted.push('\\'),
+            '$' | '%' => quoted.push(c),
+            _ => {}
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
+
+// Whether systemd started the player and waits to hear from it, as the
+// generated unit has it
+pub fn supervised() -> bool {
+    env::var_os("NOTIFY_SOCKET").is_some()
+}
+
+// Tells systemd how things are going under Type=notify: "READY=1" once the
+// player is up, "STOPPING=1" as it quits. Does nothing outside systemd.
+#[cfg(unix)]
+pub fn notify(state: &str) {
+    if let Some(socket) = env::var_os("NOTIFY_SOCKET") {
+        let _ = send(&socket, state);
+    }
+}
+
+#[cfg(not(unix))]
+pub fn notify(_state: &str) {}
+
+#[cfg(unix)]
+fn send(socket: &std::ffi::OsStr, state: &str) -> std::io::Result<()> {
+    use std::os::unix::{ffi::OsStrExt, net::{SocketAddr, UnixDatagram}};
+
+    // A leading @ names a socket in Linux's abstract namespace
+    let address = match socket.as_bytes().strip_prefix(b"@") {
+        #[cfg(target_os = "linux")]
+        Some(name) => <SocketAddr as std::os::linux::net::SocketAddrExt>::from_abstract_name(name)?,
+        _ => SocketAddr::from_pathname(socket)?,
+    };
+    UnixDatagram::unbound()?.send_to_addr(state.as_bytes(), &address)?;
+    Ok(())
+}
+
+// Sockets a .socket unit opened for the player, in the order of its
+// ListenStream= lines. Only the first call gets them. Call it before starting
+// any thread: it clears them from the environment, and glibc's setenv races
+// with getenv elsewhere.
+#[cfg(unix)]
+pub fn listeners() -> Vec<TcpListener> {
+    use std::os::fd::FromRawFd;
+
+    let fds = passed_fds(env::var("LISTEN_PID").ok().as_deref(), env::var("LISTEN_FDS").ok().as_deref(), std::process::id());
+    // Meant for this process, not the ones it starts
+    env::remove_var("LISTEN_PID");
+    env::remove_var("LISTEN_FDS");
+    env::remove_var("LISTEN_FDNAMES");
+    fds.filter_map(|fd| {
+        let passed = unsafe { TcpListener::from_raw_fd(fd) };
+        // systemd hands them over inheritable; a clone is close-on-exec, so
+        // curl and the file manager don't hold the port open
+        passed.try_clone().ok()
+    })
+    .collect()
+}
+
+#[cfg(not(unix))]
+pub fn listeners() -> Vec<TcpListener> {
+    Vec::new()
+}
+
+// Passed sockets start at descriptor 3, if they were passed to this process
+fn passed_fds(pid: Option<&str>, count: Option<&str>, own_pid: u32) -> Range<i32> {
+    const FIRST: i32 = 3;
+    let count = match (pid.and_then(|pid| pid.parse::<u32>().ok()), count.and_then(|count| count.parse::<i32>().ok())) {
+        (Some(pid), Some(count)) if pid == own_pid => count.max(0),
+        _ => 0,
+    };
+    FIRST..FIRST + count
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_sockets_passed_to_this_process_count() {
+        assert_eq!(passed_fds(Some("42"), Some("2"), 42), 3..5);
+        assert!(passed_fds(Some("41"), Some("2"), 42).is_empty());
+        assert!(passed_fds(None, None, 42).is_empty());
+    }
+
+    #[test]
+    fn player_path_is_quoted_for_the_shell_and_systemd() {
+        assert_eq!(command(Path::new("/opt/Music Player/it's $5")), r#""'/opt/Music Player/it'\\''s $$5'""#);
+    }
+
+    #[test]
+    fn socket_unit_comes_with_a_bind_address() {
+        let units = units(Path::new("/usr/bin/music-player"), Some("0.0.0.0:8080"));
+        assert!(units.contains("Type=notify\n"));
+        assert!(units.contains("ExecStart=/usr/bin/script --quiet --flush --command \"'/usr/bin/music-player'\" /dev/null\n"));
+        assert!(units.contains("ExecReload=/usr/bin/pkill -HUP --parent $MAINPID\n"));
+        assert!(units.contains("ListenStream=0.0.0.0:8080\n"));
+        assert!(!super::units(Path::new("/usr/bin/music-player"), None).contains("[Socket]"));
+    }
+}
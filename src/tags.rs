@@ -0,0 +1,176 @@
+use std::path::Path;
+
+use anyhow::Result;
+use id3::{Tag, TagLike, Version};
+
+use crate::library::{Song, UNKNOWN_ALBUM};
+use crate::stats;
+
+// What the tag editor changes
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TagField {
+    Title,
+    Artist,
+    Album,
+    AlbumArtist,
+    Genre,
+    Year,
+    Track,
+}
+
+impl TagField {
+    pub const ALL: [TagField; 7] = [
+        TagField::Title,
+        TagField::Artist,
+        TagField::Album,
+        TagField::AlbumArtist,
+        TagField::Genre,
+        TagField::Year,
+        TagField::Track,
+    ];
+
+    // Editing many songs at once leaves out what each one has to itself
+    pub const SHARED: [TagField; 5] = [TagField::Artist, TagField::Album, TagField::AlbumArtist, TagField::Genre, TagField::Year];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            TagField::Title => "Title",
+            TagField::Artist => "Artist",
+            TagField::Album => "Album",
+            TagField::AlbumArtist => "Album artist",
+            TagField::Genre => "Genre",
+            TagField::Year => "Year",
+            TagField::Track => "Track",
+        }
+    }
+
+    // As the editor shows it; empty where the library made up a name
+    pub fn value(self, song: &Song) -> String {
+        match self {
+            TagField::Title => song.title.clone(),
+            TagField::Artist => song.artist.clone(),
+            TagField::Album if song.album == UNKNOWN_ALBUM => String::new(),
+            TagField::Album => song.album.clone(),
+            TagField::AlbumArtist => song.album_artist.clone().unwrap_or_default(),
+            TagField::Genre if song.genre == "Unknown Genre" => String::new(),
+            TagField::Genre => song.genre.clone(),
+            TagField::Year => song.year.map(|year| year.to_string()).unwrap_or_default(),
+            TagField::Track => song.track.map(|track| track.to_string()).unwrap_or_default(),
+        }
+    }
+}
+
+// Whether `edits` can be written, so nothing is written if one can't
+pub fn check(edits: &[(TagField, String)]) -> Result<()> {
+    for (field, value) in edits {
+        let value = value.trim();
+        let number = match field {
+            TagField::Year => value.parse::<i32>().is_ok(),
+            TagField::Track => value.parse::<u32>().is_ok(),
+            _ => true,
+        };
+        if !value.is_empty() && !number {
+            return Err(anyhow::anyhow!("{} has to be a number", field.label()));
+        }
+    }
+    Ok(())
+}
+
+// Sets `edits` in the file's ID3 tag, an empty value taking the tag away,
+// and keeps the rest of it. Only MP3 files have tags that can be written.
+pub fn write(path: &Path, edits: &[(TagField, String)]) -> Result<()> {
+    if !stats::has_id3(path) {
+        return Err(anyhow::anyhow!("only MP3 tags can be written"));
+    }
+    check(edits)?;
+    let mut tag = load_or_new(path)?;
+    for (field, value) in edits {
+        let value = value.trim();
+        match (field, value.is_empty()) {
+            (TagField::Title, false) => tag.set_title(value),
+            (TagField::Title, true) => tag.remove_title(),
+            (TagField::Artist, false) => tag.set_artist(value),
+            (TagField::Artist, true) => tag.remove_artist(),
+            (TagField::Album, false) => tag.set_album(value),
+            (TagField::Album, true) => tag.remove_album(),
+            (TagField::AlbumArtist, false) => tag.set_album_artist(value),
+            (TagField::AlbumArtist, true) => tag.remove_album_artist(),
+            (TagField::Genre, false) => tag.set_genre(value),
+            (TagField::Genre, true) => tag.remove_genre(),
+            // The recording date would be read before the year
+            (TagField::Year, empty) => {
+                tag.remove_date_recorded();
+                tag.remove_year();
+                if !empty {
+                    tag.set_year(value.parse()?);
+                }
+            }
+            (TagField::Track, false) => tag.set_track(value.parse()?),
+            (TagField::Track, true) => tag.remove_track(),
+        }
+    }
+    save(&tag, path)
+}
+
+// The file's ID3 tag, or a new one if it has none. A tag that can't be read
+// is an error, so it's left alone rather than replaced.
+pub fn load_or_new(path: &Path) -> Result<Tag> {
+    match Tag::read_from_path(path) {
+        Ok(tag) => Ok(tag),
+        Err(e) if matches!(e.kind, id3::ErrorKind::NoTag) => Ok(Tag::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+// Writes `tag` back in the version it was read as, keeping v2.3 tags v2.3
+// for the players that can't read anything newer. v2.2 can't be written, so
+// it becomes v2.3.
+pub fn save(tag: &Tag, path: &Path) -> Result<()> {
+    let version = match tag.version() {
+        Version::Id3v22 => Version::Id3v23,
+        version => version,
+    };
+    tag.write_to_path(path, version)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn edits_keep_the_rest_of_the_tag() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("Can - Vitamin C.mp3");
+        fs::write(&path, b"").unwrap();
+        let mut tag = Tag::new();
+        tag.set_title("Vitamin C");
+        tag.set_album("Ege Bamyasi");
+        tag.set_genre("Krautrock");
+        tag.write_to_path(&path, Version::Id3v23).unwrap();
+
+        let edits = [
+            (TagField::Album, String::from(" Soundtracks ")),
+            (TagField::Genre, String::new()),
+            (TagField::Year, String::from("1972")),
+        ];
+        write(&path, &edits).unwrap();
+        let song = Song::new(path.clone());
+        assert_eq!((song.title.as_str(), song.album.as_str(), song.year), ("Vitamin C", "Soundtracks", Some(1972)));
+        assert_eq!(TagField::Genre.value(&song), "");
+        assert_eq!(Tag::read_from_path(&path).unwrap().version(), Version::Id3v23);
+    }
+
+    #[test]
+    fn numbers_are_checked_before_anything_is_written() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("song.mp3");
+        fs::write(&path, b"").unwrap();
+        let edits = [(TagField::Album, String::from("Ege Bamyasi")), (TagField::Track, String::from("three"))];
+        assert!(write(&path, &edits).is_err());
+        assert!(Tag::read_from_path(&path).is_err());
+        assert!(write(&dir.path().join("song.flac"), &edits[..1]).is_err());
+    }
+}
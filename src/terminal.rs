@@ -0,0 +1,102 @@
+use std::{
+    env,
+    io::{self, Write},
+    process::Command,
+    sync::OnceLock,
+};
+
+// tmux and GNU screen swallow escape sequences they don't understand, so
+// anything meant for the outer terminal has to be wrapped in a DCS passthrough
+#[derive(Clone, Copy, PartialEq)]
+pub enum Multiplexer {
+    None,
+    // tmux 3.3+ drops passthrough unless `allow-passthrough` is enabled
+    Tmux { passthrough: bool },
+    Screen,
+}
+
+impl Multiplexer {
+    fn detect() -> Self {
+        if env::var_os("TMUX").is_some() {
+            let passthrough = Command::new("tmux")
+                .args(["show-options", "-gv", "allow-passthrough"])
+                .output()
+                .map(|output| matches!(String::from_utf8_lossy(&output.stdout).trim(), "on" | "all"))
+                .unwrap_or(false);
+            Multiplexer::Tmux { passthrough }
+        } else if env::var("TERM").is_ok_and(|term| term.starts_with("screen")) || env::var_os("STY").is_some() {
+            Multiplexer::Screen
+        } else {
+            Multiplexer::None
+        }
+    }
+
+    // Wraps `sequence` so it reaches the outer terminal, or None if it can't get there
+    pub fn wrap(self, sequence: &str) -> Option<String> {
+        match self {
+            Multiplexer::None => Some(sequence.to_string()),
+            // Every ESC inside a tmux passthrough has to be doubled
+            Multiplexer::Tmux { passthrough: true } => {
+                Some(format!("\x1bPtmux;{}\x1b\\", sequence.replace('\x1b', "\x1b\x1b")))
+            }
+            Multiplexer::Tmux { passthrough: false } => None,
+            Multiplexer::Screen => Some(format!("\x1bP{}\x1b\\", sequence)),
+        }
+    }
+}
+
+pub fn multiplexer() -> Multiplexer {
+    static DETECTED: OnceLock<Multiplexer> = OnceLock::new();
+    *DETECTED.get_or_init(Multiplexer::detect)
+}
+
+fn write_sequence(sequence: &str) {
+    let mut stdout = io::stdout();
+    let _ = stdout.write_all(sequence.as_bytes());
+    let _ = stdout.flush();
+}
+
+// Sets the terminal window title. Without passthrough tmux still understands
+// the plain sequence as the pane title, which `set-titles on` shows outside.
+pub fn set_title(title: &str) {
+    // Control characters would end the sequence early
+    let title: String = title.chars().filter(|c| !c.is_control()).collect();
+    let sequence = format!("\x1b]2;{}\x07", title);
+    write_sequence(&multiplexer().wrap(&sequence).unwrap_or(sequence));
+}
+
+// Saves and restores the title on xterm-compatible terminals, so quitting
+// doesn't leave the last track in the window title
+pub fn push_title() {
+    if let Some(sequence) = multiplexer().wrap("\x1b[22;0t") {
+        write_sequence(&sequence);
+    }
+}
+
+pub fn pop_title() {
+    if let Some(sequence) = multiplexer().wrap("\x1b[23;0t") {
+        write_sequence(&sequence);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tmux_passthrough_doubles_escapes() {
+        let wrapped = Multiplexer::Tmux { passthrough: true }.wrap("\x1b]2;hi\x07");
+        assert_eq!(wrapped.as_deref(), Some("\x1bPtmux;\x1b\x1b]2;hi\x07\x1b\\"));
+    }
+
+    #[test]
+    fn tmux_without_passthrough_cannot_reach_outer_terminal() {
+        assert_eq!(Multiplexer::Tmux { passthrough: false }.wrap("\x1b[22;0t"), None);
+    }
+
+    #[test]
+    fn screen_and_plain_terminals() {
+        assert_eq!(Multiplexer::Screen.wrap("\x1b[23;0t").as_deref(), Some("\x1bP\x1b[23;0t\x1b\\"));
+        assert_eq!(Multiplexer::None.wrap("x").as_deref(), Some("x"));
+    }
+}
@@ -0,0 +1,167 @@
+use std::{collections::HashSet, hash::Hash};
+
+// What a tree row holds: a branch, with how many songs are somewhere under
+// it, or a song
+#[derive(Clone, Debug, PartialEq)]
+pub enum Node<K> {
+    Branch(K, usize),
+    Song(usize),
+}
+
+// One visible line of a tree
+#[derive(Clone, Debug, PartialEq)]
+pub struct TreeRow<K> {
+    pub node: Node<K>,
+    pub depth: usize,
+    pub expanded: bool,
+}
+
+// Which branches of a tree are open. The tree itself isn't kept: its rows are
+// worked out from the roots when needed, and only the children of open
+// branches are ever asked for.
+#[derive(Clone, Debug)]
+pub struct Tree<K> {
+    expanded: HashSet<K>,
+}
+
+impl<K> Default for Tree<K> {
+    fn default() -> Self {
+        Tree { expanded: HashSet::new() }
+    }
+}
+
+impl<K: Clone + Eq + Hash> Tree<K> {
+    pub fn rows(&self, roots: Vec<Node<K>>, children: impl Fn(&K) -> Vec<Node<K>>) -> Vec<TreeRow<K>> {
+        let mut rows = Vec::new();
+        self.push_rows(&mut rows, roots, 0, &children);
+        rows
+    }
+
+    fn push_rows(&self, rows: &mut Vec<TreeRow<K>>, nodes: Vec<Node<K>>, depth: usize, children: &impl Fn(&K) -> Vec<Node<K>>) {
+        for node in nodes {
+            let open = match &node {
+                Node::Branch(key, _) if self.expanded.contains(key) => Some(key.clone()),
+                _ => None,
+            };
+            rows.push(TreeRow { node, depth, expanded: open.is_some() });
+            if let Some(key) = open {
+                self.push_rows(rows, children(&key), depth + 1, children);
+            }
+        }
+    }
+
+    pub fn expand(&mut self, key: K) {
+        self.expanded.insert(key);
+    }
+
+    pub fn toggle(&mut self, key: K) {
+        if !self.expanded.remove(&key) {
+            self.expanded.insert(key);
+        }
+    }
+
+    // `l`: opens the branch on row `at`, or steps onto its first child if it's
+    // already open. Returns where the cursor goes.
+    pub fn open(&mut self, rows: &[TreeRow<K>], at: usize) -> usize {
+        match rows.get(at) {
+            Some(TreeRow { node: Node::Branch(key, _), expanded: false, .. }) => {
+                self.expanded.insert(key.clone());
+                at
+            }
+            Some(row) if row.expanded && rows.get(at + 1).is_some_and(|next| next.depth > row.depth) => at + 1,
+            _ => at,
+        }
+    }
+
+    // `h`: closes the branch on row `at` if it's open, otherwise steps out
+    // onto the branch it's in
+    pub fn close(&mut self, rows: &[TreeRow<K>], at: usize) -> usize {
+        match rows.get(at) {
+            Some(TreeRow { node: Node::Branch(key, _), expanded: true, .. }) => {
+                self.expanded.remove(key);
+                at
+            }
+            Some(row) => rows[..at].iter().rposition(|parent| parent.depth < row.depth).unwrap_or(at),
+            None => at,
+        }
+    }
+}
+
+// The song on row `at`, if it isn't a branch
+pub fn song_at<K>(rows: &[TreeRow<K>], at: usize) -> Option<usize> {
+    match rows.get(at)?.node {
+        Node::Song(index) => Some(index),
+        Node::Branch(..) => None,
+    }
+}
+
+// The songs on the rows shown, in order
+pub fn songs<K>(rows: &[TreeRow<K>]) -> Vec<usize> {
+    rows.iter()
+        .filter_map(|row| match row.node {
+            Node::Song(index) => Some(index),
+            Node::Branch(..) => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use super::*;
+
+    // Two artists with an album each, keyed by their path in the tree
+    fn children(key: &&'static str) -> Vec<Node<&'static str>> {
+        match *key {
+            "Radiohead" => vec![Node::Branch("Radiohead/OK Computer", 2)],
+            "Radiohead/OK Computer" => vec![Node::Song(0), Node::Song(1)],
+            "Daft Punk" => vec![Node::Branch("Daft Punk/Discovery", 1)],
+            "Daft Punk/Discovery" => vec![Node::Song(2)],
+            _ => Vec::new(),
+        }
+    }
+
+    fn roots() -> Vec<Node<&'static str>> {
+        vec![Node::Branch("Daft Punk", 1), Node::Branch("Radiohead", 2)]
+    }
+
+    #[test]
+    fn only_open_branches_are_asked_for_children() {
+        let asked = Cell::new(0);
+        let mut tree = Tree::default();
+        let count = |key: &&'static str| {
+            asked.set(asked.get() + 1);
+            children(key)
+        };
+        assert_eq!(tree.rows(roots(), count).len(), 2);
+        assert_eq!(asked.get(), 0);
+
+        tree.expand("Radiohead");
+        tree.expand("Radiohead/OK Computer");
+        let rows = tree.rows(roots(), count);
+        assert_eq!(asked.get(), 2);
+        assert_eq!(rows.iter().map(|row| row.depth).collect::<Vec<_>>(), vec![0, 0, 1, 2, 2]);
+        assert_eq!(songs(&rows), vec![0, 1]);
+    }
+
+    #[test]
+    fn h_and_l_open_close_and_step() {
+        let mut tree = Tree::default();
+        let rows = tree.rows(roots(), children);
+        assert_eq!(tree.open(&rows, 1), 1);
+
+        let rows = tree.rows(roots(), children);
+        assert!(rows[1].expanded);
+        assert_eq!(tree.open(&rows, 1), 2);
+        // A closed child steps out to its parent, an open branch closes
+        assert_eq!(tree.close(&rows, 2), 1);
+        assert_eq!(tree.close(&rows, 1), 1);
+        assert_eq!(tree.rows(roots(), children).len(), 2);
+
+        tree.toggle("Daft Punk");
+        assert_eq!(tree.rows(roots(), children).len(), 3);
+        tree.toggle("Daft Punk");
+        assert_eq!(tree.rows(roots(), children).len(), 2);
+    }
+}
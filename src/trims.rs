@@ -0,0 +1,111 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::persist;
+
+// Where a track should really start and stop, in milliseconds, to skip a
+// long silence, spoken intro or applause
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Debug)]
+#[serde(default)]
+pub struct Trim {
+    pub start: Option<u64>,
+    pub end: Option<u64>,
+}
+
+impl Trim {
+    pub fn start(&self) -> Option<Duration> {
+        self.start.map(Duration::from_millis)
+    }
+
+    pub fn end(&self) -> Option<Duration> {
+        self.end.map(Duration::from_millis)
+    }
+}
+
+// Trim points by file path
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct Trims {
+    pub songs: HashMap<PathBuf, Trim>,
+}
+
+impl Trims {
+    // ~/.local/share/music-player/trims.json on Linux, %APPDATA%\music-player\trims.json on Windows
+    pub fn path() -> Option<PathBuf> {
+        dirs::data_dir().map(|dir| dir.join("music-player").join("trims.json"))
+    }
+
+    // A missing or unreadable file means no trims yet
+    pub fn load() -> Self {
+        Self::path()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let path = Self::path().ok_or_else(|| anyhow::anyhow!("No data directory"))?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        persist::write_atomic(&path, &serde_json::to_string(self)?)
+    }
+
+    pub fn get(&self, path: &Path) -> Trim {
+        self.songs.get(path).copied().unwrap_or_default()
+    }
+
+    // The start has to come before the end
+    pub fn set_start(&mut self, path: &Path, at: Duration) -> Result<()> {
+        let at = at.as_millis() as u64;
+        if self.get(path).end.is_some_and(|end| at >= end) {
+            return Err(anyhow::anyhow!("The start has to come before the end"));
+        }
+        self.songs.entry(path.to_path_buf()).or_default().start = Some(at);
+        Ok(())
+    }
+
+    pub fn set_end(&mut self, path: &Path, at: Duration) -> Result<()> {
+        let at = at.as_millis() as u64;
+        if self.get(path).start.is_some_and(|start| at <= start) {
+            return Err(anyhow::anyhow!("The end has to come after the start"));
+        }
+        self.songs.entry(path.to_path_buf()).or_default().end = Some(at);
+        Ok(())
+    }
+
+    // Returns whether there was anything to clear
+    pub fn clear(&mut self, path: &Path) -> bool {
+        self.songs.remove(path).is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn start_stays_before_end() {
+        let mut trims = Trims::default();
+        let song = Path::new("/music/live.flac");
+        trims.set_start(song, Duration::from_secs(7)).unwrap();
+        assert!(trims.set_end(song, Duration::from_secs(5)).is_err());
+        trims.set_end(song, Duration::from_secs(238)).unwrap();
+        assert!(trims.set_start(song, Duration::from_secs(240)).is_err());
+
+        let json = serde_json::to_string(&trims).unwrap();
+        let mut trims: Trims = serde_json::from_str(&json).unwrap();
+        assert_eq!(trims.get(song).start(), Some(Duration::from_secs(7)));
+        assert_eq!(trims.get(song).end(), Some(Duration::from_secs(238)));
+
+        assert!(trims.clear(song));
+        assert_eq!(trims.get(song), Trim::default());
+    }
+}
@@ -0,0 +1,1916 @@
+use std::{path::PathBuf, time::{Duration, SystemTime}};
+
+use ratatui::{
+    layout::{Constraint, Direction, Layout, Rect},
+    style::{Color, Modifier, Style},
+    text::{Span, Line},
+    widgets::{block::{Position, Title}, Block, Borders, Cell, Clear, HighlightSpacing, List, ListItem, Paragraph, ListState, Row, Sparkline, Table, TableState, Tabs},
+    Frame,
+    prelude::Alignment,
+};
+
+use music_player::art::CoverBlocks;
+use music_player::bigtext;
+use music_player::clock;
+use music_player::columns::{self, Column, ColumnSpec};
+use music_player::compressor;
+use music_player::cues::PADS;
+use music_player::folders;
+use music_player::library::{format_duration, Song};
+use music_player::queue::QueueRow;
+use music_player::queues;
+use music_player::stats::{self, SongStats};
+use music_player::tree::{Node, TreeRow};
+use music_player::playback::RepeatMode;
+
+use crate::{App, HeaderCell, Pane, ViewMode, PALETTE, SPINNER};
+
+const HIGHLIGHT_SYMBOL: &str = ">> ";
+// Marks pinned artists and albums
+const PIN: char = '★';
+
+// Renders the whole screen from `App` alone, so it can run against a TestBackend
+pub fn draw(f: &mut Frame, app: &mut App) {
+    app.header_cells.clear();
+    if app.screensaver {
+        draw_screensaver(f, app, f.size());
+        return;
+    }
+    if app.karaoke {
+        draw_karaoke(f, app, f.size());
+        return;
+    }
+    if app.big_now {
+        draw_big_now(f, app, f.size());
+        return;
+    }
+    // Song list on the left, Now Playing and Queue in an optional sidebar
+    let sidebar_width = if app.layout.sidebar { app.layout.sidebar_width } else { 0 };
+    let main_chunks = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([
+            Constraint::Percentage(100 - sidebar_width),
+            Constraint::Percentage(sidebar_width),
+        ])
+        .split(f.size());
+
+    let left_chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Length(3),  // Title
+            Constraint::Length(3),  // View mode tabs
+            Constraint::Min(0),     // Main content
+            Constraint::Length(3),  // Controls
+        ])
+        .split(main_chunks[0]);
+
+    let right_chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            // Now Playing, with lines for the sample rate, channels and
+            // format while playing
+            Constraint::Length(10 + app.player.stream.map_or(0, |stream| 2 + stream.codec.is_some() as u16)),
+            Constraint::Min(0),     // Queue
+        ])
+        .split(main_chunks[1]);
+
+    draw_title(f, app, left_chunks[0]);
+    draw_tabs(f, app, left_chunks[1]);
+    if app.decks.is_some() {
+        draw_decks(f, app, left_chunks[2]);
+    } else if app.show_lyrics {
+        draw_lyrics(f, app, left_chunks[2]);
+    } else {
+        draw_content(f, app, left_chunks[2]);
+    }
+    if app.layout.sidebar {
+        draw_now_playing(f, app, right_chunks[0]);
+        draw_queue(f, app, right_chunks[1]);
+    }
+    draw_controls(f, app, left_chunks[3]);
+    if app.menu.is_some() {
+        draw_menu(f, app, left_chunks[2]);
+    }
+    if app.tag_editor.is_some() {
+        draw_tag_editor(f, app, left_chunks[2]);
+    }
+    if app.picker.is_some() {
+        draw_playlist_picker(f, app, left_chunks[2]);
+    }
+    if app.palette.is_some() {
+        draw_palette(f, app, f.size());
+    }
+    if app.report.is_some() {
+        draw_report(f, app, left_chunks[2]);
+    }
+    if app.relink.is_some() {
+        draw_relink(f, app, left_chunks[2]);
+    }
+}
+
+// Title, with a spinner while the library is still being scanned and the
+// progress of the current track on the right, so it shows even without the sidebar
+fn draw_title(f: &mut Frame, app: &App, area: Rect) {
+    let title_text = if app.player.is_scanning() {
+        // Held still in low-power mode
+        let frame = if app.low_power { 0 } else { (app.started.elapsed().as_millis() / 100) as usize % SPINNER.len() };
+        format!("Music Player  {} Scanning… {} songs found", SPINNER[frame], app.player.scan_found)
+    } else {
+        String::from("Music Player")
+    };
+    let block = Block::default().borders(Borders::ALL);
+    let inner = block.inner(area);
+    f.render_widget(block, area);
+
+    let mut progress = Vec::new();
+    if let (Some((position, length)), Some(time)) = (app.track_position(), app.time_readout()) {
+        if let Some(length) = length.filter(|length| !length.is_zero()) {
+            let width = (inner.width / 4).min(MAX_PROGRESS_WIDTH) as usize;
+            let fraction = position.as_secs_f64() / length.as_secs_f64();
+            progress.push(Span::styled(progress_bar(fraction, width), Style::default().fg(Color::Cyan).bg(Color::DarkGray)));
+            progress.push(Span::raw(" "));
+        }
+        progress.push(Span::raw(time));
+    }
+    let progress = Line::from(progress);
+    let chunks = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Min(0), Constraint::Length(progress.width() as u16)])
+        .split(inner);
+
+    let title = Paragraph::new(title_text).style(Style::default().fg(Color::Cyan));
+    f.render_widget(title, chunks[0]);
+    f.render_widget(Paragraph::new(progress), chunks[1]);
+}
+
+const MAX_PROGRESS_WIDTH: u16 = 30;
+
+// `width` cells filled to `fraction` in eighths of a cell
+fn progress_bar(fraction: f64, width: usize) -> String {
+    const PARTIAL: [char; 8] = [' ', '▏', '▎', '▍', '▌', '▋', '▊', '▉'];
+    let eighths = (fraction.clamp(0.0, 1.0) * width as f64 * 8.0).round() as usize;
+    let full = eighths / 8;
+    let mut bar = "█".repeat(full);
+    if full < width {
+        bar.push(PARTIAL[eighths % 8]);
+        bar.push_str(&" ".repeat(width - full - 1));
+    }
+    bar
+}
+
+fn draw_tabs(f: &mut Frame, app: &App, area: Rect) {
+    let mut view_modes = vec!["Songs", "Artists", "Albums", "Genres", "Queue", "Search", "Playlists", "Folders"];
+    view_modes.extend(app.player.browse.groups.iter().map(|group| group.grouping.name.as_str()));
+    let mut block = Block::default().borders(Borders::ALL);
+    // Which browse tab this is, once there is more than one
+    if app.sessions.len() > 1 {
+        block = block.title(format!("Tab {}/{}", app.session + 1, app.sessions.len()));
+    }
+    let tabs = Tabs::new(view_modes)
+        .select(app.player.view_mode.index())
+        .block(block)
+        .style(Style::default().fg(Color::White))
+        .highlight_style(Style::default().fg(Color::Cyan));
+    f.render_widget(tabs, area);
+}
+
+// Main list for the current view mode. Only the rows inside the visible
+// window are turned into list items, so huge libraries stay cheap.
+fn draw_content(f: &mut Frame, app: &mut App, area: Rect) {
+    let view = app.player.view_mode;
+    let open_playlist = app.selected_playlist.and_then(|index| app.player.playlists.get(index));
+    let is_table = match view {
+        ViewMode::Playlists => open_playlist.is_some(),
+        ViewMode::Albums | ViewMode::Genres => false,
+        _ => true,
+    };
+    // Tables lose a line to their header
+    let chrome = if is_table { 3 } else { 2 };
+    let visible_rows = area.height.saturating_sub(chrome).max(1) as usize;
+    if app.scroll_offset < app.list_offset {
+        app.list_offset = app.scroll_offset;
+    } else if app.scroll_offset >= app.list_offset + visible_rows {
+        app.list_offset = app.scroll_offset + 1 - visible_rows;
+    }
+
+    let block = Block::default().borders(Borders::ALL).border_style(pane_border(app, Pane::Library));
+    let window = |songs: &mut dyn Iterator<Item = usize>| {
+        songs.enumerate()
+            .skip(app.list_offset)
+            .take(visible_rows)
+            .map(|(n, index)| ListRow::Song { index, position: n + 1 })
+            .collect::<Vec<_>>()
+    };
+    let songs = match view {
+        // Songs under the sections count from 1 as in the plain list
+        ViewMode::AllSongs if app.has_sections() => {
+            let rows = app.song_rows();
+            let above = rows.len() - app.player.songs.len();
+            Some(rows.into_iter()
+                .enumerate()
+                .skip(app.list_offset)
+                .take(visible_rows)
+                .map(|(n, row)| match row.node {
+                    Node::Song(index) if n >= above => ListRow::Song { index, position: n + 1 - above },
+                    _ => tree_row(row, |section| section.label().to_string()),
+                })
+                .collect())
+        }
+        ViewMode::AllSongs => Some(window(&mut (0..app.player.songs.len()))),
+        ViewMode::Artists => Some(tree_window(app.artist_rows(), app.list_offset, visible_rows, |path| {
+            let pinned = match path.as_slice() {
+                [artist] => app.pins.artists.contains(artist),
+                [artist, album] => app.pins.albums.iter().any(|pin| pin.0 == *album && pin.1 == *artist),
+                _ => false,
+            };
+            let name = path.last().cloned().unwrap_or_default();
+            if pinned { format!("{} {}", PIN, name) } else { name }
+        })),
+        ViewMode::Queue => Some(window(&mut app.player.playback.queue.iter().copied())),
+        ViewMode::Search => Some(window(&mut app.player.search_results.iter().copied())),
+        // Entries that aren't in the library are still listed, by file name
+        ViewMode::Playlists => open_playlist.map(|playlist| {
+            playlist.entries.iter()
+                .enumerate()
+                .skip(app.list_offset)
+                .take(visible_rows)
+                .map(|(n, path)| match app.player.find_song(path) {
+                    Some(index) => ListRow::Song { index, position: n + 1 },
+                    None => ListRow::Missing { path: path.clone(), position: n + 1 },
+                })
+                .collect()
+        }),
+        ViewMode::Folders => Some(tree_window(app.folder_rows(), app.list_offset, visible_rows, |path| {
+            let name = path.file_name().map_or(path.display().to_string(), |name| name.to_string_lossy().to_string());
+            format!("{}/", name)
+        })),
+        ViewMode::Grouped(_) => Some(tree_window(app.group_rows(), app.list_offset, visible_rows, |path| {
+            path.last().cloned().unwrap_or_default()
+        })),
+        ViewMode::Albums | ViewMode::Genres => None,
+    };
+    if let Some(rows) = songs {
+        let shown_folder = folders::shown(&app.player.music_dirs, None);
+        let grouping = match view {
+            ViewMode::Grouped(group) => app.player.browse.groups.get(group).map(|index| index.grouping.name.clone()),
+            _ => None,
+        };
+        let block = match open_playlist.filter(|_| view == ViewMode::Playlists) {
+            Some(playlist) => block.title(playlist.name.clone()),
+            None if view == ViewMode::Search => block.title(format!("Search: {}", app.player.search_scope.label())),
+            None if view == ViewMode::Folders => block.title(shown_folder.map_or(String::from("Music directories"), |dir| dir.display().to_string())),
+            None => match grouping {
+                Some(name) => block.title(name),
+                None => block,
+            },
+        };
+        let selected = Some(app.scroll_offset - app.list_offset);
+        draw_song_table(f, app, area, block, SongRows { view, rows, selected });
+        return;
+    }
+
+    let content: Vec<ListItem> = match view {
+        ViewMode::Albums => app.album_list().into_iter()
+            .skip(app.list_offset)
+            .take(visible_rows)
+            .map(|key @ (album, artist)| {
+                let name = format!("{} (by {})", album, artist);
+                ListItem::new(if app.pins.albums.contains(key) { format!("{} {}", PIN, name) } else { name })
+            })
+            .collect(),
+        ViewMode::Genres => app.player.browse.genre_map.arranged(&app.player.browse.genres).into_iter()
+            .skip(app.list_offset)
+            .take(visible_rows)
+            .map(|(depth, genre)| ListItem::new(format!("{}{}", "  ".repeat(depth), genre)))
+            .collect(),
+        ViewMode::Playlists => app.player.playlists.iter()
+            .skip(app.list_offset)
+            .take(visible_rows)
+            .map(|playlist| ListItem::new(format!("{} ({} tracks)", playlist.name, playlist.entries.len())))
+            .collect(),
+        _ => Vec::new(),
+    };
+
+    // Clear the main content area before rendering the list
+    let clear_block = Block::default()
+        .borders(Borders::ALL)
+        .title("Songs");
+    f.render_widget(clear_block, area);
+
+    // Render the list with proper styling
+    let content_list = List::new(content)
+        .block(block)
+        .highlight_style(Style::default().add_modifier(Modifier::BOLD))
+        .highlight_symbol(HIGHLIGHT_SYMBOL);
+
+    let mut state = ListState::default();
+    state.select(Some(app.scroll_offset - app.list_offset));
+    f.render_stateful_widget(content_list, area, &mut state);
+}
+
+// The visible part of a tree, with branches named by `label`
+fn tree_window<K>(rows: Vec<TreeRow<K>>, offset: usize, visible: usize, label: impl Fn(&K) -> String) -> Vec<ListRow> {
+    rows.into_iter().skip(offset).take(visible).map(|row| tree_row(row, &label)).collect()
+}
+
+fn tree_row<K>(row: TreeRow<K>, label: impl Fn(&K) -> String) -> ListRow {
+    match row.node {
+        Node::Branch(key, songs) => ListRow::Branch { name: label(&key), songs, depth: row.depth, expanded: row.expanded },
+        Node::Song(index) => ListRow::Leaf { index, depth: row.depth },
+    }
+}
+
+// The visible part of a song list
+struct SongRows {
+    // Which list a click on the header sorts
+    view: ViewMode,
+    rows: Vec<ListRow>,
+    selected: Option<usize>,
+}
+
+enum ListRow {
+    // `position` counts from 1 in the whole list, not just the visible part
+    Song { index: usize, position: usize },
+    // Header of a run of queued tracks from one album
+    Album { songs: Vec<usize>, expanded: bool },
+    // Playlist entry whose file isn't in the library
+    Missing { path: PathBuf, position: usize },
+    // Queued track that has already played
+    Played { index: usize },
+    // Artist, album, folder or group in a tree, with how many songs are under it
+    Branch { name: String, songs: usize, depth: usize, expanded: bool },
+    // Song in a tree
+    Leaf { index: usize, depth: usize },
+}
+
+// Every song list is a table in its configured columns, with a header that
+// sorts on click. Header positions are recorded in `app.header_cells`.
+fn draw_song_table(f: &mut Frame, app: &mut App, area: Rect, block: Block, rows: SongRows) {
+    let columns: &[ColumnSpec] = match rows.view {
+        ViewMode::Queue => &app.columns.queue,
+        ViewMode::Search => &app.columns.search,
+        ViewMode::Artists => &app.columns.artist,
+        ViewMode::Playlists => &app.columns.playlist,
+        _ => &app.columns.songs,
+    };
+    let symbol_width = HIGHLIGHT_SYMBOL.chars().count() as u16;
+    let widths = columns::widths(columns, area.width.saturating_sub(2 + symbol_width));
+    let constraints: Vec<Constraint> = widths.iter().map(|&width| Constraint::Length(width)).collect();
+
+    let sort = app.sort.filter(|sort| sort.view == rows.view);
+    let cell = |column: Column, text: String| {
+        let line = Line::from(text);
+        Cell::from(if column.align_right() { line.alignment(Alignment::Right) } else { line })
+    };
+    let header = Row::new(columns.iter().map(|spec| {
+        let mut title = spec.column.header().to_string();
+        match sort {
+            Some(sort) if sort.column == spec.column => title.push(if sort.descending { '▼' } else { '▲' }),
+            _ => {}
+        }
+        cell(spec.column, title)
+    }))
+    .style(Style::default().fg(Color::Yellow));
+
+    // The current track stands out everywhere but in the queue, where it isn't listed
+    let current = (rows.view != ViewMode::Queue).then_some(app.player.playback.current);
+    let body: Vec<Row> = rows.rows.iter()
+        .map(|row| match row {
+            ListRow::Song { index, position } => {
+                let song = &app.player.songs[*index];
+                let row = Row::new(columns.iter().map(|spec| cell(spec.column, spec.column.cell(song, *position))));
+                if current == Some(*index) {
+                    row.style(Style::default().fg(Color::Green).add_modifier(Modifier::BOLD))
+                } else {
+                    row
+                }
+            }
+            ListRow::Album { songs, expanded } => {
+                let songs: Vec<&Song> = songs.iter().map(|&i| &app.player.songs[i]).collect();
+                Row::new(columns.iter().map(|spec| cell(spec.column, album_cell(spec.column, &songs, *expanded))))
+                    .style(Style::default().fg(Color::Magenta))
+            }
+            ListRow::Missing { path, position } => {
+                let name = path.file_name().unwrap_or_default().to_string_lossy();
+                Row::new(columns.iter().map(|spec| match spec.column {
+                    Column::Position => cell(spec.column, position.to_string()),
+                    Column::Title => cell(spec.column, name.to_string()),
+                    _ => cell(spec.column, String::new()),
+                }))
+                .style(Style::default().fg(Color::DarkGray))
+            }
+            ListRow::Played { index } => {
+                let song = &app.player.songs[*index];
+                Row::new(columns.iter().map(|spec| match spec.column {
+                    Column::Position => cell(spec.column, String::new()),
+                    column => cell(column, column.cell(song, 0)),
+                }))
+                .style(Style::default().fg(Color::DarkGray))
+            }
+            ListRow::Branch { name, songs, depth, expanded } => {
+                let arrow = if *expanded { '▾' } else { '▸' };
+                Row::new(columns.iter().map(|spec| match spec.column {
+                    Column::Title => cell(spec.column, format!("{}{} {} ({} tracks)", "  ".repeat(*depth), arrow, name, songs)),
+                    _ => cell(spec.column, String::new()),
+                }))
+                .style(Style::default().fg(Color::Magenta))
+            }
+            // Lined up under the name of the branch it's in
+            ListRow::Leaf { index, depth } => {
+                let song = &app.player.songs[*index];
+                let row = Row::new(columns.iter().map(|spec| match spec.column {
+                    Column::Position => cell(spec.column, String::new()),
+                    Column::Title => cell(spec.column, format!("{}  {}", "  ".repeat(*depth), song.title)),
+                    column => cell(column, column.cell(song, 0)),
+                }));
+                if current == Some(*index) {
+                    row.style(Style::default().fg(Color::Green).add_modifier(Modifier::BOLD))
+                } else {
+                    row
+                }
+            }
+        })
+        .collect();
+
+    let mut header_cells = Vec::new();
+    let mut x = area.x + 1 + symbol_width;
+    for (spec, &width) in columns.iter().zip(&widths) {
+        let width = width.min(area.right().saturating_sub(x + 1));
+        header_cells.push(HeaderCell { area: Rect::new(x, area.y + 1, width, 1), view: rows.view, column: spec.column });
+        x += width + 1;
+    }
+
+    let table = Table::new(body)
+        .header(header)
+        .widths(&constraints)
+        .block(block)
+        .highlight_style(Style::default().add_modifier(Modifier::BOLD))
+        .highlight_symbol(HIGHLIGHT_SYMBOL)
+        .highlight_spacing(HighlightSpacing::Always);
+    let mut state = TableState::default();
+    state.select(rows.selected);
+    f.render_stateful_widget(table, area, &mut state);
+    app.header_cells.extend(header_cells);
+}
+
+// Only once the song has been rated or played
+fn stats_line(stats: SongStats) -> Option<Line<'static>> {
+    match (stats.rating, stats.plays) {
+        (None, 0) => None,
+        (None, plays) => Some(Line::from(format!("Plays: {}", plays))),
+        (rating, plays) => Some(Line::from(format!("Rating: {}  Plays: {}", stats::stars_label(rating), plays))),
+    }
+}
+
+fn draw_now_playing(f: &mut Frame, app: &mut App, area: Rect) {
+    let now_playing = if let Some(song) = app.player.songs.get(app.player.playback.current) {
+        let mut status = match app.time_readout() {
+            Some(time) => format!("Status: {}  {}", app.player.playback.status(), time),
+            None => format!("Status: {}", app.player.playback.status()),
+        };
+        if let Some(buffer) = app.buffer_readout() {
+            status = format!("{}  {}", status, buffer);
+        }
+        let mut lines = vec![
+            Line::from(""),
+            //Line::from(vec![Span::raw("Now Playing:")]),
+            //Line::from(""),
+            Line::from(vec![Span::raw(format!("Title: {}", song.title))]),
+            Line::from(vec![Span::raw(format!("Artist: {}", song.artist))]),
+            Line::from(vec![Span::raw(format!("Album: {}", song.album))]),
+            Line::from(vec![Span::raw(format!("Genre: {}", song.genre))]),
+            Line::from(""),
+            Line::from(vec![Span::raw(status)]),
+            Line::from(vec![Span::raw(format!("Repeat: {}",
+                if app.player.playback.repeat == RepeatMode::All { "All" } else { "Off" }
+            ))]),
+        ];
+        if let Some(stream) = app.player.stream {
+            lines.push(Line::from(format!("Rate: {}", stream.rate())));
+            lines.push(Line::from(format!("Channels: {}", stream.layout())));
+            if let Some(codec) = stream.codec {
+                lines.push(Line::from(format!("Format: {}", codec.label())));
+            }
+        }
+        lines.extend(stats_line(app.player.stats.get(&song.path)));
+        lines
+    } else {
+        vec![
+            Line::from(""),
+            Line::from(vec![Span::raw("Nothing playing")]),
+        ]
+    };
+
+    let block = Block::default().borders(Borders::ALL).title("Now Playing");
+    let inner = block.inner(area);
+    f.render_widget(block, area);
+
+    // Cover on the left when there is room for it next to the text
+    let art_cols = inner.height * 2;
+    let mut text_area = inner;
+    if inner.width >= art_cols + 1 + MIN_NOW_PLAYING_TEXT {
+        if let Some(cover) = cover_for(app, art_cols, inner.height) {
+            draw_cover(f, cover, inner);
+            text_area.x += art_cols + 1;
+            text_area.width -= art_cols + 1;
+        }
+    }
+
+    let now_playing_widget = Paragraph::new(now_playing)
+        .style(Style::default().fg(Color::Green))
+        .alignment(Alignment::Left);
+    f.render_widget(now_playing_widget, text_area);
+}
+
+const MIN_NOW_PLAYING_TEXT: u16 = 24;
+
+// Decoding is slow, so the blocks are cached until the track or panel size
+// changes. Covers not in the art cache yet are looked up in the background.
+fn cover_for(app: &mut App, cols: u16, rows: u16) -> Option<&CoverBlocks> {
+    let current = app.player.playback.current;
+    let song = app.player.songs.get(current)?;
+    let stale = match &app.cover {
+        Some((path, cover)) => {
+            *path != song.path
+                || cover.as_ref().is_some_and(|c| c.cols != cols as u32 || c.rows != rows as u32)
+        }
+        None => true,
+    };
+    if stale {
+        let path = song.path.clone();
+        let cover = match app.art.cached(song) {
+            Some(thumbnail) => CoverBlocks::from_file(&thumbnail, cols as u32, rows as u32),
+            None if app.art.is_enabled() => {
+                app.request_cover(current);
+                None
+            }
+            None => CoverBlocks::load(&path, cols as u32, rows as u32),
+        };
+        app.cover = Some((path, cover));
+    }
+    app.cover.as_ref().and_then(|(_, cover)| cover.as_ref())
+}
+
+// Lyrics of the current song in place of the song list. Synced lyrics keep
+// the line being sung in the middle and highlighted.
+fn draw_lyrics(f: &mut Frame, app: &mut App, area: Rect) {
+    let current = app.player.playback.current;
+    let Some(song) = app.player.songs.get(current) else {
+        let block = Block::default().borders(Borders::ALL).title("Lyrics");
+        f.render_widget(Paragraph::new("Nothing playing").block(block), area);
+        return;
+    };
+    let title = format!("Lyrics: {}", song.title);
+    refresh_lyrics(app);
+    let position = app.track_position().map(|(position, _)| position);
+
+    let mut block = Block::default().borders(Borders::ALL).title(title);
+    let Some((_, Some(lyrics))) = &app.lyrics else {
+        let text = if app.lyrics_requested.is_some() { "Looking for lyrics…" } else { "No lyrics found" };
+        let placeholder = Paragraph::new(Span::styled(text, Style::default().fg(Color::DarkGray)))
+            .alignment(Alignment::Center)
+            .block(block);
+        f.render_widget(placeholder, area);
+        return;
+    };
+    block = block.title(
+        Title::from(format!("Lyrics from {}", lyrics.source))
+            .position(Position::Bottom)
+            .alignment(Alignment::Right),
+    );
+
+    let highlighted = position.and_then(|position| lyrics.current(position));
+    let lines: Vec<Line> = lyrics.lines.iter().enumerate()
+        .map(|(i, line)| {
+            if Some(i) == highlighted {
+                Line::styled(line.text.as_str(), Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD))
+            } else {
+                Line::from(line.text.as_str())
+            }
+        })
+        .collect();
+    let height = area.height.saturating_sub(2) as usize;
+    let scroll = highlighted.map_or(0, |i| i.saturating_sub(height / 2));
+    let paragraph = Paragraph::new(lines)
+        .alignment(Alignment::Center)
+        .scroll((scroll as u16, 0))
+        .block(block);
+    f.render_widget(paragraph, area);
+}
+
+// Loads the current song's lyrics if the ones held are for another song
+fn refresh_lyrics(app: &mut App) {
+    let current = app.player.playback.current;
+    let Some(song) = app.player.songs.get(current) else {
+        return;
+    };
+    if app.lyrics.as_ref().is_none_or(|(path, _)| *path != song.path) {
+        app.load_lyrics(current);
+    }
+}
+
+// The line being sung and the next one, as large as the terminal allows
+// Idle screen: a big clock, the playing track and its level bars. It drifts
+// a column each minute so nothing stays lit in one place.
+fn draw_screensaver(f: &mut Frame, app: &App, area: Rect) {
+    let drift = (app.started.elapsed().as_secs() / 60 % 8) as u16;
+    let area = Rect { x: area.x + drift.min(area.width), width: area.width.saturating_sub(8), ..area };
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Min(0),
+            Constraint::Length(bigtext::ROWS as u16),  // Clock
+            Constraint::Length(1),
+            Constraint::Length(2),  // Track and position
+            Constraint::Length(1),
+            Constraint::Length(6),  // Level bars
+            Constraint::Min(0),
+        ])
+        .split(area);
+
+    let dimmed = Style::default().fg(Color::DarkGray);
+    let time = clock::hours_minutes(SystemTime::now(), app.utc_offset);
+    let clock: Vec<Line> = bigtext::render_line(&time)
+        .map_or_else(|| vec![Line::from(time.clone())], |rows| rows.into_iter().map(Line::from).collect());
+    f.render_widget(Paragraph::new(clock).alignment(Alignment::Center), chunks[1]);
+
+    let Some((position, length)) = app.track_position() else {
+        f.render_widget(Paragraph::new(Span::styled("Nothing playing", dimmed)).alignment(Alignment::Center), chunks[3]);
+        return;
+    };
+    let song = &app.player.songs[app.player.playback.current];
+    let track = vec![
+        Line::from(format!("{} – {}", song.title, song.artist)),
+        Line::styled(app.time_display.format(position, length), dimmed),
+    ];
+    f.render_widget(Paragraph::new(track).alignment(Alignment::Center), chunks[3]);
+
+    // Newest on the right
+    let width = chunks[5].width as usize;
+    let bars: Vec<u64> = app.levels.iter().skip(app.levels.len().saturating_sub(width)).copied().collect();
+    let bars_area = Rect { x: chunks[5].x + (width - bars.len()) as u16, width: bars.len() as u16, ..chunks[5] };
+    f.render_widget(Sparkline::default().data(&bars).max(100).style(Style::default().fg(Color::Cyan)), bars_area);
+}
+
+fn draw_karaoke(f: &mut Frame, app: &mut App, area: Rect) {
+    refresh_lyrics(app);
+    let mut block = Block::default()
+        .borders(Borders::ALL)
+        .title(Title::from("Esc: Leave").position(Position::Bottom));
+    let Some(song) = app.player.songs.get(app.player.playback.current) else {
+        f.render_widget(Paragraph::new("Nothing playing").block(block.title("Karaoke")), area);
+        return;
+    };
+    block = block.title(format!("Karaoke: {} - {}", song.title, song.artist));
+    let dimmed = Style::default().fg(Color::DarkGray);
+
+    let lyrics = match &app.lyrics {
+        Some((_, Some(lyrics))) if lyrics.is_synced() => lyrics,
+        Some((_, Some(_))) => {
+            let text = Span::styled("These lyrics have no timings to sing along to", dimmed);
+            f.render_widget(Paragraph::new(text).alignment(Alignment::Center).block(block), area);
+            return;
+        }
+        _ => {
+            let text = if app.lyrics_requested.is_some() { "Looking for lyrics…" } else { "No lyrics found" };
+            f.render_widget(Paragraph::new(Span::styled(text, dimmed)).alignment(Alignment::Center).block(block), area);
+            return;
+        }
+    };
+    block = block.title(
+        Title::from(format!("Lyrics from {}", lyrics.source))
+            .position(Position::Bottom)
+            .alignment(Alignment::Right),
+    );
+
+    let position = app.track_position().map_or(Duration::ZERO, |(position, _)| position);
+    let current = lyrics.current(position);
+    let next = current.map_or(0, |i| i + 1);
+    let width = area.width.saturating_sub(2) as usize;
+    let big = |text: &str, style: Style| -> Vec<Line> {
+        match bigtext::render(text, width) {
+            Some(pieces) => pieces.into_iter().flatten().map(|row| Line::styled(row, style)).collect(),
+            // Characters the font lacks are shown as they are
+            None => vec![Line::styled(text.to_string(), style)],
+        }
+    };
+    let mut lines = Vec::new();
+    if let Some(i) = current {
+        lines.extend(big(&lyrics.lines[i].text, Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD)));
+    }
+    if let Some(line) = lyrics.lines.get(next) {
+        lines.push(Line::from(""));
+        lines.extend(big(&line.text, dimmed));
+    }
+    let height = area.height.saturating_sub(2) as usize;
+    let padding = height.saturating_sub(lines.len()) / 2;
+    let lines: Vec<Line> = std::iter::repeat_with(|| Line::from("")).take(padding).chain(lines).collect();
+    f.render_widget(Paragraph::new(lines).alignment(Alignment::Center).block(block), area);
+}
+
+// Title and artist in big letters over a full-width progress bar, to be
+// read from across the room
+fn draw_big_now(f: &mut Frame, app: &App, area: Rect) {
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .title(Title::from("Esc: Leave").position(Position::Bottom));
+    let dimmed = Style::default().fg(Color::DarkGray);
+    let Some((position, length)) = app.track_position() else {
+        let text = Span::styled("Nothing playing", dimmed);
+        f.render_widget(Paragraph::new(text).alignment(Alignment::Center).block(block), area);
+        return;
+    };
+    let song = &app.player.songs[app.player.playback.current];
+    let width = area.width.saturating_sub(2) as usize;
+    let big = |text: &str, style: Style| -> Vec<Line> {
+        match bigtext::render(text, width) {
+            Some(pieces) => pieces.into_iter().flatten().map(|row| Line::styled(row, style)).collect(),
+            // Characters the font lacks are shown as they are
+            None => vec![Line::styled(text.to_string(), style)],
+        }
+    };
+    let mut lines = big(&song.title, Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD));
+    lines.push(Line::from(""));
+    lines.extend(big(&song.artist, Style::default().fg(Color::White)));
+    lines.push(Line::from(""));
+    if let Some(length) = length.filter(|length| !length.is_zero()) {
+        let fraction = position.as_secs_f64() / length.as_secs_f64();
+        let bar = progress_bar(fraction, width.saturating_sub(4));
+        lines.push(Line::styled(bar, Style::default().fg(Color::Cyan).bg(Color::DarkGray)));
+    }
+    lines.push(Line::styled(app.time_display.format(position, length), dimmed));
+
+    let height = area.height.saturating_sub(2) as usize;
+    let padding = height.saturating_sub(lines.len()) / 2;
+    let lines: Vec<Line> = std::iter::repeat_with(|| Line::from("")).take(padding).chain(lines).collect();
+    f.render_widget(Paragraph::new(lines).alignment(Alignment::Center).block(block), area);
+}
+
+fn draw_cover(f: &mut Frame, cover: &CoverBlocks, area: Rect) {
+    let buffer = f.buffer_mut();
+    for row in 0..cover.rows.min(area.height as u32) {
+        for col in 0..cover.cols.min(area.width as u32) {
+            let ([tr, tg, tb], [br, bg, bb]) = cover.cell(col, row);
+            buffer.get_mut(area.x + col as u16, area.y + row as u16)
+                .set_symbol("▀")
+                .set_style(Style::default().fg(Color::Rgb(tr, tg, tb)).bg(Color::Rgb(br, bg, bb)));
+        }
+    }
+}
+
+// Runs of tracks from one album fold into a header row, see `queue::rows`
+fn draw_queue(f: &mut Frame, app: &mut App, area: Rect) {
+    let queue_height = area.height.saturating_sub(3).max(1) as usize;
+    let focused = app.focus == Pane::Queue;
+    let queue_rows = app.queue_rows();
+    let cursor = app.queue_cursor.min(queue_rows.len().saturating_sub(1));
+    // Played tracks that are kept sit above the queue; only as many of the
+    // latest as there is room for once what's coming up is shown
+    let played = &app.player.playback.played;
+    let total = played.len() + queue_rows.len();
+    let unfocused = total.saturating_sub(queue_height).min(played.len());
+    // Scroll just enough to keep the cursor visible
+    let offset = if focused { (played.len() + cursor + 1).saturating_sub(queue_height) } else { unfocused };
+    let queue = &app.player.playback.queue;
+    let rows = played.iter()
+        .map(|&index| ListRow::Played { index })
+        .chain(queue_rows.into_iter().map(|row| match row {
+            QueueRow::Album { positions, expanded } => ListRow::Album {
+                songs: positions.map(|position| queue[position]).collect(),
+                expanded,
+            },
+            QueueRow::Track(position) => ListRow::Song { index: queue[position], position: position + 1 },
+        }))
+        .skip(offset)
+        .take(queue_height)
+        .collect();
+    let selected = (focused && !queue.is_empty()).then_some(played.len() + cursor - offset);
+
+    let title = match app.player.queues.active.as_str() {
+        queues::DEFAULT => String::from("Queue"),
+        name => format!("Queue: {}", name),
+    };
+    let mut block = Block::default().borders(Borders::ALL).title(title).border_style(pane_border(app, Pane::Queue));
+    if let Some(ready) = app.player.next_buffered() {
+        block = block.title(
+            Title::from(if ready { "⇣ next buffered" } else { "buffering next…" })
+                .position(Position::Bottom)
+                .alignment(Alignment::Right),
+        );
+    }
+    draw_song_table(f, app, area, block, SongRows { view: ViewMode::Queue, rows, selected });
+}
+
+// What an album header shows in each column
+fn album_cell(column: Column, songs: &[&Song], expanded: bool) -> String {
+    let Some(first) = songs.first() else {
+        return String::new();
+    };
+    match column {
+        Column::Position => String::from(if expanded { "▾" } else { "▸" }),
+        Column::Title => format!("{} ({} tracks)", first.album, songs.len()),
+        Column::Artist => first.artist.clone(),
+        Column::Album => first.album.clone(),
+        // Only when every track's length is known
+        Column::Duration => songs.iter()
+            .map(|song| song.duration)
+            .sum::<Option<Duration>>()
+            .map(format_duration)
+            .unwrap_or_default(),
+        Column::Track | Column::Genre | Column::Bpm | Column::Key | Column::Dr => String::new(),
+    }
+}
+
+fn pane_border(app: &App, pane: Pane) -> Style {
+    if app.focus == pane {
+        Style::default().fg(Color::Cyan)
+    } else {
+        Style::default()
+    }
+}
+
+// Action menu popup, centered over the song list
+fn draw_menu(f: &mut Frame, app: &App, area: Rect) {
+    let Some(menu) = &app.menu else {
+        return;
+    };
+    let (title, actions) = (menu.title.as_str(), menu.target.actions());
+    let width = actions.iter().map(|a| a.label().chars().count()).max().unwrap_or(0).max(title.chars().count()) + 7;
+    let popup = centered(area, width as u16, actions.len() as u16 + 2);
+
+    let items: Vec<ListItem> = actions.iter().map(|action| ListItem::new(action.label())).collect();
+    let list = List::new(items)
+        .block(Block::default().borders(Borders::ALL).title(title))
+        .highlight_style(Style::default().fg(Color::Cyan).add_modifier(Modifier::BOLD))
+        .highlight_symbol(">> ");
+    let mut state = ListState::default();
+    state.select(Some(menu.selected));
+    f.render_widget(Clear, popup);
+    f.render_stateful_widget(list, popup, &mut state);
+}
+
+// Tag editor popup: a field per line, the selected one being typed into
+fn draw_tag_editor(f: &mut Frame, app: &App, area: Rect) {
+    let Some(editor) = &app.tag_editor else {
+        return;
+    };
+    let label_width = editor.fields.iter().map(|(field, _, _)| field.label().chars().count()).max().unwrap_or(0);
+    let mut lines: Vec<Line> = editor.fields.iter().enumerate()
+        .map(|(i, (field, shared, value))| {
+            let label = format!("{:>width$}  ", field.label(), width = label_width);
+            let value = match (i == editor.selected, value.is_empty() && shared.is_none()) {
+                (true, _) => Span::styled(format!("{}▏", value), Style::default().fg(Color::Cyan).add_modifier(Modifier::BOLD)),
+                (false, true) => Span::styled("(various)", Style::default().fg(Color::DarkGray)),
+                (false, false) => Span::raw(value.as_str()),
+            };
+            Line::from(vec![Span::raw(label), value])
+        })
+        .collect();
+    lines.push(Line::from(""));
+    lines.push(Line::from("Enter: Save | ↑↓: Field | Esc: Cancel"));
+
+    let title = match editor.songs.len() {
+        1 => String::from("Edit tags"),
+        n => format!("Edit tags of {} songs", n),
+    };
+    let width = lines.iter().map(Line::width).max().unwrap_or(0).max(title.chars().count()).max(40) + 4;
+    let popup = centered(area, width as u16, lines.len() as u16 + 2);
+    let paragraph = Paragraph::new(lines).block(Block::default().borders(Borders::ALL).title(title));
+    f.render_widget(Clear, popup);
+    f.render_widget(paragraph, popup);
+}
+
+fn draw_report(f: &mut Frame, app: &App, area: Rect) {
+    let Some(report) = &app.report else {
+        return;
+    };
+    let longest = report.lines.iter().map(|line| line.chars().count()).max().unwrap_or(0);
+    let width = (longest.max(report.title.chars().count()) + 4).min(area.width as usize);
+    let popup = centered(area, width as u16, (report.lines.len() + 2).min(area.height as usize) as u16);
+
+    let lines: Vec<Line> = report.lines.iter().map(|line| Line::from(line.as_str())).collect();
+    let paragraph = Paragraph::new(lines)
+        .block(Block::default().borders(Borders::ALL).title(report.title.as_str()))
+        .scroll((report.scroll as u16, 0));
+    f.render_widget(Clear, popup);
+    f.render_widget(paragraph, popup);
+}
+
+// One missing playlist entry with the library songs it could be relinked to
+fn draw_relink(f: &mut Frame, app: &App, area: Rect) {
+    let Some(relink) = &app.relink else {
+        return;
+    };
+    let path = app.player.playlists[relink.playlist].entries[relink.missing[0]].display().to_string();
+    let mut lines = vec![Line::from(path), Line::from("")];
+    if relink.candidates.is_empty() {
+        lines.push(Line::from(Span::styled("No file with that name in the library", Style::default().fg(Color::DarkGray))));
+    }
+    for (i, &song) in relink.candidates.iter().enumerate() {
+        let label = app.player.songs[song].path.display().to_string();
+        lines.push(if i == relink.selected {
+            Line::from(Span::styled(
+                format!("{}{}", HIGHLIGHT_SYMBOL, label),
+                Style::default().fg(Color::Cyan).add_modifier(Modifier::BOLD),
+            ))
+        } else {
+            Line::from(format!("   {}", label))
+        });
+    }
+    lines.push(Line::from(""));
+    lines.push(Line::from("Enter: Relink | d: Drop | n: Skip | Esc: Stop"));
+
+    let title = format!("Missing track ({} left)", relink.missing.len());
+    let width = lines.iter().map(Line::width).max().unwrap_or(0).max(title.chars().count()) + 4;
+    let popup = centered(area, width as u16, lines.len() as u16 + 2);
+    let paragraph = Paragraph::new(lines).block(Block::default().borders(Borders::ALL).title(title));
+    f.render_widget(Clear, popup);
+    f.render_widget(paragraph, popup);
+}
+
+// Playlist picker popup: the filter is typed into the title
+fn draw_playlist_picker(f: &mut Frame, app: &App, area: Rect) {
+    let Some(picker) = &app.picker else {
+        return;
+    };
+    let labels: Vec<String> = picker.choices(&app.player.playlists).into_iter()
+        .map(|choice| match choice {
+            Some(i) => app.player.playlists[i].name.clone(),
+            None if picker.filter.is_empty() => String::from("New playlist…"),
+            None => format!("New playlist \"{}\"", picker.filter),
+        })
+        .collect();
+    let title = format!("Add to playlist: {}", picker.filter);
+    let width = labels.iter().map(|l| l.chars().count()).max().unwrap_or(0).max(title.chars().count()) + 7;
+    let popup = centered(area, width.max(30) as u16, labels.len() as u16 + 2);
+
+    let items: Vec<ListItem> = labels.into_iter().map(ListItem::new).collect();
+    let list = List::new(items)
+        .block(Block::default().borders(Borders::ALL).title(title))
+        .highlight_style(Style::default().fg(Color::Cyan).add_modifier(Modifier::BOLD))
+        .highlight_symbol(">> ");
+    let mut state = ListState::default();
+    state.select(Some(picker.selected));
+    f.render_widget(Clear, popup);
+    f.render_stateful_widget(list, popup, &mut state);
+}
+
+// Command palette: actions that match what was typed, each with its key
+fn draw_palette(f: &mut Frame, app: &App, area: Rect) {
+    let Some(palette) = &app.palette else {
+        return;
+    };
+    let entries: Vec<(&str, String)> = palette.choices().into_iter()
+        .map(|i| (PALETTE[i].0, app.palette_key(PALETTE[i].1)))
+        .collect();
+    let label_width = entries.iter().map(|(label, _)| label.chars().count()).max().unwrap_or(0);
+    let key_width = entries.iter().map(|(_, key)| key.chars().count()).max().unwrap_or(0);
+    let title = format!("Command: {}", palette.filter);
+    let width = (label_width + key_width + 2).max(title.chars().count()) + 5;
+    let popup = centered(area, width.max(30) as u16, entries.len().max(1) as u16 + 2);
+
+    let items: Vec<ListItem> = entries.into_iter()
+        .map(|(label, key)| ListItem::new(format!("{:<lw$}  {:>kw$}", label, key, lw = label_width, kw = key_width)))
+        .collect();
+    let list = List::new(items)
+        .block(Block::default().borders(Borders::ALL).title(title))
+        .highlight_style(Style::default().fg(Color::Cyan).add_modifier(Modifier::BOLD))
+        .highlight_symbol(">> ");
+    let mut state = ListState::default();
+    state.select(Some(palette.selected));
+    f.render_widget(Clear, popup);
+    f.render_stateful_widget(list, popup, &mut state);
+}
+
+// A width x height rectangle in the middle of `area`, clamped to fit
+fn centered(area: Rect, width: u16, height: u16) -> Rect {
+    let width = width.min(area.width);
+    let height = height.min(area.height);
+    Rect {
+        x: area.x + (area.width - width) / 2,
+        y: area.y + (area.height - height) / 2,
+        width,
+        height,
+    }
+}
+
+// Key help, or the command/search prompt while typing
+// Both decks side by side over the crossfader
+fn draw_decks(f: &mut Frame, app: &App, area: Rect) {
+    let Some(decks) = &app.decks else {
+        return;
+    };
+    let rows = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Min(0), Constraint::Length(3)])
+        .split(area);
+    let columns = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
+        .split(rows[0]);
+
+    for (deck, name, area) in [(&decks.a, "Deck A", columns[0]), (&decks.b, "Deck B", columns[1])] {
+        let width = area.width.saturating_sub(2) as usize;
+        let lines = match &deck.song {
+            None => vec![Line::from(Span::styled("Empty", Style::default().fg(Color::DarkGray)))],
+            Some(song) => {
+                let status = if deck.has_ended() {
+                    "Ended"
+                } else if deck.is_playing() {
+                    "Playing"
+                } else {
+                    "Paused"
+                };
+                let time = match song.duration {
+                    Some(duration) => format!("{} / {}", format_duration(deck.elapsed()), format_duration(duration)),
+                    None => format_duration(deck.elapsed()),
+                };
+                vec![
+                    Line::from(Span::styled(song.title.clone(), Style::default().add_modifier(Modifier::BOLD))),
+                    Line::from(song.artist.clone()),
+                    Line::from(""),
+                    Line::from(format!("{}  {}", status, time)),
+                    Line::from(format!("Volume {:>3}% {}", (deck.volume * 100.0).round(), progress_bar(deck.volume as f64, width.saturating_sub(12)))),
+                    Line::from(format!("EQ: {}", deck.filter.label())),
+                ]
+            }
+        };
+        let paragraph = Paragraph::new(lines).block(Block::default().borders(Borders::ALL).title(name));
+        f.render_widget(paragraph, area);
+    }
+
+    // A ─────●───── B, the knob where the mix is
+    let width = rows[1].width.saturating_sub(6).max(1) as usize;
+    let knob = (((decks.crossfader + 1.0) / 2.0) * (width - 1) as f32).round() as usize;
+    let track: String = (0..width).map(|i| if i == knob { '●' } else { '─' }).collect();
+    let crossfader = Paragraph::new(format!("A {} B", track))
+        .alignment(Alignment::Center)
+        .block(Block::default().borders(Borders::ALL).title("Crossfader"));
+    f.render_widget(crossfader, rows[1]);
+}
+
+// Perform mode: what each of the keys 1-8 jumps to in the current track
+fn cue_pads(app: &App) -> Line<'static> {
+    let path = app.player.songs.get(app.player.playback.current).map(|song| song.path.as_path());
+    let mut spans = Vec::new();
+    for pad in 0..PADS {
+        let cue = path.and_then(|path| app.cues.get(path, pad));
+        let label = format!(" {} {} ", pad + 1, cue.map_or(String::from("-"), format_duration));
+        spans.push(match cue {
+            Some(_) => Span::styled(label, Style::default().fg(Color::Black).bg(Color::Cyan)),
+            None => Span::styled(label, Style::default().fg(Color::DarkGray)),
+        });
+        spans.push(Span::raw(" "));
+    }
+    spans.push(Span::raw("x: Clear | Esc: Leave"));
+    Line::from(spans)
+}
+
+fn draw_controls(f: &mut Frame, app: &App, area: Rect) {
+    let controls = if app.command_mode {
+        Paragraph::new(format!(":{}", app.command_input))
+    } else if app.perform {
+        Paragraph::new(cue_pads(app))
+    } else if app.decks.is_some() {
+        Paragraph::new("a/l: Load A/B | s/k: Play | w/x, o/,: Volume | d/j: EQ | ←/→: Crossfader | c: Center | Esc: Leave")
+    } else if app.search_mode {
+        Paragraph::new(format!("Search: {} (Tab: scope, ESC to stop typing)", app.search_input))
+    } else {
+        let muted = app.player.muted.then(|| Span::styled("Muted | ", Style::default().fg(Color::Red).add_modifier(Modifier::BOLD)));
+        let night = compressor::enabled(&app.player.night)
+            .then(|| Span::styled("Night | ", Style::default().fg(Color::Blue).add_modifier(Modifier::BOLD)));
+        let mono = app.player.mono.then(|| Span::styled("Mono | ", Style::default().fg(Color::Magenta).add_modifier(Modifier::BOLD)));
+        Paragraph::new(vec![
+            Line::from(muted.into_iter().chain(night).chain(mono).chain([
+                Span::raw("p: Play/Pause | "),
+                Span::raw("h/l: Prev/Next | "),
+                Span::raw("j/k: Move | "),
+                Span::raw("-/+: Volume | "),
+                Span::raw("s: Shuffle | "),
+                Span::raw("r: Repeat | "),
+                Span::raw("t: Time | "),
+                Span::raw("m: Mute | "),
+                Span::raw("N: Night | "),
+                Span::raw("M: Mono | "),
+                Span::raw("a: Add to Queue | "),
+                Span::raw("/: Search | "),
+                Span::raw("Space: Select | "),
+                Span::raw("Enter: Actions | "),
+                Span::raw("Tab/1-7: Change View | "),
+                Span::raw("gt/gT: Browse Tabs | "),
+                Span::raw("Ctrl-h/l: Focus | "),
+                Span::raw("Ctrl-p: Commands | "),
+                Span::raw("z/</>: Sidebar | "),
+                Span::raw("L: Lyrics | "),
+                Span::raw("[/]: Trim | "),
+                Span::raw("←/→: Seek | "),
+                Span::raw("q: Quit"),
+            ]).collect::<Vec<_>>())
+        ])
+    };
+    let mut controls_block = Block::default().borders(Borders::ALL);
+    if let Some(message) = &app.message {
+        controls_block = controls_block.title(message.as_str());
+    }
+    if let Some(keys) = app.showcmd() {
+        controls_block = controls_block.title(
+            Title::from(keys)
+                .position(Position::Bottom)
+                .alignment(Alignment::Right),
+        );
+    }
+    f.render_widget(controls.block(controls_block), area);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, path::PathBuf, sync::mpsc};
+
+    use ratatui::{backend::TestBackend, Terminal};
+
+    use super::*;
+    use music_player::config::{Config, DuplicatePolicy, GenresConfig, PowerMode, ResampleQuality, TimeDisplay};
+    use music_player::columns::LibrarySort;
+    use music_player::genres::Genres;
+    use music_player::lyrics::Lyrics;
+    use music_player::decode::Codec;
+    use music_player::resample::StreamInfo;
+    use music_player::{playback::PlaybackState, playlist::Playlist, queues::Queues, state::State};
+
+    use crossterm::event::{Event, KeyCode, KeyEvent, KeyModifiers, MouseButton, MouseEvent, MouseEventKind};
+
+    use music_player::tags::TagField;
+    use crate::{Action, ActionMenu, MusicPlayer, PlayerEvent, PlaylistPicker, Report, ScanEvent, TagEditor};
+
+    fn test_app(songs: &[&str]) -> App {
+        let (tx, _rx) = mpsc::channel();
+        let config = Config {
+            playlist_dir: Some(PathBuf::from("/nonexistent/playlists")),
+            ..Config::default()
+        };
+        let mut player = MusicPlayer::new(&[], &config, Vec::new(), tx).unwrap();
+        for name in songs {
+            let song = Song::new(PathBuf::from(format!("/music/{}.mp3", name)));
+            player.handle_scan_event(ScanEvent::Found(Box::new(song)));
+        }
+        App::new(player, &config, State::default())
+    }
+
+    fn render(app: &mut App, width: u16, height: u16) -> String {
+        let mut terminal = Terminal::new(TestBackend::new(width, height)).unwrap();
+        terminal.draw(|f| draw(f, app)).unwrap();
+
+        let buffer = terminal.backend().buffer();
+        let mut screen = String::new();
+        for y in 0..buffer.area.height {
+            for x in 0..buffer.area.width {
+                screen.push_str(&buffer.get(x, y).symbol);
+            }
+            screen.push('\n');
+        }
+        screen
+    }
+
+    fn library() -> App {
+        test_app(&[
+            "Daft Punk - One More Time",
+            "Radiohead - Karma Police",
+            "Daft Punk - Digital Love",
+            "Björk - Hyperballad",
+        ])
+    }
+
+    #[test]
+    fn empty_library() {
+        insta::assert_snapshot!(render(&mut test_app(&[]), 100, 20));
+    }
+
+    #[test]
+    fn all_songs_view() {
+        insta::assert_snapshot!(render(&mut library(), 100, 20));
+    }
+
+    #[test]
+    fn folders_view_follows_the_disk() {
+        let mut app = test_app(&[
+            "Electronic/Daft Punk - One More Time",
+            "Electronic/French/Daft Punk - Digital Love",
+            "Radiohead - Karma Police",
+        ]);
+        app.player.music_dirs = vec![PathBuf::from("/music")];
+        app.player.view_mode = ViewMode::Folders;
+        insta::assert_snapshot!(render(&mut app, 100, 20));
+
+        let press = |app: &mut App, c| assert!(app.handle_tree_view_key(KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE)));
+        press(&mut app, ' ');
+        press(&mut app, 'l');
+        press(&mut app, 'l');
+        insta::assert_snapshot!("folder_opened", render(&mut app, 100, 20));
+
+        // French closes, the cursor steps out to Electronic, which closes too
+        press(&mut app, 'h');
+        assert_eq!(app.scroll_offset, 1);
+        press(&mut app, 'h');
+        assert_eq!(app.scroll_offset, 0);
+        press(&mut app, 'h');
+        assert_eq!(app.folder_rows().len(), 2);
+    }
+
+    #[test]
+    fn configured_browse_tab_drills_down() {
+        let mut app = test_app(&["Daft Punk - One More Time", "Daft Punk - Digital Love", "Radiohead - Karma Police"]);
+        let config = Config { browse: vec!["By artist = artist / album".parse().unwrap()], ..Config::default() };
+        app.player.apply_config(&config).unwrap();
+        app.player.view_mode = ViewMode::Grouped(0);
+        let press = |app: &mut App, c| assert!(app.handle_tree_view_key(KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE)));
+        press(&mut app, 'j');
+        press(&mut app, ' ');
+        press(&mut app, 'l');
+        press(&mut app, ' ');
+        insta::assert_snapshot!(render(&mut app, 100, 20));
+
+        press(&mut app, 'j');
+        assert_eq!(app.selected_song(), Some(2));
+        assert_eq!(app.listed_songs().len(), 3);
+        press(&mut app, 'h');
+        press(&mut app, 'h');
+        press(&mut app, 'h');
+        assert_eq!(app.scroll_offset, 1);
+    }
+
+    #[test]
+    fn artists_view() {
+        let mut app = library();
+        app.player.view_mode = ViewMode::Artists;
+        insta::assert_snapshot!(render(&mut app, 100, 20));
+    }
+
+    #[test]
+    fn artist_songs_view() {
+        let mut app = library();
+        app.player.view_mode = ViewMode::Artists;
+        app.artist_tree.expand(vec![String::from("Daft Punk")]);
+        app.artist_tree.expand(vec![String::from("Daft Punk"), String::from("Unknown Album")]);
+        insta::assert_snapshot!(render(&mut app, 100, 20));
+    }
+
+    #[test]
+    fn pinned_artists_and_albums_come_first() {
+        let mut app = library();
+        app.pins.artists = vec![String::from("Radiohead")];
+        app.pins.albums = vec![(String::from("Unknown Album"), String::from("Radiohead"))];
+        app.player.view_mode = ViewMode::Artists;
+        app.artist_tree.expand(vec![String::from("Radiohead")]);
+        insta::assert_snapshot!(render(&mut app, 100, 20));
+
+        app.player.view_mode = ViewMode::Albums;
+        insta::assert_snapshot!("pinned_albums", render(&mut app, 100, 20));
+    }
+
+    #[test]
+    fn songs_tab_sections_fold_away() {
+        let mut app = library();
+        app.sections.recently_played = 2;
+        app.sections.most_played = 1;
+        for (index, plays, at) in [(1, 3, 100), (2, 1, 300), (3, 5, 200)] {
+            let path = app.player.songs[index].path.clone();
+            app.player.stats.songs.insert(path, SongStats { plays, last_played: Some(at), ..SongStats::default() });
+        }
+        insta::assert_snapshot!(render(&mut app, 100, 20));
+
+        app.scroll_offset = 1;
+        assert_eq!(app.selected_song(), Some(2));
+        app.scroll_offset = 0;
+        assert!(app.handle_tree_view_key(KeyEvent::new(KeyCode::Char(' '), KeyModifiers::NONE)));
+        insta::assert_snapshot!("songs_tab_section_folded", render(&mut app, 100, 20));
+    }
+
+    #[test]
+    fn m_mutes_except_where_it_relinks() {
+        let mut app = library();
+        let m = KeyEvent::new(KeyCode::Char('m'), KeyModifiers::NONE);
+        assert!(app.handle_global_key(m).unwrap());
+        assert!(app.player.muted);
+        insta::assert_snapshot!(render(&mut app, 100, 20));
+
+        app.player.view_mode = ViewMode::Playlists;
+        assert!(!app.handle_global_key(m).unwrap());
+        assert!(app.player.muted);
+    }
+
+    #[test]
+    fn merged_artist_names_browse_as_one() {
+        let mut app = test_app(&["The Beatles - Help", "Beatles, The - Yesterday", "The Beatles - Something", "the beatles - Taxman"]);
+        let suggested = music_player::artists::suggest(&app.player.songs);
+        app.player.add_aliases(suggested["The Beatles"].iter().map(|variant| (variant.clone(), String::from("The Beatles"))));
+        app.player.view_mode = ViewMode::Artists;
+        app.artist_tree.expand(vec![String::from("The Beatles")]);
+        insta::assert_snapshot!(render(&mut app, 100, 20));
+    }
+
+    #[test]
+    fn albums_view() {
+        let mut app = library();
+        app.player.view_mode = ViewMode::Albums;
+        insta::assert_snapshot!(render(&mut app, 100, 20));
+    }
+
+    #[test]
+    fn genres_view() {
+        let mut app = library();
+        app.player.view_mode = ViewMode::Genres;
+        insta::assert_snapshot!(render(&mut app, 100, 20));
+    }
+
+    #[test]
+    fn genre_aliases_and_parents_group_the_genres_view() {
+        let mut app = test_app(&["A - Breaks", "B - Rollers", "C - Grunge"]);
+        for (song, genre) in app.player.songs.iter_mut().zip(["Drum & Bass", "dnb", "alt rock"]) {
+            song.genre = genre.to_string();
+        }
+        let config = GenresConfig {
+            aliases: HashMap::from([(String::from("DnB"), String::from("Drum & Bass")), (String::from("Alt Rock"), String::from("Alternative Rock"))]),
+            parents: HashMap::from([(String::from("Drum & Bass"), String::from("Electronic"))]),
+        };
+        app.player.set_genres(Genres::new(&config));
+        assert_eq!(app.player.browse.genres["Electronic"], [0, 1]);
+        app.player.view_mode = ViewMode::Genres;
+        insta::assert_snapshot!(render(&mut app, 100, 20));
+    }
+
+    #[test]
+    fn queue_view() {
+        let mut app = library();
+        app.player.view_mode = ViewMode::Queue;
+        app.player.add_to_queue(1).unwrap();
+        app.player.add_to_queue(3).unwrap();
+        insta::assert_snapshot!(render(&mut app, 100, 20));
+    }
+
+    #[test]
+    fn search_view() {
+        let mut app = library();
+        app.player.view_mode = ViewMode::Search;
+        app.search_mode = true;
+        app.search_input = String::from("daft");
+        app.player.search("daft");
+        insta::assert_snapshot!(render(&mut app, 100, 20));
+    }
+
+    #[test]
+    fn search_within_the_queue() {
+        let mut app = library();
+        app.player.view_mode = ViewMode::Search;
+        app.player.add_to_queue(2).unwrap();
+        app.player.add_to_queue(1).unwrap();
+        app.search_mode = true;
+        app.search_input = String::from("daft");
+        app.handle_search_key(KeyEvent::new(KeyCode::Tab, KeyModifiers::NONE));
+        app.player.search("daft");
+        insta::assert_snapshot!(render(&mut app, 100, 20));
+    }
+
+    #[test]
+    fn command_palette_filters_and_runs() {
+        let mut app = library();
+        let ctrl_p = KeyEvent::new(KeyCode::Char('p'), KeyModifiers::CONTROL);
+        app.handle_global_key(ctrl_p).unwrap();
+        for c in "show".chars() {
+            app.handle_palette_key(KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE)).unwrap();
+        }
+        app.handle_palette_key(KeyEvent::new(KeyCode::Down, KeyModifiers::NONE)).unwrap();
+        insta::assert_snapshot!(render(&mut app, 100, 20));
+
+        app.handle_palette_key(KeyEvent::new(KeyCode::Enter, KeyModifiers::NONE)).unwrap();
+        assert!(app.palette.is_none() && app.player.view_mode == ViewMode::Artists);
+    }
+
+    #[test]
+    fn pending_chord_in_the_corner() {
+        let mut app = library();
+        app.last_key_time -= Duration::from_secs(1);
+        app.handle_key(KeyEvent::new(KeyCode::Char('g'), KeyModifiers::NONE)).unwrap();
+        insta::assert_snapshot!(render(&mut app, 100, 20));
+    }
+
+    #[test]
+    fn screensaver_comes_on_when_idle_and_any_key_leaves_it() {
+        let mut app = library();
+        app.player.playback.current = 2;
+        app.player.playback.state = PlaybackState::Paused { position: Duration::from_secs(65) };
+        app.screensaver_after = Some(Duration::from_secs(60));
+        app.last_input -= Duration::from_secs(61);
+        app.tick();
+        assert!(app.screensaver);
+        app.levels = [10, 50, 100, 30].into_iter().collect();
+        let screen = render(&mut app, 60, 16);
+        assert!(screen.contains("Digital Love – Daft Punk") && screen.contains("1:05"));
+        assert!(!screen.contains("Now Playing"));
+
+        let key = KeyEvent::new(KeyCode::Char('q'), KeyModifiers::NONE);
+        app.update(Action::Input(Event::Key(key))).unwrap();
+        assert!(!app.screensaver && !app.should_quit);
+    }
+
+    #[test]
+    fn huge_directories_are_added_only_once_confirmed() {
+        let mut app = library();
+        app.update(Action::Measured(PathBuf::from("/"), Some(String::from("more than 50000 files")))).unwrap();
+        assert_eq!(app.message.as_deref(), Some("/ has more than 50000 files. Add anyway? (y/n)"));
+
+        app.last_key_time -= Duration::from_secs(1);
+        app.handle_key(KeyEvent::new(KeyCode::Char('n'), KeyModifiers::NONE)).unwrap();
+        assert_eq!(app.message.as_deref(), Some("Directory not added"));
+        assert!(app.player.music_dirs.is_empty());
+    }
+
+    #[test]
+    fn big_now_playing() {
+        let mut app = library();
+        app.player.playback.current = 1;
+        app.player.playback.state = PlaybackState::Paused { position: Duration::from_secs(90) };
+        app.player.songs[1].duration = Some(Duration::from_secs(240));
+        app.big_now = true;
+        insta::assert_snapshot!(render(&mut app, 60, 20));
+        app.handle_big_now_key(KeyEvent::new(KeyCode::Esc, KeyModifiers::NONE));
+        assert!(!app.big_now);
+    }
+
+    #[test]
+    fn arrows_seek_within_a_paused_track() {
+        let mut app = library();
+        app.player.playback.current = 1;
+        app.player.playback.state = PlaybackState::Paused { position: Duration::from_secs(10) };
+        app.player.songs[1].duration = Some(Duration::from_secs(240));
+        let right = KeyEvent::new(KeyCode::Right, KeyModifiers::NONE);
+        let shift_right = KeyEvent::new(KeyCode::Right, KeyModifiers::SHIFT);
+        app.handle_global_key(right).unwrap();
+        app.handle_global_key(shift_right).unwrap();
+        assert_eq!(app.player.playback.state, PlaybackState::Paused { position: Duration::from_secs(45) });
+        app.handle_global_key(KeyEvent::new(KeyCode::Left, KeyModifiers::SHIFT)).unwrap();
+        app.handle_global_key(KeyEvent::new(KeyCode::Left, KeyModifiers::NONE)).unwrap();
+        assert_eq!(app.player.playback.state, PlaybackState::Paused { position: Duration::from_secs(10) });
+        app.handle_global_key(KeyEvent::new(KeyCode::Left, KeyModifiers::SHIFT)).unwrap();
+        assert_eq!(app.player.playback.state, PlaybackState::Paused { position: Duration::ZERO });
+    }
+
+    #[test]
+    fn low_power_ticks_only_redraw_on_change() {
+        let mut app = library();
+        app.power_mode = PowerMode::Low;
+        app.update_power();
+        assert_eq!(app.tick_ms.load(std::sync::atomic::Ordering::Relaxed), 1000);
+        assert!(app.tick_needs_draw());
+        assert!(!app.tick_needs_draw());
+        app.message = Some(String::from("Rescanned"));
+        assert!(app.tick_needs_draw());
+
+        app.power_mode = PowerMode::Normal;
+        app.update_power();
+        assert!(app.tick_needs_draw());
+    }
+
+    #[test]
+    fn position_follows_the_audio_thread() {
+        let mut app = library();
+        app.player.playback.state = PlaybackState::Playing;
+        app.player.handle_player_event(PlayerEvent::Position(Duration::from_secs(83)));
+        let position = app.player.position();
+        assert!(position >= Duration::from_secs(83) && position < Duration::from_secs(84));
+
+        // A report that arrives after pausing is out of date
+        app.player.playback.state = PlaybackState::Paused { position: Duration::from_secs(84) };
+        app.player.playing_since = None;
+        app.player.handle_player_event(PlayerEvent::Position(Duration::from_secs(90)));
+        assert_eq!(app.player.play_offset, Duration::from_secs(83));
+    }
+
+    #[test]
+    fn command_prompt_and_message() {
+        let mut app = library();
+        app.command_mode = true;
+        app.command_input = String::from("rescan");
+        app.message = Some(String::from("Added to queue"));
+        insta::assert_snapshot!(render(&mut app, 100, 20));
+    }
+
+    #[test]
+    fn scrolled_list_keeps_selection_visible() {
+        let names: Vec<String> = (1..=40).map(|i| format!("Artist - Track {:02}", i)).collect();
+        let names: Vec<&str> = names.iter().map(String::as_str).collect();
+        let mut app = test_app(&names);
+        app.scroll_offset = 30;
+        insta::assert_snapshot!(render(&mut app, 100, 20));
+    }
+
+    #[test]
+    fn narrow_terminal() {
+        insta::assert_snapshot!(render(&mut library(), 40, 12));
+    }
+
+    #[test]
+    fn action_menu() {
+        let mut app = library();
+        app.scroll_offset = 1;
+        let mut menu = ActionMenu::song(1, &app.player.songs[1]);
+        menu.selected = 2;
+        app.menu = Some(menu);
+        insta::assert_snapshot!(render(&mut app, 100, 20));
+    }
+
+    #[test]
+    fn album_menu_acts_on_every_track() {
+        let mut app = library();
+        app.player.view_mode = ViewMode::Albums;
+        app.scroll_offset = app.album_list().iter().position(|(_, artist)| artist == "Daft Punk").unwrap();
+        app.handle_library_key(KeyEvent::new(KeyCode::Enter, KeyModifiers::NONE));
+        insta::assert_snapshot!(render(&mut app, 100, 20));
+
+        // Add to queue
+        for code in [KeyCode::Down, KeyCode::Down, KeyCode::Enter] {
+            app.handle_menu_key(KeyEvent::new(code, KeyModifiers::NONE));
+        }
+        assert_eq!(Vec::from(app.player.playback.queue.clone()), vec![0, 2]);
+        assert_eq!(app.message.as_deref(), Some("Added to queue (2 tracks)"));
+    }
+
+    #[test]
+    fn tag_editor_leaves_alone_what_the_songs_differ_in() {
+        let mut app = library();
+        app.tag_editor = Some(TagEditor::new(vec![0, 1, 2], &app.player.songs));
+        let press = |app: &mut App, code| app.handle_tag_editor_key(KeyEvent::new(code, KeyModifiers::NONE));
+        // Artist, Album, Album artist, Genre
+        for code in [KeyCode::Down, KeyCode::Down, KeyCode::Down] {
+            press(&mut app, code);
+        }
+        for c in "Electronic".chars() {
+            press(&mut app, KeyCode::Char(c));
+        }
+        insta::assert_snapshot!(render(&mut app, 100, 20));
+        assert_eq!(app.tag_editor.as_ref().unwrap().edits(), vec![(TagField::Genre, String::from("Electronic"))]);
+
+        // Typing into a field they differ in writes it to all of them
+        press(&mut app, KeyCode::Up);
+        press(&mut app, KeyCode::Up);
+        press(&mut app, KeyCode::Up);
+        press(&mut app, KeyCode::Char('X'));
+        assert_eq!(app.tag_editor.as_ref().unwrap().edits()[0], (TagField::Artist, String::from("X")));
+    }
+
+    #[test]
+    fn playlist_picker() {
+        let mut app = library();
+        for name in ["Workout", "Road Trip", "Wild Orchestra Rock"] {
+            app.player.playlists.push(Playlist {
+                name: name.to_string(),
+                path: PathBuf::from(format!("/playlists/{}.m3u", name)),
+                entries: Vec::new(),
+            });
+        }
+        let mut picker = PlaylistPicker::new(vec![0]);
+        picker.filter = String::from("wo");
+        app.picker = Some(picker);
+        insta::assert_snapshot!(render(&mut app, 100, 20));
+    }
+
+    #[test]
+    fn import_report() {
+        let mut app = library();
+        app.report = Some(Report {
+            title: String::from("Not in the library (3)"),
+            lines: vec![
+                String::from("Björk - Hyperballad"),
+                String::from("Daft Punk - Digital Love"),
+                String::from("Radiohead - Creep"),
+            ],
+            scroll: 1,
+        });
+        insta::assert_snapshot!(render(&mut app, 100, 20));
+    }
+
+    #[test]
+    fn playlists_view() {
+        let mut app = library();
+        app.player.playlists.push(Playlist {
+            name: String::from("Road Trip"),
+            path: PathBuf::from("/playlists/Road Trip.m3u"),
+            entries: vec![
+                PathBuf::from("/music/Radiohead - Karma Police.mp3"),
+                PathBuf::from("/music/moved away.mp3"),
+                PathBuf::from("/music/Björk - Hyperballad.mp3"),
+            ],
+        });
+        app.player.view_mode = ViewMode::Playlists;
+        insta::assert_snapshot!("playlists_list", render(&mut app, 100, 20));
+        app.selected_playlist = Some(0);
+        app.scroll_offset = 1;
+        insta::assert_snapshot!("playlist_tracks", render(&mut app, 100, 20));
+    }
+
+    #[test]
+    fn relink_missing_tracks() {
+        let mut app = library();
+        app.player.playlists.push(Playlist {
+            name: String::from("Old"),
+            path: PathBuf::from("/playlists/Old.m3u"),
+            entries: vec![
+                PathBuf::from("/old disk/Radiohead - Karma Police.mp3"),
+                PathBuf::from("/music/Björk - Hyperballad.mp3"),
+                PathBuf::from("/old disk/gone.mp3"),
+            ],
+        });
+        app.start_relink(0);
+        insta::assert_snapshot!("relink_with_candidate", render(&mut app, 100, 20));
+        app.handle_relink_key(KeyEvent::new(KeyCode::Char('n'), KeyModifiers::NONE));
+        insta::assert_snapshot!("relink_without_candidate", render(&mut app, 100, 20));
+        app.handle_relink_key(KeyEvent::new(KeyCode::Char('n'), KeyModifiers::NONE));
+        assert!(app.relink.is_none());
+    }
+
+    #[test]
+    fn queue_keeps_played_tracks_and_turns_away_duplicates() {
+        let mut app = library();
+        app.player.playback.keep_played = true;
+        app.player.queue_config.duplicates = DuplicatePolicy::Reject;
+        app.player.queue_config.max_length = Some(2);
+        app.player.add_to_queue(1).unwrap();
+        app.player.add_to_queue(3).unwrap();
+        app.queue_song(2);
+        assert_eq!(app.message.as_deref(), Some("The queue is full (2 tracks)"));
+        app.player.playback.next(app.player.songs.len());
+        app.queue_song(3);
+        assert_eq!(app.message.as_deref(), Some("Already in the queue"));
+        app.queue_song(2);
+        insta::assert_snapshot!(render(&mut app, 100, 20));
+    }
+
+    #[test]
+    fn named_queue_in_the_title() {
+        let mut app = library();
+        app.player.queues = Queues::default();
+        app.player.add_to_queue(1).unwrap();
+        app.player.queues.active = String::from("work");
+        app.player.queues.store("evening", vec![PathBuf::from("/music/Björk - Hyperballad.mp3")]);
+        app.player.store_queue();
+        app.player.playback.queue = app.player.queue_indices("evening").into();
+        app.player.queues.active = String::from("evening");
+        insta::assert_snapshot!(render(&mut app, 100, 20));
+        assert_eq!(app.player.queue_indices("work"), vec![1]);
+    }
+
+    #[test]
+    fn browse_tabs_keep_their_own_place() {
+        let mut app = library();
+        app.scroll_offset = 2;
+        app.open_session();
+        app.player.view_mode = ViewMode::Artists;
+        app.artist_tree.expand(vec![String::from("Daft Punk")]);
+        let g = KeyEvent::new(KeyCode::Char('g'), KeyModifiers::NONE);
+        let t = KeyEvent::new(KeyCode::Char('t'), KeyModifiers::NONE);
+        app.handle_global_key(g).unwrap();
+        app.handle_global_key(t).unwrap();
+        assert!(app.player.view_mode == ViewMode::AllSongs && app.scroll_offset == 2);
+        app.handle_global_key(g).unwrap();
+        app.handle_global_key(t).unwrap();
+        insta::assert_snapshot!(render(&mut app, 100, 20));
+    }
+
+    #[test]
+    fn queue_focused() {
+        let mut app = library();
+        app.player.add_to_queue(1).unwrap();
+        app.player.add_to_queue(3).unwrap();
+        app.player.add_to_queue(2).unwrap();
+        app.focus = Pane::Queue;
+        app.queue_cursor = 1;
+        insta::assert_snapshot!(render(&mut app, 100, 20));
+    }
+
+    #[test]
+    fn queue_folds_album_runs() {
+        let mut app = library();
+        for i in [0, 2] {
+            app.player.songs[i].album = String::from("Discovery");
+            app.player.songs[i].duration = Some(Duration::from_secs(300));
+        }
+        for i in [0, 2, 1] {
+            app.player.add_to_queue(i).unwrap();
+        }
+        app.focus = Pane::Queue;
+        insta::assert_snapshot!("queue_album_collapsed", render(&mut app, 100, 20));
+        app.toggle_album(0);
+        app.queue_cursor = 2;
+        insta::assert_snapshot!("queue_album_expanded", render(&mut app, 100, 20));
+    }
+
+    #[test]
+    fn sidebar_hidden() {
+        let mut app = library();
+        app.layout.sidebar = false;
+        insta::assert_snapshot!(render(&mut app, 100, 20));
+    }
+
+    #[test]
+    fn sidebar_resized() {
+        let mut app = library();
+        app.layout.sidebar_width = 50;
+        insta::assert_snapshot!(render(&mut app, 100, 20));
+    }
+
+    #[test]
+    fn configured_columns() {
+        let mut app = library();
+        app.columns.songs = ["position:2", "artist:12", "title", "genre:14"]
+            .iter()
+            .map(|spec| spec.parse().unwrap())
+            .collect();
+        insta::assert_snapshot!(render(&mut app, 100, 20));
+    }
+    #[test]
+    fn progress_bar_uses_eighths() {
+        assert_eq!(progress_bar(0.0, 4), "    ");
+        assert_eq!(progress_bar(0.5, 4), "██  ");
+        assert_eq!(progress_bar(0.3, 4), "█▎  ");
+        assert_eq!(progress_bar(1.0, 4), "████");
+    }
+
+    #[test]
+    fn remaining_time_while_paused() {
+        let mut app = library();
+        app.player.songs[0].duration = Some(Duration::from_secs(200));
+        app.player.playback.state = PlaybackState::Paused { position: Duration::from_secs(50) };
+        app.time_display = TimeDisplay::Remaining;
+        insta::assert_snapshot!(render(&mut app, 100, 20));
+    }
+
+    #[test]
+    fn stream_info_shows_the_conversion() {
+        let mut app = library();
+        app.player.playback.state = PlaybackState::Paused { position: Duration::from_secs(5) };
+        app.player.stream = Some(StreamInfo {
+            file_rate: 44_100,
+            device_rate: 48_000,
+            conversion: Some(ResampleQuality::High),
+            file_channels: 6,
+            device_channels: 2,
+            codec: Some(Codec { name: "flac", bits: Some(24), kbps: Some(2116) }),
+        });
+        insta::assert_snapshot!(render(&mut app, 100, 24));
+    }
+
+    #[test]
+    fn queue_shows_whether_the_next_track_is_buffered() {
+        let mut app = library();
+        app.player.playback.state = PlaybackState::Paused { position: Duration::from_secs(5) };
+        let next = app.player.songs[1].path.clone();
+        app.player.preloads.push((next.clone(), false));
+        insta::assert_snapshot!(render(&mut app, 100, 24));
+
+        app.player.handle_player_event(PlayerEvent::Preloaded(next));
+        insta::assert_snapshot!("next_track_buffered", render(&mut app, 100, 24));
+    }
+
+    #[test]
+    fn synced_lyrics_highlight_the_current_line() {
+        let mut app = library();
+        let lrc = "[by:lrclib.net]\n[00:01.00]One more time\n[00:04.00]We're gonna celebrate\n[00:07.00]Oh yeah, all right\n";
+        app.lyrics = Some((app.player.songs[0].path.clone(), Some(Lyrics::parse_lrc(lrc, "x"))));
+        app.player.playback.state = PlaybackState::Paused { position: Duration::from_secs(5) };
+        app.show_lyrics = true;
+        insta::assert_snapshot!(render(&mut app, 100, 20));
+
+        app.lyrics = Some((app.player.songs[0].path.clone(), None));
+        insta::assert_snapshot!("lyrics_not_found", render(&mut app, 100, 20));
+    }
+
+    #[test]
+    fn karaoke_shows_current_and_next_line() {
+        let mut app = library();
+        let lrc = "[00:01.00]One more time\n[00:04.00]We're gonna celebrate\n[00:07.00]Oh yeah, all right\n";
+        app.lyrics = Some((app.player.songs[0].path.clone(), Some(Lyrics::parse_lrc(lrc, "lrclib.net"))));
+        app.player.playback.state = PlaybackState::Paused { position: Duration::from_secs(2) };
+        app.karaoke = true;
+        insta::assert_snapshot!(render(&mut app, 60, 20));
+        app.handle_karaoke_key(KeyEvent::new(KeyCode::Esc, KeyModifiers::NONE));
+        assert!(!app.karaoke);
+    }
+
+    #[test]
+    fn perform_mode_jumps_to_cues() {
+        let mut app = library();
+        let path = app.player.songs[0].path.clone();
+        app.cues.set(&path, 0, Duration::from_secs(12));
+        app.cues.set(&path, 3, Duration::from_secs(95));
+        app.player.playback.state = PlaybackState::Paused { position: Duration::from_secs(50) };
+        app.perform = true;
+        insta::assert_snapshot!(render(&mut app, 100, 20));
+
+        app.handle_perform_key(KeyEvent::new(KeyCode::Char('4'), KeyModifiers::NONE));
+        assert_eq!(app.player.playback.state, PlaybackState::Paused { position: Duration::from_secs(95) });
+    }
+
+    #[test]
+    fn decks_load_from_the_queue() {
+        let mut app = library();
+        app.player.playback.queue.extend([1, 3]);
+        app.player.songs[1].duration = Some(Duration::from_secs(264));
+        app.open_decks();
+        app.handle_deck_key(KeyEvent::new(KeyCode::Char('a'), KeyModifiers::NONE));
+        app.handle_deck_key(KeyEvent::new(KeyCode::Char('d'), KeyModifiers::NONE));
+        app.handle_deck_key(KeyEvent::new(KeyCode::Char('x'), KeyModifiers::NONE));
+        app.handle_deck_key(KeyEvent::new(KeyCode::Right, KeyModifiers::NONE));
+        app.handle_deck_key(KeyEvent::new(KeyCode::Right, KeyModifiers::NONE));
+        assert_eq!(app.player.playback.queue, [3]);
+        insta::assert_snapshot!(render(&mut app, 100, 20));
+
+        app.handle_deck_key(KeyEvent::new(KeyCode::Esc, KeyModifiers::NONE));
+        assert!(app.decks.is_none());
+    }
+
+    #[test]
+    fn clicking_a_header_sorts_by_it() {
+        let mut app = library();
+        render(&mut app, 100, 20);
+        let artist = app.header_cells.iter()
+            .find(|cell| cell.view == ViewMode::AllSongs && cell.column == Column::Artist)
+            .map(|cell| cell.area)
+            .unwrap();
+        let click = MouseEvent {
+            kind: MouseEventKind::Down(MouseButton::Left),
+            column: artist.x,
+            row: artist.y,
+            modifiers: KeyModifiers::NONE,
+        };
+        app.update(Action::Input(Event::Mouse(click))).unwrap();
+        app.update(Action::Input(Event::Mouse(click))).unwrap();
+        insta::assert_snapshot!(render(&mut app, 100, 20));
+    }
+
+    #[test]
+    fn library_sort_orders_songs_and_search() {
+        let mut app = library();
+        for (i, track) in [(0, 1), (2, 3)] {
+            app.player.songs[i].album = String::from("Discovery");
+            app.player.songs[i].track = Some(track);
+        }
+        app.player.add_to_queue(0).unwrap();
+        app.player.set_library_sort(Some(LibrarySort::Artist));
+        let titles: Vec<&str> = app.player.songs.iter().map(|song| song.title.as_str()).collect();
+        assert_eq!(titles, ["Hyperballad", "One More Time", "Digital Love", "Karma Police"]);
+        assert_eq!(app.player.playback.queue, [1]);
+        app.player.search("l");
+        assert_eq!(app.player.search_results, [0, 2, 3]);
+        insta::assert_snapshot!(render(&mut app, 100, 20));
+    }
+
+    #[test]
+    fn shuffle_keeps_the_queue_on_its_songs() {
+        let mut app = library();
+        app.player.set_library_sort(Some(LibrarySort::Title));
+        app.player.add_to_queue(1).unwrap();
+        let queued = app.player.songs[1].path.clone();
+        app.player.shuffle();
+        assert_eq!(app.player.songs[app.player.playback.queue[0]].path, queued);
+        assert_eq!(app.player.library_sort, None);
+    }
+
+    #[test]
+    fn a_scan_moves_numbers_to_files_found_moved() {
+        let mut app = library();
+        let (old, new) = (PathBuf::from("/music/old/Karma Police.mp3"), app.player.songs[1].path.clone());
+        app.player.stats.songs.insert(old.clone(), SongStats { plays: 3, ..SongStats::default() });
+        app.player.stats.hashes.insert(old.clone(), 42);
+        let gone = [old.clone()].into_iter().collect();
+        let hashes = HashMap::from([(new.clone(), 42), (app.player.songs[0].path.clone(), 7)]);
+        app.player.handle_scan_event(ScanEvent::Moved(gone, hashes));
+        assert_eq!(app.player.stats.get(&new).plays, 3);
+        assert!(!app.player.stats.songs.contains_key(&old));
+        assert_eq!(app.player.relinked, 1);
+    }
+
+    #[test]
+    fn a_device_found_while_stopped_starts_nothing() {
+        let mut app = library();
+        let message = app.player.handle_player_event(PlayerEvent::DeviceRestored(Duration::ZERO));
+        assert_eq!(message.as_deref(), Some("Output device found"));
+        assert_eq!(app.player.playback.state, PlaybackState::Stopped);
+    }
+
+    #[test]
+    fn every_built_in_tab_has_a_default_key() {
+        let app = library();
+        assert_eq!(app.tab_key_index(KeyCode::Char('8')), Some(ViewMode::ALL.len() - 1));
+    }
+
+    #[test]
+    fn encoding_write_leaves_the_playing_file_alone() {
+        let mut app = library();
+        app.player.songs[3].artist = String::from("BjÃ¶rk");
+        app.player.playback.current = 3;
+        app.player.playback.state = PlaybackState::Playing;
+        app.repair_encodings(true);
+        assert_eq!(app.player.songs[3].artist, "Björk");
+        let message = app.message.clone().unwrap();
+        assert!(message.ends_with("Hyperballad.mp3: playing; its tags can be written once it stops)"), "{}", message);
+    }
+}
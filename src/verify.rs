@@ -0,0 +1,281 @@
+use std::{fs, path::Path, time::Duration};
+
+use rodio::Source;
+
+use crate::decode;
+
+// Ends this much short of the length the file gives still count as whole,
+// as decoders round to their frame size
+const TRUNCATION_SLACK: Duration = Duration::from_millis(100);
+
+// What `:verify` made of a file, after decoding every sample of it
+#[derive(Clone, Debug, PartialEq)]
+pub enum Verdict {
+    // Decoded to its end, and matched its MD5 signature if it was checked
+    Intact { md5_checked: bool },
+    // No decoder could open it
+    Unreadable(String),
+    // Decoding stopped short of the length the file gives
+    Truncated { decoded: Duration, expected: Duration },
+    // Every sample is there, but they aren't the ones the encoder was given
+    Corrupt,
+}
+
+impl Verdict {
+    pub fn is_problem(&self) -> bool {
+        !matches!(self, Verdict::Intact { .. })
+    }
+
+    pub fn describe(&self) -> String {
+        match self {
+            Verdict::Intact { md5_checked: true } => String::from("Intact, MD5 matches"),
+            Verdict::Intact { md5_checked: false } => String::from("Intact"),
+            Verdict::Unreadable(error) => format!("Unreadable: {}", error),
+            Verdict::Truncated { decoded, expected } => format!(
+                "Truncated: ends at {:.1}s of {:.1}s",
+                decoded.as_secs_f64(),
+                expected.as_secs_f64()
+            ),
+            Verdict::Corrupt => String::from("Corrupt: MD5 signature doesn't match the audio"),
+        }
+    }
+}
+
+// Decodes the whole file. FLAC files carry the MD5 of their audio, which is
+// checked for 16-bit ones, as those decode to exactly the encoder's samples.
+pub fn verify(path: &Path) -> Verdict {
+    let bytes = fs::read(path).unwrap_or_default();
+    let header = &bytes[decode::stream_start(&bytes).unwrap_or(0)..];
+    let info = FlacInfo::parse(header);
+    let wav = wav_data(header);
+    let (source, diagnostics) = decode::open(path);
+    let Some(source) = source else {
+        return Verdict::Unreadable(diagnostics.first_error().to_string());
+    };
+    let (channels, rate) = (source.channels().max(1) as u64, source.sample_rate().max(1) as u64);
+    // The decoders don't all know, so the headers are asked first
+    let expected = match info {
+        Some(info) => Some(Duration::from_secs_f64(info.total_frames as f64 / info.sample_rate.max(1) as f64)),
+        None => wav.map(|(declared, _)| declared).or(source.total_duration()),
+    };
+    // An MD5 of zeros means the encoder didn't work one out
+    let mut md5 = info.filter(|info| info.bits_per_sample == 16 && info.md5 != [0; 16]).map(|_| Md5::new());
+
+    let mut samples = 0u64;
+    let mut bytes = Vec::with_capacity(8192);
+    for sample in source {
+        samples += 1;
+        if let Some(md5) = md5.as_mut() {
+            bytes.extend_from_slice(&sample.to_le_bytes());
+            if bytes.len() >= 8192 {
+                md5.update(&bytes);
+                bytes.clear();
+            }
+        }
+    }
+    let decoded = Duration::from_secs_f64((samples / channels) as f64 / rate as f64);
+    // A WAV file cut short decodes with silence in place of what's missing,
+    // so only its size gives it away
+    let decoded = wav.map_or(decoded, |(_, present)| decoded.min(present));
+    if let Some(expected) = expected.filter(|&expected| decoded + TRUNCATION_SLACK < expected) {
+        return Verdict::Truncated { decoded, expected };
+    }
+    match (md5, info) {
+        (Some(mut md5), Some(info)) => {
+            md5.update(&bytes);
+            if md5.finish() == info.md5 {
+                Verdict::Intact { md5_checked: true }
+            } else {
+                Verdict::Corrupt
+            }
+        }
+        _ => Verdict::Intact { md5_checked: false },
+    }
+}
+
+// What a FLAC file's STREAMINFO block says about its audio
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct FlacInfo {
+    sample_rate: u32,
+    bits_per_sample: u8,
+    total_frames: u64,
+    md5: [u8; 16],
+}
+
+impl FlacInfo {
+    // STREAMINFO is always the first metadata block, right after "fLaC"
+    fn parse(bytes: &[u8]) -> Option<FlacInfo> {
+        if bytes.get(..4)? != b"fLaC" || bytes.get(4)? & 0x7f != 0 {
+            return None;
+        }
+        let block = bytes.get(8..8 + 34)?;
+        // 20 bits of sample rate, 3 of channels, 5 of bits per sample and 36 of frames
+        let packed = u64::from_be_bytes(block[10..18].try_into().ok()?);
+        Some(FlacInfo {
+            sample_rate: (packed >> 44) as u32,
+            bits_per_sample: ((packed >> 36) & 0x1f) as u8 + 1,
+            total_frames: packed & 0xf_ffff_ffff,
+            md5: block[18..34].try_into().ok()?,
+        })
+    }
+}
+
+// How long a WAV file's data chunk says it plays, and how much of that the
+// file holds
+fn wav_data(bytes: &[u8]) -> Option<(Duration, Duration)> {
+    if bytes.get(..4)? != b"RIFF" || bytes.get(8..12)? != b"WAVE" {
+        return None;
+    }
+    let (mut position, mut bytes_per_second) = (12, None);
+    while let Some(chunk) = bytes.get(position..position + 8) {
+        let size = u32::from_le_bytes(chunk[4..8].try_into().ok()?);
+        match &chunk[..4] {
+            b"fmt " => {
+                let format = bytes.get(position + 8..position + 24)?;
+                bytes_per_second = Some(u32::from_le_bytes(format[8..12].try_into().ok()?));
+            }
+            b"data" => {
+                let per_second = bytes_per_second?.max(1) as f64;
+                let present = bytes.len().saturating_sub(position + 8).min(size as usize);
+                return Some((
+                    Duration::from_secs_f64(size as f64 / per_second),
+                    Duration::from_secs_f64(present as f64 / per_second),
+                ));
+            }
+            _ => {}
+        }
+        // Chunks are padded to an even length
+        position += 8 + size as usize + size as usize % 2;
+    }
+    None
+}
+
+// MD5 as in RFC 1321, only for comparing with what FLAC encoders store
+struct Md5 {
+    state: [u32; 4],
+    pending: Vec<u8>,
+    length: u64,
+}
+
+const SHIFTS: [u32; 16] = [7, 12, 17, 22, 5, 9, 14, 20, 4, 11, 16, 23, 6, 10, 15, 21];
+
+impl Md5 {
+    fn new() -> Self {
+        Md5 { state: [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476], pending: Vec::new(), length: 0 }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        self.length += data.len() as u64;
+        self.pending.extend_from_slice(data);
+        let whole = self.pending.len() / 64 * 64;
+        for block in self.pending[..whole].chunks_exact(64) {
+            self.state = compress(self.state, block);
+        }
+        self.pending.drain(..whole);
+    }
+
+    fn finish(mut self) -> [u8; 16] {
+        let bits = self.length.wrapping_mul(8);
+        let mut padding = vec![0x80];
+        padding.resize((119 - self.pending.len()) % 64 + 1, 0);
+        padding.extend_from_slice(&bits.to_le_bytes());
+        self.update(&padding);
+        let mut digest = [0; 16];
+        for (bytes, word) in digest.chunks_exact_mut(4).zip(self.state) {
+            bytes.copy_from_slice(&word.to_le_bytes());
+        }
+        digest
+    }
+}
+
+fn compress(state: [u32; 4], block: &[u8]) -> [u32; 4] {
+    let words: Vec<u32> = block.chunks_exact(4).map(|w| u32::from_le_bytes([w[0], w[1], w[2], w[3]])).collect();
+    let [mut a, mut b, mut c, mut d] = state;
+    for i in 0..64 {
+        let (f, g) = match i / 16 {
+            0 => ((b & c) | (!b & d), i),
+            1 => ((d & b) | (!d & c), (5 * i + 1) % 16),
+            2 => (b ^ c ^ d, (3 * i + 5) % 16),
+            _ => (c ^ (b | !d), (7 * i) % 16),
+        };
+        let k = ((i as f64 + 1.0).sin().abs() * 4_294_967_296.0) as u32;
+        let rotated = a.wrapping_add(f).wrapping_add(k).wrapping_add(words[g]).rotate_left(SHIFTS[i / 16 * 4 + i % 4]);
+        (a, b, c, d) = (d, b.wrapping_add(rotated), b, c);
+    }
+    [state[0].wrapping_add(a), state[1].wrapping_add(b), state[2].wrapping_add(c), state[3].wrapping_add(d)]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(digest: [u8; 16]) -> String {
+        digest.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    fn md5(data: &[u8]) -> String {
+        let mut md5 = Md5::new();
+        md5.update(data);
+        hex(md5.finish())
+    }
+
+    #[test]
+    fn md5_matches_the_rfc() {
+        assert_eq!(md5(b""), "d41d8cd98f00b204e9800998ecf8427e");
+        assert_eq!(md5(b"abc"), "900150983cd24fb0d6963f7d28e17f72");
+        assert_eq!(
+            md5(b"12345678901234567890123456789012345678901234567890123456789012345678901234567890"),
+            "57edf4a22be3c955ac49da2e2107b67a"
+        );
+    }
+
+    #[test]
+    fn reads_flac_stream_info() {
+        let mut bytes = b"fLaC".to_vec();
+        bytes.extend([0x80, 0, 0, 34]);
+        bytes.extend([0x10, 0x00, 0x10, 0x00, 0, 0, 0, 0, 0, 0]);
+        // 44100 Hz, stereo, 16 bits, 88200 frames
+        let packed: u64 = (44_100 << 44) | (1 << 41) | (15 << 36) | 88_200;
+        bytes.extend(packed.to_be_bytes());
+        bytes.extend([0xab; 16]);
+        let info = FlacInfo::parse(&bytes).unwrap();
+        assert_eq!(info, FlacInfo { sample_rate: 44_100, bits_per_sample: 16, total_frames: 88_200, md5: [0xab; 16] });
+
+        assert_eq!(FlacInfo::parse(b"OggS"), None);
+    }
+
+    // 16-bit mono PCM, with a header claiming `frames` of it
+    fn wav(frames: u32, samples: &[i16]) -> Vec<u8> {
+        let mut bytes = b"RIFF".to_vec();
+        bytes.extend((36 + frames * 2).to_le_bytes());
+        bytes.extend(b"WAVEfmt ");
+        bytes.extend(16u32.to_le_bytes());
+        bytes.extend([1, 0, 1, 0]);
+        bytes.extend(8000u32.to_le_bytes());
+        bytes.extend(16_000u32.to_le_bytes());
+        bytes.extend([2, 0, 16, 0]);
+        bytes.extend(b"data");
+        bytes.extend((frames * 2).to_le_bytes());
+        bytes.extend(samples.iter().flat_map(|s| s.to_le_bytes()));
+        bytes
+    }
+
+    #[test]
+    fn short_files_are_truncated() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("track.wav");
+        let samples = vec![100i16; 8000];
+        fs::write(&path, wav(8000, &samples)).unwrap();
+        assert_eq!(verify(&path), Verdict::Intact { md5_checked: false });
+
+        fs::write(&path, wav(8000, &samples[..4000])).unwrap();
+        assert!(matches!(verify(&path), Verdict::Truncated { .. }));
+    }
+
+    #[test]
+    fn missing_files_are_unreadable() {
+        let verdict = verify(Path::new("/nonexistent/track.flac"));
+        assert!(verdict.is_problem());
+        assert!(matches!(verdict, Verdict::Unreadable(_)));
+    }
+}
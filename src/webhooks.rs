@@ -0,0 +1,137 @@
+use std::{
+    collections::VecDeque,
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::clock;
+
+// Deliveries kept for `:webhooks`, oldest dropped first
+const LOG_LENGTH: usize = 50;
+
+// What a webhook can be told about
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Event {
+    // A track reached the device
+    Start,
+    Stop,
+    // Next or previous while something was playing; sent for the track left
+    Skip,
+    // Anything queued, removed, reordered or another queue switched to
+    Queue,
+}
+
+impl Event {
+    pub const ALL: [Event; 4] = [Event::Start, Event::Stop, Event::Skip, Event::Queue];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Event::Start => "start",
+            Event::Stop => "stop",
+            Event::Skip => "skip",
+            Event::Queue => "queue",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Delivery {
+    // Seconds since the epoch of the first attempt
+    pub at: u64,
+    pub event: Event,
+    pub url: String,
+    pub attempts: u32,
+    // Why the last attempt failed; None once one got through
+    pub error: Option<String>,
+}
+
+impl Delivery {
+    // "21:07  start  ok  https://…", with local time `offset` seconds from UTC
+    pub fn line(&self, offset: i64) -> String {
+        let time = clock::hours_minutes(UNIX_EPOCH + Duration::from_secs(self.at), offset);
+        let outcome = match &self.error {
+            None if self.attempts > 1 => format!("ok after {} attempts", self.attempts),
+            None => String::from("ok"),
+            Some(e) => format!("failed after {} attempts: {}", self.attempts, e),
+        };
+        format!("{}  {:<5}  {}  {}", time, self.event.name(), outcome, self.url)
+    }
+}
+
+#[derive(Default)]
+pub struct Log {
+    deliveries: VecDeque<Delivery>,
+}
+
+impl Log {
+    pub fn push(&mut self, delivery: Delivery) {
+        if self.deliveries.len() == LOG_LENGTH {
+            self.deliveries.pop_front();
+        }
+        self.deliveries.push_back(delivery);
+    }
+
+    // Newest first
+    pub fn recent(&self) -> Vec<Delivery> {
+        self.deliveries.iter().rev().cloned().collect()
+    }
+}
+
+// Calls `post` until it succeeds, at most `retries` more times after the
+// first, waiting `backoff` and then twice as long again between attempts
+pub fn deliver(event: Event, url: &str, retries: u32, backoff: Duration, mut post: impl FnMut() -> Result<()>) -> Delivery {
+    let at = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs());
+    let mut delivery = Delivery { at, event, url: url.to_string(), attempts: 0, error: None };
+    let mut wait = backoff;
+    loop {
+        delivery.attempts += 1;
+        match post() {
+            Ok(()) => {
+                delivery.error = None;
+                return delivery;
+            }
+            Err(e) => delivery.error = Some(e.to_string()),
+        }
+        if delivery.attempts > retries {
+            return delivery;
+        }
+        thread::sleep(wait);
+        wait *= 2;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retries_until_delivered() {
+        let mut failures = 2;
+        let delivery = deliver(Event::Start, "http://hook", 3, Duration::ZERO, || {
+            if failures == 0 {
+                return Ok(());
+            }
+            failures -= 1;
+            Err(anyhow::anyhow!("connection refused"))
+        });
+        assert_eq!((delivery.attempts, delivery.error), (3, None));
+
+        let delivery = deliver(Event::Queue, "http://hook", 1, Duration::ZERO, || Err(anyhow::anyhow!("curl exited with 22")));
+        assert_eq!((delivery.attempts, delivery.error.as_deref()), (2, Some("curl exited with 22")));
+        assert!(delivery.line(0).ends_with("queue  failed after 2 attempts: curl exited with 22  http://hook"));
+    }
+
+    #[test]
+    fn log_keeps_the_newest() {
+        let mut log = Log::default();
+        for at in 0..LOG_LENGTH as u64 + 5 {
+            log.push(Delivery { at, event: Event::Skip, url: String::new(), attempts: 1, error: None });
+        }
+        let recent = log.recent();
+        assert_eq!((recent.len(), recent[0].at, recent[LOG_LENGTH - 1].at), (LOG_LENGTH, LOG_LENGTH as u64 + 4, 5));
+    }
+}